@@ -6,8 +6,8 @@
 import 'frb_generated.dart';
 import 'package:flutter_rust_bridge/flutter_rust_bridge_for_generated.dart';
 
-// These functions are ignored because they are not marked as `pub`: `auth_over_stream`, `build_root_store_from_pem`, `fetch_history_over_stream`, `make_tls_stream`, `read_line`
-// These function are ignored because they are on traits that is not defined in current crate (put an empty `#[frb]` on it to unignore): `clone`, `clone`, `clone`, `clone`, `fmt`, `fmt`, `fmt`, `fmt`, `from`
+// These functions are ignored because they are not marked as `pub`: `auth_over_stream`, `build_root_store_from_pem`, `download_over_stream`, `exchange`, `fetch_history_over_stream`, `load_client_identity`, `make_tls_stream`, `make_tls_stream_with_identity`, `read_line`, `run_message_stream`, `sha256_hex`, `upload_over_stream`, `write_envelope`
+// These function are ignored because they are on traits that is not defined in current crate (put an empty `#[frb]` on it to unignore): `clone`, `clone`, `clone`, `clone`, `clone`, `fmt`, `fmt`, `fmt`, `fmt`, `fmt`, `from`
 
 /// Login to the TLS-only server and return the auth response.
 ///
@@ -29,6 +29,27 @@ Future<LoginResponse> loginTls({
   password: password,
 );
 
+/// Login with a client certificate (mutual TLS) instead of a password.
+///
+/// - `ca_pem`: the server's CA certificate (PEM), as for `login_tls`
+/// - `client_cert_pem`, `client_key_pem`: the device certificate and its key
+///
+/// Returns `success: false` when the server accepted the certificate but it is
+/// not linked to an account.
+Future<LoginResponse> loginMtls({
+  required String host,
+  required int port,
+  required String caPem,
+  required String clientCertPem,
+  required String clientKeyPem,
+}) => RustLib.instance.api.crateApiLoginMtls(
+  host: host,
+  port: port,
+  caPem: caPem,
+  clientCertPem: clientCertPem,
+  clientKeyPem: clientKeyPem,
+);
+
 /// Register a new user against the TLS-only server and return the auth response.
 Future<LoginResponse> registerTls({
   required String host,
@@ -45,24 +66,32 @@ Future<LoginResponse> registerTls({
 );
 
 /// Login and send a direct message in a single TLS session.
+/// `attachment_id` is a finished upload from `upload_attachment_tls`.
+/// A linked client certificate stands in for the password.
 Future<SendResult> sendDirectMessageTls({
   required String host,
   required int port,
   required String caPem,
   required String passphrase,
   required String password,
+  String? clientCertPem,
+  String? clientKeyPem,
   required PlatformInt64 toUserId,
   required String body,
   bool? saved,
+  PlatformInt64? attachmentId,
 }) => RustLib.instance.api.crateApiSendDirectMessageTls(
   host: host,
   port: port,
   caPem: caPem,
   passphrase: passphrase,
   password: password,
+  clientCertPem: clientCertPem,
+  clientKeyPem: clientKeyPem,
   toUserId: toUserId,
   body: body,
   saved: saved,
+  attachmentId: attachmentId,
 );
 
 Stream<String> openMessageStreamTls({
@@ -71,12 +100,16 @@ Stream<String> openMessageStreamTls({
   required String caPem,
   required String passphrase,
   required String password,
+  String? clientCertPem,
+  String? clientKeyPem,
 }) => RustLib.instance.api.crateApiOpenMessageStreamTls(
   host: host,
   port: port,
   caPem: caPem,
   passphrase: passphrase,
   password: password,
+  clientCertPem: clientCertPem,
+  clientKeyPem: clientKeyPem,
 );
 
 /// Send a direct message using an existing open stream session for the given user_id.
@@ -85,20 +118,79 @@ Future<void> sendDirectMessageOverStream({
   required PlatformInt64 toUserId,
   required String body,
   bool? saved,
+  PlatformInt64? attachmentId,
 }) => RustLib.instance.api.crateApiSendDirectMessageOverStream(
   userId: userId,
   toUserId: toUserId,
   body: body,
   saved: saved,
+  attachmentId: attachmentId,
+);
+
+/// Upload a file as an attachment in one TLS session, reporting progress
+/// after every chunk. The first event carries the new `attachment_id`, to
+/// pass to `send_direct_message_tls`; the stream ends once the server
+/// checked the file. Logs in like `login_and_fetch_history_tls`.
+Stream<TransferProgress> uploadAttachmentTls({
+  required String host,
+  required int port,
+  required String caPem,
+  required String passphrase,
+  required String password,
+  String? clientCertPem,
+  String? clientKeyPem,
+  required String filePath,
+  required String mimeType,
+}) => RustLib.instance.api.crateApiUploadAttachmentTls(
+  host: host,
+  port: port,
+  caPem: caPem,
+  passphrase: passphrase,
+  password: password,
+  clientCertPem: clientCertPem,
+  clientKeyPem: clientKeyPem,
+  filePath: filePath,
+  mimeType: mimeType,
+);
+
+/// Download an attachment to `dest_path` in one TLS session, reporting
+/// progress after every chunk. A failed download leaves no file behind.
+/// Logs in like `login_and_fetch_history_tls`.
+Stream<TransferProgress> downloadAttachmentTls({
+  required String host,
+  required int port,
+  required String caPem,
+  required String passphrase,
+  required String password,
+  String? clientCertPem,
+  String? clientKeyPem,
+  required PlatformInt64 attachmentId,
+  required String destPath,
+}) => RustLib.instance.api.crateApiDownloadAttachmentTls(
+  host: host,
+  port: port,
+  caPem: caPem,
+  passphrase: passphrase,
+  password: password,
+  clientCertPem: clientCertPem,
+  clientKeyPem: clientKeyPem,
+  attachmentId: attachmentId,
+  destPath: destPath,
 );
 
 /// Login and fetch message history in one TLS session.
+///
+/// With `client_cert_pem` and `client_key_pem` set, a device certificate
+/// linked to an account logs the session in as `login_mtls` does, and
+/// `passphrase`/`password` are only sent if the certificate is not linked.
 Future<HistoryBundle> loginAndFetchHistoryTls({
   required String host,
   required int port,
   required String caPem,
   required String passphrase,
   required String password,
+  String? clientCertPem,
+  String? clientKeyPem,
   BigInt? limit,
 }) => RustLib.instance.api.crateApiLoginAndFetchHistoryTls(
   host: host,
@@ -106,6 +198,8 @@ Future<HistoryBundle> loginAndFetchHistoryTls({
   caPem: caPem,
   passphrase: passphrase,
   password: password,
+  clientCertPem: clientCertPem,
+  clientKeyPem: clientKeyPem,
   limit: limit,
 );
 
@@ -164,6 +258,22 @@ class HistoryMessage {
   final String timestamp;
   final bool saved;
 
+  /// Message this one replies to, if any.
+  final PlatformInt64? replyToId;
+
+  /// Start of the replied-to message; empty when not a reply or when that
+  /// message was deleted.
+  final String replySnippet;
+
+  /// File attached to the message, if any.
+  final PlatformInt64? attachmentId;
+
+  /// Name of the attached file; empty without an attachment.
+  final String attachmentName;
+
+  /// Preview of an attached image, downloadable like the attachment.
+  final PlatformInt64? thumbnailId;
+
   const HistoryMessage({
     required this.id,
     required this.fromUserId,
@@ -171,6 +281,11 @@ class HistoryMessage {
     required this.body,
     required this.timestamp,
     required this.saved,
+    this.replyToId,
+    required this.replySnippet,
+    this.attachmentId,
+    required this.attachmentName,
+    this.thumbnailId,
   });
 
   @override
@@ -180,7 +295,12 @@ class HistoryMessage {
       toUserId.hashCode ^
       body.hashCode ^
       timestamp.hashCode ^
-      saved.hashCode;
+      saved.hashCode ^
+      replyToId.hashCode ^
+      replySnippet.hashCode ^
+      attachmentId.hashCode ^
+      attachmentName.hashCode ^
+      thumbnailId.hashCode;
 
   @override
   bool operator ==(Object other) =>
@@ -192,7 +312,12 @@ class HistoryMessage {
           toUserId == other.toUserId &&
           body == other.body &&
           timestamp == other.timestamp &&
-          saved == other.saved;
+          saved == other.saved &&
+          replyToId == other.replyToId &&
+          replySnippet == other.replySnippet &&
+          attachmentId == other.attachmentId &&
+          attachmentName == other.attachmentName &&
+          thumbnailId == other.thumbnailId;
 }
 
 /// Simple Dart-friendly login response.
@@ -238,3 +363,33 @@ class SendResult {
           success == other.success &&
           message == other.message;
 }
+
+/// Progress of an attachment upload or download.
+class TransferProgress {
+  final PlatformInt64 attachmentId;
+
+  /// Bytes sent or received so far.
+  final BigInt transferred;
+
+  /// Size of the whole file.
+  final BigInt total;
+
+  const TransferProgress({
+    required this.attachmentId,
+    required this.transferred,
+    required this.total,
+  });
+
+  @override
+  int get hashCode =>
+      attachmentId.hashCode ^ transferred.hashCode ^ total.hashCode;
+
+  @override
+  bool operator ==(Object other) =>
+      identical(this, other) ||
+      other is TransferProgress &&
+          runtimeType == other.runtimeType &&
+          attachmentId == other.attachmentId &&
+          transferred == other.transferred &&
+          total == other.total;
+}
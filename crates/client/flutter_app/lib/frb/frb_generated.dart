@@ -64,7 +64,7 @@ class RustLib extends BaseEntrypoint<RustLibApi, RustLibApiImpl, RustLibWire> {
   String get codegenVersion => '2.11.1';
 
   @override
-  int get rustContentHash => 2067188788;
+  int get rustContentHash => 2128840040;
 
   static const kDefaultExternalLibraryLoaderConfig =
       ExternalLibraryLoaderConfig(
@@ -75,15 +75,37 @@ class RustLib extends BaseEntrypoint<RustLibApi, RustLibApiImpl, RustLibWire> {
 }
 
 abstract class RustLibApi extends BaseApi {
+  Stream<TransferProgress> crateApiDownloadAttachmentTls({
+    required String host,
+    required int port,
+    required String caPem,
+    required String passphrase,
+    required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
+    required PlatformInt64 attachmentId,
+    required String destPath,
+  });
+
   Future<HistoryBundle> crateApiLoginAndFetchHistoryTls({
     required String host,
     required int port,
     required String caPem,
     required String passphrase,
     required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
     BigInt? limit,
   });
 
+  Future<LoginResponse> crateApiLoginMtls({
+    required String host,
+    required int port,
+    required String caPem,
+    required String clientCertPem,
+    required String clientKeyPem,
+  });
+
   Future<LoginResponse> crateApiLoginTls({
     required String host,
     required int port,
@@ -98,6 +120,8 @@ abstract class RustLibApi extends BaseApi {
     required String caPem,
     required String passphrase,
     required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
   });
 
   Future<HistoryBundle> crateApiRegisterAndFetchHistoryTls({
@@ -122,6 +146,7 @@ abstract class RustLibApi extends BaseApi {
     required PlatformInt64 toUserId,
     required String body,
     bool? saved,
+    PlatformInt64? attachmentId,
   });
 
   Future<SendResult> crateApiSendDirectMessageTls({
@@ -130,9 +155,24 @@ abstract class RustLibApi extends BaseApi {
     required String caPem,
     required String passphrase,
     required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
     required PlatformInt64 toUserId,
     required String body,
     bool? saved,
+    PlatformInt64? attachmentId,
+  });
+
+  Stream<TransferProgress> crateApiUploadAttachmentTls({
+    required String host,
+    required int port,
+    required String caPem,
+    required String passphrase,
+    required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
+    required String filePath,
+    required String mimeType,
   });
 }
 
@@ -144,6 +184,81 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     required super.portManager,
   });
 
+  @override
+  Stream<TransferProgress> crateApiDownloadAttachmentTls({
+    required String host,
+    required int port,
+    required String caPem,
+    required String passphrase,
+    required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
+    required PlatformInt64 attachmentId,
+    required String destPath,
+  }) {
+    final sink = RustStreamSink<TransferProgress>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_String(host, serializer);
+            sse_encode_u_16(port, serializer);
+            sse_encode_String(caPem, serializer);
+            sse_encode_String(passphrase, serializer);
+            sse_encode_String(password, serializer);
+            sse_encode_opt_String(clientCertPem, serializer);
+            sse_encode_opt_String(clientKeyPem, serializer);
+            sse_encode_i_64(attachmentId, serializer);
+            sse_encode_String(destPath, serializer);
+            sse_encode_StreamSink_transfer_progress_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 1,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_String,
+          ),
+          constMeta: kCrateApiDownloadAttachmentTlsConstMeta,
+          argValues: [
+            host,
+            port,
+            caPem,
+            passphrase,
+            password,
+            clientCertPem,
+            clientKeyPem,
+            attachmentId,
+            destPath,
+            sink,
+          ],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiDownloadAttachmentTlsConstMeta => const TaskConstMeta(
+    debugName: "download_attachment_tls",
+    argNames: [
+          "host",
+          "port",
+          "caPem",
+          "passphrase",
+          "password",
+          "clientCertPem",
+          "clientKeyPem",
+          "attachmentId",
+          "destPath",
+          "sink",
+        ],
+  );
+
   @override
   Future<HistoryBundle> crateApiLoginAndFetchHistoryTls({
     required String host,
@@ -151,6 +266,8 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     required String caPem,
     required String passphrase,
     required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
     BigInt? limit,
   }) {
     return handler.executeNormal(
@@ -162,11 +279,13 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           sse_encode_String(caPem, serializer);
           sse_encode_String(passphrase, serializer);
           sse_encode_String(password, serializer);
+          sse_encode_opt_String(clientCertPem, serializer);
+          sse_encode_opt_String(clientKeyPem, serializer);
           sse_encode_opt_box_autoadd_usize(limit, serializer);
           pdeCallFfi(
             generalizedFrbRustBinding,
             serializer,
-            funcId: 1,
+            funcId: 2,
             port: port_,
           );
         },
@@ -175,7 +294,16 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           decodeErrorData: sse_decode_String,
         ),
         constMeta: kCrateApiLoginAndFetchHistoryTlsConstMeta,
-        argValues: [host, port, caPem, passphrase, password, limit],
+        argValues: [
+          host,
+          port,
+          caPem,
+          passphrase,
+          password,
+          clientCertPem,
+          clientKeyPem,
+          limit,
+        ],
         apiImpl: this,
       ),
     );
@@ -184,9 +312,58 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   TaskConstMeta get kCrateApiLoginAndFetchHistoryTlsConstMeta =>
       const TaskConstMeta(
         debugName: "login_and_fetch_history_tls",
-        argNames: ["host", "port", "caPem", "passphrase", "password", "limit"],
+        argNames: [
+          "host",
+          "port",
+          "caPem",
+          "passphrase",
+          "password",
+          "clientCertPem",
+          "clientKeyPem",
+          "limit",
+        ],
       );
 
+  @override
+  Future<LoginResponse> crateApiLoginMtls({
+    required String host,
+    required int port,
+    required String caPem,
+    required String clientCertPem,
+    required String clientKeyPem,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(host, serializer);
+          sse_encode_u_16(port, serializer);
+          sse_encode_String(caPem, serializer);
+          sse_encode_String(clientCertPem, serializer);
+          sse_encode_String(clientKeyPem, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 3,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_login_response,
+          decodeErrorData: sse_decode_String,
+        ),
+        constMeta: kCrateApiLoginMtlsConstMeta,
+        argValues: [host, port, caPem, clientCertPem, clientKeyPem],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiLoginMtlsConstMeta => const TaskConstMeta(
+    debugName: "login_mtls",
+    argNames: ["host", "port", "caPem", "clientCertPem", "clientKeyPem"],
+  );
+
   @override
   Future<LoginResponse> crateApiLoginTls({
     required String host,
@@ -207,7 +384,7 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           pdeCallFfi(
             generalizedFrbRustBinding,
             serializer,
-            funcId: 2,
+            funcId: 4,
             port: port_,
           );
         },
@@ -234,6 +411,8 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     required String caPem,
     required String passphrase,
     required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
   }) {
     final sink = RustStreamSink<String>();
     unawaited(
@@ -246,11 +425,13 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
             sse_encode_String(caPem, serializer);
             sse_encode_String(passphrase, serializer);
             sse_encode_String(password, serializer);
+            sse_encode_opt_String(clientCertPem, serializer);
+            sse_encode_opt_String(clientKeyPem, serializer);
             sse_encode_StreamSink_String_Sse(sink, serializer);
             pdeCallFfi(
               generalizedFrbRustBinding,
               serializer,
-              funcId: 3,
+              funcId: 5,
               port: port_,
             );
           },
@@ -259,7 +440,16 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
             decodeErrorData: sse_decode_String,
           ),
           constMeta: kCrateApiOpenMessageStreamTlsConstMeta,
-          argValues: [host, port, caPem, passphrase, password, sink],
+          argValues: [
+            host,
+            port,
+            caPem,
+            passphrase,
+            password,
+            clientCertPem,
+            clientKeyPem,
+            sink,
+          ],
           apiImpl: this,
         ),
       ),
@@ -270,7 +460,16 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   TaskConstMeta get kCrateApiOpenMessageStreamTlsConstMeta =>
       const TaskConstMeta(
         debugName: "open_message_stream_tls",
-        argNames: ["host", "port", "caPem", "passphrase", "password", "sink"],
+        argNames: [
+          "host",
+          "port",
+          "caPem",
+          "passphrase",
+          "password",
+          "clientCertPem",
+          "clientKeyPem",
+          "sink",
+        ],
       );
 
   @override
@@ -295,7 +494,7 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           pdeCallFfi(
             generalizedFrbRustBinding,
             serializer,
-            funcId: 4,
+            funcId: 6,
             port: port_,
           );
         },
@@ -336,7 +535,7 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           pdeCallFfi(
             generalizedFrbRustBinding,
             serializer,
-            funcId: 5,
+            funcId: 7,
             port: port_,
           );
         },
@@ -362,6 +561,7 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     required PlatformInt64 toUserId,
     required String body,
     bool? saved,
+    PlatformInt64? attachmentId,
   }) {
     return handler.executeNormal(
       NormalTask(
@@ -371,10 +571,11 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           sse_encode_i_64(toUserId, serializer);
           sse_encode_String(body, serializer);
           sse_encode_opt_box_autoadd_bool(saved, serializer);
+          sse_encode_opt_box_autoadd_i_64(attachmentId, serializer);
           pdeCallFfi(
             generalizedFrbRustBinding,
             serializer,
-            funcId: 6,
+            funcId: 8,
             port: port_,
           );
         },
@@ -383,7 +584,7 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           decodeErrorData: sse_decode_String,
         ),
         constMeta: kCrateApiSendDirectMessageOverStreamConstMeta,
-        argValues: [userId, toUserId, body, saved],
+        argValues: [userId, toUserId, body, saved, attachmentId],
         apiImpl: this,
       ),
     );
@@ -392,7 +593,7 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   TaskConstMeta get kCrateApiSendDirectMessageOverStreamConstMeta =>
       const TaskConstMeta(
         debugName: "send_direct_message_over_stream",
-        argNames: ["userId", "toUserId", "body", "saved"],
+        argNames: ["userId", "toUserId", "body", "saved", "attachmentId"],
       );
 
   @override
@@ -402,9 +603,12 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     required String caPem,
     required String passphrase,
     required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
     required PlatformInt64 toUserId,
     required String body,
     bool? saved,
+    PlatformInt64? attachmentId,
   }) {
     return handler.executeNormal(
       NormalTask(
@@ -415,13 +619,16 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           sse_encode_String(caPem, serializer);
           sse_encode_String(passphrase, serializer);
           sse_encode_String(password, serializer);
+          sse_encode_opt_String(clientCertPem, serializer);
+          sse_encode_opt_String(clientKeyPem, serializer);
           sse_encode_i_64(toUserId, serializer);
           sse_encode_String(body, serializer);
           sse_encode_opt_box_autoadd_bool(saved, serializer);
+          sse_encode_opt_box_autoadd_i_64(attachmentId, serializer);
           pdeCallFfi(
             generalizedFrbRustBinding,
             serializer,
-            funcId: 7,
+            funcId: 9,
             port: port_,
           );
         },
@@ -436,9 +643,12 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           caPem,
           passphrase,
           password,
+          clientCertPem,
+          clientKeyPem,
           toUserId,
           body,
           saved,
+          attachmentId,
         ],
         apiImpl: this,
       ),
@@ -454,12 +664,90 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           "caPem",
           "passphrase",
           "password",
+          "clientCertPem",
+          "clientKeyPem",
           "toUserId",
           "body",
           "saved",
+          "attachmentId",
         ],
       );
 
+  @override
+  Stream<TransferProgress> crateApiUploadAttachmentTls({
+    required String host,
+    required int port,
+    required String caPem,
+    required String passphrase,
+    required String password,
+    String? clientCertPem,
+    String? clientKeyPem,
+    required String filePath,
+    required String mimeType,
+  }) {
+    final sink = RustStreamSink<TransferProgress>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_String(host, serializer);
+            sse_encode_u_16(port, serializer);
+            sse_encode_String(caPem, serializer);
+            sse_encode_String(passphrase, serializer);
+            sse_encode_String(password, serializer);
+            sse_encode_opt_String(clientCertPem, serializer);
+            sse_encode_opt_String(clientKeyPem, serializer);
+            sse_encode_String(filePath, serializer);
+            sse_encode_String(mimeType, serializer);
+            sse_encode_StreamSink_transfer_progress_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 10,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_String,
+          ),
+          constMeta: kCrateApiUploadAttachmentTlsConstMeta,
+          argValues: [
+            host,
+            port,
+            caPem,
+            passphrase,
+            password,
+            clientCertPem,
+            clientKeyPem,
+            filePath,
+            mimeType,
+            sink,
+          ],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiUploadAttachmentTlsConstMeta => const TaskConstMeta(
+    debugName: "upload_attachment_tls",
+    argNames: [
+          "host",
+          "port",
+          "caPem",
+          "passphrase",
+          "password",
+          "clientCertPem",
+          "clientKeyPem",
+          "filePath",
+          "mimeType",
+          "sink",
+        ],
+  );
+
   @protected
   AnyhowException dco_decode_AnyhowException(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
@@ -472,6 +760,14 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     throw UnimplementedError();
   }
 
+  @protected
+  RustStreamSink<TransferProgress> dco_decode_StreamSink_transfer_progress_Sse(
+    dynamic raw,
+  ) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    throw UnimplementedError();
+  }
+
   @protected
   String dco_decode_String(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
@@ -520,8 +816,8 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   HistoryMessage dco_decode_history_message(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
     final arr = raw as List<dynamic>;
-    if (arr.length != 6)
-      throw Exception('unexpected arr length: expect 6 but see ${arr.length}');
+    if (arr.length != 11)
+      throw Exception('unexpected arr length: expect 11 but see ${arr.length}');
     return HistoryMessage(
       id: dco_decode_i_64(arr[0]),
       fromUserId: dco_decode_i_64(arr[1]),
@@ -529,6 +825,11 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
       body: dco_decode_String(arr[3]),
       timestamp: dco_decode_String(arr[4]),
       saved: dco_decode_bool(arr[5]),
+      replyToId: dco_decode_opt_box_autoadd_i_64(arr[6]),
+      replySnippet: dco_decode_String(arr[7]),
+      attachmentId: dco_decode_opt_box_autoadd_i_64(arr[8]),
+      attachmentName: dco_decode_String(arr[9]),
+      thumbnailId: dco_decode_opt_box_autoadd_i_64(arr[10]),
     );
   }
 
@@ -563,6 +864,12 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     );
   }
 
+  @protected
+  String? dco_decode_opt_String(dynamic raw) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    return raw == null ? null : dco_decode_String(raw);
+  }
+
   @protected
   bool? dco_decode_opt_box_autoadd_bool(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
@@ -593,12 +900,31 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     );
   }
 
+  @protected
+  TransferProgress dco_decode_transfer_progress(dynamic raw) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    final arr = raw as List<dynamic>;
+    if (arr.length != 3)
+      throw Exception('unexpected arr length: expect 3 but see ${arr.length}');
+    return TransferProgress(
+      attachmentId: dco_decode_i_64(arr[0]),
+      transferred: dco_decode_u_64(arr[1]),
+      total: dco_decode_u_64(arr[2]),
+    );
+  }
+
   @protected
   int dco_decode_u_16(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
     return raw as int;
   }
 
+  @protected
+  BigInt dco_decode_u_64(dynamic raw) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    return dcoDecodeU64(raw);
+  }
+
   @protected
   int dco_decode_u_8(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
@@ -632,6 +958,14 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     throw UnimplementedError('Unreachable ()');
   }
 
+  @protected
+  RustStreamSink<TransferProgress> sse_decode_StreamSink_transfer_progress_Sse(
+    SseDeserializer deserializer,
+  ) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    throw UnimplementedError('Unreachable ()');
+  }
+
   @protected
   String sse_decode_String(SseDeserializer deserializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -687,6 +1021,11 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     var var_body = sse_decode_String(deserializer);
     var var_timestamp = sse_decode_String(deserializer);
     var var_saved = sse_decode_bool(deserializer);
+    var var_replyToId = sse_decode_opt_box_autoadd_i_64(deserializer);
+    var var_replySnippet = sse_decode_String(deserializer);
+    var var_attachmentId = sse_decode_opt_box_autoadd_i_64(deserializer);
+    var var_attachmentName = sse_decode_String(deserializer);
+    var var_thumbnailId = sse_decode_opt_box_autoadd_i_64(deserializer);
     return HistoryMessage(
       id: var_id,
       fromUserId: var_fromUserId,
@@ -694,6 +1033,11 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
       body: var_body,
       timestamp: var_timestamp,
       saved: var_saved,
+      replyToId: var_replyToId,
+      replySnippet: var_replySnippet,
+      attachmentId: var_attachmentId,
+      attachmentName: var_attachmentName,
+      thumbnailId: var_thumbnailId,
     );
   }
 
@@ -737,6 +1081,17 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     );
   }
 
+  @protected
+  String? sse_decode_opt_String(SseDeserializer deserializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+
+    if (sse_decode_bool(deserializer)) {
+      return (sse_decode_String(deserializer));
+    } else {
+      return null;
+    }
+  }
+
   @protected
   bool? sse_decode_opt_box_autoadd_bool(SseDeserializer deserializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -778,12 +1133,31 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     return SendResult(success: var_success, message: var_message);
   }
 
+  @protected
+  TransferProgress sse_decode_transfer_progress(SseDeserializer deserializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    var var_attachmentId = sse_decode_i_64(deserializer);
+    var var_transferred = sse_decode_u_64(deserializer);
+    var var_total = sse_decode_u_64(deserializer);
+    return TransferProgress(
+      attachmentId: var_attachmentId,
+      transferred: var_transferred,
+      total: var_total,
+    );
+  }
+
   @protected
   int sse_decode_u_16(SseDeserializer deserializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
     return deserializer.buffer.getUint16();
   }
 
+  @protected
+  BigInt sse_decode_u_64(SseDeserializer deserializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    return deserializer.buffer.getBigUint64();
+  }
+
   @protected
   int sse_decode_u_8(SseDeserializer deserializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -833,6 +1207,23 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     );
   }
 
+  @protected
+  void sse_encode_StreamSink_transfer_progress_Sse(
+    RustStreamSink<TransferProgress> self,
+    SseSerializer serializer,
+  ) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    sse_encode_String(
+      self.setupAndSerialize(
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_transfer_progress,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+      ),
+      serializer,
+    );
+  }
+
   @protected
   void sse_encode_String(String self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -887,6 +1278,11 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     sse_encode_String(self.body, serializer);
     sse_encode_String(self.timestamp, serializer);
     sse_encode_bool(self.saved, serializer);
+    sse_encode_opt_box_autoadd_i_64(self.replyToId, serializer);
+    sse_encode_String(self.replySnippet, serializer);
+    sse_encode_opt_box_autoadd_i_64(self.attachmentId, serializer);
+    sse_encode_String(self.attachmentName, serializer);
+    sse_encode_opt_box_autoadd_i_64(self.thumbnailId, serializer);
   }
 
   @protected
@@ -925,6 +1321,16 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     sse_encode_opt_box_autoadd_i_64(self.userId, serializer);
   }
 
+  @protected
+  void sse_encode_opt_String(String? self, SseSerializer serializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+
+    sse_encode_bool(self != null, serializer);
+    if (self != null) {
+      sse_encode_String(self, serializer);
+    }
+  }
+
   @protected
   void sse_encode_opt_box_autoadd_bool(bool? self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -968,12 +1374,29 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     sse_encode_String(self.message, serializer);
   }
 
+  @protected
+  void sse_encode_transfer_progress(
+    TransferProgress self,
+    SseSerializer serializer,
+  ) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    sse_encode_i_64(self.attachmentId, serializer);
+    sse_encode_u_64(self.transferred, serializer);
+    sse_encode_u_64(self.total, serializer);
+  }
+
   @protected
   void sse_encode_u_16(int self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
     serializer.buffer.putUint16(self);
   }
 
+  @protected
+  void sse_encode_u_64(BigInt self, SseSerializer serializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    serializer.buffer.putBigUint64(self);
+  }
+
   @protected
   void sse_encode_u_8(int self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -24,6 +24,11 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   @protected
   RustStreamSink<String> dco_decode_StreamSink_String_Sse(dynamic raw);
 
+  @protected
+  RustStreamSink<TransferProgress> dco_decode_StreamSink_transfer_progress_Sse(
+    dynamic raw,
+  );
+
   @protected
   String dco_decode_String(dynamic raw);
 
@@ -57,6 +62,9 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   @protected
   LoginResponse dco_decode_login_response(dynamic raw);
 
+  @protected
+  String? dco_decode_opt_String(dynamic raw);
+
   @protected
   bool? dco_decode_opt_box_autoadd_bool(dynamic raw);
 
@@ -69,9 +77,15 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   @protected
   SendResult dco_decode_send_result(dynamic raw);
 
+  @protected
+  TransferProgress dco_decode_transfer_progress(dynamic raw);
+
   @protected
   int dco_decode_u_16(dynamic raw);
 
+  @protected
+  BigInt dco_decode_u_64(dynamic raw);
+
   @protected
   int dco_decode_u_8(dynamic raw);
 
@@ -89,6 +103,11 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
     SseDeserializer deserializer,
   );
 
+  @protected
+  RustStreamSink<TransferProgress> sse_decode_StreamSink_transfer_progress_Sse(
+    SseDeserializer deserializer,
+  );
+
   @protected
   String sse_decode_String(SseDeserializer deserializer);
 
@@ -124,6 +143,9 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   @protected
   LoginResponse sse_decode_login_response(SseDeserializer deserializer);
 
+  @protected
+  String? sse_decode_opt_String(SseDeserializer deserializer);
+
   @protected
   bool? sse_decode_opt_box_autoadd_bool(SseDeserializer deserializer);
 
@@ -136,9 +158,15 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   @protected
   SendResult sse_decode_send_result(SseDeserializer deserializer);
 
+  @protected
+  TransferProgress sse_decode_transfer_progress(SseDeserializer deserializer);
+
   @protected
   int sse_decode_u_16(SseDeserializer deserializer);
 
+  @protected
+  BigInt sse_decode_u_64(SseDeserializer deserializer);
+
   @protected
   int sse_decode_u_8(SseDeserializer deserializer);
 
@@ -163,6 +191,12 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
     SseSerializer serializer,
   );
 
+  @protected
+  void sse_encode_StreamSink_transfer_progress_Sse(
+    RustStreamSink<TransferProgress> self,
+    SseSerializer serializer,
+  );
+
   @protected
   void sse_encode_String(String self, SseSerializer serializer);
 
@@ -208,6 +242,9 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   @protected
   void sse_encode_login_response(LoginResponse self, SseSerializer serializer);
 
+  @protected
+  void sse_encode_opt_String(String? self, SseSerializer serializer);
+
   @protected
   void sse_encode_opt_box_autoadd_bool(bool? self, SseSerializer serializer);
 
@@ -223,9 +260,18 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   @protected
   void sse_encode_send_result(SendResult self, SseSerializer serializer);
 
+  @protected
+  void sse_encode_transfer_progress(
+    TransferProgress self,
+    SseSerializer serializer,
+  );
+
   @protected
   void sse_encode_u_16(int self, SseSerializer serializer);
 
+  @protected
+  void sse_encode_u_64(BigInt self, SseSerializer serializer);
+
   @protected
   void sse_encode_u_8(int self, SseSerializer serializer);
 
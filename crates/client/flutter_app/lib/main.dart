@@ -352,6 +352,8 @@ class _ChatListScaffoldState extends State<_ChatListScaffold> {
         final map = jsonDecode(data) as Map;
         final from = map['from_user_id'] as int;
         final body = map['body'] as String;
+        final replyTo = map['reply_to'] as Map?;
+        final attachment = map['attachment'] as Map?;
         final msg = HistoryMessage(
           id: 0,
           fromUserId: from,
@@ -359,6 +361,11 @@ class _ChatListScaffoldState extends State<_ChatListScaffold> {
           body: body,
           timestamp: DateTime.now().toIso8601String(),
           saved: false,
+          replyToId: replyTo?['message_id'] as int?,
+          replySnippet: (replyTo?['snippet'] as String?) ?? '',
+          attachmentId: attachment?['id'] as int?,
+          attachmentName: (attachment?['file_name'] as String?) ?? '',
+          thumbnailId: (attachment?['thumbnail'] as Map?)?['id'] as int?,
         );
         _incoming.add(msg);
         final peer = from;
@@ -369,7 +376,23 @@ class _ChatListScaffoldState extends State<_ChatListScaffold> {
       } catch (_) {
         // ignore malformed event
       }
-    }, onError: (_) {});
+    }, onError: (Object error) {
+      // The stream ends with an error when the connection dies
+      if (!mounted) return;
+      ScaffoldMessenger.of(context).showSnackBar(
+        SnackBar(
+          content: Text('Disconnected: $error'),
+          duration: const Duration(days: 1),
+          action: SnackBarAction(
+            label: 'Reconnect',
+            onPressed: () {
+              _sub?.cancel();
+              _startStream();
+            },
+          ),
+        ),
+      );
+    });
   }
 
   @override
@@ -500,6 +523,8 @@ class _ChatPageState extends State<ChatPage> {
           body: text,
           timestamp: now,
           saved: false,
+          replySnippet: '',
+          attachmentName: '',
         ));
         _input.clear();
       });
@@ -549,6 +574,19 @@ class _ChatPageState extends State<ChatPage> {
                     child: Column(
                       crossAxisAlignment: fromSelf ? CrossAxisAlignment.end : CrossAxisAlignment.start,
                       children: [
+                        if (m.thumbnailId != null)
+                          Padding(
+                            padding: const EdgeInsets.only(bottom: 4),
+                            child: _ThumbnailPreview(session: widget.session, thumbnailId: m.thumbnailId!),
+                          ),
+                        if (m.attachmentName.isNotEmpty)
+                          Text(
+                            m.attachmentName,
+                            style: TextStyle(
+                              color: fromSelf ? Colors.white70 : const Color(0xCC000000),
+                              fontStyle: FontStyle.italic,
+                            ),
+                          ),
                         Text(
                           m.body,
                           style: TextStyle(
@@ -606,6 +644,60 @@ class _ChatPageState extends State<ChatPage> {
   }
 }
 
+/// Downloads an image thumbnail once and shows it; the full image is only
+/// fetched when the user asks for the attachment.
+class _ThumbnailPreview extends StatefulWidget {
+  final SessionConfig session;
+  final int thumbnailId;
+  const _ThumbnailPreview({required this.session, required this.thumbnailId});
+
+  @override
+  State<_ThumbnailPreview> createState() => _ThumbnailPreviewState();
+}
+
+class _ThumbnailPreviewState extends State<_ThumbnailPreview> {
+  late final Future<File> _file = _download();
+
+  Future<File> _download() async {
+    final file = File('${Directory.systemTemp.path}/rura-thumbnail-${widget.thumbnailId}');
+    if (await file.exists()) return file;
+    final s = widget.session;
+    await downloadAttachmentTls(
+      host: s.host,
+      port: s.port,
+      caPem: s.caPem,
+      passphrase: s.passphrase,
+      password: s.password,
+      attachmentId: widget.thumbnailId,
+      destPath: file.path,
+    ).drain<void>();
+    return file;
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    return FutureBuilder<File>(
+      future: _file,
+      builder: (context, snapshot) {
+        final file = snapshot.data;
+        if (file == null) {
+          return SizedBox(
+            width: 160,
+            height: 120,
+            child: Center(
+              child: snapshot.hasError ? const Icon(Icons.broken_image) : const CircularProgressIndicator(strokeWidth: 2),
+            ),
+          );
+        }
+        return ClipRRect(
+          borderRadius: BorderRadius.circular(8),
+          child: Image.file(file, width: 160, fit: BoxFit.cover),
+        );
+      },
+    );
+  }
+}
+
 String _two(int x) => x.toString().padLeft(2, '0');
 String _formatTime(String iso) {
   final dt = DateTime.tryParse(iso);
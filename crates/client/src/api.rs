@@ -1,4 +1,6 @@
 use crate::StreamSink;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use flutter_rust_bridge::frb;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
@@ -10,12 +12,16 @@ pub type AuthRequest = rura_models::client_message::AuthRequest;
 pub type AuthResponse = rura_models::client_message::AuthResponse;
 pub type ClientMessage = rura_models::client_message::ClientMessage;
 // NOTE: Keep client-local history/message structs to avoid tight coupling to rura_models.
-use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
 use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
 use std::sync::{Arc, Once};
 
+/// ALPN protocol id offered on every connection; servers with
+/// `tls.require_alpn` drop clients that do not negotiate it.
+const RURA_ALPN: &[u8] = b"rura/1";
+
 /// Simple Dart-friendly login response.
 #[frb]
 #[derive(Clone, Debug)]
@@ -35,6 +41,17 @@ pub struct HistoryMessage {
     pub body: String,
     pub timestamp: String,
     pub saved: bool,
+    /// Message this one replies to, if any.
+    pub reply_to_id: Option<i64>,
+    /// Start of the replied-to message; empty when not a reply or when that
+    /// message was deleted.
+    pub reply_snippet: String,
+    /// File attached to the message, if any.
+    pub attachment_id: Option<i64>,
+    /// Name of the attached file; empty without an attachment.
+    pub attachment_name: String,
+    /// Preview of an attached image, downloadable like the attachment.
+    pub thumbnail_id: Option<i64>,
 }
 
 // Use shared protocol models from rura_models for internal serialization.
@@ -45,10 +62,20 @@ impl From<ModelHistoryMessage> for HistoryMessage {
         Self {
             id: src.id,
             from_user_id: src.from_user_id,
-            to_user_id: src.to_user_id,
+            // Set for every direct message; group messages are filtered out
+            to_user_id: src.to_user_id.unwrap_or_default(),
             body: src.body,
             timestamp: src.timestamp,
             saved: src.saved,
+            reply_to_id: src.reply_to.as_ref().map(|q| q.message_id),
+            reply_snippet: src.reply_to.map(|q| q.snippet).unwrap_or_default(),
+            attachment_id: src.attachment.as_ref().map(|a| a.id),
+            thumbnail_id: src
+                .attachment
+                .as_ref()
+                .and_then(|a| a.thumbnail.as_ref())
+                .map(|t| t.id),
+            attachment_name: src.attachment.map(|a| a.file_name).unwrap_or_default(),
         }
     }
 }
@@ -110,9 +137,10 @@ pub fn login_tls(
     });
     // Build TLS client config with provided root
     let roots = build_root_store_from_pem(&ca_pem)?;
-    let config: ClientConfig = ClientConfig::builder()
+    let mut config: ClientConfig = ClientConfig::builder()
         .with_root_certificates(roots)
         .with_no_client_auth();
+    config.alpn_protocols = vec![RURA_ALPN.to_vec()];
 
     let server_name = ServerName::try_from(host.as_str())
         .map_err(|e| format!("Invalid server name: {e}"))?
@@ -167,6 +195,59 @@ pub fn login_tls(
     })
 }
 
+/// Login with a client certificate (mutual TLS) instead of a password.
+///
+/// - `ca_pem`: the server's CA certificate (PEM), as for `login_tls`
+/// - `client_cert_pem`, `client_key_pem`: the device certificate and its key
+///
+/// Returns `success: false` when the server accepted the certificate but it is
+/// not linked to an account.
+#[frb]
+pub fn login_mtls(
+    host: String,
+    port: u16,
+    ca_pem: String,
+    client_cert_pem: String,
+    client_key_pem: String,
+) -> Result<LoginResponse, String> {
+    let mut tls = make_tls_stream_with_identity(
+        &host,
+        port,
+        &ca_pem,
+        Some((&client_cert_pem, &client_key_pem)),
+    )?;
+
+    // A linked certificate is answered with auth_response right away;
+    // otherwise the server falls back to the usual auth_required prompt.
+    let raw = read_line(&mut tls).map_err(|e| format!("Read failed: {e}"))?;
+    if raw.is_empty() {
+        return Err("Connection closed during TLS handshake (client certificate rejected?)".into());
+    }
+    let wrapper: ClientMessage = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid JSON from server: {e}; raw={raw}"))?;
+    let response = match wrapper.command.as_str() {
+        "auth_response" => {
+            let resp: AuthResponse = serde_json::from_str(&wrapper.data)
+                .map_err(|e| format!("Invalid auth_response data: {e}"))?;
+            LoginResponse {
+                success: resp.success,
+                message: resp.message,
+                user_id: resp.user_id,
+            }
+        }
+        "auth_required" => LoginResponse {
+            success: false,
+            message: "Client certificate is not linked to an account".to_string(),
+            user_id: None,
+        },
+        other => return Err(format!("Unexpected command: {other}")),
+    };
+
+    tls.conn.send_close_notify();
+    let _ = tls.flush();
+    Ok(response)
+}
+
 /// Register a new user against the TLS-only server and return the auth response.
 #[frb]
 pub fn register_tls(
@@ -184,9 +265,10 @@ pub fn register_tls(
 
     // Build TLS client config with provided root
     let roots = build_root_store_from_pem(&ca_pem)?;
-    let config: ClientConfig = ClientConfig::builder()
+    let mut config: ClientConfig = ClientConfig::builder()
         .with_root_certificates(roots)
         .with_no_client_auth();
+    config.alpn_protocols = vec![RURA_ALPN.to_vec()];
 
     let server_name = ServerName::try_from(host.as_str())
         .map_err(|e| format!("Invalid server name: {e}"))?
@@ -275,10 +357,12 @@ fn fetch_history_over_stream(
     if !resp.success {
         return Err(resp.message);
     }
-    // Map model messages into FRB-friendly struct
+    // Map model messages into FRB-friendly struct. The app has no group
+    // chats yet, so group messages are left out.
     Ok(resp
         .messages
         .into_iter()
+        .filter(|m| m.conversation_id.is_none())
         .map(HistoryMessage::from)
         .collect())
 }
@@ -292,6 +376,8 @@ pub struct SendResult {
 }
 
 /// Login and send a direct message in a single TLS session.
+/// `attachment_id` is a finished upload from `upload_attachment_tls`.
+/// A linked client certificate stands in for the password.
 #[frb]
 #[allow(clippy::too_many_arguments)]
 pub fn send_direct_message_tls(
@@ -300,11 +386,15 @@ pub fn send_direct_message_tls(
     ca_pem: String,
     passphrase: String,
     password: String,
+    client_cert_pem: Option<String>,
+    client_key_pem: Option<String>,
     to_user_id: i64,
     body: String,
     saved: Option<bool>,
+    attachment_id: Option<i64>,
 ) -> Result<SendResult, String> {
-    let mut tls = make_tls_stream(&host, port, &ca_pem)?;
+    let identity = client_identity(&client_cert_pem, &client_key_pem)?;
+    let mut tls = make_tls_stream_with_identity(&host, port, &ca_pem, identity)?;
     let login = auth_over_stream(&mut tls, "login", passphrase, password)?;
     if !login.success {
         tls.conn.send_close_notify();
@@ -320,11 +410,14 @@ pub fn send_direct_message_tls(
         to_user_id: i64,
         body: String,
         saved: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        attachment_id: Option<i64>,
     }
     let req = OutgoingDM {
         to_user_id,
         body,
         saved,
+        attachment_id,
     };
     let env = ClientMessage {
         command: "message".to_string(),
@@ -346,20 +439,27 @@ pub fn send_direct_message_tls(
 
 /// Keep a TLS session open and stream incoming direct messages as JSON payloads.
 /// Emits the `data` contents of `{"command":"message","data":...}` lines.
+/// Server `ping`s are answered automatically; when the connection closes or the
+/// server stops responding, the stream ends with an error describing why.
+/// The client certificate is optional, as for `login_and_fetch_history_tls`.
 #[frb]
 static SESSIONS: Lazy<std::sync::Mutex<HashMap<i64, Sender<String>>>> =
     Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
 
+#[allow(clippy::too_many_arguments)]
 pub fn open_message_stream_tls(
     host: String,
     port: u16,
     ca_pem: String,
     passphrase: String,
     password: String,
+    client_cert_pem: Option<String>,
+    client_key_pem: Option<String>,
     sink: StreamSink<String>,
 ) -> Result<(), String> {
     // Establish TLS and authenticate
-    let mut tls = make_tls_stream(&host, port, &ca_pem)?;
+    let identity = client_identity(&client_cert_pem, &client_key_pem)?;
+    let mut tls = make_tls_stream_with_identity(&host, port, &ca_pem, identity)?;
     let login = auth_over_stream(&mut tls, "login", passphrase, password)?;
     if !login.success {
         tls.conn.send_close_notify();
@@ -382,50 +482,132 @@ pub fn open_message_stream_tls(
     // Spawn a dedicated thread to own the TLS stream, read incoming events, and perform writes.
     thread::spawn(move || {
         let mut tls = tls; // move into thread
-        let mut buf = [0u8; 1024];
-        let mut acc: Vec<u8> = Vec::new();
-        loop {
-            // 1) Drain outgoing writes, if any
-            while let Ok(line) = rx.try_recv() {
-                let _ = tls.write_all(line.as_bytes());
-                let _ = tls.flush();
-            }
+        let result = run_message_stream(&mut tls, &rx, STREAM_LIVENESS, |data| {
+            let _ = sink.add(data);
+        });
+        let _ = tls.flush();
+        // Remove session entry when exiting
+        {
+            let mut g = SESSIONS.lock().unwrap();
+            g.remove(&user_id);
+        }
+        // Surface the dead connection to Dart as a stream error
+        if let Err(reason) = result {
+            let _ = sink.add_error(reason);
+        }
+    });
+
+    Ok(())
+}
+
+/// When the stream thread probes a quiet server and gives up on it.
+#[derive(Clone, Copy)]
+struct StreamLiveness {
+    /// Send our own `ping` after this long without any incoming line.
+    probe_after: Duration,
+    /// Declare the connection dead this long after the probe went unanswered.
+    dead_after: Duration,
+}
+
+/// Servers ping every 30 s by default, so a healthy session never needs the probe.
+const STREAM_LIVENESS: StreamLiveness = StreamLiveness {
+    probe_after: Duration::from_secs(45),
+    dead_after: Duration::from_secs(15),
+};
+
+fn write_envelope(stream: &mut impl Write, command: &str, data: String) -> io::Result<()> {
+    let env = ClientMessage {
+        command: command.to_string(),
+        data,
+    };
+    let mut line = serde_json::to_string(&env)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    stream.flush()
+}
+
+/// Pump an authenticated session until it ends: write queued lines, pass
+/// `message` payloads to `on_message`, and answer server `ping`s. The stream
+/// must have a short read timeout so writes are not starved.
+/// Returns why the connection is considered dead.
+fn run_message_stream<S: Read + Write>(
+    stream: &mut S,
+    outgoing: &Receiver<String>,
+    liveness: StreamLiveness,
+    mut on_message: impl FnMut(String),
+) -> Result<(), String> {
+    let mut buf = [0u8; 1024];
+    let mut acc: Vec<u8> = Vec::new();
+    let mut last_heard = std::time::Instant::now();
+    let mut probe_sent = false;
+    loop {
+        // 1) Drain outgoing writes, if any
+        while let Ok(line) = outgoing.try_recv() {
+            stream
+                .write_all(line.as_bytes())
+                .and_then(|_| stream.flush())
+                .map_err(|e| format!("Connection lost: {e}"))?;
+        }
 
-            // 2) Attempt to read incoming data
-            match tls.read(&mut buf) {
-                Ok(0) => break, // closed
-                Ok(n) => {
-                    acc.extend_from_slice(&buf[..n]);
-                    // Process complete lines
-                    while let Some(pos) = acc.iter().position(|&b| b == b'\n') {
-                        let line = acc.drain(..=pos).collect::<Vec<u8>>();
-                        let line = String::from_utf8_lossy(&line[..line.len().saturating_sub(1)])
-                            .to_string();
-                        #[allow(clippy::collapsible_if)]
-                        if let Ok(wrapper) = serde_json::from_str::<ClientMessage>(&line) {
-                            if wrapper.command == "message" {
-                                let _ = sink.add(wrapper.data);
-                            }
+        // 2) Probe a quiet server, then give up if it stays quiet
+        let quiet_for = last_heard.elapsed();
+        if quiet_for >= liveness.probe_after + liveness.dead_after {
+            return Err(format!(
+                "Connection lost: no response from server for {}s",
+                quiet_for.as_secs()
+            ));
+        }
+        if quiet_for >= liveness.probe_after && !probe_sent {
+            write_envelope(stream, "ping", "client".to_string())
+                .map_err(|e| format!("Connection lost: {e}"))?;
+            probe_sent = true;
+        }
+
+        // 3) Attempt to read incoming data
+        match stream.read(&mut buf) {
+            Ok(0) => return Err("Connection closed by server".to_string()),
+            Ok(n) => {
+                last_heard = std::time::Instant::now();
+                probe_sent = false;
+                acc.extend_from_slice(&buf[..n]);
+                // Process complete lines
+                while let Some(pos) = acc.iter().position(|&b| b == b'\n') {
+                    let line = acc.drain(..=pos).collect::<Vec<u8>>();
+                    let line =
+                        String::from_utf8_lossy(&line[..line.len().saturating_sub(1)]).to_string();
+                    let Ok(wrapper) = serde_json::from_str::<ClientMessage>(&line) else {
+                        continue;
+                    };
+                    match wrapper.command.as_str() {
+                        "message" => on_message(wrapper.data),
+                        "ping" => write_envelope(stream, "pong", wrapper.data)
+                            .map_err(|e| format!("Connection lost: {e}"))?,
+                        "server_shutdown" => {
+                            let wait_ms = serde_json::from_str::<
+                                rura_models::client_message::ServerShutdownEvent,
+                            >(&wrapper.data)
+                            .map(|event| event.reconnect_after_ms)
+                            .unwrap_or(0);
+                            return Err(format!(
+                                "Server is shutting down; reconnect in {}s",
+                                wait_ms.div_ceil(1000)
+                            ));
                         }
+                        _ => {}
                     }
                 }
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
-                    {
-                        // No data; loop and try writes again
-                    } else {
-                        break;
-                    }
-                }
             }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                // No data; loop and try writes again
+            }
+            Err(e) => return Err(format!("Connection lost: {e}")),
         }
-        let _ = tls.flush();
-        // Remove session entry when exiting
-        let mut g = SESSIONS.lock().unwrap();
-        g.remove(&user_id);
-    });
-
-    Ok(())
+    }
 }
 
 /// Send a direct message using an existing open stream session for the given user_id.
@@ -435,6 +617,7 @@ pub fn send_direct_message_over_stream(
     to_user_id: i64,
     body: String,
     saved: Option<bool>,
+    attachment_id: Option<i64>,
 ) -> Result<(), String> {
     let tx = {
         let g = SESSIONS.lock().unwrap();
@@ -448,11 +631,14 @@ pub fn send_direct_message_over_stream(
         to_user_id: i64,
         body: String,
         saved: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        attachment_id: Option<i64>,
     }
     let req = OutgoingDM2 {
         to_user_id,
         body,
         saved,
+        attachment_id,
     };
     let env = ClientMessage {
         command: "message".to_string(),
@@ -464,19 +650,296 @@ pub fn send_direct_message_over_stream(
         .map_err(|_| "Failed to enqueue send".to_string())
 }
 
+/// Progress of an attachment upload or download.
+#[frb]
+#[derive(Clone, Debug)]
+pub struct TransferProgress {
+    pub attachment_id: i64,
+    /// Bytes sent or received so far.
+    pub transferred: u64,
+    /// Size of the whole file.
+    pub total: u64,
+}
+
+/// Upload a file as an attachment in one TLS session, reporting progress
+/// after every chunk. The first event carries the new `attachment_id`, to
+/// pass to `send_direct_message_tls`; the stream ends once the server
+/// checked the file. Logs in like `login_and_fetch_history_tls`.
+#[frb]
+#[allow(clippy::too_many_arguments)]
+pub fn upload_attachment_tls(
+    host: String,
+    port: u16,
+    ca_pem: String,
+    passphrase: String,
+    password: String,
+    client_cert_pem: Option<String>,
+    client_key_pem: Option<String>,
+    file_path: String,
+    mime_type: String,
+    sink: StreamSink<TransferProgress>,
+) -> Result<(), String> {
+    let bytes = std::fs::read(&file_path).map_err(|e| format!("Failed to read file: {e}"))?;
+    let file_name = std::path::Path::new(&file_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| "File path has no file name".to_string())?;
+    let identity = client_identity(&client_cert_pem, &client_key_pem)?;
+    let mut tls = make_tls_stream_with_identity(&host, port, &ca_pem, identity)?;
+    let login = auth_over_stream(&mut tls, "login", passphrase, password)?;
+    if !login.success {
+        tls.conn.send_close_notify();
+        let _ = tls.flush();
+        return Err(login.message);
+    }
+    let result = upload_over_stream(&mut tls, &bytes, file_name, mime_type, |progress| {
+        let _ = sink.add(progress);
+    });
+    tls.conn.send_close_notify();
+    let _ = tls.flush();
+    result.map(|_| ())
+}
+
+/// Download an attachment to `dest_path` in one TLS session, reporting
+/// progress after every chunk. A failed download leaves no file behind.
+/// Logs in like `login_and_fetch_history_tls`.
+#[frb]
+#[allow(clippy::too_many_arguments)]
+pub fn download_attachment_tls(
+    host: String,
+    port: u16,
+    ca_pem: String,
+    passphrase: String,
+    password: String,
+    client_cert_pem: Option<String>,
+    client_key_pem: Option<String>,
+    attachment_id: i64,
+    dest_path: String,
+    sink: StreamSink<TransferProgress>,
+) -> Result<(), String> {
+    let identity = client_identity(&client_cert_pem, &client_key_pem)?;
+    let mut tls = make_tls_stream_with_identity(&host, port, &ca_pem, identity)?;
+    let login = auth_over_stream(&mut tls, "login", passphrase, password)?;
+    if !login.success {
+        tls.conn.send_close_notify();
+        let _ = tls.flush();
+        return Err(login.message);
+    }
+    let result = std::fs::File::create(&dest_path)
+        .map_err(|e| format!("Failed to create file: {e}"))
+        .and_then(|mut file| {
+            download_over_stream(&mut tls, attachment_id, &mut file, |progress| {
+                let _ = sink.add(progress);
+            })
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&dest_path);
+    }
+    tls.conn.send_close_notify();
+    let _ = tls.flush();
+    result
+}
+
+/// Send one request and wait for its `response` command, answering server
+/// pings and skipping unrelated events meanwhile. An `error` reply becomes
+/// `Err`.
+fn exchange<S: Read + Write, T: serde::de::DeserializeOwned>(
+    stream: &mut S,
+    command: &str,
+    req: &impl serde::Serialize,
+    response: &str,
+) -> Result<T, String> {
+    let data = serde_json::to_string(req).map_err(|e| format!("Serialize error: {e}"))?;
+    write_envelope(stream, command, data).map_err(|e| format!("Write failed: {e}"))?;
+    loop {
+        let raw = read_line(stream).map_err(|e| format!("Read failed: {e}"))?;
+        if raw.is_empty() {
+            return Err("Connection closed by server".to_string());
+        }
+        let wrapper: ClientMessage = serde_json::from_str(&raw)
+            .map_err(|e| format!("Invalid JSON from server: {e}; raw={raw}"))?;
+        match wrapper.command.as_str() {
+            c if c == response => {
+                return serde_json::from_str(&wrapper.data)
+                    .map_err(|e| format!("Invalid {response} data: {e}"));
+            }
+            "error" => return Err(wrapper.data),
+            "ping" => write_envelope(stream, "pong", wrapper.data)
+                .map_err(|e| format!("Write failed: {e}"))?,
+            _ => {}
+        }
+    }
+}
+
+/// Lowercase hex SHA-256 of `bytes`, as `upload_begin` expects it.
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Run `upload_begin`, `upload_chunk`s and `upload_finish` for `bytes` on an
+/// authenticated session. Returns the attachment id.
+fn upload_over_stream<S: Read + Write>(
+    stream: &mut S,
+    bytes: &[u8],
+    file_name: String,
+    mime_type: String,
+    mut on_progress: impl FnMut(TransferProgress),
+) -> Result<i64, String> {
+    use rura_models::messaging::{
+        UploadBeginRequest, UploadChunkRequest, UploadFinishRequest, UploadResponse,
+    };
+    let total = bytes.len() as u64;
+    let begin = UploadBeginRequest {
+        file_name,
+        mime_type,
+        size: total,
+        sha256: sha256_hex(bytes),
+    };
+    let resp: UploadResponse = exchange(stream, "upload_begin", &begin, "upload_response")?;
+    if !resp.success {
+        return Err(resp.message);
+    }
+    let attachment_id = resp
+        .attachment_id
+        .ok_or_else(|| "Missing attachment_id".to_string())?;
+    let chunk_bytes = resp.chunk_bytes.max(1);
+    on_progress(TransferProgress {
+        attachment_id,
+        transferred: 0,
+        total,
+    });
+    let mut offset = 0u64;
+    while offset < total {
+        let start = offset as usize;
+        let end = (start + chunk_bytes).min(bytes.len());
+        let chunk = UploadChunkRequest {
+            attachment_id,
+            offset,
+            data: BASE64.encode(&bytes[start..end]),
+        };
+        let resp: UploadResponse = exchange(stream, "upload_chunk", &chunk, "upload_response")?;
+        if !resp.success {
+            return Err(resp.message);
+        }
+        offset = resp.received;
+        on_progress(TransferProgress {
+            attachment_id,
+            transferred: offset,
+            total,
+        });
+    }
+    let finish = UploadFinishRequest { attachment_id };
+    let resp: UploadResponse = exchange(stream, "upload_finish", &finish, "upload_response")?;
+    if !resp.success {
+        return Err(resp.message);
+    }
+    Ok(attachment_id)
+}
+
+/// Fetch an attachment chunk by chunk into `out` on an authenticated session.
+fn download_over_stream<S: Read + Write>(
+    stream: &mut S,
+    attachment_id: i64,
+    out: &mut impl Write,
+    mut on_progress: impl FnMut(TransferProgress),
+) -> Result<(), String> {
+    use rura_models::messaging::{DownloadChunkRequest, DownloadChunkResponse};
+    let mut offset = 0u64;
+    loop {
+        let req = DownloadChunkRequest {
+            attachment_id,
+            offset,
+            length: None,
+        };
+        let resp: DownloadChunkResponse =
+            exchange(stream, "download_chunk", &req, "download_response")?;
+        if !resp.success {
+            return Err(resp.message);
+        }
+        let data = BASE64
+            .decode(&resp.data)
+            .map_err(|e| format!("Invalid chunk data: {e}"))?;
+        out.write_all(&data)
+            .map_err(|e| format!("Failed to write file: {e}"))?;
+        offset += data.len() as u64;
+        on_progress(TransferProgress {
+            attachment_id,
+            transferred: offset,
+            total: resp.size,
+        });
+        if resp.eof || data.is_empty() {
+            return out
+                .flush()
+                .map_err(|e| format!("Failed to write file: {e}"));
+        }
+    }
+}
+
+fn load_client_identity(
+    cert_pem: &str,
+    key_pem: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    let mut reader = std::io::Cursor::new(cert_pem.as_bytes());
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse client certificate PEM: {e}"))?;
+    if certs.is_empty() {
+        return Err("No certificates found in client certificate PEM".to_string());
+    }
+    let mut reader = std::io::Cursor::new(key_pem.as_bytes());
+    let key = rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| format!("Failed to parse client key PEM: {e}"))?
+        .ok_or_else(|| "No private key found in client key PEM".to_string())?;
+    Ok((certs, key))
+}
+
+/// The device certificate and key to present, if both were given.
+fn client_identity<'a>(
+    cert_pem: &'a Option<String>,
+    key_pem: &'a Option<String>,
+) -> Result<Option<(&'a str, &'a str)>, String> {
+    match (cert_pem, key_pem) {
+        (Some(cert_pem), Some(key_pem)) => Ok(Some((cert_pem, key_pem))),
+        (None, None) => Ok(None),
+        _ => Err("client_cert_pem and client_key_pem must be given together".to_string()),
+    }
+}
+
 fn make_tls_stream(
     host: &str,
     port: u16,
     ca_pem: &str,
+) -> Result<StreamOwned<ClientConnection, TcpStream>, String> {
+    make_tls_stream_with_identity(host, port, ca_pem, None)
+}
+
+/// Like `make_tls_stream`, optionally presenting a client certificate (cert PEM, key PEM).
+fn make_tls_stream_with_identity(
+    host: &str,
+    port: u16,
+    ca_pem: &str,
+    client_identity: Option<(&str, &str)>,
 ) -> Result<StreamOwned<ClientConnection, TcpStream>, String> {
     static INIT: Once = Once::new();
     INIT.call_once(|| {
         let _ = rustls::crypto::ring::default_provider().install_default();
     });
     let roots = build_root_store_from_pem(ca_pem)?;
-    let config: ClientConfig = ClientConfig::builder()
-        .with_root_certificates(roots)
-        .with_no_client_auth();
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let mut config: ClientConfig = match client_identity {
+        Some((cert_pem, key_pem)) => {
+            let (certs, key) = load_client_identity(cert_pem, key_pem)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| format!("Invalid client certificate: {e}"))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = vec![RURA_ALPN.to_vec()];
     let server_name = ServerName::try_from(host)
         .map_err(|e| format!("Invalid server name: {e}"))?
         .to_owned();
@@ -487,13 +950,27 @@ fn make_tls_stream(
     Ok(StreamOwned::new(conn, tcp))
 }
 
+/// Log in or register with `passphrase` and `password` after the server's
+/// greeting. When a linked client certificate already logged the session
+/// in, the greeting is that `auth_response` and no password is sent.
 fn auth_over_stream(
     tls: &mut StreamOwned<ClientConnection, TcpStream>,
     command: &str,
     passphrase: String,
     password: String,
 ) -> Result<LoginResponse, String> {
-    let _ = read_line(tls);
+    let greeting = read_line(tls).map_err(|e| format!("Read failed: {e}"))?;
+    if let Ok(wrapper) = serde_json::from_str::<ClientMessage>(&greeting)
+        && wrapper.command == "auth_response"
+    {
+        let resp: AuthResponse = serde_json::from_str(&wrapper.data)
+            .map_err(|e| format!("Invalid auth_response data: {e}"))?;
+        return Ok(LoginResponse {
+            success: resp.success,
+            message: resp.message,
+            user_id: resp.user_id,
+        });
+    }
     let auth = AuthRequest {
         passphrase,
         password,
@@ -523,16 +1000,24 @@ fn auth_over_stream(
 }
 
 /// Login and fetch message history in one TLS session.
+///
+/// With `client_cert_pem` and `client_key_pem` set, a device certificate
+/// linked to an account logs the session in as `login_mtls` does, and
+/// `passphrase`/`password` are only sent if the certificate is not linked.
 #[frb]
+#[allow(clippy::too_many_arguments)]
 pub fn login_and_fetch_history_tls(
     host: String,
     port: u16,
     ca_pem: String,
     passphrase: String,
     password: String,
+    client_cert_pem: Option<String>,
+    client_key_pem: Option<String>,
     limit: Option<usize>,
 ) -> Result<HistoryBundle, String> {
-    let mut tls = make_tls_stream(&host, port, &ca_pem)?;
+    let identity = client_identity(&client_cert_pem, &client_key_pem)?;
+    let mut tls = make_tls_stream_with_identity(&host, port, &ca_pem, identity)?;
     let login = auth_over_stream(&mut tls, "login", passphrase, password)?;
     let mut messages = Vec::new();
     if login.success {
@@ -612,4 +1097,163 @@ mod tests {
         let line = read_line(&mut c).expect("read_line");
         assert_eq!(line, "no newline here");
     }
+
+    fn stream_pair() -> (TcpStream, std::io::BufReader<TcpStream>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, std::io::BufReader::new(server))
+    }
+
+    fn next_line(server: &mut std::io::BufReader<TcpStream>) -> ClientMessage {
+        use std::io::BufRead;
+        let mut line = String::new();
+        server.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    fn reply(server: &mut std::io::BufReader<TcpStream>, command: &str, data: serde_json::Value) {
+        write_envelope(server.get_mut(), command, data.to_string()).unwrap();
+    }
+
+    #[test]
+    fn upload_sends_chunks_in_order_and_reports_progress() {
+        let (mut client, mut server) = stream_pair();
+        client.set_read_timeout(None).unwrap();
+        let handle = thread::spawn(move || {
+            let mut progress = Vec::new();
+            let result = upload_over_stream(
+                &mut client,
+                b"hello world",
+                "a.txt".to_string(),
+                "text/plain".to_string(),
+                |p| progress.push((p.transferred, p.total)),
+            );
+            (result, progress)
+        });
+
+        let begin = next_line(&mut server);
+        assert_eq!(begin.command, "upload_begin");
+        let begin: serde_json::Value = serde_json::from_str(&begin.data).unwrap();
+        assert_eq!(begin["sha256"], sha256_hex(b"hello world"));
+        // Unrelated events are skipped while waiting for the answer
+        reply(&mut server, "message", serde_json::json!({}));
+        let upload = |received: usize| {
+            serde_json::json!({
+                "success": true, "message": "", "attachment_id": 7,
+                "received": received, "chunk_bytes": 6,
+            })
+        };
+        reply(&mut server, "upload_response", upload(0));
+        let mut received = Vec::new();
+        while received.len() < 11 {
+            let chunk = next_line(&mut server);
+            assert_eq!(chunk.command, "upload_chunk");
+            let chunk: serde_json::Value = serde_json::from_str(&chunk.data).unwrap();
+            assert_eq!(chunk["offset"], received.len());
+            received.extend(BASE64.decode(chunk["data"].as_str().unwrap()).unwrap());
+            reply(&mut server, "upload_response", upload(received.len()));
+        }
+        assert_eq!(received, b"hello world");
+        assert_eq!(next_line(&mut server).command, "upload_finish");
+        reply(&mut server, "upload_response", upload(11));
+
+        let (result, progress) = handle.join().unwrap();
+        assert_eq!(result, Ok(7));
+        assert_eq!(progress, vec![(0, 11), (6, 11), (11, 11)]);
+    }
+
+    #[test]
+    fn download_stops_at_eof_and_surfaces_refusals() {
+        let (mut client, mut server) = stream_pair();
+        client.set_read_timeout(None).unwrap();
+        let handle = thread::spawn(move || {
+            let mut out = Vec::new();
+            let result = download_over_stream(&mut client, 3, &mut out, |_| {});
+            let refused = download_over_stream(&mut client, 4, &mut Vec::new(), |_| {});
+            (result, out, refused)
+        });
+        for (offset, data, eof) in [(0, "abc", false), (3, "de", true)] {
+            let req = next_line(&mut server);
+            assert_eq!(req.command, "download_chunk");
+            let req: serde_json::Value = serde_json::from_str(&req.data).unwrap();
+            assert_eq!(req["offset"], offset);
+            reply(
+                &mut server,
+                "download_response",
+                serde_json::json!({
+                    "success": true, "message": "OK", "attachment_id": 3, "offset": offset,
+                    "size": 5, "data": BASE64.encode(data), "eof": eof,
+                }),
+            );
+        }
+        next_line(&mut server);
+        reply(
+            &mut server,
+            "download_response",
+            serde_json::json!({
+                "success": false, "message": "Attachment not found", "attachment_id": 4,
+                "offset": 0, "size": 0, "data": "", "eof": false,
+            }),
+        );
+        let (result, out, refused) = handle.join().unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(out, b"abcde");
+        assert_eq!(refused.unwrap_err(), "Attachment not found");
+    }
+
+    #[test]
+    fn message_stream_answers_pings_and_reports_silent_server() {
+        let (mut client, mut server) = stream_pair();
+        let (_tx, rx) = mpsc::channel::<String>();
+        let liveness = StreamLiveness {
+            probe_after: Duration::from_millis(300),
+            dead_after: Duration::from_millis(300),
+        };
+        let handle = thread::spawn(move || {
+            let mut messages = Vec::new();
+            let result = run_message_stream(&mut client, &rx, liveness, |m| messages.push(m));
+            (result, messages)
+        });
+
+        let writer = server.get_mut();
+        writer
+            .write_all(b"{\"command\":\"ping\",\"data\":\"7\"}\n{\"command\":\"message\",\"data\":\"hi\"}\n")
+            .unwrap();
+        let pong = next_line(&mut server);
+        assert_eq!((pong.command.as_str(), pong.data.as_str()), ("pong", "7"));
+
+        // Stay silent: the client probes once, then gives up
+        assert_eq!(next_line(&mut server).command, "ping");
+        let (result, messages) = handle.join().unwrap();
+        assert_eq!(messages, vec!["hi".to_string()]);
+        assert!(result.unwrap_err().contains("no response"));
+    }
+
+    #[test]
+    fn message_stream_reports_server_shutdown_with_hint() {
+        let (mut client, mut server) = stream_pair();
+        server
+            .get_mut()
+            .write_all(b"{\"command\":\"server_shutdown\",\"data\":\"{\\\"message\\\":\\\"bye\\\",\\\"reconnect_after_ms\\\":4500}\"}\n")
+            .unwrap();
+        let (_tx, rx) = mpsc::channel::<String>();
+        let result = run_message_stream(&mut client, &rx, STREAM_LIVENESS, |_| {});
+        assert_eq!(
+            result.unwrap_err(),
+            "Server is shutting down; reconnect in 5s"
+        );
+    }
+
+    #[test]
+    fn message_stream_reports_server_close() {
+        let (mut client, server) = stream_pair();
+        drop(server);
+        let (_tx, rx) = mpsc::channel::<String>();
+        let result = run_message_stream(&mut client, &rx, STREAM_LIVENESS, |_| {});
+        assert_eq!(result.unwrap_err(), "Connection closed by server");
+    }
 }
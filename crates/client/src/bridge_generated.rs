@@ -37,7 +37,7 @@ flutter_rust_bridge::frb_generated_boilerplate!(
     default_rust_auto_opaque = RustAutoOpaqueMoi,
 );
 pub(crate) const FLUTTER_RUST_BRIDGE_CODEGEN_VERSION: &str = "2.11.1";
-pub(crate) const FLUTTER_RUST_BRIDGE_CODEGEN_CONTENT_HASH: i32 = 2067188788;
+pub(crate) const FLUTTER_RUST_BRIDGE_CODEGEN_CONTENT_HASH: i32 = 2128840040;
 
 // Section: executor
 
@@ -45,6 +45,62 @@ flutter_rust_bridge::frb_generated_default_handler!();
 
 // Section: wire_funcs
 
+fn wire__crate__api__download_attachment_tls_impl(
+    port_: flutter_rust_bridge::for_generated::MessagePort,
+    ptr_: flutter_rust_bridge::for_generated::PlatformGeneralizedUint8ListPtr,
+    rust_vec_len_: i32,
+    data_len_: i32,
+) {
+    FLUTTER_RUST_BRIDGE_HANDLER.wrap_normal::<flutter_rust_bridge::for_generated::SseCodec, _, _>(
+        flutter_rust_bridge::for_generated::TaskInfo {
+            debug_name: "download_attachment_tls",
+            port: Some(port_),
+            mode: flutter_rust_bridge::for_generated::FfiCallMode::Normal,
+        },
+        move || {
+            let message = unsafe {
+                flutter_rust_bridge::for_generated::Dart2RustMessageSse::from_wire(
+                    ptr_,
+                    rust_vec_len_,
+                    data_len_,
+                )
+            };
+            let mut deserializer =
+                flutter_rust_bridge::for_generated::SseDeserializer::new(message);
+            let api_host = <String>::sse_decode(&mut deserializer);
+            let api_port = <u16>::sse_decode(&mut deserializer);
+            let api_ca_pem = <String>::sse_decode(&mut deserializer);
+            let api_passphrase = <String>::sse_decode(&mut deserializer);
+            let api_password = <String>::sse_decode(&mut deserializer);
+            let api_client_cert_pem = <Option<String>>::sse_decode(&mut deserializer);
+            let api_client_key_pem = <Option<String>>::sse_decode(&mut deserializer);
+            let api_attachment_id = <i64>::sse_decode(&mut deserializer);
+            let api_dest_path = <String>::sse_decode(&mut deserializer);
+            let api_sink = <StreamSink<
+                crate::api::TransferProgress,
+                flutter_rust_bridge::for_generated::SseCodec,
+            >>::sse_decode(&mut deserializer);
+            deserializer.end();
+            move |context| {
+                transform_result_sse::<_, String>((move || {
+                    let output_ok = crate::api::download_attachment_tls(
+                        api_host,
+                        api_port,
+                        api_ca_pem,
+                        api_passphrase,
+                        api_password,
+                        api_client_cert_pem,
+                        api_client_key_pem,
+                        api_attachment_id,
+                        api_dest_path,
+                        api_sink,
+                    )?;
+                    Ok(output_ok)
+                })())
+            }
+        },
+    )
+}
 fn wire__crate__api__login_and_fetch_history_tls_impl(
     port_: flutter_rust_bridge::for_generated::MessagePort,
     ptr_: flutter_rust_bridge::for_generated::PlatformGeneralizedUint8ListPtr,
@@ -72,6 +128,8 @@ fn wire__crate__api__login_and_fetch_history_tls_impl(
             let api_ca_pem = <String>::sse_decode(&mut deserializer);
             let api_passphrase = <String>::sse_decode(&mut deserializer);
             let api_password = <String>::sse_decode(&mut deserializer);
+            let api_client_cert_pem = <Option<String>>::sse_decode(&mut deserializer);
+            let api_client_key_pem = <Option<String>>::sse_decode(&mut deserializer);
             let api_limit = <Option<usize>>::sse_decode(&mut deserializer);
             deserializer.end();
             move |context| {
@@ -82,6 +140,8 @@ fn wire__crate__api__login_and_fetch_history_tls_impl(
                         api_ca_pem,
                         api_passphrase,
                         api_password,
+                        api_client_cert_pem,
+                        api_client_key_pem,
                         api_limit,
                     )?;
                     Ok(output_ok)
@@ -90,6 +150,49 @@ fn wire__crate__api__login_and_fetch_history_tls_impl(
         },
     )
 }
+fn wire__crate__api__login_mtls_impl(
+    port_: flutter_rust_bridge::for_generated::MessagePort,
+    ptr_: flutter_rust_bridge::for_generated::PlatformGeneralizedUint8ListPtr,
+    rust_vec_len_: i32,
+    data_len_: i32,
+) {
+    FLUTTER_RUST_BRIDGE_HANDLER.wrap_normal::<flutter_rust_bridge::for_generated::SseCodec, _, _>(
+        flutter_rust_bridge::for_generated::TaskInfo {
+            debug_name: "login_mtls",
+            port: Some(port_),
+            mode: flutter_rust_bridge::for_generated::FfiCallMode::Normal,
+        },
+        move || {
+            let message = unsafe {
+                flutter_rust_bridge::for_generated::Dart2RustMessageSse::from_wire(
+                    ptr_,
+                    rust_vec_len_,
+                    data_len_,
+                )
+            };
+            let mut deserializer =
+                flutter_rust_bridge::for_generated::SseDeserializer::new(message);
+            let api_host = <String>::sse_decode(&mut deserializer);
+            let api_port = <u16>::sse_decode(&mut deserializer);
+            let api_ca_pem = <String>::sse_decode(&mut deserializer);
+            let api_client_cert_pem = <String>::sse_decode(&mut deserializer);
+            let api_client_key_pem = <String>::sse_decode(&mut deserializer);
+            deserializer.end();
+            move |context| {
+                transform_result_sse::<_, String>((move || {
+                    let output_ok = crate::api::login_mtls(
+                        api_host,
+                        api_port,
+                        api_ca_pem,
+                        api_client_cert_pem,
+                        api_client_key_pem,
+                    )?;
+                    Ok(output_ok)
+                })())
+            }
+        },
+    )
+}
 fn wire__crate__api__login_tls_impl(
     port_: flutter_rust_bridge::for_generated::MessagePort,
     ptr_: flutter_rust_bridge::for_generated::PlatformGeneralizedUint8ListPtr,
@@ -160,6 +263,8 @@ fn wire__crate__api__open_message_stream_tls_impl(
             let api_ca_pem = <String>::sse_decode(&mut deserializer);
             let api_passphrase = <String>::sse_decode(&mut deserializer);
             let api_password = <String>::sse_decode(&mut deserializer);
+            let api_client_cert_pem = <Option<String>>::sse_decode(&mut deserializer);
+            let api_client_key_pem = <Option<String>>::sse_decode(&mut deserializer);
             let api_sink =
                 <StreamSink<String, flutter_rust_bridge::for_generated::SseCodec>>::sse_decode(
                     &mut deserializer,
@@ -173,6 +278,8 @@ fn wire__crate__api__open_message_stream_tls_impl(
                         api_ca_pem,
                         api_passphrase,
                         api_password,
+                        api_client_cert_pem,
+                        api_client_key_pem,
                         api_sink,
                     )?;
                     Ok(output_ok)
@@ -295,6 +402,7 @@ fn wire__crate__api__send_direct_message_over_stream_impl(
             let api_to_user_id = <i64>::sse_decode(&mut deserializer);
             let api_body = <String>::sse_decode(&mut deserializer);
             let api_saved = <Option<bool>>::sse_decode(&mut deserializer);
+            let api_attachment_id = <Option<i64>>::sse_decode(&mut deserializer);
             deserializer.end();
             move |context| {
                 transform_result_sse::<_, String>((move || {
@@ -303,6 +411,7 @@ fn wire__crate__api__send_direct_message_over_stream_impl(
                         api_to_user_id,
                         api_body,
                         api_saved,
+                        api_attachment_id,
                     )?;
                     Ok(output_ok)
                 })())
@@ -337,9 +446,12 @@ fn wire__crate__api__send_direct_message_tls_impl(
             let api_ca_pem = <String>::sse_decode(&mut deserializer);
             let api_passphrase = <String>::sse_decode(&mut deserializer);
             let api_password = <String>::sse_decode(&mut deserializer);
+            let api_client_cert_pem = <Option<String>>::sse_decode(&mut deserializer);
+            let api_client_key_pem = <Option<String>>::sse_decode(&mut deserializer);
             let api_to_user_id = <i64>::sse_decode(&mut deserializer);
             let api_body = <String>::sse_decode(&mut deserializer);
             let api_saved = <Option<bool>>::sse_decode(&mut deserializer);
+            let api_attachment_id = <Option<i64>>::sse_decode(&mut deserializer);
             deserializer.end();
             move |context| {
                 transform_result_sse::<_, String>((move || {
@@ -349,9 +461,68 @@ fn wire__crate__api__send_direct_message_tls_impl(
                         api_ca_pem,
                         api_passphrase,
                         api_password,
+                        api_client_cert_pem,
+                        api_client_key_pem,
                         api_to_user_id,
                         api_body,
                         api_saved,
+                        api_attachment_id,
+                    )?;
+                    Ok(output_ok)
+                })())
+            }
+        },
+    )
+}
+fn wire__crate__api__upload_attachment_tls_impl(
+    port_: flutter_rust_bridge::for_generated::MessagePort,
+    ptr_: flutter_rust_bridge::for_generated::PlatformGeneralizedUint8ListPtr,
+    rust_vec_len_: i32,
+    data_len_: i32,
+) {
+    FLUTTER_RUST_BRIDGE_HANDLER.wrap_normal::<flutter_rust_bridge::for_generated::SseCodec, _, _>(
+        flutter_rust_bridge::for_generated::TaskInfo {
+            debug_name: "upload_attachment_tls",
+            port: Some(port_),
+            mode: flutter_rust_bridge::for_generated::FfiCallMode::Normal,
+        },
+        move || {
+            let message = unsafe {
+                flutter_rust_bridge::for_generated::Dart2RustMessageSse::from_wire(
+                    ptr_,
+                    rust_vec_len_,
+                    data_len_,
+                )
+            };
+            let mut deserializer =
+                flutter_rust_bridge::for_generated::SseDeserializer::new(message);
+            let api_host = <String>::sse_decode(&mut deserializer);
+            let api_port = <u16>::sse_decode(&mut deserializer);
+            let api_ca_pem = <String>::sse_decode(&mut deserializer);
+            let api_passphrase = <String>::sse_decode(&mut deserializer);
+            let api_password = <String>::sse_decode(&mut deserializer);
+            let api_client_cert_pem = <Option<String>>::sse_decode(&mut deserializer);
+            let api_client_key_pem = <Option<String>>::sse_decode(&mut deserializer);
+            let api_file_path = <String>::sse_decode(&mut deserializer);
+            let api_mime_type = <String>::sse_decode(&mut deserializer);
+            let api_sink = <StreamSink<
+                crate::api::TransferProgress,
+                flutter_rust_bridge::for_generated::SseCodec,
+            >>::sse_decode(&mut deserializer);
+            deserializer.end();
+            move |context| {
+                transform_result_sse::<_, String>((move || {
+                    let output_ok = crate::api::upload_attachment_tls(
+                        api_host,
+                        api_port,
+                        api_ca_pem,
+                        api_passphrase,
+                        api_password,
+                        api_client_cert_pem,
+                        api_client_key_pem,
+                        api_file_path,
+                        api_mime_type,
+                        api_sink,
                     )?;
                     Ok(output_ok)
                 })())
@@ -378,6 +549,16 @@ impl SseDecode for StreamSink<String, flutter_rust_bridge::for_generated::SseCod
     }
 }
 
+impl SseDecode
+    for StreamSink<crate::api::TransferProgress, flutter_rust_bridge::for_generated::SseCodec>
+{
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
+        let mut inner = <String>::sse_decode(deserializer);
+        return StreamSink::deserialize(inner);
+    }
+}
+
 impl SseDecode for String {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
@@ -418,6 +599,11 @@ impl SseDecode for crate::api::HistoryMessage {
         let mut var_body = <String>::sse_decode(deserializer);
         let mut var_timestamp = <String>::sse_decode(deserializer);
         let mut var_saved = <bool>::sse_decode(deserializer);
+        let mut var_replyToId = <Option<i64>>::sse_decode(deserializer);
+        let mut var_replySnippet = <String>::sse_decode(deserializer);
+        let mut var_attachmentId = <Option<i64>>::sse_decode(deserializer);
+        let mut var_attachmentName = <String>::sse_decode(deserializer);
+        let mut var_thumbnailId = <Option<i64>>::sse_decode(deserializer);
         return crate::api::HistoryMessage {
             id: var_id,
             from_user_id: var_fromUserId,
@@ -425,6 +611,11 @@ impl SseDecode for crate::api::HistoryMessage {
             body: var_body,
             timestamp: var_timestamp,
             saved: var_saved,
+            reply_to_id: var_replyToId,
+            reply_snippet: var_replySnippet,
+            attachment_id: var_attachmentId,
+            attachment_name: var_attachmentName,
+            thumbnail_id: var_thumbnailId,
         };
     }
 }
@@ -474,6 +665,17 @@ impl SseDecode for crate::api::LoginResponse {
     }
 }
 
+impl SseDecode for Option<String> {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
+        if (<bool>::sse_decode(deserializer)) {
+            return Some(<String>::sse_decode(deserializer));
+        } else {
+            return None;
+        }
+    }
+}
+
 impl SseDecode for Option<bool> {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
@@ -519,6 +721,20 @@ impl SseDecode for crate::api::SendResult {
     }
 }
 
+impl SseDecode for crate::api::TransferProgress {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
+        let mut var_attachmentId = <i64>::sse_decode(deserializer);
+        let mut var_transferred = <u64>::sse_decode(deserializer);
+        let mut var_total = <u64>::sse_decode(deserializer);
+        return crate::api::TransferProgress {
+            attachment_id: var_attachmentId,
+            transferred: var_transferred,
+            total: var_total,
+        };
+    }
+}
+
 impl SseDecode for u16 {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
@@ -526,6 +742,13 @@ impl SseDecode for u16 {
     }
 }
 
+impl SseDecode for u64 {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
+        deserializer.cursor.read_u64::<NativeEndian>().unwrap()
+    }
+}
+
 impl SseDecode for u8 {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
@@ -561,20 +784,23 @@ fn pde_ffi_dispatcher_primary_impl(
 ) {
     // Codec=Pde (Serialization + dispatch), see doc to use other codecs
     match func_id {
-        1 => wire__crate__api__login_and_fetch_history_tls_impl(port, ptr, rust_vec_len, data_len),
-        2 => wire__crate__api__login_tls_impl(port, ptr, rust_vec_len, data_len),
-        3 => wire__crate__api__open_message_stream_tls_impl(port, ptr, rust_vec_len, data_len),
-        4 => {
+        1 => wire__crate__api__download_attachment_tls_impl(port, ptr, rust_vec_len, data_len),
+        2 => wire__crate__api__login_and_fetch_history_tls_impl(port, ptr, rust_vec_len, data_len),
+        3 => wire__crate__api__login_mtls_impl(port, ptr, rust_vec_len, data_len),
+        4 => wire__crate__api__login_tls_impl(port, ptr, rust_vec_len, data_len),
+        5 => wire__crate__api__open_message_stream_tls_impl(port, ptr, rust_vec_len, data_len),
+        6 => {
             wire__crate__api__register_and_fetch_history_tls_impl(port, ptr, rust_vec_len, data_len)
         }
-        5 => wire__crate__api__register_tls_impl(port, ptr, rust_vec_len, data_len),
-        6 => wire__crate__api__send_direct_message_over_stream_impl(
+        7 => wire__crate__api__register_tls_impl(port, ptr, rust_vec_len, data_len),
+        8 => wire__crate__api__send_direct_message_over_stream_impl(
             port,
             ptr,
             rust_vec_len,
             data_len,
         ),
-        7 => wire__crate__api__send_direct_message_tls_impl(port, ptr, rust_vec_len, data_len),
+        9 => wire__crate__api__send_direct_message_tls_impl(port, ptr, rust_vec_len, data_len),
+        10 => wire__crate__api__upload_attachment_tls_impl(port, ptr, rust_vec_len, data_len),
         _ => unreachable!(),
     }
 }
@@ -621,6 +847,11 @@ impl flutter_rust_bridge::IntoDart for crate::api::HistoryMessage {
             self.body.into_into_dart().into_dart(),
             self.timestamp.into_into_dart().into_dart(),
             self.saved.into_into_dart().into_dart(),
+            self.reply_to_id.into_into_dart().into_dart(),
+            self.reply_snippet.into_into_dart().into_dart(),
+            self.attachment_id.into_into_dart().into_dart(),
+            self.attachment_name.into_into_dart().into_dart(),
+            self.thumbnail_id.into_into_dart().into_dart(),
         ]
         .into_dart()
     }
@@ -664,6 +895,25 @@ impl flutter_rust_bridge::IntoIntoDart<crate::api::SendResult> for crate::api::S
         self
     }
 }
+// Codec=Dco (DartCObject based), see doc to use other codecs
+impl flutter_rust_bridge::IntoDart for crate::api::TransferProgress {
+    fn into_dart(self) -> flutter_rust_bridge::for_generated::DartAbi {
+        [
+            self.attachment_id.into_into_dart().into_dart(),
+            self.transferred.into_into_dart().into_dart(),
+            self.total.into_into_dart().into_dart(),
+        ]
+        .into_dart()
+    }
+}
+impl flutter_rust_bridge::for_generated::IntoDartExceptPrimitive for crate::api::TransferProgress {}
+impl flutter_rust_bridge::IntoIntoDart<crate::api::TransferProgress>
+    for crate::api::TransferProgress
+{
+    fn into_into_dart(self) -> crate::api::TransferProgress {
+        self
+    }
+}
 
 impl SseEncode for flutter_rust_bridge::for_generated::anyhow::Error {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -679,6 +929,15 @@ impl SseEncode for StreamSink<String, flutter_rust_bridge::for_generated::SseCod
     }
 }
 
+impl SseEncode
+    for StreamSink<crate::api::TransferProgress, flutter_rust_bridge::for_generated::SseCodec>
+{
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
+        unimplemented!("")
+    }
+}
+
 impl SseEncode for String {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
@@ -712,6 +971,11 @@ impl SseEncode for crate::api::HistoryMessage {
         <String>::sse_encode(self.body, serializer);
         <String>::sse_encode(self.timestamp, serializer);
         <bool>::sse_encode(self.saved, serializer);
+        <Option<i64>>::sse_encode(self.reply_to_id, serializer);
+        <String>::sse_encode(self.reply_snippet, serializer);
+        <Option<i64>>::sse_encode(self.attachment_id, serializer);
+        <String>::sse_encode(self.attachment_name, serializer);
+        <Option<i64>>::sse_encode(self.thumbnail_id, serializer);
     }
 }
 
@@ -751,6 +1015,16 @@ impl SseEncode for crate::api::LoginResponse {
     }
 }
 
+impl SseEncode for Option<String> {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
+        <bool>::sse_encode(self.is_some(), serializer);
+        if let Some(value) = self {
+            <String>::sse_encode(value, serializer);
+        }
+    }
+}
+
 impl SseEncode for Option<bool> {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
@@ -789,6 +1063,15 @@ impl SseEncode for crate::api::SendResult {
     }
 }
 
+impl SseEncode for crate::api::TransferProgress {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
+        <i64>::sse_encode(self.attachment_id, serializer);
+        <u64>::sse_encode(self.transferred, serializer);
+        <u64>::sse_encode(self.total, serializer);
+    }
+}
+
 impl SseEncode for u16 {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
@@ -796,6 +1079,13 @@ impl SseEncode for u16 {
     }
 }
 
+impl SseEncode for u64 {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
+        serializer.cursor.write_u64::<NativeEndian>(self).unwrap();
+    }
+}
+
 impl SseEncode for u8 {
     // Codec=Sse (Serialization based), see doc to use other codecs
     fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
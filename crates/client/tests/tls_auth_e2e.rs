@@ -6,51 +6,24 @@ use tokio::net::TcpListener;
 
 // Reuse the actual server acceptor and handlers
 use rura_server::client::handle_client;
+use rura_server::client::handle_client_with_cert;
 use rura_server::messaging::state::AppState;
-use rura_server::utils::db_utils::store_message;
+use rura_server::utils::certgen::{
+    default_subject_alt_names, generate_dev_certs, issue_client_cert, write_certs,
+};
+use rura_server::utils::db_utils::{CertBindingKind, bind_client_certificate, register_user};
+use rura_server::utils::db_utils::{init_db_with_path, store_message};
 use rura_server::utils::tls::make_tls_acceptor;
+use rura_server::utils::tls::{
+    ReloadingCertResolver, client_cert_identity, make_client_verifier, make_reloading_acceptor,
+};
 
 // The client functions under test
 use rura_client::api::login_and_fetch_history_tls;
-use rura_client::api::{login_tls, register_tls};
+use rura_client::api::{login_mtls, login_tls, register_tls};
 
 fn create_test_db() -> Arc<Mutex<Connection>> {
-    let conn = Connection::open(":memory:").expect("open in-memory db");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            passphrase TEXT NOT NULL UNIQUE,
-            password TEXT NOT NULL
-        )",
-        [],
-    )
-    .expect("create users");
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            sender INTEGER NOT NULL,
-            receiver INTEGER NOT NULL,
-            content TEXT NOT NULL,
-            timestamp TEXT NOT NULL,
-            saved INTEGER NOT NULL DEFAULT 0,
-            FOREIGN KEY(sender) REFERENCES users(id),
-            FOREIGN KEY(receiver) REFERENCES users(id)
-        )",
-        [],
-    )
-    .expect("create messages");
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS connections (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            ip TEXT NOT NULL,
-            timestamp TEXT NOT NULL
-        )",
-        [],
-    )
-    .expect("create connections");
-
+    let conn = init_db_with_path(":memory:").expect("open in-memory db");
     Arc::new(Mutex::new(conn))
 }
 
@@ -206,6 +179,8 @@ async fn tls_register_then_login_end_to_end() {
             ca_pem_hist,
             "alice".to_string(),
             "secret".to_string(),
+            None,
+            None,
             Some(50),
         )
     })
@@ -215,3 +190,127 @@ async fn tls_register_then_login_end_to_end() {
     assert!(hist.success);
     assert!(hist.messages.iter().any(|m| m.body == "hello history"));
 }
+
+#[tokio::test]
+async fn gen_cert_output_is_trusted_by_client() {
+    // Same material `rura_server gen-cert` writes to disk
+    let names = default_subject_alt_names(None, &[]);
+    let certs = generate_dev_certs(&names, 1).expect("generate certs");
+    let dir = tempfile::tempdir().expect("tmp dir");
+    let paths = write_certs(dir.path(), &certs, false).expect("write certs");
+
+    let db = create_test_db();
+    let state = Arc::new(AppState::default());
+    let port = accept_n_connections(
+        1,
+        db,
+        state,
+        paths.server_cert.to_str().unwrap(),
+        paths.server_key.to_str().unwrap(),
+    )
+    .await;
+
+    let ca_pem = std::fs::read_to_string(&paths.ca_cert).expect("read ca.crt");
+    let reg = tokio::task::spawn_blocking(move || {
+        register_tls(
+            "localhost".to_string(),
+            port,
+            ca_pem,
+            "carol".to_string(),
+            "secret".to_string(),
+        )
+    })
+    .await
+    .expect("spawn")
+    .expect("register ok");
+    assert!(reg.success, "registration should succeed: {}", reg.message);
+}
+
+#[tokio::test]
+async fn mtls_login_with_bound_client_certificate() {
+    let certs = generate_dev_certs(&default_subject_alt_names(None, &[]), 1).expect("certs");
+    let dir = tempfile::tempdir().expect("tmp dir");
+    let paths = write_certs(dir.path(), &certs, false).expect("write certs");
+    let resolver = Arc::new(
+        ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).expect("resolver"),
+    );
+    let verifier = make_client_verifier(paths.ca_cert.to_str().unwrap(), false).expect("verifier");
+    let acceptor = make_reloading_acceptor(resolver, Some(verifier));
+
+    let db = create_test_db();
+    let uid = register_user(Arc::clone(&db), "erin", "secret")
+        .await
+        .expect("register");
+    bind_client_certificate(Arc::clone(&db), uid, CertBindingKind::Subject, "erin")
+        .await
+        .expect("bind");
+    let state = Arc::new(AppState::default());
+
+    let listener = TcpListener::bind(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .expect("bind");
+    let port = listener.local_addr().expect("local addr").port();
+    let server_db = Arc::clone(&db);
+    tokio::spawn(async move {
+        for _ in 0..4 {
+            let (stream, addr) = listener.accept().await.expect("accept");
+            let (db, state, acceptor) =
+                (Arc::clone(&server_db), Arc::clone(&state), acceptor.clone());
+            tokio::spawn(async move {
+                if let Ok(tls) = acceptor.accept(stream).await {
+                    let identity = client_cert_identity(tls.get_ref().1);
+                    let _ = handle_client_with_cert(tls, db, state, addr, identity).await;
+                }
+            });
+        }
+    });
+
+    for (name, expect_success) in [("erin", true), ("frank", false)] {
+        let client =
+            issue_client_cert(&certs.ca_cert_pem, &certs.ca_key_pem, name, 1).expect("client cert");
+        let ca_pem = certs.ca_cert_pem.clone();
+        let resp = tokio::task::spawn_blocking(move || {
+            login_mtls(
+                "localhost".to_string(),
+                port,
+                ca_pem,
+                client.cert_pem,
+                client.key_pem,
+            )
+        })
+        .await
+        .expect("spawn")
+        .expect("mtls login");
+        assert_eq!(resp.success, expect_success, "{}: {}", name, resp.message);
+        assert_eq!(resp.user_id, expect_success.then_some(uid));
+    }
+
+    // Session APIs take the same certificate: a linked one needs no password,
+    // an unlinked one falls back to the password login.
+    store_message(Arc::clone(&db), uid, uid, "hello by cert", false)
+        .await
+        .expect("store message");
+    for (name, password) in [("erin", ""), ("frank", "secret")] {
+        let client =
+            issue_client_cert(&certs.ca_cert_pem, &certs.ca_key_pem, name, 1).expect("client cert");
+        let ca_pem = certs.ca_cert_pem.clone();
+        let hist = tokio::task::spawn_blocking(move || {
+            login_and_fetch_history_tls(
+                "localhost".to_string(),
+                port,
+                ca_pem,
+                "erin".to_string(),
+                password.to_string(),
+                Some(client.cert_pem),
+                Some(client.key_pem),
+                Some(50),
+            )
+        })
+        .await
+        .expect("spawn")
+        .expect("hist ok");
+        assert!(hist.success, "{}: {}", name, hist.message);
+        assert_eq!(hist.user_id, Some(uid));
+        assert!(hist.messages.iter().any(|m| m.body == "hello by cert"));
+    }
+}
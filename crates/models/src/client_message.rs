@@ -18,3 +18,24 @@ pub struct AuthResponse {
     pub message: String,
     pub user_id: Option<i64>,
 }
+
+/// Structured `data` of an `error` event for failures a client can act on,
+/// such as `RateLimited` or `Forbidden`. Other errors still carry plain text.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+    /// Milliseconds to wait before retrying the rejected command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+/// `data` of the `server_shutdown` event sent to every session before the
+/// server closes it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerShutdownEvent {
+    pub message: String,
+    /// Suggested wait before reconnecting, spread per session so clients do
+    /// not all come back at once.
+    pub reconnect_after_ms: u64,
+}
@@ -5,18 +5,63 @@ pub struct DirectMessageReq {
     pub to_user_id: i64,
     pub body: String,
     pub saved: Option<bool>,
+    /// Earlier message of the same chat this one answers.
+    #[serde(default)]
+    pub reply_to_id: Option<i64>,
+    /// Completed upload to attach.
+    #[serde(default)]
+    pub attachment_id: Option<i64>,
+    /// Delete the message for everyone this many seconds after sending.
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+    /// Delete the message once the recipient confirms reading it.
+    #[serde(default)]
+    pub view_once: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DirectMessageEvent {
+    /// Id of the stored message; needed to confirm a view-once message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
     pub from_user_id: i64,
     pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<ReplyQuote>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<AttachmentInfo>,
+    /// Unix seconds at which the message disappears.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub view_once: bool,
+}
+
+/// Quoted start of the message a reply answers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReplyQuote {
+    pub message_id: i64,
+    pub from_user_id: i64,
+    /// At most 100 characters; empty once the quoted message was deleted.
+    pub snippet: String,
+    #[serde(default)]
+    pub deleted: bool,
 }
 
+/// Bookmark a message for the requesting user only.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SaveRequest {
     pub message_id: i64,
     pub saved: Option<bool>, // default true when omitted
+    /// Folder to file the bookmark in; empty for none. Kept when omitted.
+    #[serde(default)]
+    pub folder: Option<String>,
+    /// Replaces every tag of the bookmark. Kept when omitted.
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Kept when omitted.
+    #[serde(default)]
+    pub note: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,6 +72,40 @@ pub struct SaveResponse {
     pub saved: Option<bool>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SavedListRequest {
+    /// Only bookmarks in this folder; empty for those without one.
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Only bookmarks older than this one; pass the last id seen to page back.
+    #[serde(default)]
+    pub before_id: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// A message the requesting user saved.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Bookmark {
+    pub id: i64,
+    /// Empty when not in a folder.
+    pub folder: String,
+    pub tags: Vec<String>,
+    pub note: String,
+    pub saved_at: String,
+    pub message: HistoryMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedListResponse {
+    pub success: bool,
+    pub message: String,
+    /// Newest first.
+    pub bookmarks: Vec<Bookmark>,
+}
+
 // History fetch API
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -38,10 +117,40 @@ pub struct HistoryRequest {
 pub struct HistoryMessage {
     pub id: i64,
     pub from_user_id: i64,
-    pub to_user_id: i64,
+    /// Recipient of a direct message; `None` for group messages.
+    pub to_user_id: Option<i64>,
+    /// Group the message was sent to; `None` for direct messages.
+    #[serde(default)]
+    pub conversation_id: Option<i64>,
     pub body: String,
     pub timestamp: String,
+    /// Whether the requesting user bookmarked the message.
     pub saved: bool,
+    /// Group change recorded by the server; `body` is a [`GroupSystemMessage`].
+    #[serde(default)]
+    pub system: bool,
+    /// When the sender last edited `body`; `None` if never edited.
+    #[serde(default)]
+    pub edited_at: Option<String>,
+    /// Set when the sender deleted the message for everyone; `body` is empty.
+    #[serde(default)]
+    pub deleted_at: Option<String>,
+    /// Reaction counts by emoji, in the order they were first used.
+    #[serde(default)]
+    pub reactions: Vec<ReactionCount>,
+    /// Emoji the requesting user reacted with.
+    #[serde(default)]
+    pub my_reactions: Vec<String>,
+    #[serde(default)]
+    pub reply_to: Option<ReplyQuote>,
+    #[serde(default)]
+    pub attachment: Option<AttachmentInfo>,
+    /// Unix seconds at which the message disappears.
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// Disappears once the recipient confirms reading it with `viewed`.
+    #[serde(default)]
+    pub view_once: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -50,3 +159,519 @@ pub struct HistoryResponse {
     pub message: String,
     pub messages: Vec<HistoryMessage>,
 }
+
+// Threads
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreadRequest {
+    pub root_id: i64,
+    /// Only replies with a larger id; pass the last id seen to page forward.
+    #[serde(default)]
+    pub after_id: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThreadResponse {
+    pub success: bool,
+    pub message: String,
+    pub root_id: i64,
+    /// Replies to the root and to other replies, oldest first.
+    pub messages: Vec<HistoryMessage>,
+}
+
+// Attachments
+
+/// A file attached to a message; fetch it with `download_chunk`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentInfo {
+    pub id: i64,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: u64,
+    /// SHA-256 of the content, lowercase hex.
+    pub sha256: String,
+    /// Size in pixels as displayed, for JPEG and PNG images.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// A small preview of an image, downloadable like any attachment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<ThumbnailInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ThumbnailInfo {
+    /// Attachment id to pass to `download_chunk`.
+    pub id: i64,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadBeginRequest {
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadChunkRequest {
+    pub attachment_id: i64,
+    /// Must equal the number of bytes received so far.
+    pub offset: u64,
+    /// Base64 (standard alphabet, padded).
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadFinishRequest {
+    pub attachment_id: i64,
+}
+
+/// Answer to `upload_begin`, `upload_chunk` and `upload_finish`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadResponse {
+    pub success: bool,
+    pub message: String,
+    pub attachment_id: Option<i64>,
+    /// Bytes stored so far; the next chunk starts here.
+    pub received: u64,
+    /// Largest chunk the server accepts, in bytes before encoding.
+    pub chunk_bytes: usize,
+    /// Set by a successful `upload_finish`.
+    #[serde(default)]
+    pub attachment: Option<AttachmentInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadChunkRequest {
+    pub attachment_id: i64,
+    pub offset: u64,
+    /// Bytes wanted; capped by the server's chunk size.
+    #[serde(default)]
+    pub length: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadChunkResponse {
+    pub success: bool,
+    pub message: String,
+    pub attachment_id: i64,
+    pub offset: u64,
+    /// Total size of the file.
+    pub size: u64,
+    /// Base64 of the bytes from `offset`.
+    pub data: String,
+    /// The chunk ends at the end of the file.
+    pub eof: bool,
+}
+
+// Editing messages
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EditMessageRequest {
+    pub message_id: i64,
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EditMessageResponse {
+    pub success: bool,
+    pub message: String,
+    pub message_id: i64,
+    pub edited_at: Option<String>,
+}
+
+/// Pushed to everyone else who can see an edited message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageEditedEvent {
+    pub message_id: i64,
+    pub from_user_id: i64,
+    pub to_user_id: Option<i64>,
+    pub conversation_id: Option<i64>,
+    pub body: String,
+    pub edited_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageRevisionsRequest {
+    pub message_id: i64,
+}
+
+/// Earlier text of an edited message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageRevision {
+    pub body: String,
+    /// When this text was replaced by the next version.
+    pub replaced_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageRevisionsResponse {
+    pub success: bool,
+    pub message: String,
+    pub message_id: i64,
+    /// Oldest first; the current text is not included.
+    pub revisions: Vec<MessageRevision>,
+}
+
+// Deleting messages
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteMessageRequest {
+    pub message_id: i64,
+    /// `me` hides the message from the caller's history; `everyone` (sender
+    /// only) replaces it with a tombstone.
+    pub scope: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteMessageResponse {
+    pub success: bool,
+    pub message: String,
+    pub message_id: i64,
+    pub scope: String,
+}
+
+/// Pushed to everyone else who can see a message deleted for everyone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageDeletedEvent {
+    pub message_id: i64,
+    pub from_user_id: i64,
+    pub to_user_id: Option<i64>,
+    pub conversation_id: Option<i64>,
+    pub deleted_at: String,
+}
+
+// Disappearing messages
+
+/// Confirms the recipient read a view-once message, which deletes it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ViewedRequest {
+    pub message_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ViewedResponse {
+    pub success: bool,
+    pub message: String,
+    pub message_id: i64,
+}
+
+/// Pushed to everyone who could see a disappearing message once it is
+/// deleted for good. Unlike `message_deleted` no tombstone remains.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageExpiredEvent {
+    pub message_id: i64,
+    pub from_user_id: i64,
+    pub to_user_id: Option<i64>,
+    pub conversation_id: Option<i64>,
+    /// `expired` when its time ran out, `viewed` when its recipient read it.
+    pub reason: String,
+}
+
+// Reactions
+
+/// Body of both `react` and `unreact`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionRequest {
+    pub message_id: i64,
+    pub emoji: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionResponse {
+    pub success: bool,
+    pub message: String,
+    pub message_id: i64,
+    pub emoji: String,
+}
+
+/// Pushed to the other participants when a reaction is added or removed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionEvent {
+    pub message_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+    /// `false` when the reaction was removed.
+    pub added: bool,
+    pub to_user_id: Option<i64>,
+    pub conversation_id: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+}
+
+// Group conversations
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupCreateRequest {
+    pub name: String,
+    /// Other initial members; the creator is always a member.
+    #[serde(default)]
+    pub member_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupInviteRequest {
+    pub conversation_id: i64,
+    pub user_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupLeaveRequest {
+    pub conversation_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupRenameRequest {
+    pub conversation_id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupMessageReq {
+    pub conversation_id: i64,
+    pub body: String,
+    pub saved: Option<bool>,
+    /// Earlier message of the same group this one answers.
+    #[serde(default)]
+    pub reply_to_id: Option<i64>,
+    /// Completed upload to attach.
+    #[serde(default)]
+    pub attachment_id: Option<i64>,
+    /// Delete the message for everyone this many seconds after sending.
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+    /// Refused: view-once messages are direct only.
+    #[serde(default)]
+    pub view_once: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupMessageEvent {
+    pub conversation_id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
+    pub from_user_id: i64,
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<ReplyQuote>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<AttachmentInfo>,
+    /// Unix seconds at which the message disappears.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupKickRequest {
+    pub conversation_id: i64,
+    pub user_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupMuteRequest {
+    pub conversation_id: i64,
+    pub user_id: i64,
+    /// How long the member may not post; `0` lifts the mute.
+    pub duration_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupSetRoleRequest {
+    pub conversation_id: i64,
+    pub user_id: i64,
+    /// `admin` or `member`.
+    pub role: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupTransferRequest {
+    pub conversation_id: i64,
+    /// Member that becomes the owner; the previous owner becomes an admin.
+    pub user_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GroupMember {
+    pub user_id: i64,
+    /// `owner`, `admin` or `member`.
+    pub role: String,
+    /// Unix seconds until which the member may not post.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted_until: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConversationInfo {
+    pub id: i64,
+    pub name: String,
+    /// Current members, ascending.
+    pub member_ids: Vec<i64>,
+    /// Roles and mutes of `member_ids`, in the same order.
+    #[serde(default)]
+    pub members: Vec<GroupMember>,
+}
+
+/// Reply to `group_create`, `group_invite`, `group_leave` and `group_rename`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupResponse {
+    pub success: bool,
+    pub message: String,
+    pub conversation: Option<ConversationInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupListResponse {
+    pub success: bool,
+    pub message: String,
+    pub conversations: Vec<ConversationInfo>,
+}
+
+/// Pushed to the other members when a group is created or changed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupUpdatedEvent {
+    /// `created`, `invited`, `left`, `renamed`, `kicked`, `muted`,
+    /// `unmuted`, `role_changed` or `transferred`.
+    pub change: String,
+    pub actor_id: i64,
+    /// Members the change applies to, if not the actor.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_ids: Vec<i64>,
+    pub conversation: ConversationInfo,
+}
+
+/// Body of a system message: the record of a group change in its history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GroupSystemMessage {
+    /// Same values as [`GroupUpdatedEvent::change`].
+    pub change: String,
+    pub actor_id: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_ids: Vec<i64>,
+    /// Group name after a `created` or `renamed` change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Role after a `role_changed` change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// End of a `muted` change, in unix seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted_until: Option<i64>,
+}
+
+// Broadcast channels
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelCreateRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Only invited users may subscribe (and see the channel in listings).
+    #[serde(default)]
+    pub invite_only: bool,
+}
+
+/// `data` of `channel_subscribe` and `channel_unsubscribe`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelRequest {
+    pub channel_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelInviteRequest {
+    pub channel_id: i64,
+    pub user_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelSetRoleRequest {
+    pub channel_id: i64,
+    pub user_id: i64,
+    /// `publisher` or `subscriber`.
+    pub role: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelPostReq {
+    pub channel_id: i64,
+    pub body: String,
+}
+
+/// A channel post, pushed live as `channel_post` and returned by
+/// `channel_history`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelPostEvent {
+    pub channel_id: i64,
+    pub post_id: i64,
+    pub from_user_id: i64,
+    pub body: String,
+    pub timestamp: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelHistoryRequest {
+    pub channel_id: i64,
+    /// Only posts with a larger id, to catch up from the last one seen.
+    #[serde(default)]
+    pub after_id: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelHistoryResponse {
+    pub success: bool,
+    pub message: String,
+    pub channel_id: i64,
+    /// Oldest first.
+    pub posts: Vec<ChannelPostEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub invite_only: bool,
+    pub owner_id: Option<i64>,
+    /// Members of any role.
+    pub member_count: i64,
+    /// The caller's role: `owner`, `publisher` or `subscriber`; `None` when
+    /// not a member.
+    pub role: Option<String>,
+    /// The caller holds an invite and may subscribe.
+    #[serde(default)]
+    pub invited: bool,
+}
+
+/// Reply to the channel commands other than `channel_list`, `channel_post`
+/// and `channel_history`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelResponse {
+    pub success: bool,
+    pub message: String,
+    pub channel: Option<ChannelInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelListResponse {
+    pub success: bool,
+    pub message: String,
+    pub channels: Vec<ChannelInfo>,
+}
+
+/// Pushed to invitees of an invite-only channel.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelInvitedEvent {
+    pub inviter_id: i64,
+    pub channel: ChannelInfo,
+}
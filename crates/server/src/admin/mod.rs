@@ -0,0 +1,442 @@
+use rusqlite::Connection;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::messaging::attachments::collect_garbage;
+use crate::messaging::retention::apply_retention;
+use crate::models::args::{
+    AttachmentsCommand, Command, DbCommand, MessagesCommand, RetentionCommand, UserCommand,
+};
+use crate::models::config::Config;
+use crate::utils::certgen::sha256_fingerprint;
+use crate::utils::db_utils::{
+    CertBindingKind, ThrottleScope, attachment_usage, bind_client_certificate, check_db,
+    clear_login_failures, db_stats, delete_user, find_user_id, list_users, purge_messages_before,
+    register_user, retention_preview, set_conversation_retention, set_user_disabled,
+    set_user_password, unbind_client_certificates, vacuum_db,
+};
+use crate::utils::tls::normalize_fingerprint;
+
+fn db_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn user_not_found(passphrase: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no user with passphrase `{passphrase}`"),
+    )
+}
+
+/// Normalize `--before` into RFC 3339; a bare date means local midnight.
+pub fn parse_before(raw: &str) -> io::Result<String> {
+    if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(raw) {
+        return Ok(ts.to_rfc3339());
+    }
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{raw}` is neither an RFC 3339 timestamp nor a YYYY-MM-DD date"),
+        )
+    };
+    let date = chrono::NaiveDate::parse_from_str(raw, "%Y-%m-%d").map_err(|_| invalid())?;
+    let midnight = date.and_hms_opt(0, 0, 0).ok_or_else(invalid)?;
+    midnight
+        .and_local_timezone(chrono::Local)
+        .earliest()
+        .map(|ts| ts.to_rfc3339())
+        .ok_or_else(invalid)
+}
+
+fn read_password<R: BufRead>(given: &Option<String>, input: &mut R) -> io::Result<String> {
+    if let Some(password) = given {
+        return Ok(password.clone());
+    }
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "password must not be empty",
+        ));
+    }
+    Ok(password)
+}
+
+fn fingerprint_of_pem_file(path: &str) -> io::Result<String> {
+    let pem = std::fs::read(path)?;
+    let der = rustls_pemfile::certs(&mut pem.as_slice())
+        .next()
+        .transpose()?
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no certificate found in {path}"),
+            )
+        })?;
+    Ok(sha256_fingerprint(&der))
+}
+
+/// Turn the `bind-cert` match options into the stored (kind, value) pair.
+fn cert_binding(
+    fingerprint: &Option<String>,
+    subject: &Option<String>,
+    cert: &Option<String>,
+) -> io::Result<(CertBindingKind, String)> {
+    if let Some(raw) = fingerprint {
+        let normalized = normalize_fingerprint(raw).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`{raw}` is not a SHA-256 fingerprint"),
+            )
+        })?;
+        return Ok((CertBindingKind::Fingerprint, normalized));
+    }
+    if let Some(path) = cert {
+        return Ok((CertBindingKind::Fingerprint, fingerprint_of_pem_file(path)?));
+    }
+    match subject {
+        Some(cn) if !cn.is_empty() => Ok((CertBindingKind::Subject, cn.clone())),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "one of --fingerprint, --subject or --cert is required",
+        )),
+    }
+}
+
+async fn resolve_user(conn: &Arc<Mutex<Connection>>, passphrase: &str) -> io::Result<i64> {
+    find_user_id(Arc::clone(conn), passphrase)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| user_not_found(passphrase))
+}
+
+/// Run an administrative subcommand against `conn`, reporting on `out`.
+/// Passwords not given on the command line are read from `input`.
+pub async fn run_admin_command<R, W>(
+    command: &Command,
+    config: &Config,
+    conn: Arc<Mutex<Connection>>,
+    input: &mut R,
+    out: &mut W,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    match command {
+        Command::User(cmd) => run_user_command(cmd, conn, input, out).await,
+        Command::Messages(MessagesCommand::Purge {
+            before,
+            include_saved,
+        }) => {
+            let before = parse_before(before)?;
+            let removed = purge_messages_before(conn, &before, *include_saved)
+                .await
+                .map_err(db_error)?;
+            writeln!(out, "Purged {} message(s) older than {}", removed, before)
+        }
+        Command::Retention(cmd) => run_retention_command(cmd, config, conn, out).await,
+        Command::Attachments(AttachmentsCommand::Usage) => {
+            let usage = attachment_usage(conn).await.map_err(db_error)?;
+            let quota = match config.attachments.quota_bytes {
+                0 => "unlimited".to_string(),
+                bytes => bytes.to_string(),
+            };
+            writeln!(out, "quota_bytes: {}", quota)?;
+            writeln!(
+                out,
+                "{:>6}  {:>11}  {:>14}  passphrase",
+                "id", "attachments", "bytes"
+            )?;
+            for user in &usage.users {
+                let passphrase = user.passphrase.as_deref().unwrap_or("(deleted)");
+                let id = user.user_id.map_or("-".to_string(), |id| id.to_string());
+                writeln!(
+                    out,
+                    "{:>6}  {:>11}  {:>14}  {}",
+                    id, user.attachments, user.bytes, passphrase
+                )?;
+            }
+            writeln!(out, "charged_bytes: {}", usage.charged_bytes)?;
+            writeln!(
+                out,
+                "stored_bytes: {} in {} blob(s)",
+                usage.stored_bytes, usage.blobs
+            )
+        }
+        Command::Attachments(AttachmentsCommand::Gc) => {
+            let report = collect_garbage(conn, &config.attachments)
+                .await
+                .map_err(db_error)?;
+            writeln!(
+                out,
+                "Removed {} attachment(s) and {} blob(s), {} byte(s) freed",
+                report.attachments, report.blobs, report.bytes
+            )
+        }
+        Command::Db(DbCommand::Check) => {
+            let problems = check_db(conn).await.map_err(db_error)?;
+            if problems.is_empty() {
+                return writeln!(out, "Database OK");
+            }
+            for problem in &problems {
+                writeln!(out, "{}", problem)?;
+            }
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("database check found {} problem(s)", problems.len()),
+            ))
+        }
+        Command::Db(DbCommand::Vacuum) => {
+            vacuum_db(conn).await.map_err(db_error)?;
+            writeln!(out, "Database vacuumed")
+        }
+        Command::Stats => {
+            let stats = db_stats(conn).await.map_err(db_error)?;
+            writeln!(out, "schema_version: {}", stats.schema_version)?;
+            writeln!(out, "users: {}", stats.users)?;
+            writeln!(out, "disabled_users: {}", stats.disabled_users)?;
+            writeln!(out, "messages: {}", stats.messages)?;
+            writeln!(out, "saved_messages: {}", stats.saved_messages)?;
+            writeln!(out, "connections: {}", stats.connections)?;
+            writeln!(out, "auth_events: {}", stats.auth_events)?;
+            writeln!(out, "throttled_logins: {}", stats.throttled_logins)
+        }
+        Command::Serve | Command::Migrate(_) | Command::GenCert(_) | Command::GenClientCert(_) => {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an administrative command",
+            ))
+        }
+    }
+}
+
+/// A retention limit as reports show it; 0 keeps rows forever.
+fn describe_days(days: u32) -> String {
+    match days {
+        0 => "forever".to_string(),
+        days => format!("{days} day(s)"),
+    }
+}
+
+async fn run_retention_command<W: Write>(
+    command: &RetentionCommand,
+    config: &Config,
+    conn: Arc<Mutex<Connection>>,
+    out: &mut W,
+) -> io::Result<()> {
+    match command {
+        RetentionCommand::Run { dry_run: false } => {
+            let report = apply_retention(conn, config).await.map_err(db_error)?;
+            writeln!(
+                out,
+                "Deleted {} message(s), {} connection record(s), {} auth event(s) and {} login throttle record(s)",
+                report.messages, report.connections, report.auth_events, report.login_throttle
+            )
+        }
+        RetentionCommand::Run { dry_run: true } => {
+            let policy = &config.retention;
+            let now = chrono::Utc::now().to_rfc3339();
+            let preview =
+                retention_preview(conn, &now, policy.message_days, policy.connection_days)
+                    .await
+                    .map_err(db_error)?;
+            writeln!(out, "message_days: {}", describe_days(policy.message_days))?;
+            writeln!(
+                out,
+                "connection_days: {}",
+                describe_days(policy.connection_days)
+            )?;
+            writeln!(
+                out,
+                "direct messages to delete: {}",
+                preview.direct_messages
+            )?;
+            writeln!(out, "{:>6}  {:>10}  {:>9}  name", "group", "keep", "delete")?;
+            for group in &preview.groups {
+                let keep = group
+                    .retention_days
+                    .map_or("default".to_string(), describe_days);
+                writeln!(
+                    out,
+                    "{:>6}  {:>10}  {:>9}  {}",
+                    group.conversation_id, keep, group.messages, group.name
+                )?;
+            }
+            writeln!(out, "connection records to delete: {}", preview.connections)?;
+            writeln!(out, "auth events to delete: {}", preview.auth_events)?;
+            writeln!(
+                out,
+                "login throttle records to delete: {}",
+                preview.login_throttle
+            )
+        }
+        RetentionCommand::Set {
+            conversation_id,
+            days,
+        } => override_retention(conn, *conversation_id, Some(*days), out).await,
+        RetentionCommand::Unset { conversation_id } => {
+            override_retention(conn, *conversation_id, None, out).await
+        }
+    }
+}
+
+async fn override_retention<W: Write>(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    days: Option<u32>,
+    out: &mut W,
+) -> io::Result<()> {
+    let found = set_conversation_retention(conn, conversation_id, days)
+        .await
+        .map_err(db_error)?;
+    if !found {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no group with id {conversation_id}"),
+        ));
+    }
+    match days {
+        Some(days) => writeln!(
+            out,
+            "Group {} keeps unsaved messages {}",
+            conversation_id,
+            describe_days(days)
+        ),
+        None => writeln!(
+            out,
+            "Group {} follows retention.message_days again",
+            conversation_id
+        ),
+    }
+}
+
+async fn run_user_command<R, W>(
+    command: &UserCommand,
+    conn: Arc<Mutex<Connection>>,
+    input: &mut R,
+    out: &mut W,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    match command {
+        UserCommand::Add {
+            passphrase,
+            password,
+        } => {
+            let password = read_password(password, input)?;
+            let user_id = register_user(conn, passphrase, &password)
+                .await
+                .map_err(db_error)?;
+            writeln!(out, "Created user {} (id {})", passphrase, user_id)
+        }
+        UserCommand::List => {
+            let users = list_users(conn).await.map_err(db_error)?;
+            writeln!(out, "{:>6}  {:<8}  passphrase", "id", "status")?;
+            for user in users {
+                let status = if user.disabled { "disabled" } else { "active" };
+                writeln!(out, "{:>6}  {:<8}  {}", user.id, status, user.passphrase)?;
+            }
+            Ok(())
+        }
+        UserCommand::Disable { passphrase } | UserCommand::Enable { passphrase } => {
+            let disabled = matches!(command, UserCommand::Disable { .. });
+            let user_id = resolve_user(&conn, passphrase).await?;
+            set_user_disabled(conn, user_id, disabled)
+                .await
+                .map_err(db_error)?;
+            let verb = if disabled { "Disabled" } else { "Enabled" };
+            writeln!(out, "{} user {} (id {})", verb, passphrase, user_id)
+        }
+        UserCommand::ResetPassword {
+            passphrase,
+            password,
+        } => {
+            let user_id = resolve_user(&conn, passphrase).await?;
+            let password = read_password(password, input)?;
+            set_user_password(conn, user_id, &password)
+                .await
+                .map_err(db_error)?;
+            writeln!(out, "Password reset for {} (id {})", passphrase, user_id)
+        }
+        UserCommand::Delete { passphrase } => {
+            let user_id = resolve_user(&conn, passphrase).await?;
+            delete_user(conn, user_id).await.map_err(db_error)?;
+            writeln!(out, "Deleted user {} (id {})", passphrase, user_id)
+        }
+        UserCommand::BindCert {
+            passphrase,
+            fingerprint,
+            subject,
+            cert,
+        } => {
+            let (kind, value) = cert_binding(fingerprint, subject, cert)?;
+            let user_id = resolve_user(&conn, passphrase).await?;
+            bind_client_certificate(conn, user_id, kind, &value)
+                .await
+                .map_err(|e| match e {
+                    rusqlite::Error::SqliteFailure(err, _)
+                        if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+                    {
+                        io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("`{value}` is already bound to an account"),
+                        )
+                    }
+                    other => db_error(other),
+                })?;
+            let label = match kind {
+                CertBindingKind::Fingerprint => "fingerprint",
+                CertBindingKind::Subject => "subject",
+            };
+            writeln!(
+                out,
+                "Bound certificate {} {} to {} (id {})",
+                label, value, passphrase, user_id
+            )
+        }
+        UserCommand::UnbindCert { passphrase } => {
+            let user_id = resolve_user(&conn, passphrase).await?;
+            let removed = unbind_client_certificates(conn, user_id)
+                .await
+                .map_err(db_error)?;
+            writeln!(
+                out,
+                "Removed {} certificate binding(s) from {} (id {})",
+                removed, passphrase, user_id
+            )
+        }
+        // Throttling is keyed by the passphrase as typed, so no account is required
+        UserCommand::Unlock { passphrase, ip } => {
+            let ip = ip
+                .as_deref()
+                .map(|raw| {
+                    raw.parse::<std::net::IpAddr>().map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("`{raw}` is not an IP address: {e}"),
+                        )
+                    })
+                })
+                .transpose()?;
+            let mut targets = vec![(ThrottleScope::Account, passphrase.clone())];
+            targets.extend(ip.map(|ip| (ThrottleScope::Ip, ip.to_string())));
+            for (scope, key) in targets {
+                let cleared = clear_login_failures(Arc::clone(&conn), scope, &key)
+                    .await
+                    .map_err(db_error)?;
+                let verb = if cleared {
+                    "Unlocked"
+                } else {
+                    "No failed logins recorded for"
+                };
+                writeln!(out, "{} {}", verb, key)?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -4,8 +4,14 @@ use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use super::responses::{send_auth_error_response, send_auth_success_response};
+use super::throttle;
 use crate::models::client_message::{AuthRequest, ClientMessage};
-use crate::utils::db_utils::{authenticate_user, register_user};
+use crate::models::config::{LogLevel, LoginSection};
+use crate::utils::db_utils::{
+    AuthEventKind, authenticate_user, find_user_by_client_cert, log_auth_event, register_user,
+};
+use crate::utils::logging;
+use crate::utils::tls::ClientCertIdentity;
 
 pub async fn handle_auth_command_error<W>(stream: &mut W) -> tokio::io::Result<Option<i64>>
 where
@@ -31,13 +37,55 @@ where
     W: AsyncWrite + Unpin,
 {
     send_auth_success_response(stream, user_id, "Authentication successful").await?;
-    println!(
-        "User {} authenticated successfully from {}",
-        user_id, client_addr
-    );
+    if logging::enabled(LogLevel::Info) {
+        println!(
+            "User {} authenticated successfully from {}",
+            user_id, client_addr
+        );
+    }
     Ok(Some(user_id))
 }
 
+/// Log in with a verified client certificate, skipping the password exchange.
+/// Returns `None` without writing anything when the certificate is not linked
+/// to an enabled account, so the caller can fall back to `auth_required`.
+pub async fn handle_auth_client_cert<W>(
+    stream: &mut W,
+    conn: Arc<Mutex<Connection>>,
+    client_addr: SocketAddr,
+    identity: &ClientCertIdentity,
+) -> tokio::io::Result<Option<i64>>
+where
+    W: AsyncWrite + Unpin,
+{
+    match find_user_by_client_cert(conn, &identity.fingerprint, identity.subject.as_deref()).await {
+        Ok(Some(user_id)) => {
+            send_auth_success_response(stream, user_id, "Authenticated by client certificate")
+                .await?;
+            if logging::enabled(LogLevel::Info) {
+                println!(
+                    "User {} authenticated by client certificate from {}",
+                    user_id, client_addr
+                );
+            }
+            Ok(Some(user_id))
+        }
+        Ok(None) => {
+            if logging::enabled(LogLevel::Warn) {
+                println!(
+                    "Client certificate {} from {} is not linked to an account",
+                    identity.fingerprint, client_addr
+                );
+            }
+            Ok(None)
+        }
+        Err(e) => {
+            eprintln!("Database error during certificate authentication: {}", e);
+            Ok(None)
+        }
+    }
+}
+
 pub async fn handle_auth_failure<W>(stream: &mut W) -> tokio::io::Result<Option<i64>>
 where
     W: AsyncWrite + Unpin,
@@ -80,10 +128,12 @@ where
     W: AsyncWrite + Unpin,
 {
     send_auth_success_response(stream, user_id, "Registration successful").await?;
-    println!(
-        "User {} registered successfully from {}",
-        user_id, client_addr
-    );
+    if logging::enabled(LogLevel::Info) {
+        println!(
+            "User {} registered successfully from {}",
+            user_id, client_addr
+        );
+    }
     Ok(Some(user_id))
 }
 
@@ -117,28 +167,113 @@ where
     Ok(None)
 }
 
+async fn record_auth_event(
+    conn: Arc<Mutex<Connection>>,
+    policy: &LoginSection,
+    client_addr: SocketAddr,
+    passphrase: &str,
+    user_id: Option<i64>,
+    kind: AuthEventKind,
+) {
+    if !policy.event_log {
+        return;
+    }
+    if let Err(e) = log_auth_event(conn, client_addr, passphrase, user_id, kind).await {
+        eprintln!("Failed to log auth event: {}", e);
+    }
+}
+
+/// Password login with back-off per account and IP. Blocked, locked, unknown
+/// and wrong-password attempts all get the same response.
 pub async fn handle_auth_login<W>(
     stream: &mut W,
     conn: Arc<Mutex<Connection>>,
     client_addr: SocketAddr,
     msg: &ClientMessage,
+    policy: &LoginSection,
 ) -> tokio::io::Result<Option<i64>>
 where
     W: AsyncWrite + Unpin,
 {
-    match serde_json::from_str::<AuthRequest>(&msg.data) {
-        Ok(login_data) => match authenticate_user(
-            Arc::clone(&conn),
-            &login_data.passphrase,
-            &login_data.password,
-        )
-        .await
-        {
-            Ok(Some(user_id)) => handle_auth_success(stream, client_addr, user_id).await,
-            Ok(None) => handle_auth_failure(stream).await,
-            Err(e) => handle_auth_db_error(stream, e).await,
-        },
-        Err(e) => handle_auth_parse_error(stream, client_addr, e).await,
+    let login_data = match serde_json::from_str::<AuthRequest>(&msg.data) {
+        Ok(login_data) => login_data,
+        Err(e) => return handle_auth_parse_error(stream, client_addr, e).await,
+    };
+    let passphrase = login_data.passphrase.as_str();
+
+    match throttle::is_blocked(Arc::clone(&conn), passphrase, client_addr).await {
+        Ok(false) => {}
+        Ok(true) => {
+            // Refused before the password is checked, so it cannot be guessed meanwhile
+            record_auth_event(
+                conn,
+                policy,
+                client_addr,
+                passphrase,
+                None,
+                AuthEventKind::Failure,
+            )
+            .await;
+            return handle_auth_failure(stream).await;
+        }
+        Err(e) => return handle_auth_db_error(stream, e).await,
+    }
+
+    match authenticate_user(Arc::clone(&conn), passphrase, &login_data.password).await {
+        Ok(Some(user_id)) => {
+            if let Err(e) =
+                throttle::record_success(Arc::clone(&conn), passphrase, client_addr).await
+            {
+                eprintln!("Failed to reset login failures: {}", e);
+            }
+            record_auth_event(
+                conn,
+                policy,
+                client_addr,
+                passphrase,
+                Some(user_id),
+                AuthEventKind::Success,
+            )
+            .await;
+            handle_auth_success(stream, client_addr, user_id).await
+        }
+        Ok(None) => {
+            let locked =
+                match throttle::record_failure(Arc::clone(&conn), policy, passphrase, client_addr)
+                    .await
+                {
+                    Ok(locked) => locked,
+                    Err(e) => return handle_auth_db_error(stream, e).await,
+                };
+            record_auth_event(
+                Arc::clone(&conn),
+                policy,
+                client_addr,
+                passphrase,
+                None,
+                AuthEventKind::Failure,
+            )
+            .await;
+            if locked {
+                if logging::enabled(LogLevel::Warn) {
+                    println!(
+                        "Locked login for `{}` after repeated failures from {}",
+                        passphrase, client_addr
+                    );
+                }
+                record_auth_event(
+                    conn,
+                    policy,
+                    client_addr,
+                    passphrase,
+                    None,
+                    AuthEventKind::Lockout,
+                )
+                .await;
+            }
+            handle_auth_failure(stream).await
+        }
+        Err(e) => handle_auth_db_error(stream, e).await,
     }
 }
 
@@ -166,6 +301,8 @@ where
     }
 }
 
+/// Route an unauthenticated `login`/`register` command, with the default
+/// `[login]` policy.
 pub async fn handle_auth<W>(
     stream: &mut W,
     conn: Arc<Mutex<Connection>>,
@@ -176,7 +313,9 @@ where
     W: AsyncWrite + Unpin,
 {
     match message.command.as_str() {
-        "login" => handle_auth_login(stream, conn, client_addr, message).await,
+        "login" => {
+            handle_auth_login(stream, conn, client_addr, message, &LoginSection::default()).await
+        }
         "register" => handle_auth_register(stream, conn, client_addr, message).await,
         _ => handle_auth_command_error(stream).await,
     }
@@ -2,6 +2,7 @@ pub mod handlers;
 pub mod responses;
 #[cfg(test)]
 mod tests;
+pub mod throttle;
 
 pub use handlers::*;
 pub use responses::*;
@@ -1,6 +1,6 @@
 use crate::auth::handlers::*;
 use crate::models::client_message::{AuthRequest, AuthResponse, ClientMessage};
-use crate::utils::db_utils::register_user;
+use crate::utils::db_utils::{init_db_with_path, register_user};
 use rusqlite::Connection;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
@@ -11,19 +11,7 @@ fn test_socket_addr() -> SocketAddr {
 }
 
 async fn create_test_db() -> Arc<Mutex<Connection>> {
-    let conn = Connection::open(":memory:").unwrap();
-
-    // Create tables
-    conn.execute(
-        "CREATE TABLE users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            passphrase TEXT NOT NULL UNIQUE,
-            password TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
-
+    let conn = init_db_with_path(":memory:").unwrap();
     Arc::new(Mutex::new(conn))
 }
 
@@ -0,0 +1,103 @@
+use rusqlite::{Connection, Result as SqliteResult};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::models::config::LoginSection;
+use crate::utils::db_utils::{
+    ThrottleScope, block_login, clear_login_failures, login_blocked_until, record_login_failure,
+};
+
+/// Seconds a key stays blocked after its `failures`-th consecutive failure:
+/// nothing for the first `free_failures`, then doubling from the base delay.
+pub fn backoff_secs(policy: &LoginSection, failures: u32) -> u64 {
+    let Some(extra) = failures.checked_sub(policy.free_failures + 1) else {
+        return 0;
+    };
+    let factor = 1u64.checked_shl(extra).unwrap_or(u64::MAX);
+    policy
+        .backoff_base_secs
+        .saturating_mul(factor)
+        .min(policy.backoff_max_secs)
+}
+
+fn keys(passphrase: &str, client_addr: SocketAddr) -> [(ThrottleScope, String); 2] {
+    [
+        (ThrottleScope::Account, passphrase.to_string()),
+        (ThrottleScope::Ip, client_addr.ip().to_string()),
+    ]
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Whether a login for `passphrase` from `client_addr` must be refused
+/// without checking the password.
+pub async fn is_blocked(
+    conn: Arc<Mutex<Connection>>,
+    passphrase: &str,
+    client_addr: SocketAddr,
+) -> SqliteResult<bool> {
+    let keys = keys(passphrase, client_addr);
+    let keys: Vec<_> = keys.iter().map(|(s, k)| (*s, k.as_str())).collect();
+    Ok(login_blocked_until(conn, &keys, now()).await?.is_some())
+}
+
+/// Count a failed password check against the account and the IP. Returns
+/// `true` when this failure locked the account.
+pub async fn record_failure(
+    conn: Arc<Mutex<Connection>>,
+    policy: &LoginSection,
+    passphrase: &str,
+    client_addr: SocketAddr,
+) -> SqliteResult<bool> {
+    let now = now();
+    let mut locked = false;
+    for (scope, key) in keys(passphrase, client_addr) {
+        let failures = record_login_failure(Arc::clone(&conn), scope, &key).await?;
+        let mut block = backoff_secs(policy, failures);
+        // Only accounts lock: an IP may be shared by many legitimate users
+        if scope == ThrottleScope::Account
+            && policy.lockout_after > 0
+            && failures >= policy.lockout_after
+        {
+            block = block.max(policy.lockout_secs);
+            locked = true;
+        }
+        if block > 0 {
+            let until = now.saturating_add(i64::try_from(block).unwrap_or(i64::MAX));
+            block_login(Arc::clone(&conn), scope, &key, until).await?;
+        }
+    }
+    Ok(locked)
+}
+
+/// A successful login resets both counters.
+pub async fn record_success(
+    conn: Arc<Mutex<Connection>>,
+    passphrase: &str,
+    client_addr: SocketAddr,
+) -> SqliteResult<()> {
+    for (scope, key) in keys(passphrase, client_addr) {
+        clear_login_failures(Arc::clone(&conn), scope, &key).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_after_free_failures_and_is_capped() {
+        let policy = LoginSection {
+            free_failures: 2,
+            backoff_base_secs: 5,
+            backoff_max_secs: 60,
+            ..LoginSection::default()
+        };
+        let delays: Vec<u64> = (1..=7).map(|n| backoff_secs(&policy, n)).collect();
+        assert_eq!(delays, [0, 0, 5, 10, 20, 40, 60]);
+        assert_eq!(backoff_secs(&policy, u32::MAX), 60);
+    }
+}
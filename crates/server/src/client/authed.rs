@@ -1,12 +1,22 @@
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 
-use tokio::sync::mpsc;
-
-use crate::messaging::handlers::send_direct;
+use crate::messaging::actions::{handle_message_action, is_message_action};
+use crate::messaging::attachments::{
+    attachment_info, handle_attachment_command, is_attachment_command,
+};
+use crate::messaging::bookmarks::{handle_bookmark_command, is_bookmark_command};
+use crate::messaging::channels::{handle_channel_command, is_channel_command};
+use crate::messaging::expiry::invalid_expiry;
+use crate::messaging::groups::{handle_group_command, is_group_command};
+use crate::messaging::handlers::{reply_to, send_direct};
+use crate::messaging::models::{AttachmentInfo, ReactionCount, ReplyQuote};
+use crate::messaging::queue::SessionSender;
 use crate::messaging::state::AppState;
 use crate::models::client_message::ClientMessage;
-use crate::utils::db_utils::{fetch_messages_for_user, set_message_saved};
+use crate::models::config::LogLevel;
+use crate::utils::db_utils::{fetch_messages_for_user, readable_attachment, reply_quote};
+use crate::utils::logging;
 use rusqlite::Connection;
 
 #[derive(serde::Deserialize)]
@@ -18,10 +28,30 @@ struct LocalHistoryRequest {
 struct LocalHistoryMessage {
     id: i64,
     from_user_id: i64,
-    to_user_id: i64,
+    to_user_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversation_id: Option<i64>,
     body: String,
     timestamp: String,
     saved: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    system: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edited_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reactions: Vec<ReactionCount>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    my_reactions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<ReplyQuote>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attachment: Option<AttachmentInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    view_once: bool,
 }
 
 #[derive(serde::Serialize)]
@@ -31,24 +61,10 @@ struct LocalHistoryResponse {
     messages: Vec<LocalHistoryMessage>,
 }
 
-#[derive(serde::Deserialize)]
-struct LocalSaveRequest {
-    message_id: i64,
-    saved: Option<bool>,
-}
-
-#[derive(serde::Serialize)]
-struct LocalSaveResponse {
-    success: bool,
-    message: String,
-    message_id: Option<i64>,
-    saved: Option<bool>,
-}
-
 pub(super) async fn handle_client_message(
     state: Arc<AppState>,
     conn: Arc<Mutex<Connection>>,
-    outbound: &mpsc::UnboundedSender<ClientMessage>,
+    outbound: &SessionSender,
     client_addr: SocketAddr,
     user_id: i64,
     buffer: &[u8],
@@ -56,10 +72,12 @@ pub(super) async fn handle_client_message(
     let received = String::from_utf8_lossy(buffer).to_string();
     match serde_json::from_str::<ClientMessage>(&received) {
         Ok(msg) => {
-            println!(
-                "Received from authenticated user {} ({}): {:?}",
-                user_id, client_addr, msg
-            );
+            if logging::enabled(LogLevel::Debug) {
+                println!(
+                    "Received from authenticated user {} ({}): {:?}",
+                    user_id, client_addr, msg
+                );
+            }
             match msg.command.as_str() {
                 "message" => {
                     #[derive(serde::Deserialize)]
@@ -67,13 +85,75 @@ pub(super) async fn handle_client_message(
                         to_user_id: i64,
                         body: String,
                         saved: Option<bool>,
+                        reply_to_id: Option<i64>,
+                        attachment_id: Option<i64>,
+                        expires_in_secs: Option<u64>,
+                        #[serde(default)]
+                        view_once: bool,
                     }
                     match serde_json::from_str::<LocalDM>(&msg.data) {
+                        Ok(req) if req.body.len() > state.config().limits.max_body_bytes => {
+                            let err = ClientMessage {
+                                command: "error".to_string(),
+                                data: "Message too long".to_string(),
+                            };
+                            let _ = outbound.send(err);
+                        }
                         Ok(req) => {
+                            if let Some(text) =
+                                invalid_expiry(&state.config().expiry, req.expires_in_secs)
+                            {
+                                let err = ClientMessage {
+                                    command: "error".to_string(),
+                                    data: text,
+                                };
+                                let _ = outbound.send(err);
+                                return Ok(());
+                            }
+                            let reply_ok = match req.reply_to_id {
+                                Some(reply_to_id) => matches!(
+                                    reply_quote(
+                                        Arc::clone(&conn),
+                                        reply_to_id,
+                                        user_id,
+                                        Some(req.to_user_id),
+                                        None,
+                                    )
+                                    .await,
+                                    Ok(Some(_))
+                                ),
+                                None => true,
+                            };
+                            if !reply_ok {
+                                let err = ClientMessage {
+                                    command: "error".to_string(),
+                                    data: "Reply target not found in this chat".to_string(),
+                                };
+                                let _ = outbound.send(err);
+                                return Ok(());
+                            }
+                            if let Some(attachment_id) = req.attachment_id
+                                && !matches!(
+                                    readable_attachment(Arc::clone(&conn), user_id, attachment_id)
+                                        .await,
+                                    Ok(Some(_))
+                                )
+                            {
+                                let err = ClientMessage {
+                                    command: "error".to_string(),
+                                    data: "Attachment not found".to_string(),
+                                };
+                                let _ = outbound.send(err);
+                                return Ok(());
+                            }
                             let req2 = crate::messaging::models::DirectMessageReq {
                                 to_user_id: req.to_user_id,
                                 body: req.body,
                                 saved: req.saved,
+                                reply_to_id: req.reply_to_id,
+                                attachment_id: req.attachment_id,
+                                expires_in_secs: req.expires_in_secs,
+                                view_once: req.view_once,
                             };
                             send_direct(Arc::clone(&state), Arc::clone(&conn), user_id, req2)
                                 .await?;
@@ -90,7 +170,11 @@ pub(super) async fn handle_client_message(
                 }
                 "history" => match serde_json::from_str::<LocalHistoryRequest>(&msg.data) {
                     Ok(req) => {
-                        let limit = req.limit.unwrap_or(100);
+                        let config = state.config();
+                        let limit = req
+                            .limit
+                            .unwrap_or(config.history.default_limit)
+                            .min(config.limits.max_history_limit);
                         match fetch_messages_for_user(Arc::clone(&conn), user_id, limit).await {
                             Ok(messages) => {
                                 let mapped: Vec<LocalHistoryMessage> = messages
@@ -99,9 +183,26 @@ pub(super) async fn handle_client_message(
                                         id: m.id,
                                         from_user_id: m.sender,
                                         to_user_id: m.receiver,
+                                        conversation_id: m.conversation_id,
                                         body: m.content,
                                         timestamp: m.timestamp,
                                         saved: m.saved,
+                                        system: m.system,
+                                        edited_at: m.edited_at,
+                                        deleted_at: m.deleted_at,
+                                        reactions: m
+                                            .reactions
+                                            .into_iter()
+                                            .map(|r| ReactionCount {
+                                                emoji: r.emoji,
+                                                count: r.count,
+                                            })
+                                            .collect(),
+                                        my_reactions: m.my_reactions,
+                                        reply_to: m.reply_to.map(reply_to),
+                                        attachment: m.attachment.map(attachment_info),
+                                        expires_at: m.expires_at,
+                                        view_once: m.view_once,
                                     })
                                     .collect();
                                 let resp = LocalHistoryResponse {
@@ -137,69 +238,68 @@ pub(super) async fn handle_client_message(
                         let _ = outbound.send(err);
                     }
                 },
-                "save" => match serde_json::from_str::<LocalSaveRequest>(&msg.data) {
-                    Ok(req) => {
-                        let saved_flag = req.saved.unwrap_or(true);
-                        match set_message_saved(
-                            Arc::clone(&conn),
-                            user_id,
-                            req.message_id,
-                            saved_flag,
-                        )
-                        .await
-                        {
-                            Ok(true) => {
-                                let resp = LocalSaveResponse {
-                                    success: true,
-                                    message: "Message updated".to_string(),
-                                    message_id: Some(req.message_id),
-                                    saved: Some(saved_flag),
-                                };
-                                let wrapper = ClientMessage {
-                                    command: "save_response".to_string(),
-                                    data: serde_json::to_string(&resp).unwrap(),
-                                };
-                                let _ = outbound.send(wrapper);
-                            }
-                            Ok(false) => {
-                                let resp = LocalSaveResponse {
-                                    success: false,
-                                    message: "Message not found or not authorized".to_string(),
-                                    message_id: Some(req.message_id),
-                                    saved: Some(saved_flag),
-                                };
-                                let wrapper = ClientMessage {
-                                    command: "save_response".to_string(),
-                                    data: serde_json::to_string(&resp).unwrap(),
-                                };
-                                let _ = outbound.send(wrapper);
-                            }
-                            Err(_) => {
-                                let resp = LocalSaveResponse {
-                                    success: false,
-                                    message: "Failed to update message".to_string(),
-                                    message_id: Some(req.message_id),
-                                    saved: Some(saved_flag),
-                                };
-                                let wrapper = ClientMessage {
-                                    command: "save_response".to_string(),
-                                    data: serde_json::to_string(&resp).unwrap(),
-                                };
-                                let _ = outbound.send(wrapper);
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        let err = ClientMessage {
-                            command: "error".to_string(),
-                            data: "Invalid save format".to_string(),
-                        };
-                        let _ = outbound.send(err);
-                    }
-                },
+                command if is_bookmark_command(command) => {
+                    handle_bookmark_command(
+                        Arc::clone(&state),
+                        Arc::clone(&conn),
+                        outbound,
+                        user_id,
+                        msg,
+                    )
+                    .await;
+                }
+                command if is_message_action(command) => {
+                    handle_message_action(
+                        Arc::clone(&state),
+                        Arc::clone(&conn),
+                        outbound,
+                        user_id,
+                        msg,
+                    )
+                    .await;
+                }
+                command if is_attachment_command(command) => {
+                    handle_attachment_command(
+                        Arc::clone(&state),
+                        Arc::clone(&conn),
+                        outbound,
+                        user_id,
+                        msg,
+                    )
+                    .await;
+                }
+                command if is_group_command(command) => {
+                    handle_group_command(
+                        Arc::clone(&state),
+                        Arc::clone(&conn),
+                        outbound,
+                        user_id,
+                        msg,
+                    )
+                    .await;
+                }
+                command if is_channel_command(command) => {
+                    handle_channel_command(
+                        Arc::clone(&state),
+                        Arc::clone(&conn),
+                        outbound,
+                        user_id,
+                        msg,
+                    )
+                    .await;
+                }
+                // Heartbeats: answer client probes, and a `pong` only needs to be received
+                "ping" => {
+                    let pong = ClientMessage {
+                        command: "pong".to_string(),
+                        data: msg.data,
+                    };
+                    let _ = outbound.send_ephemeral(pong);
+                }
+                "pong" => {}
                 // default: echo back via outbound to keep behavior simple
                 _ => {
-                    let _ = outbound.send(msg);
+                    let _ = outbound.send_ephemeral(msg);
                 }
             }
             Ok(())
@@ -1,27 +1,58 @@
 use rusqlite::Connection;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::mpsc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
-use crate::models::client_message::ClientMessage;
+use crate::models::client_message::{ClientMessage, ErrorResponse};
+use crate::models::config::LogLevel;
+use crate::utils::logging;
 
+use super::rate_limit::{CommandClass, RateKey, Verdict};
 use super::{authed, unauth};
+use crate::messaging::queue::SessionSender;
 use crate::messaging::state::AppState;
 
+/// What the connection loop does once a read has been handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ReadOutcome {
+    Continue,
+    /// The client kept exceeding its rate limits; close the connection.
+    Disconnect,
+}
+
+// Only the command name is needed to pick a rate-limit class.
+#[derive(serde::Deserialize)]
+struct CommandPeek {
+    command: String,
+}
+
 pub(super) async fn handle_read_success<S>(
     stream: &mut S,
     conn: Arc<Mutex<Connection>>,
     state: Arc<AppState>,
     client_addr: SocketAddr,
     authenticated_user_id: &mut Option<i64>,
-    outbound_tx: Option<&mpsc::UnboundedSender<ClientMessage>>,
+    outbound_tx: Option<&SessionSender>,
     buffer: &[u8],
-) -> tokio::io::Result<()>
+) -> tokio::io::Result<ReadOutcome>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
+    if let Some(outcome) = enforce_rate_limit(
+        stream,
+        &state,
+        client_addr,
+        *authenticated_user_id,
+        outbound_tx,
+        buffer,
+    )
+    .await?
+    {
+        return Ok(outcome);
+    }
+
     if let Some(user_id) = *authenticated_user_id {
         // User is authenticated, allow normal communication
         let tx = outbound_tx.expect("outbound sender not set for authenticated user");
@@ -33,7 +64,7 @@ where
             user_id,
             buffer,
         )
-        .await
+        .await?;
     } else {
         // User not authenticated, only allow auth commands
         let received = String::from_utf8_lossy(buffer).to_string();
@@ -42,13 +73,88 @@ where
                 unauth::handle_unauthenticated_message(
                     stream,
                     Arc::clone(&conn),
+                    &state,
                     client_addr,
                     msg,
                     authenticated_user_id,
                 )
-                .await
+                .await?
             }
-            Err(e) => unauth::handle_unauthenticated_parse_error(stream, client_addr, e).await,
+            Err(e) => unauth::handle_unauthenticated_parse_error(stream, client_addr, e).await?,
         }
     }
+    Ok(ReadOutcome::Continue)
+}
+
+/// Charge the command against its class bucket, keyed by user once logged
+/// in and by IP before. Returns `Some` when the command was rejected.
+async fn enforce_rate_limit<W>(
+    stream: &mut W,
+    state: &AppState,
+    client_addr: SocketAddr,
+    authenticated_user_id: Option<i64>,
+    outbound_tx: Option<&SessionSender>,
+    buffer: &[u8],
+) -> tokio::io::Result<Option<ReadOutcome>>
+where
+    W: AsyncWrite + Unpin,
+{
+    // Unparseable input still costs a token, so garbage cannot flood the server
+    let class = match serde_json::from_slice::<CommandPeek>(buffer) {
+        Ok(peek) => CommandClass::of(&peek.command),
+        Err(_) => Some(CommandClass::Other),
+    };
+    let Some(class) = class else {
+        return Ok(None);
+    };
+    let key = match authenticated_user_id {
+        Some(user_id) => RateKey::User(user_id),
+        None => RateKey::Ip(client_addr.ip()),
+    };
+    let Verdict::Limited {
+        retry_after,
+        disconnect,
+    } = state.rate_limiter().check(key, class, Instant::now())
+    else {
+        return Ok(None);
+    };
+
+    let retry_after_ms =
+        u64::try_from(retry_after.as_nanos().div_ceil(1_000_000)).unwrap_or(u64::MAX);
+    if logging::enabled(LogLevel::Warn) {
+        println!(
+            "Rate limited {} command from {} ({:?})",
+            class.name(),
+            client_addr,
+            key
+        );
+    }
+    let error = ClientMessage {
+        command: "error".to_string(),
+        data: serde_json::to_string(&ErrorResponse {
+            code: "RateLimited".to_string(),
+            message: format!(
+                "Too many {} commands, retry after {} ms",
+                class.name(),
+                retry_after_ms
+            ),
+            retry_after_ms: Some(retry_after_ms),
+        })?,
+    };
+    match outbound_tx.filter(|_| !disconnect) {
+        // Ephemeral: a flooding client must not fill its own queue with rejections
+        Some(tx) => {
+            let _ = tx.send_ephemeral(error);
+        }
+        None => {
+            let response = serde_json::to_string(&error)? + "\n";
+            stream.write_all(response.as_bytes()).await?;
+            stream.flush().await?;
+        }
+    }
+    Ok(Some(if disconnect {
+        ReadOutcome::Disconnect
+    } else {
+        ReadOutcome::Continue
+    }))
 }
@@ -0,0 +1,80 @@
+/// The pending partial line grew beyond `limits.max_line_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct LineTooLong;
+
+/// Splits the bytes read from a connection into newline-terminated lines,
+/// however the reads happen to be cut.
+pub(super) struct LineBuffer {
+    buf: Vec<u8>,
+    /// Bytes of `buf` already searched for a newline.
+    scanned: usize,
+    max_line: usize,
+}
+
+impl LineBuffer {
+    pub(super) fn new(max_line: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            scanned: 0,
+            max_line,
+        }
+    }
+
+    pub(super) fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Next complete line without its `\n` (or `\r\n`). Blank lines are
+    /// skipped.
+    pub(super) fn next_line(&mut self) -> Result<Option<Vec<u8>>, LineTooLong> {
+        loop {
+            let Some(pos) = self.buf[self.scanned..].iter().position(|&b| b == b'\n') else {
+                self.scanned = self.buf.len();
+                if self.buf.len() > self.max_line {
+                    return Err(LineTooLong);
+                }
+                return Ok(None);
+            };
+            let end = self.scanned + pos;
+            let mut line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.scanned = 0;
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.len() > self.max_line {
+                return Err(LineTooLong);
+            }
+            if !line.is_empty() {
+                return Ok(Some(line));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_joined_across_reads_and_split_within_one() {
+        let mut lines = LineBuffer::new(64);
+        lines.extend(b"{\"a\":");
+        assert_eq!(lines.next_line(), Ok(None));
+        lines.extend(b"1}\r\n\n{\"b\":2}\n{\"c\"");
+        assert_eq!(lines.next_line(), Ok(Some(b"{\"a\":1}".to_vec())));
+        assert_eq!(lines.next_line(), Ok(Some(b"{\"b\":2}".to_vec())));
+        assert_eq!(lines.next_line(), Ok(None));
+        lines.extend(b":3}\n");
+        assert_eq!(lines.next_line(), Ok(Some(b"{\"c\":3}".to_vec())));
+    }
+
+    #[test]
+    fn overlong_lines_are_refused_before_their_end_arrives() {
+        let mut lines = LineBuffer::new(8);
+        lines.extend(b"12345678\n");
+        assert_eq!(lines.next_line(), Ok(Some(b"12345678".to_vec())));
+        lines.extend(b"123456789");
+        assert_eq!(lines.next_line(), Err(LineTooLong));
+    }
+}
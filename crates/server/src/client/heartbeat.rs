@@ -0,0 +1,92 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::models::config::TimeoutsSection;
+
+/// What the connection loop has to do when the heartbeat timer fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Tick {
+    /// Send a `ping` carrying this sequence number.
+    Ping(u64),
+    /// The client did not log in within `timeouts.auth_secs`.
+    AuthTimeout,
+    /// Nothing was received for `timeouts.idle_secs`.
+    IdleTimeout,
+}
+
+/// Per-connection liveness timers driven by `[timeouts]`.
+pub(super) struct Heartbeat {
+    auth_deadline: Option<Instant>,
+    idle_after: Option<Duration>,
+    ping_every: Option<Duration>,
+    last_seen: Instant,
+    next_ping: Option<Instant>,
+    ping_seq: u64,
+}
+
+fn secs(value: u64) -> Option<Duration> {
+    (value > 0).then(|| Duration::from_secs(value))
+}
+
+impl Heartbeat {
+    pub(super) fn new(timeouts: &TimeoutsSection, authenticated: bool) -> Self {
+        let now = Instant::now();
+        let ping_every = secs(timeouts.ping_interval_secs);
+        Self {
+            auth_deadline: secs(timeouts.auth_secs)
+                .filter(|_| !authenticated)
+                .map(|d| now + d),
+            idle_after: secs(timeouts.idle_secs),
+            ping_every,
+            last_seen: now,
+            next_ping: ping_every.map(|d| now + d),
+            ping_seq: 0,
+        }
+    }
+
+    /// Record that the client sent something.
+    pub(super) fn seen(&mut self) {
+        self.last_seen = Instant::now();
+        self.next_ping = self.ping_every.map(|d| self.last_seen + d);
+    }
+
+    /// The client logged in; the auth deadline no longer applies.
+    pub(super) fn authenticated(&mut self) {
+        self.auth_deadline = None;
+    }
+
+    /// When the timer should next fire, if ever.
+    pub(super) fn deadline(&self) -> Option<Instant> {
+        let idle = self.idle_after.map(|d| self.last_seen + d);
+        [self.auth_deadline, idle, self.next_ping]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Work due at `now`; timeouts take precedence over pings.
+    pub(super) fn due(&mut self, now: Instant) -> Option<Tick> {
+        if self.auth_deadline.is_some_and(|at| at <= now) {
+            return Some(Tick::AuthTimeout);
+        }
+        if self.idle_after.is_some_and(|d| self.last_seen + d <= now) {
+            return Some(Tick::IdleTimeout);
+        }
+        match (self.next_ping, self.ping_every) {
+            (Some(at), Some(every)) if at <= now => {
+                self.next_ping = Some(now + every);
+                self.ping_seq += 1;
+                Some(Tick::Ping(self.ping_seq))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Sleep until the heartbeat deadline; never completes when no timer is set.
+pub(super) async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
@@ -1,5 +1,8 @@
 use std::net::SocketAddr;
 
+use crate::models::config::LogLevel;
+use crate::utils::logging;
+
 pub(super) async fn handle_connection_closed(client_addr: SocketAddr) {
     println!("Connection closed by {}", client_addr);
 }
@@ -7,3 +10,9 @@ pub(super) async fn handle_connection_closed(client_addr: SocketAddr) {
 pub(super) async fn handle_read_error(client_addr: SocketAddr, e: std::io::Error) {
     eprintln!("Error reading from {}: {}", client_addr, e);
 }
+
+pub(super) async fn handle_timeout(client_addr: SocketAddr, reason: &str) {
+    if logging::enabled(LogLevel::Info) {
+        println!("Closing connection from {}: {}", client_addr, reason);
+    }
+}
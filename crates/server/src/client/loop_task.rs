@@ -1,123 +1,246 @@
 use rusqlite::Connection;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::select;
-use tokio::sync::mpsc;
 
+use crate::messaging::queue::{SessionReceiver, SessionSender};
 use crate::messaging::state::{AppState, ClientHandle};
+use crate::models::client_message::{ClientMessage, ServerShutdownEvent};
 
+use super::dispatch::ReadOutcome;
+use super::framing::LineBuffer;
+use super::heartbeat::{self, Heartbeat, Tick};
 use super::{dispatch, io_helpers};
 
+async fn recv_outbound(rx: &mut Option<SessionReceiver>) -> Option<Arc<ClientMessage>> {
+    match rx {
+        Some(rx) => rx.recv_shared().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn write_message<S>(stream: &mut S, msg: &ClientMessage) -> tokio::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut json = serde_json::to_string(msg)?;
+    json.push('\n');
+    stream.write_all(json.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// Reconnect hint between `base` and twice `base`, so clients spread out.
+fn reconnect_after_ms(base_secs: u64) -> u64 {
+    let base_ms = base_secs.saturating_mul(1000);
+    base_ms.saturating_add(rand_core::RngCore::next_u64(&mut rand_core::OsRng) % (base_ms + 1))
+}
+
+/// Flush events still queued for the session, then tell the client the
+/// server is going away. Gives up on a client that stops reading.
+async fn drain_for_shutdown<S>(
+    stream: &mut S,
+    outbound_rx: &mut Option<SessionReceiver>,
+    state: &AppState,
+    write_timeout: Duration,
+) -> tokio::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let timed_out = || std::io::Error::from(std::io::ErrorKind::TimedOut);
+    if let Some(rx) = outbound_rx.as_mut() {
+        while !rx.is_empty() {
+            let Some(msg) = rx.recv_shared().await else {
+                break;
+            };
+            tokio::time::timeout(write_timeout, write_message(stream, &msg))
+                .await
+                .map_err(|_| timed_out())??;
+        }
+    }
+    let event = ServerShutdownEvent {
+        message: "Server is shutting down".to_string(),
+        reconnect_after_ms: reconnect_after_ms(state.config().shutdown.reconnect_after_secs),
+    };
+    let msg = ClientMessage {
+        command: "server_shutdown".to_string(),
+        data: serde_json::to_string(&event)?,
+    };
+    tokio::time::timeout(write_timeout, write_message(stream, &msg))
+        .await
+        .map_err(|_| timed_out())?
+}
+
 pub(super) async fn handle_client_loop<S>(
     stream: &mut S,
     conn: Arc<Mutex<Connection>>,
     state: Arc<AppState>,
     client_addr: SocketAddr,
+    preauthenticated_user_id: Option<i64>,
 ) -> tokio::io::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    let mut buffer = [0; 1024];
-    let mut authenticated_user_id: Option<i64> = None;
-    let mut outbound_tx: Option<
-        mpsc::UnboundedSender<crate::models::client_message::ClientMessage>,
-    > = None;
-    let mut outbound_rx: Option<
-        mpsc::UnboundedReceiver<crate::models::client_message::ClientMessage>,
-    > = None;
+    let mut buffer = [0; 8192];
+    let mut lines = LineBuffer::new(state.config().limits.max_line_bytes);
+    let mut authenticated_user_id: Option<i64> = preauthenticated_user_id;
+    let mut outbound_tx: Option<SessionSender> = None;
+    let mut outbound_rx: Option<SessionReceiver> = None;
+    // A client that cannot take a single line within this long is too slow
+    let write_timeout = Duration::from_secs(state.config().limits.slow_consumer_secs);
+    let mut heartbeat =
+        Heartbeat::new(&state.config().timeouts, preauthenticated_user_id.is_some());
+    let shutdown = state.shutdown_token();
+
+    // Already authenticated during the handshake (client certificate)
+    if let Some(user_id) = preauthenticated_user_id {
+        let (tx, rx) = state.outbound_channel();
+        state
+            .register(user_id, ClientHandle { tx: tx.clone() })
+            .await;
+        outbound_tx = Some(tx);
+        outbound_rx = Some(rx);
+    }
 
     loop {
-        if let Some(rx) = outbound_rx.as_mut() {
-            select! {
-                read_res = stream.read(&mut buffer) => {
-                    match read_res {
-                        Ok(0) => {
-                            io_helpers::handle_connection_closed(client_addr).await;
-                            break;
-                        }
-                        Ok(n) => {
+        select! {
+            // Commands already being handled run to completion first: a
+            // select arm's body is never interrupted by another arm
+            _ = shutdown.cancelled() => {
+                if let Err(e) =
+                    drain_for_shutdown(stream, &mut outbound_rx, &state, write_timeout).await
+                {
+                    io_helpers::handle_read_error(client_addr, e).await;
+                }
+                io_helpers::handle_timeout(client_addr, "server shutting down").await;
+                break;
+            },
+            read_res = stream.read(&mut buffer) => {
+                match read_res {
+                    Ok(0) => {
+                        io_helpers::handle_connection_closed(client_addr).await;
+                        break;
+                    }
+                    Ok(n) => {
+                        heartbeat.seen();
+                        lines.extend(&buffer[..n]);
+                        let mut close_reason = None;
+                        loop {
+                            let line = match lines.next_line() {
+                                Ok(Some(line)) => line,
+                                Ok(None) => break,
+                                Err(_) => {
+                                    close_reason = Some("line too long");
+                                    break;
+                                }
+                            };
                             let was_unauth = authenticated_user_id.is_none();
-                            dispatch::handle_read_success(
+                            let outcome = dispatch::handle_read_success(
                                 stream,
                                 Arc::clone(&conn),
                                 Arc::clone(&state),
                                 client_addr,
                                 &mut authenticated_user_id,
                                 outbound_tx.as_ref(),
-                                &buffer[..n],
-                            ).await?;
+                                &line,
+                            )
+                            .await?;
+                            if outcome == ReadOutcome::Disconnect {
+                                close_reason = Some("repeatedly exceeded rate limits");
+                                break;
+                            }
 
+                            // If we just became authenticated, set up outbound channel and register
                             if let Some(user_id) = authenticated_user_id.filter(|_| was_unauth) {
-                                let (tx, new_rx) = mpsc::unbounded_channel();
+                                heartbeat.authenticated();
+                                let (tx, rx) = state.outbound_channel();
                                 state.register(user_id, ClientHandle { tx: tx.clone() }).await;
                                 outbound_tx = Some(tx);
-                                outbound_rx = Some(new_rx);
+                                outbound_rx = Some(rx);
                             }
                         }
-                        Err(e) => {
-                            io_helpers::handle_read_error(client_addr, e).await;
+                        if let Some(reason) = close_reason {
+                            io_helpers::handle_timeout(client_addr, reason).await;
                             break;
                         }
                     }
-                },
-                maybe_msg = rx.recv() => {
-                    match maybe_msg {
-                        Some(msg) => {
-                            if let Ok(mut json) = serde_json::to_string(&msg) {
-                                json.push('\n');
-                                if let Err(e) = stream.write_all(json.as_bytes()).await {
-                                    io_helpers::handle_read_error(client_addr, e).await;
-                                    break;
-                                }
-                                let _ = stream.flush().await;
-                            }
-                        }
-                        None => {
-                            // Sender dropped; keep loop running and wait for read events
-                        }
+                    Err(e) => {
+                        io_helpers::handle_read_error(client_addr, e).await;
+                        break;
                     }
                 }
-            }
-        } else {
-            match stream.read(&mut buffer).await {
-                Ok(0) => {
-                    io_helpers::handle_connection_closed(client_addr).await;
+            },
+            maybe_msg = recv_outbound(&mut outbound_rx) => {
+                // The queue only closes when the client fell too far behind
+                let Some(msg) = maybe_msg else {
+                    io_helpers::handle_timeout(client_addr, "outbound queue overflowed").await;
                     break;
-                }
-                Ok(n) => {
-                    let was_unauth = authenticated_user_id.is_none();
-                    dispatch::handle_read_success(
-                        stream,
-                        Arc::clone(&conn),
-                        Arc::clone(&state),
-                        client_addr,
-                        &mut authenticated_user_id,
-                        outbound_tx.as_ref(),
-                        &buffer[..n],
-                    )
-                    .await?;
-
-                    // If we just became authenticated, set up outbound channel and register
-                    if let Some(user_id) = authenticated_user_id.filter(|_| was_unauth) {
-                        let (tx, rx) = mpsc::unbounded_channel();
-                        state
-                            .register(user_id, ClientHandle { tx: tx.clone() })
-                            .await;
-                        outbound_tx = Some(tx);
-                        outbound_rx = Some(rx);
+                };
+                match tokio::time::timeout(write_timeout, write_message(stream, &msg)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        io_helpers::handle_read_error(client_addr, e).await;
+                        break;
+                    }
+                    Err(_) => {
+                        if let Some(rx) = outbound_rx.as_ref() {
+                            rx.close_as_slow();
+                        }
+                        io_helpers::handle_timeout(client_addr, "client stopped reading").await;
+                        break;
                     }
                 }
-                Err(e) => {
-                    io_helpers::handle_read_error(client_addr, e).await;
-                    break;
+            },
+            _ = heartbeat::sleep_until(heartbeat.deadline()) => {
+                match heartbeat.due(tokio::time::Instant::now()) {
+                    Some(Tick::Ping(seq)) => {
+                        let ping = ClientMessage {
+                            command: "ping".to_string(),
+                            data: seq.to_string(),
+                        };
+                        // A peer that stopped reading would otherwise block
+                        // the loop here and never reach the idle timeout
+                        match tokio::time::timeout(write_timeout, write_message(stream, &ping)).await
+                        {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => {
+                                io_helpers::handle_read_error(client_addr, e).await;
+                                break;
+                            }
+                            Err(_) => {
+                                if let Some(rx) = outbound_rx.as_ref() {
+                                    rx.close_as_slow();
+                                }
+                                io_helpers::handle_timeout(client_addr, "client stopped reading")
+                                    .await;
+                                break;
+                            }
+                        }
+                    }
+                    Some(Tick::AuthTimeout) => {
+                        let err = ClientMessage {
+                            command: "error".to_string(),
+                            data: "Authentication timed out".to_string(),
+                        };
+                        let _ = tokio::time::timeout(write_timeout, write_message(stream, &err)).await;
+                        io_helpers::handle_timeout(client_addr, "authentication timed out").await;
+                        break;
+                    }
+                    Some(Tick::IdleTimeout) => {
+                        io_helpers::handle_timeout(client_addr, "idle timeout").await;
+                        break;
+                    }
+                    None => {}
                 }
             }
         }
     }
-    // Cleanup: unregister user and drop outbound sender if any
-    if let Some(user_id) = authenticated_user_id {
-        state.unregister(user_id).await;
+    // Cleanup: unregister this connection (unless a newer one replaced it)
+    // and close the stream so a half-open peer does not linger
+    if let (Some(user_id), Some(tx)) = (authenticated_user_id, outbound_tx.as_ref()) {
+        state.unregister_handle(user_id, tx).await;
     }
+    let _ = tokio::time::timeout(write_timeout, stream.shutdown()).await;
     Ok(())
 }
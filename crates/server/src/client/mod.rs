@@ -3,31 +3,65 @@ use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
+use crate::auth::handle_auth_client_cert;
 use crate::messaging::state::AppState;
 use crate::models::client_message::ClientMessage;
 use crate::utils::db_utils::log_client_connection;
+use crate::utils::tls::ClientCertIdentity;
 
 mod authed;
 mod dispatch;
+mod framing;
+mod heartbeat;
 mod io_helpers;
 mod loop_task;
+pub(crate) mod rate_limit;
 mod unauth;
 
 pub async fn handle_client<S>(
+    stream: S,
+    conn: Arc<Mutex<Connection>>,
+    state: Arc<AppState>,
+    client_addr: SocketAddr,
+) -> tokio::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    handle_client_with_cert(stream, conn, state, client_addr, None).await
+}
+
+/// Like [`handle_client`], for connections whose TLS handshake verified a
+/// client certificate. A certificate linked to an account logs in directly;
+/// otherwise the client gets the usual `auth_required` prompt.
+pub async fn handle_client_with_cert<S>(
     mut stream: S,
     conn: Arc<Mutex<Connection>>,
     state: Arc<AppState>,
     client_addr: SocketAddr,
+    client_cert: Option<ClientCertIdentity>,
 ) -> tokio::io::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     // Log client connection to SQLite
-    log_client_connection(Arc::clone(&conn), client_addr)
-        .await
-        .unwrap_or_else(|e| {
-            eprintln!("Failed to log connection: {}", e);
-        });
+    if state.config().features.connection_log {
+        log_client_connection(Arc::clone(&conn), client_addr)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to log connection: {}", e);
+            });
+    }
+
+    let cert_user_id = match &client_cert {
+        Some(identity) => {
+            handle_auth_client_cert(&mut stream, Arc::clone(&conn), client_addr, identity).await?
+        }
+        None => None,
+    };
+    if let Some(user_id) = cert_user_id {
+        loop_task::handle_client_loop(&mut stream, conn, state, client_addr, Some(user_id)).await?;
+        return Ok(());
+    }
 
     // Send initial authentication request
     let auth_prompt = ClientMessage {
@@ -40,6 +74,6 @@ where
     stream.flush().await?;
 
     // Handle client authentication and subsequent messages
-    loop_task::handle_client_loop(&mut stream, Arc::clone(&conn), state, client_addr).await?;
+    loop_task::handle_client_loop(&mut stream, Arc::clone(&conn), state, client_addr, None).await?;
     Ok(())
 }
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::models::config::{RateLimit, RateLimitsSection};
+
+/// Window in which rejected commands count towards `disconnect_after`.
+const STRIKE_WINDOW: Duration = Duration::from_secs(60);
+/// Idle buckets and expired strikes are pruned every this many checks.
+const PRUNE_EVERY: u64 = 1024;
+
+/// Commands sharing one token bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum CommandClass {
+    Auth,
+    Message,
+    History,
+    Transfer,
+    Other,
+}
+
+impl CommandClass {
+    /// Class of a command; `None` for `pong`, which only answers the
+    /// server's own `ping` and is never limited. A client `ping` makes the
+    /// server write a reply, so it counts as `Other`.
+    pub(crate) fn of(command: &str) -> Option<Self> {
+        match command {
+            "pong" => None,
+            "login" | "register" => Some(Self::Auth),
+            "message" | "group_message" | "channel_post" | "edit_message" => Some(Self::Message),
+            "history" | "channel_history" | "message_revisions" | "thread" | "saved_list" => {
+                Some(Self::History)
+            }
+            "upload_chunk" | "download_chunk" => Some(Self::Transfer),
+            _ => Some(Self::Other),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::Message => "message",
+            Self::History => "history",
+            Self::Transfer => "transfer",
+            Self::Other => "other",
+        }
+    }
+
+    fn limit(self, config: &RateLimitsSection) -> RateLimit {
+        match self {
+            Self::Auth => config.auth,
+            Self::Message => config.message,
+            Self::History => config.history,
+            Self::Transfer => config.transfer,
+            Self::Other => config.other,
+        }
+    }
+}
+
+/// Who a bucket belongs to: the user once logged in, the client IP before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum RateKey {
+    User(i64),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Allowed,
+    /// Rejected; retry after the given delay. `disconnect` is set once the
+    /// key collected `disconnect_after` rejections within a minute.
+    Limited {
+        retry_after: Duration,
+        disconnect: bool,
+    },
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Strikes {
+    count: u32,
+    since: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    buckets: HashMap<(RateKey, CommandClass), Bucket>,
+    strikes: HashMap<RateKey, Strikes>,
+    checks: u64,
+}
+
+/// Token buckets shared by all connections, configured by `[rate_limits]`.
+#[derive(Default)]
+pub struct RateLimiter {
+    config: RateLimitsSection,
+    inner: Mutex<Inner>,
+}
+
+fn refill_rate(limit: RateLimit) -> f64 {
+    f64::from(limit.per_minute) / 60.0
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill_rate(limit)).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitsSection) -> Self {
+        Self {
+            config,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Take a token for one command of `class` from `key`'s bucket.
+    pub(crate) fn check(&self, key: RateKey, class: CommandClass, now: Instant) -> Verdict {
+        let limit = class.limit(&self.config);
+        if limit.burst == 0 {
+            return Verdict::Allowed;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.checks += 1;
+        if inner.checks.is_multiple_of(PRUNE_EVERY) {
+            self.prune(&mut inner, now);
+        }
+
+        let bucket = inner.buckets.entry((key, class)).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Verdict::Allowed;
+        }
+        let missing = 1.0 - bucket.tokens;
+        let retry_after = Duration::from_secs_f64(missing / refill_rate(limit));
+
+        let strikes = inner.strikes.entry(key).or_insert(Strikes {
+            count: 0,
+            since: now,
+        });
+        if now.saturating_duration_since(strikes.since) >= STRIKE_WINDOW {
+            strikes.count = 0;
+            strikes.since = now;
+        }
+        strikes.count += 1;
+        let threshold = self.config.disconnect_after;
+        Verdict::Limited {
+            retry_after,
+            disconnect: threshold > 0 && strikes.count >= threshold,
+        }
+    }
+
+    // Full buckets and old strikes carry no state worth keeping.
+    fn prune(&self, inner: &mut Inner, now: Instant) {
+        inner.buckets.retain(|(_, class), bucket| {
+            let limit = class.limit(&self.config);
+            bucket.refill(limit, now);
+            bucket.tokens < f64::from(limit.burst)
+        });
+        inner
+            .strikes
+            .retain(|_, s| now.saturating_duration_since(s.since) < STRIKE_WINDOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn limiter(disconnect_after: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitsSection {
+            disconnect_after,
+            message: RateLimit::new(2, 60),
+            ..RateLimitsSection::default()
+        })
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let limiter = limiter(0);
+        let key = RateKey::User(1);
+        let t0 = Instant::now();
+        assert_eq!(
+            limiter.check(key, CommandClass::Message, t0),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check(key, CommandClass::Message, t0),
+            Verdict::Allowed
+        );
+        let Verdict::Limited { retry_after, .. } = limiter.check(key, CommandClass::Message, t0)
+        else {
+            panic!("third command within the burst must be limited");
+        };
+        assert_eq!(retry_after, Duration::from_secs(1));
+
+        // One token per second at 60 per minute; other classes are unaffected
+        assert_eq!(
+            limiter.check(key, CommandClass::History, t0),
+            Verdict::Allowed
+        );
+        let later = t0 + Duration::from_secs(1);
+        assert_eq!(
+            limiter.check(key, CommandClass::Message, later),
+            Verdict::Allowed
+        );
+    }
+
+    #[test]
+    fn keys_have_separate_buckets() {
+        let limiter = limiter(0);
+        let t0 = Instant::now();
+        let ip = RateKey::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        for _ in 0..2 {
+            limiter.check(ip, CommandClass::Message, t0);
+        }
+        assert_ne!(
+            limiter.check(ip, CommandClass::Message, t0),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check(RateKey::User(1), CommandClass::Message, t0),
+            Verdict::Allowed
+        );
+    }
+
+    #[test]
+    fn repeat_offender_is_flagged_for_disconnect() {
+        let limiter = limiter(3);
+        let key = RateKey::User(7);
+        let t0 = Instant::now();
+        let mut disconnects = Vec::new();
+        for _ in 0..5 {
+            if let Verdict::Limited { disconnect, .. } =
+                limiter.check(key, CommandClass::Message, t0)
+            {
+                disconnects.push(disconnect);
+            }
+        }
+        assert_eq!(disconnects, [false, false, true]);
+
+        // Strikes expire with the window
+        let later = t0 + STRIKE_WINDOW;
+        limiter.check(key, CommandClass::Message, later);
+        limiter.check(key, CommandClass::Message, later);
+        assert_eq!(
+            limiter.check(key, CommandClass::Message, later),
+            Verdict::Limited {
+                retry_after: Duration::from_secs(1),
+                disconnect: false,
+            }
+        );
+    }
+
+    #[test]
+    fn pongs_and_disabled_classes_are_not_limited() {
+        assert_eq!(CommandClass::of("pong"), None);
+        assert_eq!(CommandClass::of("ping"), Some(CommandClass::Other));
+        assert_eq!(CommandClass::of("save"), Some(CommandClass::Other));
+        let limiter = RateLimiter::new(RateLimitsSection {
+            auth: RateLimit::new(0, 0),
+            ..RateLimitsSection::default()
+        });
+        let key = RateKey::User(1);
+        let t0 = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(limiter.check(key, CommandClass::Auth, t0), Verdict::Allowed);
+        }
+    }
+}
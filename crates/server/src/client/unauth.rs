@@ -3,12 +3,16 @@ use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use crate::auth::{handle_auth_command_error, handle_auth_login, handle_auth_register};
+use crate::auth::{
+    handle_auth_command_error, handle_auth_login, handle_auth_register, send_auth_error_response,
+};
+use crate::messaging::state::AppState;
 use crate::models::client_message::ClientMessage;
 
 pub(super) async fn handle_unauthenticated_message<W>(
     stream: &mut W,
     conn: Arc<Mutex<Connection>>,
+    state: &AppState,
     client_addr: SocketAddr,
     msg: ClientMessage,
     authenticated_user_id: &mut Option<i64>,
@@ -18,11 +22,25 @@ where
 {
     match msg.command.as_str() {
         "login" => {
-            *authenticated_user_id = handle_auth_login(stream, conn, client_addr, &msg).await?;
+            *authenticated_user_id =
+                handle_auth_login(stream, conn, client_addr, &msg, &state.config().login).await?;
+        }
+        "register" if !state.config().features.registration => {
+            send_auth_error_response(stream, "Registration is disabled").await?;
         }
         "register" => {
             *authenticated_user_id = handle_auth_register(stream, conn, client_addr, &msg).await?;
         }
+        "ping" => {
+            let pong = ClientMessage {
+                command: "pong".to_string(),
+                data: msg.data,
+            };
+            let response = serde_json::to_string(&pong)? + "\n";
+            stream.write_all(response.as_bytes()).await?;
+            stream.flush().await?;
+        }
+        "pong" => {}
         _ => {
             *authenticated_user_id = handle_auth_command_error(stream).await?;
         }
@@ -1,5 +1,7 @@
+pub mod admin;
 pub mod auth;
 pub mod client;
 pub mod messaging;
 pub mod models;
+pub mod server;
 pub mod utils;
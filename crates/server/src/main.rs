@@ -1,60 +1,447 @@
 use clap::Parser;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio::task::JoinSet;
 use tokio_rustls::TlsAcceptor;
 
-use rura_server::client::handle_client;
+use rura_server::admin::run_admin_command;
+use rura_server::messaging::attachments::collect_garbage;
+use rura_server::messaging::expiry::sweep_expired_messages;
+use rura_server::messaging::retention::{RetentionReport, apply_retention};
 use rura_server::messaging::state::AppState;
-use rura_server::models::args::Args;
-use rura_server::utils::db_utils::init_db;
+use rura_server::models::args::{Args, Command, GenCertArgs, GenClientCertArgs};
+use rura_server::models::config::{Config, LogLevel};
+use rura_server::server::accept_loop;
+use rura_server::utils::certgen::{
+    default_subject_alt_names, generate_dev_certs, issue_client_cert, write_certs,
+    write_client_cert,
+};
+use rura_server::utils::db_utils::{close_db, init_db_with_path};
 use rura_server::utils::get_local_ip::get_local_ip;
-use rura_server::utils::tls::make_tls_acceptor;
+use rura_server::utils::logging;
+use rura_server::utils::migrations::{
+    current_version, latest_version, pending_migrations, run_migrations,
+};
+use rura_server::utils::shutdown::CancellationToken;
+use rura_server::utils::tls::{
+    ReloadingCertResolver, make_client_verifier, make_server_config, spawn_cert_reloader,
+};
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
-    // Parse command-line arguments
+    // Parse command-line arguments and merge them with config file + environment
     let args = Args::parse();
-    let bind_addr = format!("0.0.0.0:{}", args.port);
+    let config = Config::load(&args).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(2);
+    });
+    if args.print_config {
+        print!("{}", config.to_toml_string());
+        return Ok(());
+    }
+    logging::set_level(config.logging.level);
+
+    match &args.command {
+        None | Some(Command::Serve) => {}
+        Some(Command::Migrate(migrate)) => {
+            return run_migrate_command(&config.database.path, migrate.dry_run);
+        }
+        Some(Command::GenCert(gen_cert)) => return run_gen_cert_command(gen_cert),
+        Some(Command::GenClientCert(gen_client_cert)) => {
+            return run_gen_client_cert_command(gen_client_cert);
+        }
+        Some(command) => {
+            let conn = Arc::new(Mutex::new(
+                init_db_with_path(&config.database.path).expect("Failed to init the db"),
+            ));
+            let stdin = std::io::stdin();
+            let result = run_admin_command(
+                command,
+                &config,
+                conn,
+                &mut stdin.lock(),
+                &mut std::io::stdout(),
+            )
+            .await;
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    }
+
+    if let Err(e) = config.require_tls() {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(2);
+    }
 
     // Get and display local IP
     let local_ip = get_local_ip().unwrap_or_else(|| "Unknown".to_string());
     println!("Server's local IP address: {}", local_ip);
 
     // Initialize SQLite database
-    let conn = Arc::new(Mutex::new(init_db().expect("Failed to init the db")));
+    let conn = Arc::new(Mutex::new(
+        init_db_with_path(&config.database.path).expect("Failed to init the db"),
+    ));
 
-    // Initialize shared in-memory state (online users)
-    let state = Arc::new(AppState::default());
+    // Build TLS acceptor (TLS-only server); the certificate is reloaded on
+    // SIGHUP or when the files change, without touching open connections
+    let mut cert_resolver = ReloadingCertResolver::new(&config.tls.cert, &config.tls.key)
+        .expect("Failed to initialize TLS (check tls.cert/tls.key)");
+    for entry in &config.tls.sni {
+        cert_resolver = cert_resolver
+            .with_sni(&entry.name, &entry.cert, &entry.key)
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "Configuration error: invalid `tls.sni` entry {}: {}",
+                    entry.name, e
+                );
+                std::process::exit(2);
+            });
+    }
+    let cert_resolver = Arc::new(cert_resolver);
+    let client_verifier = (!config.tls.client_ca.is_empty()).then(|| {
+        make_client_verifier(&config.tls.client_ca, config.tls.require_client_cert)
+            .expect("Failed to initialize mutual TLS (check tls.client_ca)")
+    });
+    let server_config =
+        make_server_config(&config.tls, Arc::clone(&cert_resolver), client_verifier)
+            .unwrap_or_else(|e| {
+                eprintln!("Configuration error: invalid TLS policy: {}", e);
+                std::process::exit(2);
+            });
+    let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let poll_interval = (config.tls.reload_interval_secs > 0)
+        .then(|| Duration::from_secs(config.tls.reload_interval_secs));
+    spawn_cert_reloader(cert_resolver, poll_interval)?;
 
-    // Build TLS acceptor (TLS-only server)
-    let tls_acceptor: TlsAcceptor = make_tls_acceptor(&args.tls_cert, &args.tls_key)
-        .expect("Failed to initialize TLS (check --tls-cert/--tls-key)");
+    // Initialize shared in-memory state (online users + effective config)
+    let config = Arc::new(config);
+    let state = Arc::new(AppState::new(Arc::clone(&config)));
+    spawn_queue_metrics_logger(Arc::clone(&state));
+    let collector = spawn_attachment_collector(Arc::clone(&state), Arc::clone(&conn));
+    let sweeper = spawn_message_sweeper(Arc::clone(&state), Arc::clone(&conn));
+    let retention = spawn_retention_job(Arc::clone(&state), Arc::clone(&conn));
+    spawn_signal_handler(state.shutdown_token())?;
 
-    // Start TCP listener
-    let listener = TcpListener::bind(&bind_addr).await?;
-    println!(
-        "Server listening on {}:{} (accessible from other devices)",
-        local_ip, args.port
-    );
+    // Start one TCP listener per configured bind address
+    let mut listeners = JoinSet::new();
+    for bind_addr in &config.server.bind {
+        let listener = TcpListener::bind(bind_addr).await?;
+        println!(
+            "Server listening on {} (local IP {})",
+            listener.local_addr()?,
+            local_ip
+        );
+        listeners.spawn(accept_loop(
+            listener,
+            tls_acceptor.clone(),
+            Arc::clone(&conn),
+            Arc::clone(&state),
+        ));
+    }
+
+    while let Some(res) = listeners.join_next().await {
+        res.map_err(std::io::Error::other)??;
+    }
+
+    // Every session is gone: close the database cleanly before exiting
+    if let Some(collector) = collector {
+        let _ = collector.await;
+    }
+    if let Some(sweeper) = sweeper {
+        let _ = sweeper.await;
+    }
+    if let Some(retention) = retention {
+        let _ = retention.await;
+    }
+    drop(state);
+    if let Err(e) = close_db(conn) {
+        eprintln!("Failed to close the database cleanly: {}", e);
+    }
+    println!("Server stopped");
+    Ok(())
+}
 
-    // Accept connections
-    loop {
-        let (stream, client_addr) = listener.accept().await?;
+/// Start a graceful shutdown on SIGTERM or SIGINT; a second signal exits at once.
+fn spawn_signal_handler(shutdown: CancellationToken) -> tokio::io::Result<()> {
+    #[cfg(unix)]
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::spawn(async move {
+        for first in [true, false] {
+            #[cfg(unix)]
+            let signal = tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = sigterm.recv() => "SIGTERM",
+            };
+            #[cfg(not(unix))]
+            let signal = {
+                let _ = tokio::signal::ctrl_c().await;
+                "Ctrl-C"
+            };
+            if !first {
+                eprintln!("Received {} again, exiting without draining", signal);
+                std::process::exit(130);
+            }
+            println!("Received {}, shutting down", signal);
+            shutdown.cancel();
+        }
+    });
+    Ok(())
+}
+
+/// Log outbound queue drops and slow-consumer disconnects once a minute when they changed.
+fn spawn_queue_metrics_logger(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(60));
+        let mut last = state.queue_metrics_snapshot();
+        loop {
+            ticker.tick().await;
+            let now = state.queue_metrics_snapshot();
+            if now != last && logging::enabled(LogLevel::Info) {
+                println!(
+                    "Outbound queues: {} ephemeral events dropped, {} slow clients disconnected (totals since start)",
+                    now.ephemeral_dropped, now.slow_consumer_disconnects
+                );
+            }
+            last = now;
+        }
+    });
+}
+
+/// Run `job` every `interval_secs` until shutdown; the task ends then so the
+/// database can be closed. Nothing is spawned when the interval is 0.
+fn spawn_periodic<F, Fut>(
+    interval_secs: u64,
+    shutdown: CancellationToken,
+    mut job: F,
+) -> Option<tokio::task::JoinHandle<()>>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    if interval_secs == 0 {
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            job().await;
+        }
+    }))
+}
+
+/// Remove unreferenced attachments and blobs every `attachments.gc_interval_secs`.
+fn spawn_attachment_collector(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<rusqlite::Connection>>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let interval = state.config().attachments.gc_interval_secs;
+    spawn_periodic(interval, state.shutdown_token(), move || {
+        let state = Arc::clone(&state);
         let conn = Arc::clone(&conn);
+        async move {
+            match collect_garbage(conn, &state.config().attachments).await {
+                Ok(report) if report.attachments > 0 || report.blobs > 0 => {
+                    if logging::enabled(LogLevel::Info) {
+                        println!(
+                            "Attachments: removed {} unreferenced attachment(s) and {} blob(s), {} byte(s) freed",
+                            report.attachments, report.blobs, report.bytes
+                        );
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if logging::enabled(LogLevel::Error) {
+                        eprintln!("Attachment garbage collection failed: {}", e);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Delete expired messages every `expiry.sweep_interval_secs`, whether or
+/// not anyone is online.
+fn spawn_message_sweeper(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<rusqlite::Connection>>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let interval = state.config().expiry.sweep_interval_secs;
+    spawn_periodic(interval, state.shutdown_token(), move || {
         let state = Arc::clone(&state);
-        let acceptor = tls_acceptor.clone();
+        let conn = Arc::clone(&conn);
+        async move {
+            match sweep_expired_messages(&state, conn).await {
+                Ok(deleted) if deleted > 0 => {
+                    if logging::enabled(LogLevel::Info) {
+                        println!("Expiry: deleted {} expired message(s)", deleted);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if logging::enabled(LogLevel::Error) {
+                        eprintln!("Expired message sweep failed: {}", e);
+                    }
+                }
+            }
+        }
+    })
+}
 
-        tokio::spawn(async move {
-            match acceptor.accept(stream).await {
-                Ok(tls_stream) => {
-                    if let Err(e) = handle_client(tls_stream, conn, state, client_addr).await {
-                        eprintln!("Error handling TLS client {}: {}", client_addr, e);
+/// Apply the `[retention]` policy every `retention.interval_secs`.
+fn spawn_retention_job(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<rusqlite::Connection>>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let interval = state.config().retention.interval_secs;
+    spawn_periodic(interval, state.shutdown_token(), move || {
+        let state = Arc::clone(&state);
+        let conn = Arc::clone(&conn);
+        async move {
+            match apply_retention(conn, state.config()).await {
+                Ok(report) if report != RetentionReport::default() => {
+                    if logging::enabled(LogLevel::Info) {
+                        println!(
+                            "Retention: deleted {} message(s), {} connection record(s), {} auth event(s) and {} login throttle record(s)",
+                            report.messages,
+                            report.connections,
+                            report.auth_events,
+                            report.login_throttle
+                        );
                     }
                 }
+                Ok(_) => {}
                 Err(e) => {
-                    eprintln!("TLS handshake failed with {}: {}", client_addr, e);
+                    if logging::enabled(LogLevel::Error) {
+                        eprintln!("Retention run failed: {}", e);
+                    }
                 }
             }
-        });
+        }
+    })
+}
+
+fn run_migrate_command(db_path: &str, dry_run: bool) -> tokio::io::Result<()> {
+    let to_io = |e: rusqlite::Error| std::io::Error::other(e.to_string());
+
+    if dry_run {
+        // Inspect without creating the database file when it does not exist yet
+        let conn = if std::path::Path::new(db_path).exists() {
+            rusqlite::Connection::open_with_flags(
+                db_path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+            )
+        } else {
+            rusqlite::Connection::open_in_memory()
+        }
+        .map_err(to_io)?;
+        let current = current_version(&conn).map_err(to_io)?;
+        let pending = pending_migrations(&conn).map_err(to_io)?;
+        println!(
+            "Database {}: schema version {} (latest {})",
+            db_path,
+            current,
+            latest_version()
+        );
+        if pending.is_empty() {
+            println!("No pending migrations");
+        }
+        for migration in pending {
+            println!(
+                "Would apply migration {}: {}",
+                migration.version, migration.description
+            );
+        }
+        return Ok(());
+    }
+
+    let mut conn = rusqlite::Connection::open(db_path).map_err(to_io)?;
+    let applied = run_migrations(&mut conn).map_err(to_io)?;
+    if applied.is_empty() {
+        println!(
+            "Database {} is already at version {}",
+            db_path,
+            latest_version()
+        );
     }
+    for version in applied {
+        println!("Applied migration {}", version);
+    }
+    Ok(())
+}
+
+fn run_gen_cert_command(args: &GenCertArgs) -> tokio::io::Result<()> {
+    let names = default_subject_alt_names(get_local_ip(), &args.names);
+    let result = generate_dev_certs(&names, args.days).and_then(|certs| {
+        write_certs(std::path::Path::new(&args.out_dir), &certs, args.force)
+            .map(|paths| (certs, paths))
+    });
+    let (certs, paths) = match result {
+        Ok(generated) => generated,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "Subject alternative names: {}",
+        certs.subject_alt_names.join(", ")
+    );
+    println!("Server certificate: {}", paths.server_cert.display());
+    println!("Server private key: {}", paths.server_key.display());
+    println!("CA certificate:     {}", paths.ca_cert.display());
+    println!("CA private key:     {}", paths.ca_key.display());
+    println!("CA SHA-256 fingerprint: {}", certs.ca_fingerprint);
+    println!(
+        "Give {} to clients as `ca_pem`; start the server with --tls-cert {} --tls-key {}",
+        paths.ca_cert.display(),
+        paths.server_cert.display(),
+        paths.server_key.display()
+    );
+    Ok(())
+}
+
+fn run_gen_client_cert_command(args: &GenClientCertArgs) -> tokio::io::Result<()> {
+    let ca_dir = std::path::Path::new(&args.out_dir);
+    let result = std::fs::read_to_string(ca_dir.join("ca.crt"))
+        .and_then(|ca_cert| Ok((ca_cert, std::fs::read_to_string(ca_dir.join("ca.key"))?)))
+        .and_then(|(ca_cert, ca_key)| issue_client_cert(&ca_cert, &ca_key, &args.name, args.days))
+        .and_then(|cert| {
+            write_client_cert(&ca_dir.join("clients"), &args.name, &cert, args.force)
+                .map(|paths| (cert, paths))
+        });
+    let (cert, (cert_path, key_path)) = match result {
+        Ok(generated) => generated,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!(
+                "Error: no CA in {} ({}); run `gen-cert` first",
+                args.out_dir, e
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Client certificate: {}", cert_path.display());
+    println!("Client private key: {}", key_path.display());
+    println!("SHA-256 fingerprint: {}", cert.fingerprint);
+    println!(
+        "Link it to an account with `user bind-cert <passphrase> --fingerprint {}` \
+         (or --subject {}), and set tls.client_ca to {}",
+        cert.fingerprint,
+        args.name,
+        ca_dir.join("ca.crt").display()
+    );
+    Ok(())
 }
@@ -0,0 +1,416 @@
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+
+use super::attachments::attachment_info;
+use super::expiry::announce_purged;
+use super::handlers::{forbidden, reply_to};
+use super::models::{
+    DeleteMessageRequest, DeleteMessageResponse, EditMessageRequest, EditMessageResponse,
+    HistoryMessage, MessageDeletedEvent, MessageEditedEvent, MessageRevision,
+    MessageRevisionsRequest, MessageRevisionsResponse, ReactionCount, ReactionEvent,
+    ReactionRequest, ReactionResponse, ThreadRequest, ThreadResponse, ViewedRequest,
+    ViewedResponse,
+};
+use super::queue::SessionSender;
+use super::state::AppState;
+use crate::models::client_message::ClientMessage;
+use crate::utils::db_utils::{
+    MessageRefusal, RawMessageRow, delete_message_for_everyone, edit_message, fetch_thread,
+    hide_message, message_participants, message_revisions, purge_viewed_message, set_reaction,
+};
+
+/// Longest accepted reaction, in bytes; enough for multi-codepoint emoji
+/// such as flags and ZWJ sequences.
+pub const MAX_REACTION_BYTES: usize = 32;
+
+/// Commands handled by [`handle_message_action`]: changes to messages that
+/// were already sent, confirming view-once messages, and reading revisions
+/// and reply threads. Direct and group messages alike.
+pub fn is_message_action(command: &str) -> bool {
+    matches!(
+        command,
+        "edit_message"
+            | "message_revisions"
+            | "delete_message"
+            | "react"
+            | "unreact"
+            | "thread"
+            | "viewed"
+    )
+}
+
+pub async fn handle_message_action(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    msg: ClientMessage,
+) {
+    match msg.command.as_str() {
+        "edit_message" => edit(&state, conn, outbound, user_id, &msg.data).await,
+        "message_revisions" => revisions(conn, outbound, user_id, &msg.data).await,
+        "delete_message" => delete(&state, conn, outbound, user_id, &msg.data).await,
+        "thread" => thread(&state, conn, outbound, user_id, &msg.data).await,
+        "viewed" => viewed(&state, conn, outbound, user_id, &msg.data).await,
+        "react" | "unreact" => {
+            let on = msg.command == "react";
+            react(&state, conn, outbound, user_id, &msg.command, &msg.data, on).await
+        }
+        other => unreachable!("not a message action: {other}"),
+    }
+}
+
+fn error(outbound: &SessionSender, text: &str) {
+    let _ = outbound.send(ClientMessage {
+        command: "error".to_string(),
+        data: text.to_string(),
+    });
+}
+
+/// Push `msg` to everyone who can see `message_id`, except `actor`.
+async fn notify_participants(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    message_id: i64,
+    actor: i64,
+    msg: ClientMessage,
+) {
+    let Ok(mut audience) = message_participants(conn, message_id).await else {
+        // The change is stored; peers see it in their next history
+        return;
+    };
+    audience.retain(|&id| id != actor);
+    let msg = Arc::new(msg);
+    for tx in state.senders_for_sorted(&audience).await {
+        // Ignore send errors (receiver might have just disconnected)
+        let _ = tx.send_shared(Arc::clone(&msg));
+    }
+}
+
+async fn edit(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let Ok(req) = serde_json::from_str::<EditMessageRequest>(data) else {
+        return error(outbound, "Invalid edit_message format");
+    };
+    let limits = &state.config().limits;
+    if req.body.len() > limits.max_body_bytes {
+        return error(outbound, "Message too long");
+    }
+    let respond = |success: bool, message: &str, edited_at: Option<String>| {
+        let resp = EditMessageResponse {
+            success,
+            message: message.to_string(),
+            message_id: req.message_id,
+            edited_at,
+        };
+        let _ = outbound.send(ClientMessage {
+            command: "edit_response".to_string(),
+            data: serde_json::to_string(&resp).unwrap(),
+        });
+    };
+    let edited = match edit_message(
+        Arc::clone(&conn),
+        user_id,
+        req.message_id,
+        &req.body,
+        limits.edit_window_secs,
+    )
+    .await
+    {
+        Ok(Ok(row)) => row,
+        Ok(Err(MessageRefusal::NotFound)) => return respond(false, "Message not found", None),
+        Ok(Err(MessageRefusal::NotSender)) => {
+            let _ = outbound.send(forbidden("Only the sender can edit a message".to_string()));
+            return;
+        }
+        Ok(Err(MessageRefusal::Expired)) => {
+            let _ = outbound.send(forbidden(format!(
+                "Messages can only be edited within {} seconds of sending",
+                limits.edit_window_secs
+            )));
+            return;
+        }
+        Err(_) => return respond(false, "Failed to edit message", None),
+    };
+    let edited_at = edited.edited_at.clone().unwrap_or_default();
+    respond(true, "Message edited", Some(edited_at.clone()));
+    let event = MessageEditedEvent {
+        message_id: edited.id,
+        from_user_id: edited.sender,
+        to_user_id: edited.receiver,
+        conversation_id: edited.conversation_id,
+        body: edited.content,
+        edited_at,
+    };
+    let msg = ClientMessage {
+        command: "message_edited".to_string(),
+        data: serde_json::to_string(&event).unwrap(),
+    };
+    notify_participants(state, conn, edited.id, user_id, msg).await;
+}
+
+async fn delete(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let Ok(req) = serde_json::from_str::<DeleteMessageRequest>(data) else {
+        return error(outbound, "Invalid delete_message format");
+    };
+    let respond = |success: bool, message: &str| {
+        let resp = DeleteMessageResponse {
+            success,
+            message: message.to_string(),
+            message_id: req.message_id,
+            scope: req.scope.clone(),
+        };
+        let _ = outbound.send(ClientMessage {
+            command: "delete_response".to_string(),
+            data: serde_json::to_string(&resp).unwrap(),
+        });
+    };
+    match req.scope.as_str() {
+        "me" => {
+            return match hide_message(conn, user_id, req.message_id).await {
+                Ok(true) => respond(true, "Message deleted"),
+                Ok(false) => respond(false, "Message not found"),
+                Err(_) => respond(false, "Failed to delete message"),
+            };
+        }
+        "everyone" => {}
+        _ => return respond(false, "Scope must be me or everyone"),
+    }
+    let window_secs = state.config().limits.delete_window_secs;
+    let deleted =
+        match delete_message_for_everyone(Arc::clone(&conn), user_id, req.message_id, window_secs)
+            .await
+        {
+            Ok(Ok(row)) => row,
+            Ok(Err(MessageRefusal::NotFound)) => return respond(false, "Message not found"),
+            Ok(Err(MessageRefusal::NotSender)) => {
+                let _ = outbound.send(forbidden(
+                    "Only the sender can delete a message for everyone".to_string(),
+                ));
+                return;
+            }
+            Ok(Err(MessageRefusal::Expired)) => {
+                let _ = outbound.send(forbidden(format!(
+                "Messages can only be deleted for everyone within {window_secs} seconds of sending"
+            )));
+                return;
+            }
+            Err(_) => return respond(false, "Failed to delete message"),
+        };
+    respond(true, "Message deleted");
+    let event = MessageDeletedEvent {
+        message_id: deleted.id,
+        from_user_id: deleted.sender,
+        to_user_id: deleted.receiver,
+        conversation_id: deleted.conversation_id,
+        deleted_at: deleted.deleted_at.unwrap_or_default(),
+    };
+    let msg = ClientMessage {
+        command: "message_deleted".to_string(),
+        data: serde_json::to_string(&event).unwrap(),
+    };
+    notify_participants(state, conn, deleted.id, user_id, msg).await;
+}
+
+/// The recipient read a view-once message: delete it for good and tell the
+/// sender.
+async fn viewed(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let Ok(req) = serde_json::from_str::<ViewedRequest>(data) else {
+        return error(outbound, "Invalid viewed format");
+    };
+    let respond = |success: bool, message: &str| {
+        let resp = ViewedResponse {
+            success,
+            message: message.to_string(),
+            message_id: req.message_id,
+        };
+        let _ = outbound.send(ClientMessage {
+            command: "viewed_response".to_string(),
+            data: serde_json::to_string(&resp).unwrap(),
+        });
+    };
+    let purged = match purge_viewed_message(Arc::clone(&conn), user_id, req.message_id).await {
+        Ok(Some(purged)) => purged,
+        Ok(None) => return respond(false, "View-once message not found"),
+        Err(_) => return respond(false, "Failed to remove message"),
+    };
+    respond(true, "Message removed");
+    announce_purged(state, conn, &[purged], Some(user_id), "viewed").await;
+}
+
+async fn react(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    command: &str,
+    data: &str,
+    on: bool,
+) {
+    let Ok(req) = serde_json::from_str::<ReactionRequest>(data) else {
+        return error(outbound, &format!("Invalid {command} format"));
+    };
+    let respond = |success: bool, message: &str| {
+        let resp = ReactionResponse {
+            success,
+            message: message.to_string(),
+            message_id: req.message_id,
+            emoji: req.emoji.clone(),
+        };
+        let _ = outbound.send(ClientMessage {
+            command: "reaction_response".to_string(),
+            data: serde_json::to_string(&resp).unwrap(),
+        });
+    };
+    let emoji = req.emoji.as_str();
+    if emoji.is_empty() || emoji.len() > MAX_REACTION_BYTES || emoji.contains(char::is_whitespace) {
+        return respond(
+            false,
+            &format!("Reaction must be 1 to {MAX_REACTION_BYTES} bytes without spaces"),
+        );
+    }
+    let message = match set_reaction(Arc::clone(&conn), user_id, req.message_id, emoji, on).await {
+        Ok(Some((message, true))) => message,
+        Ok(Some((_, false))) if on => return respond(true, "Already reacted"),
+        Ok(Some((_, false))) => return respond(true, "No such reaction"),
+        Ok(None) => return respond(false, "Message not found or not authorized"),
+        Err(_) => return respond(false, "Failed to update reaction"),
+    };
+    respond(
+        true,
+        if on {
+            "Reaction added"
+        } else {
+            "Reaction removed"
+        },
+    );
+    let event = ReactionEvent {
+        message_id: message.id,
+        user_id,
+        emoji: req.emoji.clone(),
+        added: on,
+        to_user_id: message.receiver,
+        conversation_id: message.conversation_id,
+    };
+    let msg = ClientMessage {
+        command: "reaction".to_string(),
+        data: serde_json::to_string(&event).unwrap(),
+    };
+    notify_participants(state, conn, message.id, user_id, msg).await;
+}
+
+async fn revisions(
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let Ok(req) = serde_json::from_str::<MessageRevisionsRequest>(data) else {
+        return error(outbound, "Invalid message_revisions format");
+    };
+    let (success, message, revisions) = match message_revisions(conn, user_id, req.message_id).await
+    {
+        Ok(Some(rows)) => (
+            true,
+            "OK",
+            rows.into_iter()
+                .map(|r| MessageRevision {
+                    body: r.content,
+                    replaced_at: r.replaced_at,
+                })
+                .collect(),
+        ),
+        Ok(None) => (false, "Message not found", Vec::new()),
+        Err(_) => (false, "Failed to load revisions", Vec::new()),
+    };
+    let resp = MessageRevisionsResponse {
+        success,
+        message: message.to_string(),
+        message_id: req.message_id,
+        revisions,
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "message_revisions_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
+
+pub(super) fn history_message(row: RawMessageRow) -> HistoryMessage {
+    HistoryMessage {
+        id: row.id,
+        from_user_id: row.sender,
+        to_user_id: row.receiver,
+        conversation_id: row.conversation_id,
+        body: row.content,
+        timestamp: row.timestamp,
+        saved: row.saved,
+        system: row.system,
+        edited_at: row.edited_at,
+        deleted_at: row.deleted_at,
+        reactions: row
+            .reactions
+            .into_iter()
+            .map(|r| ReactionCount {
+                emoji: r.emoji,
+                count: r.count,
+            })
+            .collect(),
+        my_reactions: row.my_reactions,
+        reply_to: row.reply_to.map(reply_to),
+        attachment: row.attachment.map(attachment_info),
+        expires_at: row.expires_at,
+        view_once: row.view_once,
+    }
+}
+
+/// One page of the replies under `root_id`, paged with `after_id` like
+/// `channel_history`.
+async fn thread(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let Ok(req) = serde_json::from_str::<ThreadRequest>(data) else {
+        return error(outbound, "Invalid thread format");
+    };
+    let config = state.config();
+    let limit = req
+        .limit
+        .unwrap_or(config.history.default_limit)
+        .min(config.limits.max_history_limit);
+    let (success, message, messages) =
+        match fetch_thread(conn, user_id, req.root_id, req.after_id, limit).await {
+            Ok(Some(rows)) => (true, "OK", rows.into_iter().map(history_message).collect()),
+            Ok(None) => (false, "Message not found", Vec::new()),
+            Err(_) => (false, "Failed to load thread", Vec::new()),
+        };
+    let resp = ThreadResponse {
+        success,
+        message: message.to_string(),
+        root_id: req.root_id,
+        messages,
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "thread_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
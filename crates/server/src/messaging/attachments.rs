@@ -0,0 +1,595 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rusqlite::{Connection, Result as SqliteResult};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+use super::models::{
+    AttachmentInfo, DownloadChunkRequest, DownloadChunkResponse, ThumbnailInfo, UploadBeginRequest,
+    UploadChunkRequest, UploadFinishRequest, UploadResponse,
+};
+use super::queue::SessionSender;
+use super::state::AppState;
+use crate::models::client_message::ClientMessage;
+use crate::models::config::{AttachmentsSection, LogLevel};
+use crate::utils::attachment_store::{AttachmentStore, is_sha256_hex, sha256_hex};
+use crate::utils::db_utils::{
+    AttachmentRow, ThumbnailRow, collect_unreferenced_blobs, complete_attachment,
+    create_attachment, delete_pending_attachment, expire_unreferenced_attachments,
+    get_own_attachment, readable_attachment, release_attachments, set_attachment_media,
+    set_attachment_received,
+};
+use crate::utils::images::{self, ImageKind, ImageLimits, Thumbnail};
+use crate::utils::logging;
+
+/// Longest accepted attachment file name, in characters.
+pub const MAX_FILE_NAME_CHARS: usize = 255;
+/// Longest accepted MIME type, in characters.
+pub const MAX_MIME_TYPE_CHARS: usize = 127;
+/// Rows the garbage collector deletes per database lock.
+const GC_BATCH: usize = 500;
+/// Images decoded at once across all sessions; decoding is CPU-bound.
+static IMAGE_JOBS: Semaphore = Semaphore::const_new(2);
+
+/// Commands handled by [`handle_attachment_command`].
+pub fn is_attachment_command(command: &str) -> bool {
+    matches!(
+        command,
+        "upload_begin" | "upload_chunk" | "upload_finish" | "download_chunk"
+    )
+}
+
+/// Attachment as shown in message events and history.
+pub fn attachment_info(row: AttachmentRow) -> AttachmentInfo {
+    AttachmentInfo {
+        id: row.id,
+        file_name: row.file_name,
+        mime_type: row.mime_type,
+        size: row.size as u64,
+        sha256: row.sha256,
+        width: row.width.map(|width| width as u32),
+        height: row.height.map(|height| height as u32),
+        thumbnail: row.thumbnail.map(|thumbnail| ThumbnailInfo {
+            id: thumbnail.id,
+            mime_type: thumbnail.mime_type,
+            width: thumbnail.width as u32,
+            height: thumbnail.height as u32,
+        }),
+    }
+}
+
+pub async fn handle_attachment_command(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    msg: ClientMessage,
+) {
+    let data = msg.data.as_str();
+    let store = AttachmentStore::new(&state.config().attachments.dir);
+    let resp = match msg.command.as_str() {
+        "upload_begin" => match serde_json::from_str(data) {
+            Ok(req) => begin(&state, conn, user_id, req).await,
+            Err(_) => return error(outbound, "Invalid upload_begin format"),
+        },
+        "upload_chunk" => match serde_json::from_str(data) {
+            Ok(req) => chunk(&state, &store, conn, user_id, req).await,
+            Err(_) => return error(outbound, "Invalid upload_chunk format"),
+        },
+        "upload_finish" => match serde_json::from_str(data) {
+            Ok(req) => finish(&state, &store, conn, user_id, req).await,
+            Err(_) => return error(outbound, "Invalid upload_finish format"),
+        },
+        "download_chunk" => {
+            let Ok(req) = serde_json::from_str(data) else {
+                return error(outbound, "Invalid download_chunk format");
+            };
+            let resp = download(&state, &store, conn, user_id, req).await;
+            let _ = outbound.send(ClientMessage {
+                command: "download_response".to_string(),
+                data: serde_json::to_string(&resp).unwrap(),
+            });
+            return;
+        }
+        other => unreachable!("not an attachment command: {other}"),
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "upload_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
+
+fn error(outbound: &SessionSender, text: &str) {
+    let _ = outbound.send(ClientMessage {
+        command: "error".to_string(),
+        data: text.to_string(),
+    });
+}
+
+fn upload_response(
+    state: &AppState,
+    success: bool,
+    message: impl Into<String>,
+    upload: Option<&AttachmentRow>,
+) -> UploadResponse {
+    UploadResponse {
+        success,
+        message: message.into(),
+        attachment_id: upload.map(|row| row.id),
+        received: upload.map_or(0, |row| row.received as u64),
+        chunk_bytes: state.config().attachments.chunk_bytes,
+        attachment: None,
+    }
+}
+
+async fn begin(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: UploadBeginRequest,
+) -> UploadResponse {
+    let fail = |message: String| upload_response(state, false, message, None);
+    let file_name = req.file_name.trim();
+    if file_name.is_empty() || file_name.chars().count() > MAX_FILE_NAME_CHARS {
+        return fail(format!(
+            "File name must be 1 to {MAX_FILE_NAME_CHARS} characters"
+        ));
+    }
+    let mime_type = req.mime_type.trim();
+    if mime_type.is_empty() || mime_type.chars().count() > MAX_MIME_TYPE_CHARS {
+        return fail(format!(
+            "MIME type must be 1 to {MAX_MIME_TYPE_CHARS} characters"
+        ));
+    }
+    let config = &state.config().attachments;
+    let max_bytes = config.max_bytes;
+    if req.size > max_bytes {
+        return fail(format!("Attachment too large (at most {max_bytes} bytes)"));
+    }
+    let sha256 = req.sha256.to_ascii_lowercase();
+    if !is_sha256_hex(&sha256) {
+        return fail("sha256 must be 64 hex digits".to_string());
+    }
+    match create_attachment(
+        conn,
+        user_id,
+        file_name,
+        mime_type,
+        req.size as i64,
+        &sha256,
+        config.quota_bytes,
+    )
+    .await
+    {
+        Ok(Ok(row)) => upload_response(state, true, "Upload started", Some(&row)),
+        Ok(Err(exceeded)) => fail(format!(
+            "Storage quota exceeded: {} of {} bytes used",
+            exceeded.used, config.quota_bytes
+        )),
+        Err(_) => fail("Failed to start upload".to_string()),
+    }
+}
+
+/// Append one chunk. Chunks must arrive in order; a client that lost track
+/// (e.g. after reconnecting) learns where to resume from `received`.
+async fn chunk(
+    state: &AppState,
+    store: &AttachmentStore,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: UploadChunkRequest,
+) -> UploadResponse {
+    let mut upload = match get_own_attachment(Arc::clone(&conn), user_id, req.attachment_id).await {
+        Ok(Some(row)) if !row.completed => row,
+        Ok(Some(row)) => {
+            return upload_response(state, false, "Upload already finished", Some(&row));
+        }
+        Ok(None) => return upload_response(state, false, "Upload not found", None),
+        Err(_) => return upload_response(state, false, "Failed to store chunk", None),
+    };
+    let Ok(bytes) = BASE64.decode(&req.data) else {
+        return upload_response(state, false, "Chunk data must be base64", Some(&upload));
+    };
+    if req.offset != upload.received as u64 {
+        let message = format!("Expected offset {}", upload.received);
+        return upload_response(state, false, message, Some(&upload));
+    }
+    if bytes.len() > state.config().attachments.chunk_bytes {
+        return upload_response(state, false, "Chunk too large", Some(&upload));
+    }
+    let received = req.offset + bytes.len() as u64;
+    if received > upload.size as u64 {
+        return upload_response(state, false, "Chunk exceeds declared size", Some(&upload));
+    }
+    if let Err(e) = store.write_chunk(upload.id, req.offset, &bytes).await {
+        if logging::enabled(LogLevel::Error) {
+            eprintln!("Failed to write attachment {}: {}", upload.id, e);
+        }
+        return upload_response(state, false, "Failed to store chunk", Some(&upload));
+    }
+    if set_attachment_received(conn, upload.id, received as i64)
+        .await
+        .is_err()
+    {
+        return upload_response(state, false, "Failed to store chunk", Some(&upload));
+    }
+    upload.received = received as i64;
+    upload_response(state, true, "Chunk stored", Some(&upload))
+}
+
+async fn finish(
+    state: &AppState,
+    store: &AttachmentStore,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: UploadFinishRequest,
+) -> UploadResponse {
+    let mut upload = match get_own_attachment(Arc::clone(&conn), user_id, req.attachment_id).await {
+        Ok(Some(row)) => row,
+        Ok(None) => return upload_response(state, false, "Upload not found", None),
+        Err(_) => return upload_response(state, false, "Failed to finish upload", None),
+    };
+    let done = |upload: AttachmentRow| UploadResponse {
+        attachment: Some(attachment_info(upload.clone())),
+        ..upload_response(state, true, "Upload complete", Some(&upload))
+    };
+    if upload.completed {
+        return done(upload);
+    }
+    if upload.received != upload.size {
+        let message = format!(
+            "Upload incomplete: {} of {} bytes",
+            upload.received, upload.size
+        );
+        return upload_response(state, false, message, Some(&upload));
+    }
+    match store.verify(upload.id, &upload.sha256).await {
+        Ok(true) => {}
+        Ok(false) => {
+            // The bytes are gone; the client has to start over
+            let _ = delete_pending_attachment(conn, upload.id).await;
+            return upload_response(state, false, "Checksum mismatch", None);
+        }
+        Err(e) => {
+            if logging::enabled(LogLevel::Error) {
+                eprintln!("Failed to finish attachment {}: {}", upload.id, e);
+            }
+            return upload_response(state, false, "Failed to finish upload", Some(&upload));
+        }
+    }
+    if !inspect_image(state, store, Arc::clone(&conn), &mut upload).await {
+        return upload_response(state, false, "Failed to finish upload", Some(&upload));
+    }
+    // Identical content uploaded before is stored once; the new file
+    // simply replaces it
+    let publish = || match store.publish(upload.id, &upload.sha256) {
+        Ok(()) => true,
+        Err(e) => {
+            if logging::enabled(LogLevel::Error) {
+                eprintln!("Failed to store attachment {}: {}", upload.id, e);
+            }
+            false
+        }
+    };
+    if !matches!(
+        complete_attachment(conn, upload.id, publish).await,
+        Ok(true)
+    ) {
+        return upload_response(state, false, "Failed to finish upload", Some(&upload));
+    }
+    done(AttachmentRow {
+        completed: true,
+        ..upload
+    })
+}
+
+/// Record the type and dimensions of a verified JPEG or PNG upload, blank
+/// the location in its EXIF data and store a thumbnail. Images that fail to
+/// decode still lose their location but keep their declared type; other
+/// files are kept as uploaded. Returns false if the upload can no longer be
+/// completed, including when it could not be checked for a location.
+async fn inspect_image(
+    state: &AppState,
+    store: &AttachmentStore,
+    conn: Arc<Mutex<Connection>>,
+    upload: &mut AttachmentRow,
+) -> bool {
+    let config = &state.config().attachments;
+    let limits = ImageLimits {
+        thumbnail_px: config.thumbnail_px,
+        max_pixels: config.image_max_pixels,
+    };
+    let head = store.read_part(upload.id, 8).await;
+    if !head.is_ok_and(|head| ImageKind::sniff(&head).is_some()) {
+        return true;
+    }
+    let (bytes, meta) = {
+        let _permit = IMAGE_JOBS.acquire().await.expect("never closed");
+        let mut bytes = match store.read_part(upload.id, upload.size as u64).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                if logging::enabled(LogLevel::Error) {
+                    eprintln!("Failed to read attachment {}: {}", upload.id, e);
+                }
+                return false;
+            }
+        };
+        match tokio::task::spawn_blocking(move || {
+            let meta = images::inspect(&mut bytes, &limits);
+            (bytes, meta)
+        })
+        .await
+        {
+            Ok(inspected) => inspected,
+            Err(e) => {
+                // Storing the file unchecked could leak where it was taken
+                if logging::enabled(LogLevel::Error) {
+                    eprintln!("Failed to inspect attachment {}: {}", upload.id, e);
+                }
+                return false;
+            }
+        }
+    };
+    let Some(meta) = meta else {
+        return true;
+    };
+    let thumbnail = match meta.thumbnail {
+        Some(thumbnail) => store_thumbnail(store, Arc::clone(&conn), upload, thumbnail).await,
+        None => None,
+    };
+    let mut sha256 = upload.sha256.clone();
+    if meta.location_removed {
+        // The scrubbed file is what gets stored and shared
+        if let Err(e) = store.rewrite_part(upload.id, &bytes).await {
+            if logging::enabled(LogLevel::Error) {
+                eprintln!("Failed to rewrite attachment {}: {}", upload.id, e);
+            }
+            return false;
+        }
+        sha256 = sha256_hex(&bytes);
+    }
+    let mime_type = match meta.size {
+        Some(_) => meta.kind.mime_type().to_string(),
+        None => upload.mime_type.clone(),
+    };
+    let size = meta
+        .size
+        .map(|(width, height)| (width as i64, height as i64));
+    let thumbnail_id = thumbnail.as_ref().map(|thumbnail| thumbnail.id);
+    if set_attachment_media(conn, upload.id, &mime_type, &sha256, size, thumbnail_id)
+        .await
+        .is_err()
+    {
+        return false;
+    }
+    upload.mime_type = mime_type;
+    upload.sha256 = sha256;
+    upload.width = size.map(|size| size.0);
+    upload.height = size.map(|size| size.1);
+    upload.thumbnail = thumbnail;
+    true
+}
+
+/// Store `thumbnail` as a completed attachment owned by the uploader of
+/// `image`. It is not charged against their quota when created, but counts
+/// toward it afterwards like any of their files.
+async fn store_thumbnail(
+    store: &AttachmentStore,
+    conn: Arc<Mutex<Connection>>,
+    image: &AttachmentRow,
+    thumbnail: Thumbnail,
+) -> Option<ThumbnailRow> {
+    let sha256 = sha256_hex(&thumbnail.bytes);
+    let mime_type = thumbnail.kind.mime_type();
+    let file_name = match thumbnail.kind {
+        ImageKind::Jpeg => "thumbnail.jpg",
+        ImageKind::Png => "thumbnail.png",
+    };
+    let size = thumbnail.bytes.len() as i64;
+    let Ok(Ok(row)) = create_attachment(
+        Arc::clone(&conn),
+        image.owner_id?,
+        file_name,
+        mime_type,
+        size,
+        &sha256,
+        0,
+    )
+    .await
+    else {
+        return None;
+    };
+    if let Err(e) = store.write_chunk(row.id, 0, &thumbnail.bytes).await {
+        if logging::enabled(LogLevel::Error) {
+            eprintln!(
+                "Failed to write thumbnail of attachment {}: {}",
+                image.id, e
+            );
+        }
+        return None;
+    }
+    let (width, height) = (thumbnail.width as i64, thumbnail.height as i64);
+    let stored = set_attachment_received(Arc::clone(&conn), row.id, size)
+        .await
+        .is_ok()
+        && set_attachment_media(
+            Arc::clone(&conn),
+            row.id,
+            mime_type,
+            &sha256,
+            Some((width, height)),
+            None,
+        )
+        .await
+        .is_ok();
+    // Left pending on failure, so the collector removes it
+    let publish = || store.publish(row.id, &sha256).is_ok();
+    if !stored || !matches!(complete_attachment(conn, row.id, publish).await, Ok(true)) {
+        return None;
+    }
+    Some(ThumbnailRow {
+        id: row.id,
+        mime_type: mime_type.to_string(),
+        width,
+        height,
+    })
+}
+
+/// One ranged chunk of an attachment the caller may read.
+async fn download(
+    state: &AppState,
+    store: &AttachmentStore,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: DownloadChunkRequest,
+) -> DownloadChunkResponse {
+    let respond = |success: bool, message: &str, size: u64, data: Vec<u8>| DownloadChunkResponse {
+        success,
+        message: message.to_string(),
+        attachment_id: req.attachment_id,
+        offset: req.offset,
+        size,
+        eof: success && req.offset + data.len() as u64 >= size,
+        data: BASE64.encode(&data),
+    };
+    let attachment = match readable_attachment(conn, user_id, req.attachment_id).await {
+        Ok(Some(row)) => row,
+        Ok(None) => return respond(false, "Attachment not found", 0, Vec::new()),
+        Err(_) => return respond(false, "Failed to read attachment", 0, Vec::new()),
+    };
+    let size = attachment.size as u64;
+    if req.offset > size {
+        return respond(false, "Offset beyond end of file", size, Vec::new());
+    }
+    let chunk_bytes = state.config().attachments.chunk_bytes;
+    let len = req.length.unwrap_or(chunk_bytes).min(chunk_bytes);
+    match store
+        .read_chunk(attachment.id, &attachment.sha256, req.offset, len)
+        .await
+    {
+        Ok(data) => respond(true, "OK", size, data),
+        Err(e) => {
+            if logging::enabled(LogLevel::Error) {
+                eprintln!("Failed to read attachment {}: {}", attachment.id, e);
+            }
+            respond(false, "Failed to read attachment", size, Vec::new())
+        }
+    }
+}
+
+/// What one [`collect_garbage`] run removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GarbageReport {
+    /// Attachments no message carried once their grace period was over.
+    pub attachments: usize,
+    /// Stored files no attachment used any more.
+    pub blobs: usize,
+    /// Bytes those files took up.
+    pub bytes: i64,
+}
+
+/// Remove attachments no message carries that are older than
+/// `orphan_grace_secs`, then the blobs nothing refers to any more. Works in
+/// batches so the database is never locked for long.
+pub async fn collect_garbage(
+    conn: Arc<Mutex<Connection>>,
+    config: &AttachmentsSection,
+) -> SqliteResult<GarbageReport> {
+    let store = AttachmentStore::new(&config.dir);
+    // A grace period too long to represent expires nothing
+    let before = i64::try_from(config.orphan_grace_secs)
+        .ok()
+        .and_then(chrono::Duration::try_seconds)
+        .and_then(|grace| chrono::Local::now().checked_sub_signed(grace))
+        .map(|ts| ts.to_rfc3339())
+        .unwrap_or_default();
+    let mut report = GarbageReport::default();
+    loop {
+        let ids = expire_unreferenced_attachments(Arc::clone(&conn), &before, GC_BATCH).await?;
+        remove_files(&store, &ids).await;
+        report.attachments += ids.len();
+        // Expiring an image frees its thumbnail for the next batch
+        if ids.is_empty() {
+            break;
+        }
+    }
+    let (blobs, bytes) = collect_blobs(&store, conn).await?;
+    report.blobs = blobs;
+    report.bytes = bytes;
+    Ok(report)
+}
+
+/// Remove the attachments of messages that were deleted for good right
+/// away, without waiting for their grace period: disappearing messages and
+/// messages past their retention period should not leave their files
+/// behind. Attachments other messages still carry are kept.
+pub async fn release_message_attachments(
+    conn: Arc<Mutex<Connection>>,
+    config: &AttachmentsSection,
+    ids: &[i64],
+) -> SqliteResult<GarbageReport> {
+    let store = AttachmentStore::new(&config.dir);
+    let released = release_attachments(Arc::clone(&conn), ids).await?;
+    remove_files(&store, &released).await;
+    let (blobs, bytes) = collect_blobs(&store, conn).await?;
+    Ok(GarbageReport {
+        attachments: released.len(),
+        blobs,
+        bytes,
+    })
+}
+
+/// [`release_message_attachments`] for messages that are already gone:
+/// failures are only logged, as the collector removes the files after their
+/// grace period instead.
+pub async fn release_purged_attachments(
+    conn: Arc<Mutex<Connection>>,
+    config: &AttachmentsSection,
+    ids: &[i64],
+) {
+    if !ids.is_empty()
+        && let Err(e) = release_message_attachments(conn, config, ids).await
+        && logging::enabled(LogLevel::Error)
+    {
+        eprintln!("Failed to release attachments of deleted messages: {}", e);
+    }
+}
+
+async fn remove_files(store: &AttachmentStore, ids: &[i64]) {
+    for &id in ids {
+        if let Err(e) = store.remove(id).await
+            && logging::enabled(LogLevel::Error)
+        {
+            eprintln!("Failed to remove files of attachment {}: {}", id, e);
+        }
+    }
+}
+
+/// Remove every blob no attachment uses; returns the blobs and bytes freed.
+async fn collect_blobs(
+    store: &AttachmentStore,
+    conn: Arc<Mutex<Connection>>,
+) -> SqliteResult<(usize, i64)> {
+    let (mut freed, mut freed_bytes) = (0, 0);
+    loop {
+        let mut failed = false;
+        let remove = |sha256: &str| match store.remove_blob(sha256) {
+            Ok(()) => true,
+            Err(e) => {
+                if logging::enabled(LogLevel::Error) {
+                    eprintln!("Failed to remove blob {}: {}", sha256, e);
+                }
+                failed = true;
+                false
+            }
+        };
+        let (blobs, bytes) =
+            collect_unreferenced_blobs(Arc::clone(&conn), GC_BATCH, remove).await?;
+        freed += blobs;
+        freed_bytes += bytes;
+        // Blobs that could not be removed stay selected; retry next run
+        if blobs < GC_BATCH || failed {
+            return Ok((freed, freed_bytes));
+        }
+    }
+}
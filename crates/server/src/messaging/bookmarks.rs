@@ -0,0 +1,164 @@
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+
+use super::actions::history_message;
+use super::models::{Bookmark, SaveRequest, SaveResponse, SavedListRequest, SavedListResponse};
+use super::queue::SessionSender;
+use super::state::AppState;
+use crate::models::client_message::ClientMessage;
+use crate::utils::db_utils::{
+    BookmarkFilter, BookmarkUpdate, list_bookmarks, remove_bookmark, save_bookmark,
+};
+
+/// Longest accepted folder name, in bytes.
+pub const MAX_FOLDER_BYTES: usize = 64;
+/// Longest accepted tag, in bytes.
+pub const MAX_TAG_BYTES: usize = 32;
+/// Most tags one bookmark can carry.
+pub const MAX_TAGS: usize = 16;
+/// Longest accepted note, in bytes.
+pub const MAX_NOTE_BYTES: usize = 1024;
+
+/// Commands handled by [`handle_bookmark_command`]: saving messages for the
+/// requesting user alone and listing what they saved.
+pub fn is_bookmark_command(command: &str) -> bool {
+    matches!(command, "save" | "saved_list")
+}
+
+pub async fn handle_bookmark_command(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    msg: ClientMessage,
+) {
+    match msg.command.as_str() {
+        "save" => save(conn, outbound, user_id, &msg.data).await,
+        "saved_list" => saved_list(&state, conn, outbound, user_id, &msg.data).await,
+        other => unreachable!("not a bookmark command: {other}"),
+    }
+}
+
+fn error(outbound: &SessionSender, text: &str) {
+    let _ = outbound.send(ClientMessage {
+        command: "error".to_string(),
+        data: text.to_string(),
+    });
+}
+
+/// Why `update` cannot be stored, if it cannot.
+fn invalid_update(update: &BookmarkUpdate) -> Option<String> {
+    if let Some(folder) = &update.folder
+        && (folder.len() > MAX_FOLDER_BYTES || folder.trim() != folder)
+    {
+        return Some(format!(
+            "Folder must be at most {MAX_FOLDER_BYTES} bytes without surrounding spaces"
+        ));
+    }
+    if let Some(tags) = &update.tags {
+        if tags.len() > MAX_TAGS {
+            return Some(format!("At most {MAX_TAGS} tags"));
+        }
+        if tags.iter().any(|tag| {
+            tag.is_empty() || tag.len() > MAX_TAG_BYTES || tag.contains(char::is_whitespace)
+        }) {
+            return Some(format!(
+                "Tags must be 1 to {MAX_TAG_BYTES} bytes without spaces"
+            ));
+        }
+    }
+    if let Some(note) = &update.note
+        && note.len() > MAX_NOTE_BYTES
+    {
+        return Some(format!("Note must be at most {MAX_NOTE_BYTES} bytes"));
+    }
+    None
+}
+
+/// Bookmark or unbookmark a message; folder, tags and note only apply when
+/// saving.
+async fn save(conn: Arc<Mutex<Connection>>, outbound: &SessionSender, user_id: i64, data: &str) {
+    let Ok(req) = serde_json::from_str::<SaveRequest>(data) else {
+        return error(outbound, "Invalid save format");
+    };
+    let saved = req.saved.unwrap_or(true);
+    let respond = |success: bool, message: &str| {
+        let resp = SaveResponse {
+            success,
+            message: message.to_string(),
+            message_id: Some(req.message_id),
+            saved: Some(saved),
+        };
+        let _ = outbound.send(ClientMessage {
+            command: "save_response".to_string(),
+            data: serde_json::to_string(&resp).unwrap(),
+        });
+    };
+    let update = BookmarkUpdate {
+        folder: req.folder.clone(),
+        tags: req.tags.clone(),
+        note: req.note.clone(),
+    };
+    if let Some(text) = invalid_update(&update) {
+        return respond(false, &text);
+    }
+    let result = if saved {
+        save_bookmark(conn, user_id, req.message_id, &update).await
+    } else {
+        remove_bookmark(conn, user_id, req.message_id).await
+    };
+    match result {
+        Ok(true) => respond(true, "Message updated"),
+        Ok(false) => respond(false, "Message not found or not authorized"),
+        Err(_) => respond(false, "Failed to update message"),
+    }
+}
+
+/// One page of the requesting user's bookmarks, newest first.
+async fn saved_list(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let Ok(req) = serde_json::from_str::<SavedListRequest>(data) else {
+        return error(outbound, "Invalid saved_list format");
+    };
+    let config = state.config();
+    let limit = req
+        .limit
+        .unwrap_or(config.history.default_limit)
+        .min(config.limits.max_history_limit);
+    let filter = BookmarkFilter {
+        folder: req.folder,
+        tag: req.tag,
+        before_id: req.before_id,
+    };
+    let (success, message, bookmarks) = match list_bookmarks(conn, user_id, &filter, limit).await {
+        Ok(rows) => (
+            true,
+            "OK",
+            rows.into_iter()
+                .map(|row| Bookmark {
+                    id: row.id,
+                    folder: row.folder,
+                    tags: row.tags,
+                    note: row.note,
+                    saved_at: row.created_at,
+                    message: history_message(row.message),
+                })
+                .collect(),
+        ),
+        Err(_) => (false, "Failed to load saved messages", Vec::new()),
+    };
+    let resp = SavedListResponse {
+        success,
+        message: message.to_string(),
+        bookmarks,
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "saved_list_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
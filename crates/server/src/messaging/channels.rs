@@ -0,0 +1,441 @@
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+
+use super::handlers::forbidden;
+use super::models::{
+    ChannelCreateRequest, ChannelHistoryRequest, ChannelHistoryResponse, ChannelInfo,
+    ChannelInviteRequest, ChannelInvitedEvent, ChannelListResponse, ChannelPostEvent,
+    ChannelPostReq, ChannelRequest, ChannelResponse, ChannelSetRoleRequest,
+};
+use super::queue::SessionSender;
+use super::state::AppState;
+use crate::models::client_message::ClientMessage;
+use crate::utils::db_utils::{
+    ChannelPostRow, ChannelRole, UserChannelRow, channel_member_ids, channels_for_user,
+    create_channel, fetch_channel_posts, get_user_channel, invite_to_channel, missing_user_ids,
+    set_channel_role, store_channel_post, subscribe_channel, unsubscribe_channel,
+};
+
+/// Longest accepted channel name, in characters.
+pub const MAX_CHANNEL_NAME_CHARS: usize = 100;
+/// Longest accepted channel description, in characters.
+pub const MAX_CHANNEL_DESCRIPTION_CHARS: usize = 500;
+
+/// Commands handled by [`handle_channel_command`].
+pub fn is_channel_command(command: &str) -> bool {
+    matches!(
+        command,
+        "channel_create"
+            | "channel_invite"
+            | "channel_subscribe"
+            | "channel_unsubscribe"
+            | "channel_set_role"
+            | "channel_list"
+            | "channel_post"
+            | "channel_history"
+    )
+}
+
+/// Why a channel command was refused.
+enum ChannelError {
+    /// Answered with `success: false` in the command's response.
+    Failed(String),
+    /// The caller's role does not allow the command; answered with a
+    /// `Forbidden` error.
+    Forbidden(String),
+}
+
+impl From<String> for ChannelError {
+    fn from(message: String) -> Self {
+        ChannelError::Failed(message)
+    }
+}
+
+type ChannelResult = Result<(String, ChannelInfo), ChannelError>;
+
+pub async fn handle_channel_command(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    msg: ClientMessage,
+) {
+    fn invalid(command: &str) -> ChannelResult {
+        Err(ChannelError::Failed(format!("Invalid {command} format")))
+    }
+    let data = msg.data.as_str();
+    let result = match msg.command.as_str() {
+        "channel_create" => match serde_json::from_str(data) {
+            Ok(req) => create(conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "channel_invite" => match serde_json::from_str(data) {
+            Ok(req) => invite(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "channel_subscribe" => match serde_json::from_str(data) {
+            Ok(req) => subscribe(conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "channel_unsubscribe" => match serde_json::from_str(data) {
+            Ok(req) => unsubscribe(conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "channel_set_role" => match serde_json::from_str(data) {
+            Ok(req) => set_role(conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "channel_list" => {
+            list(conn, outbound, user_id).await;
+            return;
+        }
+        "channel_post" => {
+            post(&state, conn, outbound, user_id, data).await;
+            return;
+        }
+        "channel_history" => {
+            history(&state, conn, outbound, user_id, data).await;
+            return;
+        }
+        other => unreachable!("not a channel command: {other}"),
+    };
+    let resp = match result {
+        Ok((message, channel)) => ChannelResponse {
+            success: true,
+            message,
+            channel: Some(channel),
+        },
+        Err(ChannelError::Failed(message)) => ChannelResponse {
+            success: false,
+            message,
+            channel: None,
+        },
+        Err(ChannelError::Forbidden(message)) => {
+            let _ = outbound.send(forbidden(message));
+            return;
+        }
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "channel_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
+
+fn info(row: UserChannelRow) -> ChannelInfo {
+    ChannelInfo {
+        id: row.channel.id,
+        name: row.channel.name,
+        description: row.channel.description,
+        invite_only: row.channel.invite_only,
+        owner_id: row.channel.owner_id,
+        member_count: row.channel.member_count,
+        role: row.role.map(|r| r.as_str().to_string()),
+        invited: row.invited,
+    }
+}
+
+fn post_event(row: ChannelPostRow) -> ChannelPostEvent {
+    ChannelPostEvent {
+        channel_id: row.channel_id,
+        post_id: row.id,
+        from_user_id: row.sender,
+        body: row.content,
+        timestamp: row.timestamp,
+    }
+}
+
+fn db_error(_: rusqlite::Error) -> ChannelError {
+    ChannelError::Failed("Failed to update channel".to_string())
+}
+
+fn not_found() -> ChannelError {
+    ChannelError::Failed("Channel not found".to_string())
+}
+
+fn check_length(what: &str, value: &str, max: usize) -> Result<(), String> {
+    if value.chars().count() > max {
+        return Err(format!("{what} must be at most {max} characters"));
+    }
+    Ok(())
+}
+
+/// The channel as `user_id` sees it. Invite-only channels do not exist for
+/// users who are neither members nor invited.
+async fn visible_channel(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    user_id: i64,
+) -> Result<UserChannelRow, ChannelError> {
+    match get_user_channel(conn, channel_id, user_id).await {
+        Ok(Some(row)) if !row.channel.invite_only || row.role.is_some() || row.invited => Ok(row),
+        Ok(_) => Err(not_found()),
+        Err(e) => Err(db_error(e)),
+    }
+}
+
+async fn reload(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    user_id: i64,
+) -> Result<ChannelInfo, ChannelError> {
+    match get_user_channel(conn, channel_id, user_id).await {
+        Ok(Some(row)) => Ok(info(row)),
+        Ok(None) => Err(not_found()),
+        Err(e) => Err(db_error(e)),
+    }
+}
+
+async fn create(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: ChannelCreateRequest,
+) -> ChannelResult {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err("Channel name must not be empty".to_string().into());
+    }
+    check_length("Channel name", name, MAX_CHANNEL_NAME_CHARS)?;
+    let description = req.description.trim();
+    check_length("Description", description, MAX_CHANNEL_DESCRIPTION_CHARS)?;
+    let Some(channel) = create_channel(conn, user_id, name, description, req.invite_only)
+        .await
+        .map_err(db_error)?
+    else {
+        return Err("Channel name is already taken".to_string().into());
+    };
+    let row = UserChannelRow {
+        channel,
+        role: Some(ChannelRole::Owner),
+        invited: false,
+    };
+    Ok(("Channel created".to_string(), info(row)))
+}
+
+async fn invite(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: ChannelInviteRequest,
+) -> ChannelResult {
+    let row = visible_channel(Arc::clone(&conn), req.channel_id, user_id).await?;
+    if !row.role.is_some_and(ChannelRole::can_post) {
+        return Err(ChannelError::Forbidden(
+            "Only the owner and publishers can invite".to_string(),
+        ));
+    }
+    let missing = missing_user_ids(Arc::clone(&conn), &req.user_ids)
+        .await
+        .map_err(db_error)?;
+    if !missing.is_empty() {
+        let ids: Vec<String> = missing.iter().map(i64::to_string).collect();
+        return Err(format!("Unknown user ids: {}", ids.join(", ")).into());
+    }
+    let invited = invite_to_channel(Arc::clone(&conn), req.channel_id, user_id, &req.user_ids)
+        .await
+        .map_err(db_error)?;
+    let channel = info(row);
+    if !invited.is_empty() {
+        let event = ChannelInvitedEvent {
+            inviter_id: user_id,
+            channel: ChannelInfo {
+                role: None,
+                invited: true,
+                ..channel.clone()
+            },
+        };
+        let msg = Arc::new(ClientMessage {
+            command: "channel_invited".to_string(),
+            data: serde_json::to_string(&event).unwrap(),
+        });
+        for tx in state.senders_for(&invited).await {
+            let _ = tx.send_shared(Arc::clone(&msg));
+        }
+    }
+    let message = format!("Invited {} user(s)", invited.len());
+    Ok((message, channel))
+}
+
+async fn subscribe(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: ChannelRequest,
+) -> ChannelResult {
+    let row = visible_channel(Arc::clone(&conn), req.channel_id, user_id).await?;
+    if row.role.is_some() {
+        return Ok(("Already subscribed".to_string(), info(row)));
+    }
+    subscribe_channel(Arc::clone(&conn), req.channel_id, user_id)
+        .await
+        .map_err(db_error)?;
+    Ok((
+        "Subscribed".to_string(),
+        reload(conn, req.channel_id, user_id).await?,
+    ))
+}
+
+async fn unsubscribe(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: ChannelRequest,
+) -> ChannelResult {
+    let row = visible_channel(Arc::clone(&conn), req.channel_id, user_id).await?;
+    match row.role {
+        None => return Err("Not subscribed".to_string().into()),
+        Some(ChannelRole::Owner) => {
+            return Err(ChannelError::Forbidden(
+                "The owner cannot unsubscribe".to_string(),
+            ));
+        }
+        Some(_) => {}
+    }
+    unsubscribe_channel(Arc::clone(&conn), req.channel_id, user_id)
+        .await
+        .map_err(db_error)?;
+    Ok((
+        "Unsubscribed".to_string(),
+        reload(conn, req.channel_id, user_id).await?,
+    ))
+}
+
+async fn set_role(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: ChannelSetRoleRequest,
+) -> ChannelResult {
+    let row = visible_channel(Arc::clone(&conn), req.channel_id, user_id).await?;
+    if row.role != Some(ChannelRole::Owner) {
+        return Err(ChannelError::Forbidden(
+            "Only the owner can change roles".to_string(),
+        ));
+    }
+    let role = match ChannelRole::parse(&req.role) {
+        Some(role @ (ChannelRole::Publisher | ChannelRole::Subscriber)) => role,
+        _ => return Err("Role must be publisher or subscriber".to_string().into()),
+    };
+    if !set_channel_role(Arc::clone(&conn), req.channel_id, req.user_id, role)
+        .await
+        .map_err(db_error)?
+    {
+        return Err("User is not a subscriber".to_string().into());
+    }
+    Ok((
+        "Role updated".to_string(),
+        reload(conn, req.channel_id, user_id).await?,
+    ))
+}
+
+async fn list(conn: Arc<Mutex<Connection>>, outbound: &SessionSender, user_id: i64) {
+    let resp = match channels_for_user(conn, user_id).await {
+        Ok(rows) => ChannelListResponse {
+            success: true,
+            message: "OK".to_string(),
+            channels: rows.into_iter().map(info).collect(),
+        },
+        Err(_) => ChannelListResponse {
+            success: false,
+            message: "Failed to load channels".to_string(),
+            channels: Vec::new(),
+        },
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "channel_list_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
+
+/// Persist a post and push it to every online member. The event is
+/// serialized once and shared by all queues; only the senders are collected
+/// under the `AppState` lock.
+async fn post(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let error = |text: &str| {
+        let _ = outbound.send(ClientMessage {
+            command: "error".to_string(),
+            data: text.to_string(),
+        });
+    };
+    let Ok(req) = serde_json::from_str::<ChannelPostReq>(data) else {
+        return error("Invalid channel_post format");
+    };
+    if req.body.len() > state.config().limits.max_body_bytes {
+        return error("Message too long");
+    }
+    match visible_channel(Arc::clone(&conn), req.channel_id, user_id).await {
+        Ok(row) if row.role.is_some_and(ChannelRole::can_post) => {}
+        Ok(_) => {
+            let _ = outbound.send(forbidden(
+                "Only the owner and publishers can post".to_string(),
+            ));
+            return;
+        }
+        Err(ChannelError::Failed(text) | ChannelError::Forbidden(text)) => return error(&text),
+    }
+    let stored =
+        match store_channel_post(Arc::clone(&conn), req.channel_id, user_id, &req.body).await {
+            Ok(stored) => stored,
+            Err(_) => return error("Failed to store message"),
+        };
+    let Ok(mut audience) = channel_member_ids(conn, req.channel_id).await else {
+        // Stored; members catch up through channel_history
+        return;
+    };
+    audience.retain(|&id| id != user_id);
+    let msg = Arc::new(ClientMessage {
+        command: "channel_post".to_string(),
+        data: serde_json::to_string(&post_event(stored)).unwrap(),
+    });
+    for tx in state.senders_for_sorted(&audience).await {
+        // Ignore send errors (receiver might have just disconnected)
+        let _ = tx.send_shared(Arc::clone(&msg));
+    }
+}
+
+async fn history(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let Ok(req) = serde_json::from_str::<ChannelHistoryRequest>(data) else {
+        let _ = outbound.send(ClientMessage {
+            command: "error".to_string(),
+            data: "Invalid channel_history format".to_string(),
+        });
+        return;
+    };
+    let config = state.config();
+    let limit = req
+        .limit
+        .unwrap_or(config.history.default_limit)
+        .min(config.limits.max_history_limit);
+    let posts = match visible_channel(Arc::clone(&conn), req.channel_id, user_id).await {
+        Ok(_) => fetch_channel_posts(conn, req.channel_id, req.after_id.unwrap_or(0), limit)
+            .await
+            .map_err(|_| "Failed to load channel history".to_string()),
+        Err(ChannelError::Failed(text) | ChannelError::Forbidden(text)) => Err(text),
+    };
+    let resp = match posts {
+        Ok(posts) => ChannelHistoryResponse {
+            success: true,
+            message: "OK".to_string(),
+            channel_id: req.channel_id,
+            posts: posts.into_iter().map(post_event).collect(),
+        },
+        Err(message) => ChannelHistoryResponse {
+            success: false,
+            message,
+            channel_id: req.channel_id,
+            posts: Vec::new(),
+        },
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "channel_history_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
@@ -0,0 +1,83 @@
+use rusqlite::{Connection, Result as SqliteResult};
+use std::sync::{Arc, Mutex};
+
+use super::attachments::release_purged_attachments;
+use super::models::MessageExpiredEvent;
+use super::state::AppState;
+use crate::models::client_message::ClientMessage;
+use crate::models::config::ExpirySection;
+use crate::utils::db_utils::{PurgedMessage, purge_expired_messages};
+
+/// Messages the sweeper deletes per database lock.
+const SWEEP_BATCH: usize = 500;
+
+/// The error to report for an `expires_in_secs` outside what the server
+/// allows, if it is.
+pub fn invalid_expiry(config: &ExpirySection, expires_in_secs: Option<u64>) -> Option<String> {
+    let max = config.max_expires_in_secs;
+    expires_in_secs
+        .filter(|secs| !(1..=max).contains(secs))
+        .map(|_| format!("expires_in_secs must be between 1 and {max}"))
+}
+
+/// Unix seconds at which a message sent now with `expires_in_secs` expires.
+/// Lifetimes outside the allowed range are clamped rather than dropped, so a
+/// message never outlives what its sender asked for.
+pub fn expires_at(config: &ExpirySection, expires_in_secs: Option<u64>) -> Option<i64> {
+    let secs = expires_in_secs?.clamp(1, config.max_expires_in_secs);
+    Some(chrono::Utc::now().timestamp() + secs as i64)
+}
+
+/// Release the attachments of messages that were deleted for good, then
+/// tell everyone who could see them except `actor`.
+pub async fn announce_purged(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    purged: &[PurgedMessage],
+    actor: Option<i64>,
+    reason: &str,
+) {
+    let attachment_ids: Vec<i64> = purged.iter().filter_map(|m| m.attachment_id).collect();
+    release_purged_attachments(conn, &state.config().attachments, &attachment_ids).await;
+    for message in purged {
+        let event = MessageExpiredEvent {
+            message_id: message.id,
+            from_user_id: message.sender,
+            to_user_id: message.receiver,
+            conversation_id: message.conversation_id,
+            reason: reason.to_string(),
+        };
+        let msg = Arc::new(ClientMessage {
+            command: "message_expired".to_string(),
+            data: serde_json::to_string(&event).unwrap(),
+        });
+        let audience: Vec<i64> = message
+            .audience
+            .iter()
+            .copied()
+            .filter(|&id| Some(id) != actor)
+            .collect();
+        for tx in state.senders_for_sorted(&audience).await {
+            // Ignore send errors (receiver might have just disconnected)
+            let _ = tx.send_shared(Arc::clone(&msg));
+        }
+    }
+}
+
+/// Delete every message whose time ran out, in batches, and announce each
+/// batch. Returns how many were deleted.
+pub async fn sweep_expired_messages(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+) -> SqliteResult<usize> {
+    let now = chrono::Utc::now().timestamp();
+    let mut deleted = 0;
+    loop {
+        let purged = purge_expired_messages(Arc::clone(&conn), now, SWEEP_BATCH).await?;
+        announce_purged(state, Arc::clone(&conn), &purged, None, "expired").await;
+        deleted += purged.len();
+        if purged.len() < SWEEP_BATCH {
+            return Ok(deleted);
+        }
+    }
+}
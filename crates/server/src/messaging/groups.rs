@@ -0,0 +1,686 @@
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+
+use super::attachments::attachment_info;
+use super::expiry::{expires_at, invalid_expiry};
+use super::handlers::{forbidden, reply_to};
+use super::models::{
+    ConversationInfo, GroupCreateRequest, GroupInviteRequest, GroupKickRequest, GroupLeaveRequest,
+    GroupListResponse, GroupMember, GroupMessageEvent, GroupMessageReq, GroupMuteRequest,
+    GroupRenameRequest, GroupResponse, GroupSetRoleRequest, GroupSystemMessage,
+    GroupTransferRequest, GroupUpdatedEvent,
+};
+use super::queue::SessionSender;
+use super::state::AppState;
+use crate::models::client_message::ClientMessage;
+use crate::models::config::LogLevel;
+use crate::utils::db_utils::{
+    ConversationMember, ConversationRow, GroupRole, MessageLinks, add_conversation_members,
+    conversations_for_user, create_conversation, get_conversation, leave_conversation,
+    message_participants, missing_user_ids, readable_attachment, rename_conversation, reply_quote,
+    set_member_muted_until, set_member_role, store_message_with, store_system_message,
+    transfer_conversation_ownership,
+};
+use crate::utils::logging;
+
+/// Longest accepted group name, in characters.
+pub const MAX_GROUP_NAME_CHARS: usize = 100;
+
+/// Commands handled by [`handle_group_command`].
+pub fn is_group_command(command: &str) -> bool {
+    matches!(
+        command,
+        "group_create"
+            | "group_invite"
+            | "group_leave"
+            | "group_rename"
+            | "group_kick"
+            | "group_mute"
+            | "group_set_role"
+            | "group_transfer"
+            | "group_list"
+            | "group_message"
+    )
+}
+
+/// Why a group command was refused.
+enum GroupError {
+    /// Answered with `group_response { success: false }`.
+    Failed(String),
+    /// The caller's role does not allow the command; answered with a
+    /// `Forbidden` error.
+    Forbidden(String),
+}
+
+impl From<String> for GroupError {
+    fn from(message: String) -> Self {
+        GroupError::Failed(message)
+    }
+}
+
+type GroupResult = Result<(String, ConversationInfo), GroupError>;
+
+pub async fn handle_group_command(
+    state: Arc<AppState>,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    msg: ClientMessage,
+) {
+    fn invalid(command: &str) -> GroupResult {
+        Err(GroupError::Failed(format!("Invalid {command} format")))
+    }
+    let data = msg.data.as_str();
+    let result = match msg.command.as_str() {
+        "group_create" => match serde_json::from_str(data) {
+            Ok(req) => create(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_invite" => match serde_json::from_str(data) {
+            Ok(req) => invite(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_leave" => match serde_json::from_str(data) {
+            Ok(req) => leave(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_rename" => match serde_json::from_str(data) {
+            Ok(req) => rename(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_kick" => match serde_json::from_str(data) {
+            Ok(req) => kick(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_mute" => match serde_json::from_str(data) {
+            Ok(req) => mute(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_set_role" => match serde_json::from_str(data) {
+            Ok(req) => set_role(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_transfer" => match serde_json::from_str(data) {
+            Ok(req) => transfer(&state, conn, user_id, req).await,
+            Err(_) => invalid(&msg.command),
+        },
+        "group_list" => {
+            list(conn, outbound, user_id).await;
+            return;
+        }
+        "group_message" => {
+            send_group(&state, conn, outbound, user_id, data).await;
+            return;
+        }
+        other => unreachable!("not a group command: {other}"),
+    };
+    let resp = match result {
+        Ok((message, conversation)) => GroupResponse {
+            success: true,
+            message,
+            conversation: Some(conversation),
+        },
+        Err(GroupError::Failed(message)) => GroupResponse {
+            success: false,
+            message,
+            conversation: None,
+        },
+        Err(GroupError::Forbidden(message)) => {
+            let _ = outbound.send(forbidden(message));
+            return;
+        }
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "group_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn info(row: ConversationRow) -> ConversationInfo {
+    ConversationInfo {
+        id: row.id,
+        member_ids: row.member_ids(),
+        members: row
+            .members
+            .into_iter()
+            .map(|m| GroupMember {
+                user_id: m.user_id,
+                role: m.role.as_str().to_string(),
+                muted_until: m.muted_until,
+            })
+            .collect(),
+        name: row.name,
+    }
+}
+
+fn db_error(_: rusqlite::Error) -> GroupError {
+    GroupError::Failed("Failed to update group".to_string())
+}
+
+fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Group name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_GROUP_NAME_CHARS {
+        return Err(format!(
+            "Group name must be at most {MAX_GROUP_NAME_CHARS} characters"
+        ));
+    }
+    Ok(name.to_string())
+}
+
+async fn check_users_exist(
+    conn: Arc<Mutex<Connection>>,
+    user_ids: &[i64],
+) -> Result<(), GroupError> {
+    let missing = missing_user_ids(conn, user_ids).await.map_err(db_error)?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<String> = missing.iter().map(i64::to_string).collect();
+    Err(GroupError::Failed(format!(
+        "Unknown user ids: {}",
+        ids.join(", ")
+    )))
+}
+
+/// The group, provided `user_id` currently belongs to it. Non-members get the
+/// same answer as for a missing group.
+async fn member_conversation(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    user_id: i64,
+) -> Result<ConversationRow, GroupError> {
+    match get_conversation(conn, conversation_id).await {
+        Ok(Some(row)) if row.member(user_id).is_some() => Ok(row),
+        Ok(_) => Err(GroupError::Failed(
+            "Group not found or not a member".to_string(),
+        )),
+        Err(e) => Err(db_error(e)),
+    }
+}
+
+/// `user_id`'s membership, which [`member_conversation`] guarantees.
+fn membership(row: &ConversationRow, user_id: i64) -> &ConversationMember {
+    row.member(user_id).expect("caller is a member")
+}
+
+/// Fails with `Forbidden` unless `actor` holds `role` or a higher one.
+fn require_role(
+    actor: &ConversationMember,
+    role: GroupRole,
+    action: &str,
+) -> Result<(), GroupError> {
+    if actor.role <= role {
+        return Ok(());
+    }
+    let who = match role {
+        GroupRole::Owner => "the owner",
+        _ => "owners and admins",
+    };
+    Err(GroupError::Forbidden(format!("Only {who} can {action}")))
+}
+
+/// The target of a kick or mute: a current member ranked below `actor`.
+fn moderation_target<'a>(
+    row: &'a ConversationRow,
+    actor: &ConversationMember,
+    target_id: i64,
+    action: &str,
+) -> Result<&'a ConversationMember, GroupError> {
+    require_role(actor, GroupRole::Admin, action)?;
+    let Some(target) = row.member(target_id) else {
+        return Err(GroupError::Failed("User is not a member".to_string()));
+    };
+    if target.role <= actor.role {
+        return Err(GroupError::Forbidden(format!(
+            "Cannot {action} a member with an equal or higher role"
+        )));
+    }
+    Ok(target)
+}
+
+/// Push `msg` to every online user in `user_ids` except `actor`.
+async fn fan_out(state: &AppState, user_ids: &[i64], actor: i64, msg: ClientMessage) {
+    let recipients: Vec<i64> = user_ids.iter().copied().filter(|&id| id != actor).collect();
+    let msg = Arc::new(msg);
+    for tx in state.senders_for(&recipients).await {
+        // Ignore send errors (receiver might have just disconnected)
+        let _ = tx.send_shared(Arc::clone(&msg));
+    }
+}
+
+/// Record `change` as a system message in the group's history and push
+/// `group_updated` to the members, plus `also_notify` (e.g. a kicked user).
+async fn record_change(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    conversation: &ConversationInfo,
+    change: GroupSystemMessage,
+    also_notify: &[i64],
+) {
+    let body = serde_json::to_string(&change).unwrap();
+    if let Err(e) = store_system_message(conn, change.actor_id, conversation.id, &body).await
+        && logging::enabled(LogLevel::Error)
+    {
+        eprintln!(
+            "Failed to record {} in group {}: {}",
+            change.change, conversation.id, e
+        );
+    }
+    let event = GroupUpdatedEvent {
+        change: change.change,
+        actor_id: change.actor_id,
+        user_ids: change.user_ids,
+        conversation: conversation.clone(),
+    };
+    let msg = ClientMessage {
+        command: "group_updated".to_string(),
+        data: serde_json::to_string(&event).unwrap(),
+    };
+    let mut recipients = conversation.member_ids.clone();
+    recipients.extend_from_slice(also_notify);
+    fan_out(state, &recipients, event.actor_id, msg).await;
+}
+
+fn change(kind: &str, actor_id: i64, user_ids: Vec<i64>) -> GroupSystemMessage {
+    GroupSystemMessage {
+        change: kind.to_string(),
+        actor_id,
+        user_ids,
+        name: None,
+        role: None,
+        muted_until: None,
+    }
+}
+
+/// Reload the group after a change made by `user_id`.
+async fn reload(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    user_id: i64,
+) -> Result<ConversationInfo, GroupError> {
+    member_conversation(conn, conversation_id, user_id)
+        .await
+        .map(info)
+}
+
+async fn create(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupCreateRequest,
+) -> GroupResult {
+    let name = validate_name(&req.name)?;
+    let mut member_ids: Vec<i64> = req
+        .member_ids
+        .into_iter()
+        .filter(|&id| id != user_id)
+        .collect();
+    member_ids.sort_unstable();
+    member_ids.dedup();
+    let max = state.config().limits.max_group_members;
+    if member_ids.len() + 1 > max {
+        return Err(format!("A group can have at most {max} members").into());
+    }
+    check_users_exist(Arc::clone(&conn), &member_ids).await?;
+    let row = create_conversation(Arc::clone(&conn), user_id, &name, &member_ids)
+        .await
+        .map_err(db_error)?;
+    let conversation = info(row);
+    let change = GroupSystemMessage {
+        name: Some(name),
+        ..change("created", user_id, member_ids)
+    };
+    record_change(state, conn, &conversation, change, &[]).await;
+    Ok(("Group created".to_string(), conversation))
+}
+
+async fn invite(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupInviteRequest,
+) -> GroupResult {
+    let row = member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    require_role(membership(&row, user_id), GroupRole::Admin, "invite")?;
+    let mut new_ids: Vec<i64> = req
+        .user_ids
+        .into_iter()
+        .filter(|&id| row.member(id).is_none())
+        .collect();
+    new_ids.sort_unstable();
+    new_ids.dedup();
+    if new_ids.is_empty() {
+        return Ok(("Already members".to_string(), info(row)));
+    }
+    let max = state.config().limits.max_group_members;
+    if row.members.len() + new_ids.len() > max {
+        return Err(format!("A group can have at most {max} members").into());
+    }
+    check_users_exist(Arc::clone(&conn), &new_ids).await?;
+    add_conversation_members(Arc::clone(&conn), req.conversation_id, &new_ids)
+        .await
+        .map_err(db_error)?;
+    let conversation = reload(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    let change = change("invited", user_id, new_ids);
+    record_change(state, conn, &conversation, change, &[]).await;
+    Ok(("Members added".to_string(), conversation))
+}
+
+async fn leave(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupLeaveRequest,
+) -> GroupResult {
+    let mut row = member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    // A group always has an owner while it has members
+    if membership(&row, user_id).role == GroupRole::Owner && row.members.len() > 1 {
+        return Err(GroupError::Forbidden(
+            "Transfer ownership before leaving".to_string(),
+        ));
+    }
+    if !leave_conversation(Arc::clone(&conn), req.conversation_id, user_id)
+        .await
+        .map_err(db_error)?
+    {
+        return Err("Group not found or not a member".to_string().into());
+    }
+    row.members.retain(|m| m.user_id != user_id);
+    let conversation = info(row);
+    let change = change("left", user_id, Vec::new());
+    record_change(state, conn, &conversation, change, &[]).await;
+    Ok(("Left group".to_string(), conversation))
+}
+
+async fn rename(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupRenameRequest,
+) -> GroupResult {
+    let name = validate_name(&req.name)?;
+    let mut row = member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    require_role(
+        membership(&row, user_id),
+        GroupRole::Admin,
+        "rename the group",
+    )?;
+    rename_conversation(Arc::clone(&conn), req.conversation_id, &name)
+        .await
+        .map_err(db_error)?;
+    row.name = name.clone();
+    let conversation = info(row);
+    let change = GroupSystemMessage {
+        name: Some(name),
+        ..change("renamed", user_id, Vec::new())
+    };
+    record_change(state, conn, &conversation, change, &[]).await;
+    Ok(("Group renamed".to_string(), conversation))
+}
+
+async fn kick(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupKickRequest,
+) -> GroupResult {
+    let row = member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    if req.user_id == user_id {
+        return Err("Use group_leave to leave the group".to_string().into());
+    }
+    moderation_target(&row, membership(&row, user_id), req.user_id, "kick")?;
+    leave_conversation(Arc::clone(&conn), req.conversation_id, req.user_id)
+        .await
+        .map_err(db_error)?;
+    let conversation = reload(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    let change = change("kicked", user_id, vec![req.user_id]);
+    record_change(state, conn, &conversation, change, &[req.user_id]).await;
+    Ok(("Member removed".to_string(), conversation))
+}
+
+async fn mute(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupMuteRequest,
+) -> GroupResult {
+    let row = member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    moderation_target(&row, membership(&row, user_id), req.user_id, "mute")?;
+    let muted_until = (req.duration_secs > 0)
+        .then(|| now().saturating_add(i64::try_from(req.duration_secs).unwrap_or(i64::MAX)));
+    set_member_muted_until(
+        Arc::clone(&conn),
+        req.conversation_id,
+        req.user_id,
+        muted_until,
+    )
+    .await
+    .map_err(db_error)?;
+    let conversation = reload(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    let (kind, message) = match muted_until {
+        Some(_) => ("muted", "Member muted"),
+        None => ("unmuted", "Member unmuted"),
+    };
+    let change = GroupSystemMessage {
+        muted_until,
+        ..change(kind, user_id, vec![req.user_id])
+    };
+    record_change(state, conn, &conversation, change, &[]).await;
+    Ok((message.to_string(), conversation))
+}
+
+async fn set_role(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupSetRoleRequest,
+) -> GroupResult {
+    let row = member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    require_role(membership(&row, user_id), GroupRole::Owner, "change roles")?;
+    let role = match GroupRole::parse(&req.role) {
+        Some(role @ (GroupRole::Admin | GroupRole::Member)) => role,
+        _ => return Err("Role must be admin or member".to_string().into()),
+    };
+    if req.user_id == user_id {
+        return Err("Use group_transfer to hand over the group"
+            .to_string()
+            .into());
+    }
+    if !set_member_role(Arc::clone(&conn), req.conversation_id, req.user_id, role)
+        .await
+        .map_err(db_error)?
+    {
+        return Err("User is not a member".to_string().into());
+    }
+    let conversation = reload(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    let change = GroupSystemMessage {
+        role: Some(role.as_str().to_string()),
+        ..change("role_changed", user_id, vec![req.user_id])
+    };
+    record_change(state, conn, &conversation, change, &[]).await;
+    Ok(("Role updated".to_string(), conversation))
+}
+
+async fn transfer(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    req: GroupTransferRequest,
+) -> GroupResult {
+    let row = member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    require_role(
+        membership(&row, user_id),
+        GroupRole::Owner,
+        "transfer ownership",
+    )?;
+    if req.user_id == user_id {
+        return Err("You already own this group".to_string().into());
+    }
+    if !transfer_conversation_ownership(
+        Arc::clone(&conn),
+        req.conversation_id,
+        user_id,
+        req.user_id,
+    )
+    .await
+    .map_err(db_error)?
+    {
+        return Err("User is not a member".to_string().into());
+    }
+    let conversation = reload(Arc::clone(&conn), req.conversation_id, user_id).await?;
+    let change = change("transferred", user_id, vec![req.user_id]);
+    record_change(state, conn, &conversation, change, &[]).await;
+    Ok(("Ownership transferred".to_string(), conversation))
+}
+
+async fn list(conn: Arc<Mutex<Connection>>, outbound: &SessionSender, user_id: i64) {
+    let resp = match conversations_for_user(conn, user_id).await {
+        Ok(rows) => GroupListResponse {
+            success: true,
+            message: "OK".to_string(),
+            conversations: rows.into_iter().map(info).collect(),
+        },
+        Err(_) => GroupListResponse {
+            success: false,
+            message: "Failed to load groups".to_string(),
+            conversations: Vec::new(),
+        },
+    };
+    let _ = outbound.send(ClientMessage {
+        command: "group_list_response".to_string(),
+        data: serde_json::to_string(&resp).unwrap(),
+    });
+}
+
+/// Persist a group message and deliver it to the online members; offline
+/// members read it from `history`.
+async fn send_group(
+    state: &AppState,
+    conn: Arc<Mutex<Connection>>,
+    outbound: &SessionSender,
+    user_id: i64,
+    data: &str,
+) {
+    let error = |text: &str| {
+        let _ = outbound.send(ClientMessage {
+            command: "error".to_string(),
+            data: text.to_string(),
+        });
+    };
+    let Ok(req) = serde_json::from_str::<GroupMessageReq>(data) else {
+        return error("Invalid group_message format");
+    };
+    if req.body.len() > state.config().limits.max_body_bytes {
+        return error("Message too long");
+    }
+    if let Some(text) = invalid_expiry(&state.config().expiry, req.expires_in_secs) {
+        return error(&text);
+    }
+    if req.view_once {
+        return error("View-once messages can only be sent to one user");
+    }
+    let row = match member_conversation(Arc::clone(&conn), req.conversation_id, user_id).await {
+        Ok(row) => row,
+        Err(GroupError::Failed(text)) => return error(&text),
+        Err(GroupError::Forbidden(text)) => {
+            let _ = outbound.send(forbidden(text));
+            return;
+        }
+    };
+    if let Some(until) = membership(&row, user_id).muted_until {
+        let remaining = until - now();
+        if remaining > 0 {
+            let _ = outbound.send(forbidden(format!(
+                "You are muted in this group for another {remaining} s"
+            )));
+            return;
+        }
+    }
+    let quote = match req.reply_to_id {
+        Some(id) => {
+            match reply_quote(
+                Arc::clone(&conn),
+                id,
+                user_id,
+                None,
+                Some(req.conversation_id),
+            )
+            .await
+            {
+                Ok(Some(quote)) => Some(quote),
+                Ok(None) => return error("Reply target not found in this group"),
+                Err(_) => return error("Failed to store message"),
+            }
+        }
+        None => None,
+    };
+    let attachment = match req.attachment_id {
+        Some(id) => match readable_attachment(Arc::clone(&conn), user_id, id).await {
+            Ok(Some(row)) => Some(row),
+            Ok(None) => return error("Attachment not found"),
+            Err(_) => return error("Failed to store message"),
+        },
+        None => None,
+    };
+    let links = MessageLinks {
+        reply_to_id: quote.as_ref().map(|quote| quote.message_id),
+        attachment_id: attachment.as_ref().map(|row| row.id),
+        expires_at: expires_at(&state.config().expiry, req.expires_in_secs),
+        view_once: false,
+    };
+    let stored = store_message_with(
+        Arc::clone(&conn),
+        user_id,
+        None,
+        Some(req.conversation_id),
+        &req.body,
+        req.saved.unwrap_or(false),
+        links,
+    )
+    .await;
+    let Ok(message_id) = stored else {
+        return error("Failed to store message");
+    };
+    // Members who joined after the quoted message get the quote without its text
+    let mut members = row.member_ids();
+    let mut late: Vec<i64> = Vec::new();
+    if let Some(quote) = &quote {
+        let audience = message_participants(conn, quote.message_id)
+            .await
+            .unwrap_or_default();
+        (late, members) = members
+            .into_iter()
+            .partition(|id| audience.binary_search(id).is_err());
+    }
+    let mut event = GroupMessageEvent {
+        conversation_id: req.conversation_id,
+        message_id: Some(message_id),
+        from_user_id: user_id,
+        body: req.body,
+        reply_to: quote.map(reply_to),
+        attachment: attachment.map(attachment_info),
+        expires_at: links.expires_at,
+    };
+    let msg = ClientMessage {
+        command: "group_message".to_string(),
+        data: serde_json::to_string(&event).unwrap(),
+    };
+    fan_out(state, &members, user_id, msg).await;
+    if !late.is_empty() {
+        if let Some(quote) = event.reply_to.as_mut() {
+            quote.snippet.clear();
+        }
+        let msg = ClientMessage {
+            command: "group_message".to_string(),
+            data: serde_json::to_string(&event).unwrap(),
+        };
+        fan_out(state, &late, user_id, msg).await;
+    }
+}
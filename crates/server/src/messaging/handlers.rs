@@ -2,31 +2,95 @@ use rusqlite::Connection;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use crate::models::client_message::ClientMessage;
+use crate::models::client_message::{ClientMessage, ErrorResponse};
 
-use super::models::{DirectMessageEvent, DirectMessageReq};
+use super::attachments::attachment_info;
+use super::expiry::expires_at;
+use super::models::{DirectMessageEvent, DirectMessageReq, ReplyQuote};
 use super::state::AppState;
-use crate::utils::db_utils::store_message;
+use crate::utils::db_utils::{
+    MessageLinks, ReplyQuoteRow, readable_attachment, reply_quote, store_message,
+    store_message_with,
+};
 
+/// Quote shown with a reply in events and history.
+pub fn reply_to(row: ReplyQuoteRow) -> ReplyQuote {
+    ReplyQuote {
+        message_id: row.message_id,
+        from_user_id: row.sender,
+        snippet: row.snippet,
+        deleted: row.deleted,
+    }
+}
+
+/// Persist and deliver a direct message. A `reply_to_id` outside the chat
+/// between the two users, or an `attachment_id` the sender cannot read, is
+/// dropped, and `expires_in_secs` is clamped to the allowed range; callers
+/// report these to the sender first.
 pub async fn send_direct(
     state: Arc<AppState>,
     conn: Arc<Mutex<Connection>>,
     from_user_id: i64,
     req: DirectMessageReq,
 ) -> tokio::io::Result<()> {
+    let quote = match req.reply_to_id {
+        Some(id) => reply_quote(
+            Arc::clone(&conn),
+            id,
+            from_user_id,
+            Some(req.to_user_id),
+            None,
+        )
+        .await
+        .ok()
+        .flatten(),
+        None => None,
+    };
+    let attachment = match req.attachment_id {
+        Some(id) => readable_attachment(Arc::clone(&conn), from_user_id, id)
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let links = MessageLinks {
+        reply_to_id: quote.as_ref().map(|quote| quote.message_id),
+        attachment_id: attachment.as_ref().map(|row| row.id),
+        expires_at: expires_at(&state.config().expiry, req.expires_in_secs),
+        view_once: req.view_once,
+    };
+    let saved = req.saved.unwrap_or(false);
     // Persist the message regardless of recipient online status
-    let _ = store_message(
-        Arc::clone(&conn),
-        from_user_id,
-        req.to_user_id,
-        &req.body,
-        req.saved.unwrap_or(false),
-    )
-    .await;
+    let stored = if links == MessageLinks::default() {
+        store_message(
+            Arc::clone(&conn),
+            from_user_id,
+            req.to_user_id,
+            &req.body,
+            saved,
+        )
+        .await
+    } else {
+        store_message_with(
+            Arc::clone(&conn),
+            from_user_id,
+            Some(req.to_user_id),
+            None,
+            &req.body,
+            saved,
+            links,
+        )
+        .await
+    };
     if let Some(tx) = state.get_sender(req.to_user_id).await {
         let event = DirectMessageEvent {
+            message_id: stored.ok(),
             from_user_id,
             body: req.body,
+            reply_to: quote.map(reply_to),
+            attachment: attachment.map(attachment_info),
+            expires_at: links.expires_at,
+            view_once: links.view_once,
         };
         let msg = ClientMessage {
             command: "message".to_string(),
@@ -37,3 +101,17 @@ pub async fn send_direct(
     }
     Ok(())
 }
+
+/// `error` event with a structured `Forbidden` code: the caller's role does
+/// not allow the command.
+pub fn forbidden(message: String) -> ClientMessage {
+    ClientMessage {
+        command: "error".to_string(),
+        data: serde_json::to_string(&ErrorResponse {
+            code: "Forbidden".to_string(),
+            message,
+            retry_after_ms: None,
+        })
+        .unwrap(),
+    }
+}
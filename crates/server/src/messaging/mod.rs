@@ -1,4 +1,12 @@
+pub mod actions;
+pub mod attachments;
+pub mod bookmarks;
+pub mod channels;
+pub mod expiry;
+pub mod groups;
 pub mod handlers;
+pub mod queue;
+pub mod retention;
 pub mod state;
 
 // Preserve `rura_server::messaging::models::*` path by re-exporting shared models.
@@ -0,0 +1,327 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::models::client_message::ClientMessage;
+
+/// Counters shared by every session queue of the server.
+#[derive(Debug, Default)]
+pub struct QueueMetrics {
+    ephemeral_dropped: AtomicU64,
+    slow_consumer_disconnects: AtomicU64,
+}
+
+/// Point-in-time copy of [`QueueMetrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueMetricsSnapshot {
+    /// Ephemeral events discarded to make room in a full queue.
+    pub ephemeral_dropped: u64,
+    /// Sessions closed because their client stopped keeping up.
+    pub slow_consumer_disconnects: u64,
+}
+
+impl QueueMetrics {
+    pub fn snapshot(&self) -> QueueMetricsSnapshot {
+        QueueMetricsSnapshot {
+            ephemeral_dropped: self.ephemeral_dropped.load(Ordering::Relaxed),
+            slow_consumer_disconnects: self.slow_consumer_disconnects.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_slow_consumer(&self) {
+        self.slow_consumer_disconnects
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Size and patience of a session queue, from `[limits]`.
+#[derive(Debug, Clone, Copy)]
+pub struct QueueLimits {
+    /// Events held before ephemeral ones start being dropped.
+    pub capacity: usize,
+    /// How long a queue may stay full before the session is closed.
+    pub stall_timeout: Duration,
+}
+
+/// The session was closed; nothing more is delivered through this queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError;
+
+struct Entry {
+    msg: Arc<ClientMessage>,
+    ephemeral: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    items: VecDeque<Entry>,
+    full_since: Option<Instant>,
+    closed: bool,
+    overflowed: bool,
+}
+
+struct Shared {
+    inner: Mutex<Inner>,
+    notify: Notify,
+    limits: QueueLimits,
+    metrics: Arc<QueueMetrics>,
+}
+
+/// Producer side of a session's outbound queue; cheap to clone.
+///
+/// When the queue is full, the oldest queued ephemeral event is dropped to
+/// make room. Persisted events are never dropped: if the queue holds nothing
+/// else, it grows up to twice its capacity, and a queue that stays full for
+/// `stall_timeout` (or hits that hard limit) closes the session instead. The
+/// client then catches up on persisted events through history.
+#[derive(Clone)]
+pub struct SessionSender {
+    shared: Arc<Shared>,
+}
+
+/// Consumer side, owned by the connection task.
+pub struct SessionReceiver {
+    shared: Arc<Shared>,
+}
+
+pub fn session_channel(
+    limits: QueueLimits,
+    metrics: Arc<QueueMetrics>,
+) -> (SessionSender, SessionReceiver) {
+    let shared = Arc::new(Shared {
+        inner: Mutex::new(Inner::default()),
+        notify: Notify::new(),
+        limits,
+        metrics,
+    });
+    (
+        SessionSender {
+            shared: Arc::clone(&shared),
+        },
+        SessionReceiver { shared },
+    )
+}
+
+impl SessionSender {
+    /// Queue an event that must reach the client (persisted messages, replies).
+    pub fn send(&self, msg: ClientMessage) -> Result<(), SendError> {
+        self.push(Arc::new(msg), false)
+    }
+
+    /// Like [`Self::send`] for an event fanned out to many sessions: every
+    /// queue holds the same allocation instead of its own copy.
+    pub fn send_shared(&self, msg: Arc<ClientMessage>) -> Result<(), SendError> {
+        self.push(msg, false)
+    }
+
+    /// Queue an event that may be dropped when the client falls behind.
+    pub fn send_ephemeral(&self, msg: ClientMessage) -> Result<(), SendError> {
+        self.push(Arc::new(msg), true)
+    }
+
+    /// Whether both senders feed the same session.
+    pub fn same_channel(&self, other: &SessionSender) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    fn push(&self, msg: Arc<ClientMessage>, ephemeral: bool) -> Result<(), SendError> {
+        let shared = &self.shared;
+        let capacity = shared.limits.capacity.max(1);
+        let mut inner = shared.inner.lock().unwrap();
+        if inner.closed {
+            return Err(SendError);
+        }
+        let mut queued = true;
+        if inner.items.len() >= capacity {
+            let dropped = if let Some(pos) = inner.items.iter().position(|e| e.ephemeral) {
+                inner.items.remove(pos);
+                true
+            } else if ephemeral {
+                queued = false;
+                true
+            } else {
+                // Only persisted events are queued: keep them all
+                false
+            };
+            if dropped {
+                shared
+                    .metrics
+                    .ephemeral_dropped
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if queued {
+            inner.items.push_back(Entry { msg, ephemeral });
+        }
+
+        let now = Instant::now();
+        if inner.items.len() >= capacity {
+            let full_since = *inner.full_since.get_or_insert(now);
+            if inner.items.len() >= capacity * 2
+                || now.duration_since(full_since) >= shared.limits.stall_timeout
+            {
+                close_as_slow(shared, &mut inner);
+            }
+        }
+        drop(inner);
+        shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+fn close_as_slow(shared: &Shared, inner: &mut Inner) {
+    if !inner.overflowed {
+        inner.closed = true;
+        inner.overflowed = true;
+        inner.items.clear();
+        shared.metrics.record_slow_consumer();
+    }
+}
+
+impl SessionReceiver {
+    /// Next queued event. `None` means the session must end, see [`Self::overflowed`].
+    pub async fn recv(&mut self) -> Option<ClientMessage> {
+        self.recv_shared().await.map(Arc::unwrap_or_clone)
+    }
+
+    /// [`Self::recv`] without copying events that other sessions share.
+    pub async fn recv_shared(&mut self) -> Option<Arc<ClientMessage>> {
+        loop {
+            {
+                let mut inner = self.shared.inner.lock().unwrap();
+                if inner.closed {
+                    return None;
+                }
+                if let Some(entry) = inner.items.pop_front() {
+                    if inner.items.len() < self.shared.limits.capacity {
+                        inner.full_since = None;
+                    }
+                    return Some(entry.msg);
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// Close the session because the client stopped reading (e.g. a write
+    /// timed out). Counted once, even if the queue overflowed as well.
+    pub fn close_as_slow(&self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        close_as_slow(&self.shared, &mut inner);
+    }
+
+    /// Whether the queue was closed because the client stopped keeping up.
+    pub fn overflowed(&self) -> bool {
+        self.shared.inner.lock().unwrap().overflowed
+    }
+
+    /// Number of events waiting to be written.
+    pub fn len(&self) -> usize {
+        self.shared.inner.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for SessionReceiver {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.closed = true;
+        inner.items.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(command: &str, data: &str) -> ClientMessage {
+        ClientMessage {
+            command: command.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    fn channel(capacity: usize) -> (SessionSender, SessionReceiver, Arc<QueueMetrics>) {
+        let metrics = Arc::new(QueueMetrics::default());
+        let limits = QueueLimits {
+            capacity,
+            stall_timeout: Duration::from_secs(60),
+        };
+        let (tx, rx) = session_channel(limits, Arc::clone(&metrics));
+        (tx, rx, metrics)
+    }
+
+    async fn drain(rx: &mut SessionReceiver) -> Vec<String> {
+        let mut out = Vec::new();
+        while !rx.is_empty() {
+            out.push(rx.recv().await.unwrap().data);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_oldest_ephemeral_first() {
+        let (tx, mut rx, metrics) = channel(3);
+        tx.send_ephemeral(msg("presence", "e1")).unwrap();
+        tx.send(msg("message", "m1")).unwrap();
+        tx.send_ephemeral(msg("presence", "e2")).unwrap();
+        tx.send(msg("message", "m2")).unwrap();
+        tx.send(msg("message", "m3")).unwrap();
+
+        assert_eq!(drain(&mut rx).await, ["m1", "m2", "m3"]);
+        assert_eq!(metrics.snapshot().ephemeral_dropped, 2);
+
+        // With only persisted events queued, a new ephemeral one is dropped
+        for data in ["m4", "m5", "m6"] {
+            tx.send(msg("message", data)).unwrap();
+        }
+        tx.send_ephemeral(msg("presence", "e3")).unwrap();
+        assert_eq!(drain(&mut rx).await, ["m4", "m5", "m6"]);
+        assert_eq!(metrics.snapshot().ephemeral_dropped, 3);
+    }
+
+    #[tokio::test]
+    async fn persisted_overflow_closes_the_session() {
+        let (tx, mut rx, metrics) = channel(2);
+        for i in 0..3 {
+            tx.send(msg("message", &i.to_string())).unwrap();
+        }
+        assert_eq!(rx.len(), 3, "persisted events are kept past capacity");
+        tx.send(msg("message", "3")).unwrap();
+
+        assert!(rx.recv().await.is_none());
+        assert!(rx.overflowed());
+        assert_eq!(tx.send(msg("message", "4")), Err(SendError));
+        assert_eq!(metrics.snapshot().slow_consumer_disconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn queue_full_for_too_long_closes_the_session() {
+        let metrics = Arc::new(QueueMetrics::default());
+        let limits = QueueLimits {
+            capacity: 1,
+            stall_timeout: Duration::from_millis(20),
+        };
+        let (tx, mut rx) = session_channel(limits, metrics);
+        tx.send(msg("message", "a")).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        tx.send_ephemeral(msg("presence", "b")).unwrap();
+        assert!(rx.recv().await.is_none());
+        assert!(rx.overflowed());
+    }
+
+    #[tokio::test]
+    async fn recv_waits_for_the_next_event() {
+        let (tx, mut rx, _) = channel(4);
+        let reader = tokio::spawn(async move { rx.recv().await.map(|m| m.data) });
+        tokio::task::yield_now().await;
+        tx.send(msg("message", "late")).unwrap();
+        assert_eq!(reader.await.unwrap().as_deref(), Some("late"));
+    }
+}
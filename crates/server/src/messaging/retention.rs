@@ -0,0 +1,77 @@
+use rusqlite::{Connection, Result as SqliteResult};
+use std::sync::{Arc, Mutex};
+
+use super::attachments::release_purged_attachments;
+use crate::models::config::Config;
+use crate::utils::db_utils::{
+    purge_old_auth_events, purge_old_connections, purge_old_login_throttle, purge_old_messages,
+};
+
+/// Rows one run of [`apply_retention`] deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub messages: usize,
+    pub connections: usize,
+    pub auth_events: usize,
+    pub login_throttle: usize,
+}
+
+/// Delete unsaved messages, and connection records and login history older
+/// than the `[retention]` limits allow, `retention.batch_size` rows per
+/// database lock, and release the attachments of the deleted messages.
+/// Nobody is notified, as with `messages purge`.
+pub async fn apply_retention(
+    conn: Arc<Mutex<Connection>>,
+    config: &Config,
+) -> SqliteResult<RetentionReport> {
+    let policy = &config.retention;
+    // One cutoff for the whole run, so batches agree on what is old
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut report = RetentionReport::default();
+    loop {
+        let (deleted, attachment_ids) = purge_old_messages(
+            Arc::clone(&conn),
+            &now,
+            policy.message_days,
+            policy.batch_size,
+        )
+        .await?;
+        report.messages += deleted;
+        release_purged_attachments(Arc::clone(&conn), &config.attachments, &attachment_ids).await;
+        if deleted < policy.batch_size {
+            break;
+        }
+        tokio::task::yield_now().await;
+    }
+    let days = policy.connection_days;
+    report.connections = in_batches(policy.batch_size, || {
+        purge_old_connections(Arc::clone(&conn), &now, days, policy.batch_size)
+    })
+    .await?;
+    report.auth_events = in_batches(policy.batch_size, || {
+        purge_old_auth_events(Arc::clone(&conn), &now, days, policy.batch_size)
+    })
+    .await?;
+    report.login_throttle = in_batches(policy.batch_size, || {
+        purge_old_login_throttle(Arc::clone(&conn), &now, days, policy.batch_size)
+    })
+    .await?;
+    Ok(report)
+}
+
+/// Run `purge` until a batch comes back short; returns the rows deleted.
+async fn in_batches<F, Fut>(batch_size: usize, mut purge: F) -> SqliteResult<usize>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = SqliteResult<usize>>,
+{
+    let mut total = 0;
+    loop {
+        let deleted = purge().await?;
+        total += deleted;
+        if deleted < batch_size {
+            return Ok(total);
+        }
+        tokio::task::yield_now().await;
+    }
+}
@@ -1,20 +1,71 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{RwLock, mpsc};
+use std::time::Duration;
+use tokio::sync::RwLock;
 
-use crate::models::client_message::ClientMessage;
+use super::queue::{
+    QueueLimits, QueueMetrics, QueueMetricsSnapshot, SessionReceiver, SessionSender,
+    session_channel,
+};
+use crate::client::rate_limit::RateLimiter;
+use crate::models::config::Config;
+use crate::utils::shutdown::CancellationToken;
 
 #[derive(Clone)]
 pub struct ClientHandle {
-    pub tx: mpsc::UnboundedSender<ClientMessage>,
+    pub tx: SessionSender,
 }
 
 #[derive(Default)]
 pub struct AppState {
     users: RwLock<HashMap<i64, ClientHandle>>, // user_id -> handle
+    config: Arc<Config>,
+    queue_metrics: Arc<QueueMetrics>,
+    rate_limiter: RateLimiter,
+    shutdown: CancellationToken,
 }
 
 impl AppState {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            users: RwLock::default(),
+            rate_limiter: RateLimiter::new(config.rate_limits.clone()),
+            config,
+            queue_metrics: Arc::default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// A bounded outbound queue for one session, sized by `[limits]`.
+    pub fn outbound_channel(&self) -> (SessionSender, SessionReceiver) {
+        let limits = QueueLimits {
+            capacity: self.config.limits.outbound_queue_len,
+            stall_timeout: Duration::from_secs(self.config.limits.slow_consumer_secs),
+        };
+        session_channel(limits, Arc::clone(&self.queue_metrics))
+    }
+
+    /// Drop and disconnect counters of all outbound queues.
+    pub fn queue_metrics_snapshot(&self) -> QueueMetricsSnapshot {
+        self.queue_metrics.snapshot()
+    }
+
+    /// Cancelled when the server starts shutting down; every session then
+    /// sends `server_shutdown` and closes.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Command rate limits shared by all connections.
+    pub(crate) fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Effective server configuration (defaults when built with `AppState::default()`).
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub async fn register(&self, user_id: i64, handle: ClientHandle) {
         let mut guard = self.users.write().await;
         guard.insert(user_id, handle);
@@ -25,10 +76,51 @@ impl AppState {
         guard.remove(&user_id);
     }
 
-    pub async fn get_sender(&self, user_id: i64) -> Option<mpsc::UnboundedSender<ClientMessage>> {
+    /// Remove `user_id` only while it is still registered with `tx`, so a
+    /// closing connection does not unregister a newer one of the same user.
+    pub async fn unregister_handle(&self, user_id: i64, tx: &SessionSender) {
+        let mut guard = self.users.write().await;
+        if guard
+            .get(&user_id)
+            .is_some_and(|handle| handle.tx.same_channel(tx))
+        {
+            guard.remove(&user_id);
+        }
+    }
+
+    pub async fn get_sender(&self, user_id: i64) -> Option<SessionSender> {
         let guard = self.users.read().await;
         guard.get(&user_id).map(|h| h.tx.clone())
     }
+
+    /// Senders of the online users among `user_ids`, collected under a
+    /// single read lock. Callers build and send their events after the lock
+    /// is released.
+    pub async fn senders_for(&self, user_ids: &[i64]) -> Vec<SessionSender> {
+        let guard = self.users.read().await;
+        user_ids
+            .iter()
+            .filter_map(|id| guard.get(id).map(|h| h.tx.clone()))
+            .collect()
+    }
+
+    /// [`Self::senders_for`] for large audiences; `sorted_ids` must be
+    /// ascending. Walks whichever is smaller, the audience or the online
+    /// users, so the read lock is held for at most one pass over either.
+    pub async fn senders_for_sorted(&self, sorted_ids: &[i64]) -> Vec<SessionSender> {
+        let guard = self.users.read().await;
+        if sorted_ids.len() <= guard.len() {
+            return sorted_ids
+                .iter()
+                .filter_map(|id| guard.get(id).map(|h| h.tx.clone()))
+                .collect();
+        }
+        guard
+            .iter()
+            .filter(|(id, _)| sorted_ids.binary_search(id).is_ok())
+            .map(|(_, h)| h.tx.clone())
+            .collect()
+    }
 }
 
 pub type SharedAppState = Arc<AppState>;
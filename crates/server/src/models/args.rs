@@ -1,16 +1,198 @@
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand};
 
-#[derive(Parser, Debug)]
+/// Command-line arguments. Every serving option can also come from the config
+/// file or `RURA_*` environment variables; flags given here take precedence.
+#[derive(Parser, Debug, Default)]
 #[command(about = "Simple TCP server with configurable port")]
 pub struct Args {
-    #[arg(short, long, default_value_t = 8080)]
-    pub port: u16,
+    /// TOML config file (default: ./rura.toml when present, or $RURA_CONFIG)
+    #[arg(short, long, global = true)]
+    pub config: Option<String>,
 
-    // TLS certificate (PEM). Required: server is TLS-only.
-    #[arg(long, required = true)]
-    pub tls_cert: String,
+    /// Override the port of every configured bind address
+    #[arg(short, long, global = true)]
+    pub port: Option<u16>,
 
-    // TLS private key (PEM; PKCS#8 or RSA). Required: server is TLS-only.
-    #[arg(long, required = true)]
-    pub tls_key: String,
+    /// Address to listen on (repeatable; replaces `server.bind`)
+    #[arg(long, global = true)]
+    pub bind: Vec<String>,
+
+    /// SQLite database file (`database.path`)
+    #[arg(long, global = true)]
+    pub db: Option<String>,
+
+    // TLS certificate (PEM). Required to serve: server is TLS-only.
+    #[arg(long, global = true)]
+    pub tls_cert: Option<String>,
+
+    // TLS private key (PEM; PKCS#8 or RSA). Required to serve: server is TLS-only.
+    #[arg(long, global = true)]
+    pub tls_key: Option<String>,
+
+    /// Override any config key, e.g. `--set history.default_limit=50` (repeatable)
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    pub set: Vec<String>,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the TLS chat server (default when no subcommand is given)
+    Serve,
+    /// Apply pending database schema migrations and exit
+    Migrate(MigrateArgs),
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Maintain stored messages
+    #[command(subcommand)]
+    Messages(MessagesCommand),
+    /// Apply or adjust the message, connection log and auth audit trail retention policy
+    #[command(subcommand)]
+    Retention(RetentionCommand),
+    /// Report attachment storage or reclaim unreferenced files
+    #[command(subcommand)]
+    Attachments(AttachmentsCommand),
+    /// Check or compact the database file
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Print row counts and schema version
+    Stats,
+    /// Generate a local CA and a server certificate for development
+    GenCert(GenCertArgs),
+    /// Issue a client certificate for mutual TLS from the gen-cert CA
+    GenClientCert(GenClientCertArgs),
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct GenCertArgs {
+    /// Directory to write ca.crt, ca.key, server.crt and server.key into
+    #[arg(long, default_value = "certs")]
+    pub out_dir: String,
+    /// Extra DNS name or IP for the server certificate (repeatable)
+    #[arg(long = "name")]
+    pub names: Vec<String>,
+    /// Validity period in days
+    #[arg(long, default_value_t = 365)]
+    pub days: u32,
+    /// Replace existing files in the output directory
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct GenClientCertArgs {
+    /// Subject common name, e.g. the passphrase of the account it will log in as
+    pub name: String,
+    /// Directory holding ca.crt and ca.key; the client files go to <out-dir>/clients
+    #[arg(long, default_value = "certs")]
+    pub out_dir: String,
+    /// Validity period in days
+    #[arg(long, default_value_t = 365)]
+    pub days: u32,
+    /// Replace an existing certificate with the same name
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct MigrateArgs {
+    /// Only list the migrations that would be applied
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommand {
+    /// Create a user (password read from stdin when --password is omitted)
+    Add {
+        passphrase: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// List all users
+    List,
+    /// Prevent a user from logging in
+    Disable { passphrase: String },
+    /// Allow a disabled user to log in again
+    Enable { passphrase: String },
+    /// Replace a user's password (read from stdin when --password is omitted)
+    ResetPassword {
+        passphrase: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Delete a user and every message they sent or received
+    Delete { passphrase: String },
+    /// Let a client certificate log in as this user without a password
+    #[command(group(clap::ArgGroup::new("match").required(true)))]
+    BindCert {
+        passphrase: String,
+        /// SHA-256 fingerprint of the certificate (hex, colons optional)
+        #[arg(long, group = "match")]
+        fingerprint: Option<String>,
+        /// Subject common name; any certificate from the client CA with this CN matches
+        #[arg(long, group = "match")]
+        subject: Option<String>,
+        /// PEM certificate file to take the fingerprint from
+        #[arg(long, group = "match")]
+        cert: Option<String>,
+    },
+    /// Remove every client certificate binding of a user
+    UnbindCert { passphrase: String },
+    /// Lift a login lockout or back-off for a passphrase
+    Unlock {
+        passphrase: String,
+        /// Also clear the back-off of this client IP
+        #[arg(long)]
+        ip: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MessagesCommand {
+    /// Delete messages older than a date (saved messages are kept unless --include-saved)
+    Purge {
+        /// RFC 3339 timestamp or YYYY-MM-DD (local midnight)
+        #[arg(long)]
+        before: String,
+        #[arg(long)]
+        include_saved: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RetentionCommand {
+    /// Delete unsaved messages, connection records, auth events and idle login throttle records past their retention period
+    Run {
+        /// Only report what would be deleted, per group
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Keep a group's unsaved messages for DAYS instead of `retention.message_days` (0 = forever)
+    Set { conversation_id: i64, days: u32 },
+    /// Make a group follow `retention.message_days` again
+    Unset { conversation_id: i64 },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AttachmentsCommand {
+    /// Show storage per user against the quota, and the space shared content saves
+    Usage,
+    /// Remove unsent or no longer sent attachments past the grace period and unused files
+    Gc,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Run SQLite integrity and foreign key checks
+    Check,
+    /// Rebuild the database file to reclaim free space
+    Vacuum,
 }
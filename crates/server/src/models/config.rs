@@ -0,0 +1,1036 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use super::args::Args;
+
+/// Config file read when neither `--config` nor `RURA_CONFIG` is given.
+pub const DEFAULT_CONFIG_PATH: &str = "rura.toml";
+
+/// Prefix of environment variables that override config keys,
+/// e.g. `RURA_DATABASE_PATH` overrides `database.path`.
+pub const ENV_PREFIX: &str = "RURA_";
+
+/// Room left on a line for the envelope around a base64 attachment chunk.
+const CHUNK_ENVELOPE_BYTES: usize = 512;
+/// Largest `attachments.thumbnail_px`; thumbnails are meant to be small.
+const MAX_THUMBNAIL_PX: u32 = 2048;
+
+/// Effective server configuration. Sources are applied in order:
+/// built-in defaults, TOML config file, `RURA_*` environment variables, CLI flags.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSection,
+    pub database: DatabaseSection,
+    pub tls: TlsSection,
+    pub limits: LimitsSection,
+    pub history: HistorySection,
+    pub attachments: AttachmentsSection,
+    pub expiry: ExpirySection,
+    pub retention: RetentionSection,
+    pub timeouts: TimeoutsSection,
+    pub rate_limits: RateLimitsSection,
+    pub login: LoginSection,
+    pub shutdown: ShutdownSection,
+    pub logging: LoggingSection,
+    pub features: FeaturesSection,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// Socket addresses to listen on; one listener is started per entry.
+    pub bind: Vec<String>,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            bind: vec!["0.0.0.0:8080".to_string()],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+    pub path: String,
+}
+
+impl Default for DatabaseSection {
+    fn default() -> Self {
+        Self {
+            path: crate::utils::db_utils::DEFAULT_DB_PATH.to_string(),
+        }
+    }
+}
+
+/// Empty paths mean "not configured"; serving requires both.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    pub cert: String,
+    pub key: String,
+    /// Seconds between checks for changed cert/key files; 0 disables polling
+    /// (SIGHUP still triggers a reload).
+    pub reload_interval_secs: u64,
+    /// CA used to verify client certificates; empty disables mutual TLS.
+    pub client_ca: String,
+    /// Refuse handshakes without a client certificate (needs `client_ca`).
+    pub require_client_cert: bool,
+    /// Oldest protocol version accepted.
+    pub min_version: TlsVersion,
+    /// Cipher suites to offer, by IANA name; empty keeps the rustls defaults.
+    pub cipher_suites: Vec<String>,
+    /// Issue stateless session tickets so clients can resume cheaply.
+    pub session_tickets: bool,
+    /// Drop clients that do not negotiate the `rura/1` ALPN protocol.
+    pub require_alpn: bool,
+    /// Extra certificates chosen by the SNI name the client asks for;
+    /// `cert`/`key` above serve every other name.
+    pub sni: Vec<SniCertificate>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SniCertificate {
+    /// Host name, or `*.example.com` for any single-label subdomain.
+    pub name: String,
+    pub cert: String,
+    pub key: String,
+}
+
+impl Default for TlsSection {
+    fn default() -> Self {
+        Self {
+            cert: String::new(),
+            key: String::new(),
+            reload_interval_secs: 5,
+            client_ca: String::new(),
+            require_client_cert: false,
+            min_version: TlsVersion::default(),
+            cipher_suites: Vec::new(),
+            session_tickets: false,
+            require_alpn: false,
+            sni: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    /// Largest accepted direct message body, in bytes.
+    pub max_body_bytes: usize,
+    /// Upper bound applied to the `limit` of `history` requests.
+    pub max_history_limit: usize,
+    /// Largest number of members a group conversation may have.
+    pub max_group_members: usize,
+    /// Seconds after sending during which the sender may edit a message.
+    pub edit_window_secs: u64,
+    /// Seconds after sending during which the sender may delete a message
+    /// for everyone.
+    pub delete_window_secs: u64,
+    /// Events queued per session before ephemeral ones are dropped.
+    pub outbound_queue_len: usize,
+    /// Seconds a session's queue (or a single write) may stay stuck before
+    /// the client is disconnected as too slow.
+    pub slow_consumer_secs: u64,
+    /// Longest accepted line from a client, in bytes; longer ones close the
+    /// connection.
+    pub max_line_bytes: usize,
+}
+
+impl Default for LimitsSection {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 4096,
+            max_history_limit: 1000,
+            max_group_members: 256,
+            edit_window_secs: 900,
+            delete_window_secs: 3600,
+            outbound_queue_len: 256,
+            slow_consumer_secs: 10,
+            max_line_bytes: 128 * 1024,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistorySection {
+    /// Number of messages returned when a `history` request omits `limit`.
+    pub default_limit: usize,
+}
+
+impl Default for HistorySection {
+    fn default() -> Self {
+        Self { default_limit: 100 }
+    }
+}
+
+/// Files uploaded with `upload_begin`/`upload_chunk`/`upload_finish`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentsSection {
+    /// Directory holding uploaded files; created on first upload.
+    pub dir: String,
+    /// Largest accepted attachment, in bytes.
+    pub max_bytes: u64,
+    /// Largest chunk accepted by `upload_chunk` and returned by
+    /// `download_chunk`, in bytes before base64 encoding.
+    pub chunk_bytes: usize,
+    /// Total declared size of the attachments each user may keep, pending
+    /// uploads included. 0 disables the quota.
+    pub quota_bytes: u64,
+    /// How long an upload may go without being attached to a message, and
+    /// how long a stalled upload is kept, before it is collected.
+    pub orphan_grace_secs: u64,
+    /// Collect unreferenced attachments and blobs this often. 0 disables the
+    /// background collector; `rura_server attachments gc` still works.
+    pub gc_interval_secs: u64,
+    /// Longest side of the thumbnails made for JPEG and PNG uploads, in
+    /// pixels. 0 disables thumbnails; dimensions are still recorded.
+    pub thumbnail_px: u32,
+    /// Images with more pixels than this get no thumbnail, bounding the
+    /// memory and CPU spent decoding one.
+    pub image_max_pixels: u64,
+}
+
+impl Default for AttachmentsSection {
+    fn default() -> Self {
+        Self {
+            dir: "attachments".to_string(),
+            max_bytes: 25 * 1024 * 1024,
+            chunk_bytes: 48 * 1024,
+            quota_bytes: 1024 * 1024 * 1024,
+            orphan_grace_secs: 24 * 60 * 60,
+            gc_interval_secs: 60 * 60,
+            thumbnail_px: 320,
+            image_max_pixels: 40_000_000,
+        }
+    }
+}
+
+/// Disappearing messages: sends with `expires_in_secs` or `view_once`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpirySection {
+    /// Largest `expires_in_secs` a send may ask for.
+    pub max_expires_in_secs: u64,
+    /// Delete expired messages this often. 0 disables the sweeper; expired
+    /// messages are hidden from everyone either way.
+    pub sweep_interval_secs: u64,
+}
+
+impl Default for ExpirySection {
+    fn default() -> Self {
+        Self {
+            max_expires_in_secs: 30 * 24 * 60 * 60,
+            sweep_interval_secs: 10,
+        }
+    }
+}
+
+/// Age limits for stored messages and connection records. A limit of 0
+/// keeps rows forever.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionSection {
+    /// Delete unsaved messages older than this many days. Groups can
+    /// override it with `rura_server retention set`.
+    pub message_days: u32,
+    /// Delete `connections` and `auth_events` rows older than this many days,
+    /// and `login_throttle` counters idle for as long. This erases the auth
+    /// audit trail along with the connection log.
+    pub connection_days: u32,
+    /// Apply the policy this often. 0 leaves it to `rura_server retention run`.
+    pub interval_secs: u64,
+    /// Rows deleted per database lock.
+    pub batch_size: usize,
+}
+
+impl Default for RetentionSection {
+    fn default() -> Self {
+        Self {
+            message_days: 0,
+            connection_days: 0,
+            interval_secs: 3600,
+            batch_size: 500,
+        }
+    }
+}
+
+/// Connection liveness. A value of 0 disables the corresponding timer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsSection {
+    /// Close connections that have not logged in this many seconds after connecting.
+    /// The TLS handshake gets the same limit, before the login clock starts.
+    pub auth_secs: u64,
+    /// Close connections that sent nothing (not even a `pong`) for this long.
+    pub idle_secs: u64,
+    /// Send a `ping` after this many seconds without hearing from the client.
+    pub ping_interval_secs: u64,
+}
+
+impl Default for TimeoutsSection {
+    fn default() -> Self {
+        Self {
+            auth_secs: 10,
+            idle_secs: 90,
+            ping_interval_secs: 30,
+        }
+    }
+}
+
+/// Token buckets per command class, keyed by user id once logged in and by
+/// client IP before that.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitsSection {
+    /// Close a connection after this many rejected commands within a minute (0 = never).
+    pub disconnect_after: u32,
+    /// `login` and `register`.
+    pub auth: RateLimit,
+    /// `message`.
+    pub message: RateLimit,
+    /// `history`.
+    pub history: RateLimit,
+    /// `upload_chunk` and `download_chunk`.
+    pub transfer: RateLimit,
+    /// Every other command, including unknown ones.
+    pub other: RateLimit,
+}
+
+impl Default for RateLimitsSection {
+    fn default() -> Self {
+        Self {
+            disconnect_after: 20,
+            auth: RateLimit::new(5, 10),
+            message: RateLimit::new(30, 120),
+            history: RateLimit::new(10, 30),
+            transfer: RateLimit::new(60, 1200),
+            other: RateLimit::new(30, 120),
+        }
+    }
+}
+
+/// Back-off and lockout after failed password logins, tracked per account
+/// (the passphrase as typed) and per client IP.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginSection {
+    /// Consecutive failures allowed before back-off starts.
+    pub free_failures: u32,
+    /// First back-off delay; it doubles with every further failure.
+    pub backoff_base_secs: u64,
+    /// Upper bound of the back-off delay.
+    pub backoff_max_secs: u64,
+    /// Lock an account after this many consecutive failures (0 = never).
+    pub lockout_after: u32,
+    /// How long a lockout lasts unless an admin lifts it with `user unlock`.
+    pub lockout_secs: u64,
+    /// Record logins, failures and lockouts in the `auth_events` table.
+    pub event_log: bool,
+}
+
+impl Default for LoginSection {
+    fn default() -> Self {
+        Self {
+            free_failures: 3,
+            backoff_base_secs: 1,
+            backoff_max_secs: 300,
+            lockout_after: 10,
+            lockout_secs: 900,
+            event_log: true,
+        }
+    }
+}
+
+/// Behaviour on SIGTERM/SIGINT.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownSection {
+    /// Seconds open sessions get to finish in-flight commands and flush
+    /// queued events before they are cut off.
+    pub drain_secs: u64,
+    /// Base reconnect hint sent in `server_shutdown`; each session gets a
+    /// value between this and twice this.
+    pub reconnect_after_secs: u64,
+}
+
+impl Default for ShutdownSection {
+    fn default() -> Self {
+        Self {
+            drain_secs: 10,
+            reconnect_after_secs: 5,
+        }
+    }
+}
+
+/// A single token bucket. `burst = 0` disables the limit.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Commands accepted back to back from a full bucket.
+    pub burst: u32,
+    /// Tokens refilled per minute.
+    pub per_minute: u32,
+}
+
+impl RateLimit {
+    pub const fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    pub level: LogLevel,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesSection {
+    /// Accept `register` commands from new users.
+    pub registration: bool,
+    /// Record every incoming connection in the `connections` table.
+    pub connection_log: bool,
+}
+
+impl Default for FeaturesSection {
+    fn default() -> Self {
+        Self {
+            registration: true,
+            connection_log: true,
+        }
+    }
+}
+
+/// A configuration problem attributed to a single dotted key such as `tls.cert`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid `{}`: {}", self.key, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Build the effective configuration from the CLI arguments, the config
+    /// file they point at, and the process environment.
+    pub fn load(args: &Args) -> Result<Self, ConfigError> {
+        let explicit = args
+            .config
+            .clone()
+            .or_else(|| std::env::var(format!("{ENV_PREFIX}CONFIG")).ok());
+        let mut config = match &explicit {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            None => Self::default(),
+        };
+        config.apply_env(std::env::vars())?;
+        config.apply_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path).map_err(|e| {
+            ConfigError::new("config", format!("cannot read {}: {e}", path.display()))
+        })?;
+        Self::from_toml_str(&raw)
+    }
+
+    /// Parse a TOML document on top of the defaults. Each key is merged
+    /// separately so errors can name the key that caused them.
+    pub fn from_toml_str(raw: &str) -> Result<Self, ConfigError> {
+        let doc: toml::Table = raw
+            .parse()
+            .map_err(|e: toml::de::Error| ConfigError::new("config", e.message()))?;
+        let mut config = Self::default();
+        for (key, value) in flatten(&doc, "") {
+            config.set_value(&key, value)?;
+        }
+        Ok(config)
+    }
+
+    /// Apply `RURA_<SECTION>_<KEY>` overrides from the given environment.
+    pub fn apply_env<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let keys = self.keys();
+        for (name, raw) in vars {
+            if let Some(key) = keys.iter().find(|k| env_var_name(k) == name) {
+                self.set_raw(key, &raw)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: &Args) -> Result<(), ConfigError> {
+        if !args.bind.is_empty() {
+            self.server.bind = args.bind.clone();
+        }
+        if let Some(port) = args.port {
+            for addr in &mut self.server.bind {
+                let parsed: SocketAddr = addr.parse().map_err(|e| {
+                    ConfigError::new(
+                        "server.bind",
+                        format!("`{addr}` is not a socket address: {e}"),
+                    )
+                })?;
+                *addr = SocketAddr::new(parsed.ip(), port).to_string();
+            }
+        }
+        if let Some(path) = &args.db {
+            self.database.path = path.clone();
+        }
+        if let Some(cert) = &args.tls_cert {
+            self.tls.cert = cert.clone();
+        }
+        if let Some(key) = &args.tls_key {
+            self.tls.key = key.clone();
+        }
+        for pair in &args.set {
+            let (key, raw) = pair
+                .split_once('=')
+                .ok_or_else(|| ConfigError::new(pair.as_str(), "expected KEY=VALUE"))?;
+            self.set_raw(key.trim(), raw.trim())?;
+        }
+        Ok(())
+    }
+
+    /// Every dotted key this configuration understands.
+    pub fn keys(&self) -> Vec<String> {
+        flatten(&self.to_table(), "")
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Set a key from its textual form (environment variable or `--set`),
+    /// interpreted according to the type of the current value.
+    pub fn set_raw(&mut self, key: &str, raw: &str) -> Result<(), ConfigError> {
+        let current = lookup(&self.to_table(), key)
+            .cloned()
+            .ok_or_else(|| ConfigError::new(key, "unknown configuration key"))?;
+        let value = match current {
+            toml::Value::String(_) => toml::Value::String(raw.to_string()),
+            toml::Value::Array(_) if !raw.starts_with('[') => toml::Value::Array(
+                raw.split(',')
+                    .map(|s| s.trim())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| toml::Value::String(s.to_string()))
+                    .collect(),
+            ),
+            _ => parse_inline_value(raw).map_err(|message| ConfigError::new(key, message))?,
+        };
+        self.set_value(key, value)
+    }
+
+    fn set_value(&mut self, key: &str, value: toml::Value) -> Result<(), ConfigError> {
+        let mut root = self.to_table();
+        let slot = lookup_mut(&mut root, key)
+            .ok_or_else(|| ConfigError::new(key, "unknown configuration key"))?;
+        *slot = value;
+        *self = toml::Value::Table(root)
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::new(key, e.message()))?;
+        Ok(())
+    }
+
+    fn to_table(&self) -> toml::Table {
+        toml::Table::try_from(self).expect("config always serializes to a TOML table")
+    }
+
+    /// Checks that hold for every subcommand.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.bind.is_empty() {
+            return Err(ConfigError::new(
+                "server.bind",
+                "at least one address is required",
+            ));
+        }
+        for addr in &self.server.bind {
+            addr.parse::<SocketAddr>().map_err(|e| {
+                ConfigError::new(
+                    "server.bind",
+                    format!("`{addr}` is not a socket address: {e}"),
+                )
+            })?;
+        }
+        if self.tls.require_client_cert && self.tls.client_ca.trim().is_empty() {
+            return Err(ConfigError::new(
+                "tls.require_client_cert",
+                "requires tls.client_ca to be set",
+            ));
+        }
+        crate::utils::tls::validate_policy(&self.tls)?;
+        if self.database.path.trim().is_empty() {
+            return Err(ConfigError::new("database.path", "must not be empty"));
+        }
+        if self.limits.max_body_bytes == 0 {
+            return Err(ConfigError::new(
+                "limits.max_body_bytes",
+                "must be greater than 0",
+            ));
+        }
+        if self.limits.max_history_limit == 0 {
+            return Err(ConfigError::new(
+                "limits.max_history_limit",
+                "must be greater than 0",
+            ));
+        }
+        if self.limits.max_group_members < 2 {
+            return Err(ConfigError::new(
+                "limits.max_group_members",
+                "must be at least 2",
+            ));
+        }
+        if self.limits.edit_window_secs == 0 {
+            return Err(ConfigError::new(
+                "limits.edit_window_secs",
+                "must be greater than 0",
+            ));
+        }
+        if self.limits.delete_window_secs == 0 {
+            return Err(ConfigError::new(
+                "limits.delete_window_secs",
+                "must be greater than 0",
+            ));
+        }
+        if self.limits.outbound_queue_len == 0 {
+            return Err(ConfigError::new(
+                "limits.outbound_queue_len",
+                "must be greater than 0",
+            ));
+        }
+        if self.limits.slow_consumer_secs == 0 {
+            return Err(ConfigError::new(
+                "limits.slow_consumer_secs",
+                "must be greater than 0",
+            ));
+        }
+        if self.limits.max_line_bytes == 0 {
+            return Err(ConfigError::new(
+                "limits.max_line_bytes",
+                "must be greater than 0",
+            ));
+        }
+        if self.attachments.dir.trim().is_empty() {
+            return Err(ConfigError::new("attachments.dir", "must not be empty"));
+        }
+        if self.attachments.max_bytes == 0 {
+            return Err(ConfigError::new(
+                "attachments.max_bytes",
+                "must be greater than 0",
+            ));
+        }
+        // A base64 chunk plus its envelope has to fit on one line
+        let chunk_line = self.attachments.chunk_bytes.div_ceil(3) * 4 + CHUNK_ENVELOPE_BYTES;
+        if self.attachments.chunk_bytes == 0 || chunk_line > self.limits.max_line_bytes {
+            return Err(ConfigError::new(
+                "attachments.chunk_bytes",
+                format!(
+                    "must be greater than 0 and fit base64-encoded within limits.max_line_bytes ({})",
+                    self.limits.max_line_bytes
+                ),
+            ));
+        }
+        if self.attachments.orphan_grace_secs == 0 {
+            return Err(ConfigError::new(
+                "attachments.orphan_grace_secs",
+                "must be greater than 0",
+            ));
+        }
+        if self.attachments.thumbnail_px > MAX_THUMBNAIL_PX {
+            return Err(ConfigError::new(
+                "attachments.thumbnail_px",
+                format!("must be at most {MAX_THUMBNAIL_PX}"),
+            ));
+        }
+        // Expiry times are stored as i64 Unix seconds
+        if self.expiry.max_expires_in_secs == 0 || self.expiry.max_expires_in_secs > i32::MAX as u64
+        {
+            return Err(ConfigError::new(
+                "expiry.max_expires_in_secs",
+                format!("must be between 1 and {}", i32::MAX),
+            ));
+        }
+        if self.retention.batch_size == 0 {
+            return Err(ConfigError::new(
+                "retention.batch_size",
+                "must be greater than 0",
+            ));
+        }
+        if self.history.default_limit == 0
+            || self.history.default_limit > self.limits.max_history_limit
+        {
+            return Err(ConfigError::new(
+                "history.default_limit",
+                format!(
+                    "must be between 1 and limits.max_history_limit ({})",
+                    self.limits.max_history_limit
+                ),
+            ));
+        }
+        let timeouts = &self.timeouts;
+        if timeouts.idle_secs > 0
+            && (timeouts.ping_interval_secs == 0
+                || timeouts.ping_interval_secs >= timeouts.idle_secs)
+        {
+            return Err(ConfigError::new(
+                "timeouts.ping_interval_secs",
+                format!(
+                    "must be between 1 and timeouts.idle_secs ({}) so quiet clients are pinged before being dropped",
+                    timeouts.idle_secs
+                ),
+            ));
+        }
+        if self.login.backoff_base_secs > self.login.backoff_max_secs {
+            return Err(ConfigError::new(
+                "login.backoff_base_secs",
+                format!(
+                    "must not exceed login.backoff_max_secs ({})",
+                    self.login.backoff_max_secs
+                ),
+            ));
+        }
+        if self.login.lockout_after > 0 && self.login.lockout_secs == 0 {
+            return Err(ConfigError::new(
+                "login.lockout_secs",
+                "must be greater than 0 while lockout is enabled (set lockout_after = 0 to disable)",
+            ));
+        }
+        let rate_limits = &self.rate_limits;
+        for (class, limit) in [
+            ("auth", rate_limits.auth),
+            ("message", rate_limits.message),
+            ("history", rate_limits.history),
+            ("transfer", rate_limits.transfer),
+            ("other", rate_limits.other),
+        ] {
+            if limit.burst > 0 && limit.per_minute == 0 {
+                return Err(ConfigError::new(
+                    format!("rate_limits.{class}.per_minute"),
+                    "must be greater than 0 while the class is limited (set burst = 0 to disable)",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Additional checks for `serve`: the server is TLS-only.
+    pub fn require_tls(&self) -> Result<(), ConfigError> {
+        if self.tls.cert.trim().is_empty() {
+            return Err(ConfigError::new(
+                "tls.cert",
+                "a certificate path is required (--tls-cert)",
+            ));
+        }
+        if self.tls.key.trim().is_empty() {
+            return Err(ConfigError::new(
+                "tls.key",
+                "a private key path is required (--tls-key)",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Render the configuration as TOML, as printed by `--print-config`.
+    pub fn to_toml_string(&self) -> String {
+        toml::to_string_pretty(self).expect("config always serializes to TOML")
+    }
+}
+
+/// Environment variable that overrides `key`, e.g. `tls.cert` -> `RURA_TLS_CERT`.
+pub fn env_var_name(key: &str) -> String {
+    format!("{ENV_PREFIX}{}", key.replace('.', "_").to_uppercase())
+}
+
+// Leaf values of a table keyed by their dotted path. Arrays are leaves.
+fn flatten(table: &toml::Table, prefix: &str) -> Vec<(String, toml::Value)> {
+    let mut out = Vec::new();
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{prefix}.{name}")
+        };
+        match value {
+            toml::Value::Table(inner) => out.extend(flatten(inner, &key)),
+            other => out.push((key, other.clone())),
+        }
+    }
+    out
+}
+
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let (head, rest) = match key.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (key, None),
+    };
+    match (table.get(head)?, rest) {
+        (toml::Value::Table(inner), Some(rest)) => lookup(inner, rest),
+        (toml::Value::Table(_), None) => None,
+        (value, None) => Some(value),
+        (_, Some(_)) => None,
+    }
+}
+
+fn lookup_mut<'a>(table: &'a mut toml::Table, key: &str) -> Option<&'a mut toml::Value> {
+    let (head, rest) = match key.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (key, None),
+    };
+    match (table.get_mut(head)?, rest) {
+        (toml::Value::Table(inner), Some(rest)) => lookup_mut(inner, rest),
+        (toml::Value::Table(_), None) => None,
+        (value, None) => Some(value),
+        (_, Some(_)) => None,
+    }
+}
+
+fn parse_inline_value(raw: &str) -> Result<toml::Value, String> {
+    let doc: toml::Table = format!("value = {raw}")
+        .parse()
+        .map_err(|_| format!("`{raw}` is not a valid value"))?;
+    doc.get("value")
+        .cloned()
+        .ok_or_else(|| format!("`{raw}` is not a valid value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid_and_round_trip() {
+        let config = Config::default();
+        config.validate().expect("defaults must validate");
+        let parsed = Config::from_toml_str(&config.to_toml_string()).expect("round trip");
+        assert_eq!(parsed.server.bind, config.server.bind);
+        assert_eq!(parsed.database.path, "rura.db");
+    }
+
+    #[test]
+    fn file_values_override_defaults() {
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            bind = ["127.0.0.1:9000", "[::1]:9000"]
+
+            [database]
+            path = "/var/lib/rura/rura.db"
+
+            [history]
+            default_limit = 20
+            "#,
+        )
+        .expect("parse");
+        assert_eq!(config.server.bind.len(), 2);
+        assert_eq!(config.database.path, "/var/lib/rura/rura.db");
+        assert_eq!(config.history.default_limit, 20);
+        // Untouched keys keep their defaults
+        assert_eq!(config.limits.max_body_bytes, 4096);
+    }
+
+    #[test]
+    fn unknown_and_mistyped_keys_are_named() {
+        let err = Config::from_toml_str("[database]\nfile = \"x.db\"\n").unwrap_err();
+        assert_eq!(err.key, "database.file");
+
+        let err = Config::from_toml_str("[limits]\nmax_body_bytes = \"big\"\n").unwrap_err();
+        assert_eq!(err.key, "limits.max_body_bytes");
+
+        let err = Config::from_toml_str("[logging]\nlevel = \"loud\"\n").unwrap_err();
+        assert_eq!(err.key, "logging.level");
+    }
+
+    #[test]
+    fn env_overrides_use_prefixed_names() {
+        let mut config = Config::default();
+        config
+            .apply_env(vec![
+                ("RURA_DATABASE_PATH".to_string(), "env.db".to_string()),
+                (
+                    "RURA_FEATURES_REGISTRATION".to_string(),
+                    "false".to_string(),
+                ),
+                (
+                    "RURA_SERVER_BIND".to_string(),
+                    "127.0.0.1:1,127.0.0.1:2".to_string(),
+                ),
+                ("UNRELATED".to_string(), "ignored".to_string()),
+            ])
+            .expect("env");
+        assert_eq!(config.database.path, "env.db");
+        assert!(!config.features.registration);
+        assert_eq!(config.server.bind, vec!["127.0.0.1:1", "127.0.0.1:2"]);
+
+        let err = config
+            .apply_env(vec![(
+                "RURA_LIMITS_MAX_HISTORY_LIMIT".to_string(),
+                "lots".to_string(),
+            )])
+            .unwrap_err();
+        assert_eq!(err.key, "limits.max_history_limit");
+    }
+
+    #[test]
+    fn validation_names_offending_key() {
+        let mut config = Config::default();
+        config.history.default_limit = config.limits.max_history_limit + 1;
+        assert_eq!(config.validate().unwrap_err().key, "history.default_limit");
+
+        let mut config = Config::default();
+        config.server.bind = vec!["not-an-address".to_string()];
+        assert_eq!(config.validate().unwrap_err().key, "server.bind");
+
+        let mut config = Config::default();
+        config.tls.require_client_cert = true;
+        assert_eq!(
+            config.validate().unwrap_err().key,
+            "tls.require_client_cert"
+        );
+
+        let mut config = Config::default();
+        config.timeouts.ping_interval_secs = config.timeouts.idle_secs;
+        assert_eq!(
+            config.validate().unwrap_err().key,
+            "timeouts.ping_interval_secs"
+        );
+
+        // A partial class table keeps the other field's default
+        let mut config = Config::from_toml_str("[rate_limits.history]\nper_minute = 0\n").unwrap();
+        assert_eq!(config.rate_limits.history.burst, 10);
+        assert_eq!(
+            config.validate().unwrap_err().key,
+            "rate_limits.history.per_minute"
+        );
+        config.rate_limits.history.burst = 0;
+        config
+            .validate()
+            .expect("disabled class needs no refill rate");
+
+        // Base64 chunks must fit on one protocol line
+        let mut config = Config::default();
+        config.attachments.chunk_bytes = config.limits.max_line_bytes;
+        assert_eq!(
+            config.validate().unwrap_err().key,
+            "attachments.chunk_bytes"
+        );
+
+        let mut config = Config::default();
+        config.attachments.thumbnail_px = MAX_THUMBNAIL_PX + 1;
+        assert_eq!(
+            config.validate().unwrap_err().key,
+            "attachments.thumbnail_px"
+        );
+
+        let mut config = Config::default();
+        config.expiry.max_expires_in_secs = 0;
+        assert_eq!(
+            config.validate().unwrap_err().key,
+            "expiry.max_expires_in_secs"
+        );
+
+        let mut config = Config::default();
+        config.retention.batch_size = 0;
+        assert_eq!(config.validate().unwrap_err().key, "retention.batch_size");
+
+        let config = Config::default();
+        assert_eq!(config.require_tls().unwrap_err().key, "tls.cert");
+    }
+
+    #[test]
+    fn tls_policy_is_parsed_and_validated() {
+        let config = Config::from_toml_str(
+            r#"
+            [tls]
+            min_version = "1.3"
+            cipher_suites = ["TLS13_AES_256_GCM_SHA384"]
+            session_tickets = true
+
+            [[tls.sni]]
+            name = "chat.example.com"
+            cert = "chat.crt"
+            key = "chat.key"
+            "#,
+        )
+        .expect("parse");
+        config.validate().expect("valid policy");
+        assert_eq!(config.tls.min_version, TlsVersion::Tls13);
+        assert_eq!(config.tls.sni[0].name, "chat.example.com");
+        let reparsed = Config::from_toml_str(&config.to_toml_string()).expect("round trip");
+        assert_eq!(reparsed.tls.sni, config.tls.sni);
+
+        let err = Config::from_toml_str("[tls]\nmin_version = \"1.1\"\n").unwrap_err();
+        assert_eq!(err.key, "tls.min_version");
+
+        let mut config = Config::default();
+        config.tls.cipher_suites = vec!["TLS_RSA_WITH_RC4_128_MD5".to_string()];
+        assert_eq!(config.validate().unwrap_err().key, "tls.cipher_suites");
+
+        config.tls.cipher_suites = vec!["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256".to_string()];
+        config
+            .validate()
+            .expect("TLS 1.2 suite with the default minimum");
+        config.tls.min_version = TlsVersion::Tls13;
+        assert_eq!(config.validate().unwrap_err().key, "tls.cipher_suites");
+
+        let mut config = Config::default();
+        let entry = SniCertificate {
+            name: "chat.example.com".to_string(),
+            cert: "a.crt".to_string(),
+            key: "a.key".to_string(),
+        };
+        config.tls.sni = vec![entry.clone(), entry];
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.key, "tls.sni");
+        assert!(err.message.contains("more than once"));
+    }
+}
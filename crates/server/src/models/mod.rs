@@ -1,4 +1,5 @@
 pub mod args;
+pub mod config;
 
 // Re-export protocol models from the shared crate to use paths like
 // `rura_server::models::client_message::ClientMessage` in integration tests.
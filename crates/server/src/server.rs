@@ -0,0 +1,123 @@
+use rusqlite::Connection;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+
+use crate::client::handle_client_with_cert;
+use crate::messaging::state::AppState;
+use crate::models::config::LogLevel;
+use crate::utils::logging;
+use crate::utils::tls::{client_cert_identity, negotiated_rura_alpn};
+
+/// Accept TLS clients on `listener` until the state's shutdown token is
+/// cancelled. Then stop accepting and give open sessions up to
+/// `shutdown.drain_secs` to say goodbye before cutting them off.
+pub async fn accept_loop(
+    listener: TcpListener,
+    tls_acceptor: TlsAcceptor,
+    conn: Arc<Mutex<Connection>>,
+    state: Arc<AppState>,
+) -> tokio::io::Result<()> {
+    let shutdown = state.shutdown_token();
+    let local_addr = listener.local_addr()?;
+    let mut sessions = JoinSet::new();
+    loop {
+        select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => {
+                let (stream, client_addr) = accepted?;
+                sessions.spawn(serve_connection(
+                    stream,
+                    client_addr,
+                    tls_acceptor.clone(),
+                    Arc::clone(&conn),
+                    Arc::clone(&state),
+                ));
+            },
+            // Reap finished sessions so the set only tracks open ones
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+        }
+    }
+    drop(listener);
+
+    let deadline = Duration::from_secs(state.config().shutdown.drain_secs);
+    if logging::enabled(LogLevel::Info) {
+        println!(
+            "Stopped accepting on {}; draining {} session(s) for up to {}s",
+            local_addr,
+            sessions.len(),
+            deadline.as_secs()
+        );
+    }
+    let drained = tokio::time::timeout(deadline, async {
+        while sessions.join_next().await.is_some() {}
+    })
+    .await
+    .is_ok();
+    if !drained {
+        eprintln!(
+            "Closing {} session(s) on {} that did not finish within {}s",
+            sessions.len(),
+            local_addr,
+            deadline.as_secs()
+        );
+        sessions.abort_all();
+        while sessions.join_next().await.is_some() {}
+    }
+    Ok(())
+}
+
+async fn serve_connection(
+    stream: tokio::net::TcpStream,
+    client_addr: SocketAddr,
+    acceptor: TlsAcceptor,
+    conn: Arc<Mutex<Connection>>,
+    state: Arc<AppState>,
+) {
+    // The handshake counts against `timeouts.auth_secs` too, so a client
+    // that connects and never speaks TLS does not hold its socket forever
+    let auth_secs = state.config().timeouts.auth_secs;
+    let accepted = if auth_secs == 0 {
+        acceptor.accept(stream).await
+    } else {
+        match tokio::time::timeout(Duration::from_secs(auth_secs), acceptor.accept(stream)).await {
+            Ok(accepted) => accepted,
+            Err(_) => {
+                if logging::enabled(LogLevel::Warn) {
+                    println!(
+                        "Dropping {}: TLS handshake not finished within {}s",
+                        client_addr, auth_secs
+                    );
+                }
+                return;
+            }
+        }
+    };
+    match accepted {
+        Ok(tls_stream) => {
+            let require_alpn = state.config().tls.require_alpn;
+            if require_alpn && !negotiated_rura_alpn(tls_stream.get_ref().1) {
+                if logging::enabled(LogLevel::Warn) {
+                    println!(
+                        "Dropping {}: client did not negotiate the rura/1 protocol",
+                        client_addr
+                    );
+                }
+                return;
+            }
+            let client_cert = client_cert_identity(tls_stream.get_ref().1);
+            if let Err(e) =
+                handle_client_with_cert(tls_stream, conn, state, client_addr, client_cert).await
+            {
+                eprintln!("Error handling TLS client {}: {}", client_addr, e);
+            }
+        }
+        Err(e) => {
+            eprintln!("TLS handshake failed with {}: {}", client_addr, e);
+        }
+    }
+}
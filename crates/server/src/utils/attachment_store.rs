@@ -0,0 +1,169 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Attachment files under `attachments.dir`: `<id>.part` while an upload is
+/// in progress, then `blobs/<sha256[..2]>/<sha256>`, shared by every
+/// attachment with that content. Attachments completed before blobs existed
+/// are still read from `<id>`.
+#[derive(Debug, Clone)]
+pub struct AttachmentStore {
+    dir: PathBuf,
+}
+
+/// Lowercase hex SHA-256, the form attachments are identified by on the wire.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Whether `value` looks like a [`sha256_hex`] digest.
+pub fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+impl AttachmentStore {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn part_path(&self, id: i64) -> PathBuf {
+        self.dir.join(format!("{id}.part"))
+    }
+
+    fn legacy_path(&self, id: i64) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(&sha256[..2]).join(sha256)
+    }
+
+    /// Write `bytes` at `offset` of a pending upload, dropping anything
+    /// stored past `offset` by an earlier, unacknowledged attempt.
+    pub async fn write_chunk(&self, id: i64, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(self.part_path(id))
+            .await?;
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(bytes).await?;
+        file.sync_data().await
+    }
+
+    /// Check a fully received upload against `sha256`. A mismatching file
+    /// is removed. Returns whether it matched.
+    pub async fn verify(&self, id: i64, sha256: &str) -> io::Result<bool> {
+        let part = self.part_path(id);
+        let mut file = match fs::File::open(&part).await {
+            Ok(file) => file,
+            // Zero-byte uploads never wrote a chunk
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(&self.dir).await?;
+                fs::File::create(&part).await?;
+                fs::File::open(&part).await?
+            }
+            Err(e) => return Err(e),
+        };
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        drop(file);
+        if hex(&hasher.finalize()) != sha256 {
+            fs::remove_file(&part).await?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Up to `len` bytes from the start of a pending upload.
+    pub async fn read_part(&self, id: i64, len: u64) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        fs::File::open(self.part_path(id))
+            .await?
+            .take(len)
+            .read_to_end(&mut buf)
+            .await?;
+        Ok(buf)
+    }
+
+    /// Replace the content of a pending upload.
+    pub async fn rewrite_part(&self, id: i64, bytes: &[u8]) -> io::Result<()> {
+        let mut file = fs::File::create(self.part_path(id)).await?;
+        file.write_all(bytes).await?;
+        file.sync_data().await
+    }
+
+    /// Move a verified upload to the blob for its content, replacing an
+    /// identical copy if there is one. Blocking: callers hold the database
+    /// lock so this cannot interleave with [`Self::remove_blob`].
+    pub fn publish(&self, id: i64, sha256: &str) -> io::Result<()> {
+        let blob = self.blob_path(sha256);
+        if let Some(parent) = blob.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(self.part_path(id), blob)
+    }
+
+    /// Up to `len` bytes of completed attachment `id` with content `sha256`,
+    /// starting at `offset`.
+    pub async fn read_chunk(
+        &self,
+        id: i64,
+        sha256: &str,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let mut file = match fs::File::open(self.blob_path(sha256)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::File::open(self.legacy_path(id)).await?
+            }
+            opened => opened?,
+        };
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut buf = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Remove the files kept for `id` alone: a pending upload, or a file
+    /// completed before blobs existed.
+    pub async fn remove(&self, id: i64) -> io::Result<()> {
+        for path in [self.part_path(id), self.legacy_path(id)] {
+            match fs::remove_file(path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the blob for `sha256`; a missing file counts as removed.
+    /// Blocking, see [`Self::publish`].
+    pub fn remove_blob(&self, sha256: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.blob_path(sha256)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
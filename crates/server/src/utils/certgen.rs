@@ -0,0 +1,302 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, Duration, Utc};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType,
+};
+use sha2::{Digest, Sha256};
+
+/// PEM material for a local CA and a server leaf certificate signed by it.
+pub struct GeneratedCerts {
+    /// CA certificate; this is what `rura_client` expects as `ca_pem`.
+    pub ca_cert_pem: String,
+    pub ca_key_pem: String,
+    /// Leaf followed by the CA, so the server presents the full chain.
+    pub server_chain_pem: String,
+    pub server_key_pem: String,
+    /// SHA-256 of the CA certificate DER, colon-separated upper-case hex.
+    pub ca_fingerprint: String,
+    pub subject_alt_names: Vec<String>,
+}
+
+/// Where `write_certs` placed each file.
+pub struct CertPaths {
+    pub ca_cert: PathBuf,
+    pub ca_key: PathBuf,
+    pub server_cert: PathBuf,
+    pub server_key: PathBuf,
+}
+
+fn cert_error(e: rcgen::Error) -> io::Error {
+    io::Error::other(format!("certificate generation failed: {e}"))
+}
+
+/// SHA-256 fingerprint of a DER certificate, formatted like `openssl x509 -fingerprint`.
+pub fn sha256_fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn first_cert_der(pem: &str) -> io::Result<Vec<u8>> {
+    let mut reader = io::Cursor::new(pem.as_bytes());
+    rustls_pemfile::certs(&mut reader)
+        .next()
+        .transpose()?
+        .map(|der| der.to_vec())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no certificate in PEM"))
+}
+
+fn validity(params: &mut CertificateParams, days: u32) {
+    // Backdate slightly so clients with skewed clocks accept a fresh cert
+    let start = Utc::now() - Duration::days(1);
+    let end = Utc::now() + Duration::days(i64::from(days));
+    params.not_before = rcgen::date_time_ymd(start.year(), start.month() as u8, start.day() as u8);
+    params.not_after = rcgen::date_time_ymd(end.year(), end.month() as u8, end.day() as u8);
+}
+
+/// Subject alternative names for the leaf: the detected LAN address,
+/// loopback names, and any extra names, without duplicates.
+pub fn default_subject_alt_names(local_ip: Option<String>, extra: &[String]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let candidates = local_ip
+        .into_iter()
+        .chain(["localhost".to_string(), "127.0.0.1".to_string()])
+        .chain(extra.iter().cloned());
+    for name in candidates {
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Create a fresh CA and a server certificate valid for `days` covering `names`.
+pub fn generate_dev_certs(names: &[String], days: u32) -> io::Result<GeneratedCerts> {
+    let mut ca_params = CertificateParams::default();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let mut ca_dn = DistinguishedName::new();
+    ca_dn.push(DnType::CommonName, "rura local CA");
+    ca_params.distinguished_name = ca_dn;
+    validity(&mut ca_params, days);
+    let ca = Certificate::from_params(ca_params).map_err(cert_error)?;
+
+    let mut srv_params = CertificateParams::new(names.to_vec());
+    let mut srv_dn = DistinguishedName::new();
+    srv_dn.push(
+        DnType::CommonName,
+        names.first().map(String::as_str).unwrap_or("rura server"),
+    );
+    srv_params.distinguished_name = srv_dn;
+    srv_params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    srv_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    validity(&mut srv_params, days);
+    let subject_alt_names = srv_params
+        .subject_alt_names
+        .iter()
+        .map(|san| match san {
+            SanType::IpAddress(ip) => format!("IP:{ip}"),
+            SanType::DnsName(name) => format!("DNS:{name}"),
+            other => format!("{other:?}"),
+        })
+        .collect();
+    let server = Certificate::from_params(srv_params).map_err(cert_error)?;
+
+    // Serialize each certificate once: ECDSA signatures differ per call
+    let ca_cert_pem = ca.serialize_pem().map_err(cert_error)?;
+    let server_pem = server.serialize_pem_with_signer(&ca).map_err(cert_error)?;
+    let ca_fingerprint = sha256_fingerprint(&first_cert_der(&ca_cert_pem)?);
+
+    Ok(GeneratedCerts {
+        server_chain_pem: format!("{server_pem}{ca_cert_pem}"),
+        ca_cert_pem,
+        ca_key_pem: ca.serialize_private_key_pem(),
+        server_key_pem: server.serialize_private_key_pem(),
+        ca_fingerprint,
+        subject_alt_names,
+    })
+}
+
+/// A client certificate for mutual TLS, signed by the local CA.
+pub struct GeneratedClientCert {
+    pub cert_pem: String,
+    pub key_pem: String,
+    /// SHA-256 of the certificate DER; what `user bind-cert --fingerprint` expects.
+    pub fingerprint: String,
+}
+
+/// Issue a client certificate with subject CN `common_name`, signed by the
+/// CA previously written by `write_certs`.
+pub fn issue_client_cert(
+    ca_cert_pem: &str,
+    ca_key_pem: &str,
+    common_name: &str,
+    days: u32,
+) -> io::Result<GeneratedClientCert> {
+    let ca_key = KeyPair::from_pem(ca_key_pem).map_err(cert_error)?;
+    let ca_params = CertificateParams::from_ca_cert_pem(ca_cert_pem, ca_key).map_err(cert_error)?;
+    let ca = Certificate::from_params(ca_params).map_err(cert_error)?;
+
+    let mut params = CertificateParams::default();
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CommonName, common_name);
+    params.distinguished_name = dn;
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    validity(&mut params, days);
+    let client = Certificate::from_params(params).map_err(cert_error)?;
+
+    let cert_pem = client.serialize_pem_with_signer(&ca).map_err(cert_error)?;
+    let fingerprint = sha256_fingerprint(&first_cert_der(&cert_pem)?);
+    Ok(GeneratedClientCert {
+        cert_pem,
+        key_pem: client.serialize_private_key_pem(),
+        fingerprint,
+    })
+}
+
+fn write_file(path: &Path, contents: &str, private: bool) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Write `ca.crt`, `ca.key`, `server.crt` and `server.key` into `dir`.
+/// Existing files are only replaced when `force` is set.
+pub fn write_certs(dir: &Path, certs: &GeneratedCerts, force: bool) -> io::Result<CertPaths> {
+    let paths = CertPaths {
+        ca_cert: dir.join("ca.crt"),
+        ca_key: dir.join("ca.key"),
+        server_cert: dir.join("server.crt"),
+        server_key: dir.join("server.key"),
+    };
+    if !force {
+        for path in [
+            &paths.ca_cert,
+            &paths.ca_key,
+            &paths.server_cert,
+            &paths.server_key,
+        ] {
+            if path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists (use --force to replace)", path.display()),
+                ));
+            }
+        }
+    }
+    fs::create_dir_all(dir)?;
+    write_file(&paths.ca_cert, &certs.ca_cert_pem, false)?;
+    write_file(&paths.ca_key, &certs.ca_key_pem, true)?;
+    write_file(&paths.server_cert, &certs.server_chain_pem, false)?;
+    write_file(&paths.server_key, &certs.server_key_pem, true)?;
+    Ok(paths)
+}
+
+/// Write `<name>.crt` and `<name>.key` into `dir`, refusing to overwrite unless `force`.
+pub fn write_client_cert(
+    dir: &Path,
+    name: &str,
+    cert: &GeneratedClientCert,
+    force: bool,
+) -> io::Result<(PathBuf, PathBuf)> {
+    let cert_path = dir.join(format!("{name}.crt"));
+    let key_path = dir.join(format!("{name}.key"));
+    if !force {
+        for path in [&cert_path, &key_path] {
+            if path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists (use --force to replace)", path.display()),
+                ));
+            }
+        }
+    }
+    fs::create_dir_all(dir)?;
+    write_file(&cert_path, &cert.cert_pem, false)?;
+    write_file(&key_path, &cert.key_pem, true)?;
+    Ok((cert_path, key_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tls::make_tls_acceptor;
+
+    #[test]
+    fn subject_alt_names_are_deduplicated() {
+        let names = default_subject_alt_names(
+            Some("192.168.1.20".to_string()),
+            &["localhost".to_string(), "chat.example".to_string()],
+        );
+        assert_eq!(
+            names,
+            vec!["192.168.1.20", "localhost", "127.0.0.1", "chat.example"]
+        );
+    }
+
+    #[test]
+    fn generated_certs_load_into_acceptor() {
+        let names = default_subject_alt_names(None, &["rura.test".to_string()]);
+        let certs = generate_dev_certs(&names, 30).expect("generate");
+        assert!(
+            certs
+                .subject_alt_names
+                .contains(&"IP:127.0.0.1".to_string())
+        );
+        assert!(
+            certs
+                .subject_alt_names
+                .contains(&"DNS:rura.test".to_string())
+        );
+        assert_eq!(certs.ca_fingerprint.split(':').count(), 32);
+
+        let dir = std::env::temp_dir().join(format!("rura-gen-cert-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let paths = write_certs(&dir, &certs, false).expect("write");
+        assert!(
+            write_certs(&dir, &certs, false).is_err(),
+            "must not overwrite"
+        );
+        write_certs(&dir, &certs, true).expect("force overwrite");
+
+        make_tls_acceptor(
+            paths.server_cert.to_str().unwrap(),
+            paths.server_key.to_str().unwrap(),
+        )
+        .expect("server accepts generated material");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn client_cert_is_signed_by_generated_ca() {
+        let certs = generate_dev_certs(&["localhost".to_string()], 30).expect("generate");
+        let client =
+            issue_client_cert(&certs.ca_cert_pem, &certs.ca_key_pem, "alice", 30).expect("issue");
+        let der = first_cert_der(&client.cert_pem).unwrap();
+        assert_eq!(client.fingerprint, sha256_fingerprint(&der));
+
+        let (_, parsed) = x509_parser::parse_x509_certificate(&der).unwrap();
+        let subject_cn = parsed.subject().iter_common_name().next().unwrap();
+        assert_eq!(subject_cn.as_str().unwrap(), "alice");
+        let ca_der = first_cert_der(&certs.ca_cert_pem).unwrap();
+        let (_, ca) = x509_parser::parse_x509_certificate(&ca_der).unwrap();
+        assert!(parsed.verify_signature(Some(ca.public_key())).is_ok());
+    }
+}
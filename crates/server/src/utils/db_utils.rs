@@ -5,88 +5,199 @@ use argon2::password_hash::{
 };
 use rand_core::OsRng;
 use rusqlite::{Connection, Result as SqliteResult, ffi, params};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 
-fn init_db_with_path<P: AsRef<std::path::Path>>(path: P) -> SqliteResult<Connection> {
-    let conn = Connection::open(path)?;
+use super::logging;
+use super::migrations::{current_version, run_migrations};
+use crate::models::config::LogLevel;
 
-    // Create users table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            passphrase TEXT NOT NULL UNIQUE,
-            password TEXT NOT NULL
-        )",
-        [],
-    )?;
+/// Open (or create) the database at `path` and bring its schema up to date.
+pub fn init_db_with_path<P: AsRef<std::path::Path>>(path: P) -> SqliteResult<Connection> {
+    let mut conn = Connection::open(path)?;
+    let applied = run_migrations(&mut conn)?;
+    if let Some(version) = applied.last() {
+        println!("Database schema migrated to version {}", version);
+    }
+    Ok(conn)
+}
+
+/// Database file used when no other location is configured.
+pub const DEFAULT_DB_PATH: &str = "rura.db";
 
-    // Create messages table (legacy columns sender/receiver) if missing
+pub fn init_db() -> SqliteResult<Connection> {
+    init_db_with_path(DEFAULT_DB_PATH)
+}
+
+/// Close the shared connection at shutdown so SQLite finishes pending writes
+/// and releases the file. Fails while other clones of `conn` are alive.
+pub fn close_db(conn: Arc<Mutex<Connection>>) -> SqliteResult<()> {
+    let conn = Arc::try_unwrap(conn)
+        .map_err(|_| {
+            rusqlite::Error::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_BUSY),
+                Some("database connection is still in use".to_string()),
+            )
+        })?
+        .into_inner()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    conn.execute_batch("PRAGMA optimize")?;
+    conn.close().map_err(|(_, e)| e)
+}
+
+pub async fn log_client_connection(
+    conn: Arc<Mutex<Connection>>,
+    client_addr: SocketAddr,
+) -> SqliteResult<()> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            sender INTEGER NOT NULL,
-            receiver INTEGER NOT NULL,
-            content TEXT NOT NULL,
-            timestamp TEXT NOT NULL,
-            saved INTEGER NOT NULL DEFAULT 0,
-            FOREIGN KEY(sender) REFERENCES users(id),
-            FOREIGN KEY(receiver) REFERENCES users(id)
-        )",
-        [],
+        "INSERT INTO connections (ip, timestamp) VALUES (?1, ?2)",
+        params![client_addr.to_string(), timestamp],
     )?;
+    if logging::enabled(LogLevel::Info) {
+        println!("Logged connection from: {} at {}", client_addr, timestamp);
+    }
+    Ok(())
+}
 
-    // Ensure `saved` column exists for older databases
-    {
-        let mut stmt = conn.prepare("PRAGMA table_info(messages)")?;
-        let mut rows = stmt.query([])?;
-        let mut has_saved = false;
-        while let Some(row) = rows.next()? {
-            let col_name: String = row.get(1)?;
-            if col_name == "saved" {
-                has_saved = true;
-                break;
-            }
-        }
-        if !has_saved {
-            conn.execute(
-                "ALTER TABLE messages ADD COLUMN saved INTEGER NOT NULL DEFAULT 0",
-                [],
-            )?;
+/// Outcome of a password login recorded in `auth_events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthEventKind {
+    Success,
+    Failure,
+    Lockout,
+}
+
+impl AuthEventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            AuthEventKind::Success => "success",
+            AuthEventKind::Failure => "failure",
+            AuthEventKind::Lockout => "lockout",
         }
     }
+}
 
-    // Create connections table
+pub async fn log_auth_event(
+    conn: Arc<Mutex<Connection>>,
+    client_addr: SocketAddr,
+    passphrase: &str,
+    user_id: Option<i64>,
+    kind: AuthEventKind,
+) -> SqliteResult<()> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS connections (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            ip TEXT NOT NULL,
-            timestamp TEXT NOT NULL
-        )",
-        [],
+        "INSERT INTO auth_events (ip, passphrase, user_id, kind, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            client_addr.ip().to_string(),
+            passphrase,
+            user_id,
+            kind.as_str(),
+            timestamp
+        ],
     )?;
+    Ok(())
+}
 
-    Ok(conn)
+/// What failed logins are counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleScope {
+    /// The passphrase as typed, whether or not the account exists.
+    Account,
+    Ip,
 }
 
-pub fn init_db() -> SqliteResult<Connection> {
-    init_db_with_path("rura.db")
+impl ThrottleScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            ThrottleScope::Account => "account",
+            ThrottleScope::Ip => "ip",
+        }
+    }
 }
 
-pub async fn log_client_connection(
+/// Latest `blocked_until` (unix seconds) among `keys` that is still after `now`.
+pub async fn login_blocked_until(
     conn: Arc<Mutex<Connection>>,
-    client_addr: SocketAddr,
+    keys: &[(ThrottleScope, &str)],
+    now: i64,
+) -> SqliteResult<Option<i64>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT blocked_until FROM login_throttle
+         WHERE scope = ?1 AND key = ?2 AND blocked_until > ?3",
+    )?;
+    let mut latest = None;
+    for (scope, key) in keys {
+        if let Some(until) = stmt
+            .query_row(params![scope.as_str(), key, now], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(Some)
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                e => Err(e),
+            })?
+        {
+            latest = latest.max(Some(until));
+        }
+    }
+    Ok(latest)
+}
+
+/// Count one more consecutive failure and return the new total.
+pub async fn record_login_failure(
+    conn: Arc<Mutex<Connection>>,
+    scope: ThrottleScope,
+    key: &str,
+) -> SqliteResult<u32> {
+    let conn = conn.lock().unwrap();
+    conn.query_row(
+        "INSERT INTO login_throttle (scope, key, failures, updated_at)
+         VALUES (?1, ?2, 1, CAST(strftime('%s', 'now') AS INTEGER))
+         ON CONFLICT(scope, key) DO UPDATE
+             SET failures = failures + 1, updated_at = excluded.updated_at
+         RETURNING failures",
+        params![scope.as_str(), key],
+        |row| row.get(0),
+    )
+}
+
+pub async fn block_login(
+    conn: Arc<Mutex<Connection>>,
+    scope: ThrottleScope,
+    key: &str,
+    until: i64,
 ) -> SqliteResult<()> {
-    let timestamp = chrono::Local::now().to_rfc3339();
     let conn = conn.lock().unwrap();
     conn.execute(
-        "INSERT INTO connections (ip, timestamp) VALUES (?1, ?2)",
-        params![client_addr.to_string(), timestamp],
+        "UPDATE login_throttle
+         SET blocked_until = MAX(blocked_until, ?3),
+             updated_at = CAST(strftime('%s', 'now') AS INTEGER)
+         WHERE scope = ?1 AND key = ?2",
+        params![scope.as_str(), key, until],
     )?;
-    println!("Logged connection from: {} at {}", client_addr, timestamp);
     Ok(())
 }
 
+/// Forget failures and blocks for `key`; returns whether there were any.
+pub async fn clear_login_failures(
+    conn: Arc<Mutex<Connection>>,
+    scope: ThrottleScope,
+    key: &str,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let removed = conn.execute(
+        "DELETE FROM login_throttle WHERE scope = ?1 AND key = ?2",
+        params![scope.as_str(), key],
+    )?;
+    Ok(removed > 0)
+}
+
 fn map_password_error(err: PasswordHashError) -> rusqlite::Error {
     rusqlite::Error::SqliteFailure(
         ffi::Error::new(ffi::SQLITE_ERROR),
@@ -138,31 +249,265 @@ pub async fn register_user(
     Ok(conn.last_insert_rowid())
 }
 
+/// Argon2 hash, with `hash_password`'s parameters, of a password nobody
+/// uses. Unknown passphrases are verified against it so they take as long
+/// as a wrong password and do not reveal which accounts exist.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$nuvYWJB5/J/fBR8FoYFStg$CN+2IUbu8zKSN2PtNfrPzIXhKohfebKYAeTZla1n890";
+
 pub async fn authenticate_user(
     conn: Arc<Mutex<Connection>>,
     passphrase: &str,
     password: &str,
 ) -> SqliteResult<Option<i64>> {
-    let (user_id, stored_hash) = {
+    let row = {
         let conn = conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id, password FROM users WHERE passphrase = ?1")?;
+        let mut stmt =
+            conn.prepare("SELECT id, password, disabled FROM users WHERE passphrase = ?1")?;
 
         match stmt.query_row(params![passphrase], |row| {
-            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)? != 0,
+            ))
         }) {
-            Ok(result) => result,
-            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Ok(result) => Some(result),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
             Err(e) => return Err(e),
         }
     };
 
-    if password_matches(&stored_hash, password)? {
+    // Verify for unknown and disabled accounts too, so every failure costs
+    // the same as a wrong password
+    let Some((user_id, stored_hash, disabled)) = row else {
+        password_matches(DUMMY_PASSWORD_HASH, password)?;
+        return Ok(None);
+    };
+    if password_matches(&stored_hash, password)? && !disabled {
         Ok(Some(user_id))
     } else {
         Ok(None)
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct UserRow {
+    pub id: i64,
+    pub passphrase: String,
+    pub disabled: bool,
+}
+
+pub async fn list_users(conn: Arc<Mutex<Connection>>) -> SqliteResult<Vec<UserRow>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT id, passphrase, disabled FROM users ORDER BY id ASC")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(UserRow {
+            id: row.get(0)?,
+            passphrase: row.get(1)?,
+            disabled: row.get::<_, i64>(2)? != 0,
+        })
+    })?;
+    rows.collect()
+}
+
+pub async fn find_user_id(
+    conn: Arc<Mutex<Connection>>,
+    passphrase: &str,
+) -> SqliteResult<Option<i64>> {
+    let conn = conn.lock().unwrap();
+    match conn.query_row(
+        "SELECT id FROM users WHERE passphrase = ?1",
+        params![passphrase],
+        |row| row.get(0),
+    ) {
+        Ok(id) => Ok(Some(id)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn set_user_disabled(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    disabled: bool,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE users SET disabled = ?1 WHERE id = ?2",
+        params![if disabled { 1 } else { 0 }, user_id],
+    )?;
+    Ok(updated == 1)
+}
+
+pub async fn set_user_password(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    password: &str,
+) -> SqliteResult<bool> {
+    let hashed_password = hash_password(password)?;
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE users SET password = ?1 WHERE id = ?2",
+        params![hashed_password, user_id],
+    )?;
+    Ok(updated == 1)
+}
+
+/// Delete a user together with every message they sent or received and
+/// their group memberships. Their uploads lose their owner: files other
+/// messages still carry stay, the rest are left to the garbage collector.
+pub async fn delete_user(conn: Arc<Mutex<Connection>>, user_id: i64) -> SqliteResult<bool> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM messages WHERE sender = ?1 OR receiver = ?1",
+        params![user_id],
+    )?;
+    tx.execute(
+        "UPDATE attachments SET owner_id = NULL WHERE owner_id = ?1",
+        params![user_id],
+    )?;
+    for table in ["message_hidden", "message_reactions", "bookmarks"] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE user_id = ?1"),
+            params![user_id],
+        )?;
+    }
+    tx.execute(
+        "DELETE FROM client_certificates WHERE user_id = ?1",
+        params![user_id],
+    )?;
+    // Groups the user owns pass to an admin, or else the longest-standing member
+    tx.execute(
+        "UPDATE conversation_members SET role = 'owner'
+         WHERE rowid IN (
+            SELECT (SELECT m.rowid FROM conversation_members m
+                    WHERE m.conversation_id = o.conversation_id
+                      AND m.user_id != ?1 AND m.left_after IS NULL
+                    ORDER BY m.role = 'admin' DESC, m.joined_after, m.user_id
+                    LIMIT 1)
+            FROM conversation_members o
+            WHERE o.user_id = ?1 AND o.role = 'owner' AND o.left_after IS NULL
+         )",
+        params![user_id],
+    )?;
+    tx.execute(
+        "DELETE FROM conversation_members WHERE user_id = ?1",
+        params![user_id],
+    )?;
+    // Channels the user owns pass to the longest-standing publisher; without
+    // one, the channel goes away with its owner
+    tx.execute(
+        "UPDATE channel_members SET role = 'owner'
+         WHERE rowid IN (
+            SELECT (SELECT p.rowid FROM channel_members p
+                    WHERE p.channel_id = o.channel_id AND p.role = 'publisher'
+                    ORDER BY p.joined_at, p.user_id
+                    LIMIT 1)
+            FROM channel_members o
+            WHERE o.user_id = ?1 AND o.role = 'owner'
+         )",
+        params![user_id],
+    )?;
+    let orphaned = "SELECT channel_id FROM channel_members WHERE user_id = ?1 AND role = 'owner'
+        AND NOT EXISTS (SELECT 1 FROM channel_members n
+                        WHERE n.channel_id = channel_members.channel_id AND n.role = 'owner'
+                          AND n.user_id != ?1)";
+    for table in ["channel_posts", "channel_invites", "channel_members"] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE channel_id IN ({orphaned})"),
+            params![user_id],
+        )?;
+    }
+    // Every live channel has its owner as a member
+    tx.execute(
+        "DELETE FROM channels
+         WHERE NOT EXISTS (SELECT 1 FROM channel_members WHERE channel_id = channels.id)",
+        [],
+    )?;
+    tx.execute(
+        "DELETE FROM channel_posts WHERE sender = ?1",
+        params![user_id],
+    )?;
+    tx.execute(
+        "DELETE FROM channel_invites WHERE user_id = ?1",
+        params![user_id],
+    )?;
+    tx.execute(
+        "DELETE FROM channel_members WHERE user_id = ?1",
+        params![user_id],
+    )?;
+    let deleted = tx.execute("DELETE FROM users WHERE id = ?1", params![user_id])?;
+    tx.commit()?;
+    Ok(deleted == 1)
+}
+
+/// How a client certificate is linked to an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertBindingKind {
+    Fingerprint,
+    Subject,
+}
+
+impl CertBindingKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            CertBindingKind::Fingerprint => "fingerprint",
+            CertBindingKind::Subject => "subject",
+        }
+    }
+}
+
+pub async fn bind_client_certificate(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    kind: CertBindingKind,
+    value: &str,
+) -> SqliteResult<()> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "INSERT INTO client_certificates (user_id, kind, value, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![user_id, kind.as_str(), value, ts],
+    )?;
+    Ok(())
+}
+
+pub async fn unbind_client_certificates(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+) -> SqliteResult<usize> {
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "DELETE FROM client_certificates WHERE user_id = ?1",
+        params![user_id],
+    )
+}
+
+/// Resolve a verified client certificate to an enabled account.
+/// A fingerprint binding wins over a subject binding.
+pub async fn find_user_by_client_cert(
+    conn: Arc<Mutex<Connection>>,
+    fingerprint: &str,
+    subject: Option<&str>,
+) -> SqliteResult<Option<i64>> {
+    let conn = conn.lock().unwrap();
+    match conn.query_row(
+        "SELECT u.id FROM client_certificates c JOIN users u ON u.id = c.user_id
+         WHERE u.disabled = 0
+           AND ((c.kind = 'fingerprint' AND c.value = ?1)
+             OR (c.kind = 'subject' AND c.value = ?2))
+         ORDER BY c.kind = 'fingerprint' DESC
+         LIMIT 1",
+        params![fingerprint, subject],
+        |row| row.get(0),
+    ) {
+        Ok(id) => Ok(Some(id)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 pub async fn store_message(
     conn: Arc<Mutex<Connection>>,
     from_user_id: i64,
@@ -173,72 +518,2322 @@ pub async fn store_message(
     let ts = chrono::Local::now().to_rfc3339();
     let conn = conn.lock().unwrap();
     conn.execute(
-        "INSERT INTO messages (sender, receiver, content, timestamp, saved) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![from_user_id, to_user_id, content, ts, if saved { 1 } else { 0 }],
+        "INSERT INTO messages (sender, receiver, content, timestamp) VALUES (?1, ?2, ?3, ?4)",
+        params![from_user_id, to_user_id, content, ts],
     )?;
-    Ok(conn.last_insert_rowid())
+    let id = conn.last_insert_rowid();
+    if saved {
+        bookmark_for_sender(&conn, from_user_id, id, &ts)?;
+    }
+    Ok(id)
+}
+
+/// Bookmark a message that was just sent with `saved` for its sender; the
+/// other participants keep their own bookmarks.
+fn bookmark_for_sender(
+    conn: &Connection,
+    sender: i64,
+    message_id: i64,
+    ts: &str,
+) -> SqliteResult<()> {
+    conn.execute(
+        "INSERT INTO bookmarks (user_id, message_id, created_at) VALUES (?1, ?2, ?3)",
+        params![sender, message_id, ts],
+    )?;
+    Ok(())
+}
+
+/// Optional links and lifetime of a new direct or group text message. Check
+/// the links with [`reply_quote`] and [`readable_attachment`] first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageLinks {
+    pub reply_to_id: Option<i64>,
+    pub attachment_id: Option<i64>,
+    /// Unix seconds after which the message is deleted for everyone.
+    pub expires_at: Option<i64>,
+    /// Deleted once the recipient confirms reading it; direct messages only.
+    pub view_once: bool,
+}
+
+/// Store a direct (`receiver`) or group (`conversation_id`) text message
+/// together with its `links`.
+pub async fn store_message_with(
+    conn: Arc<Mutex<Connection>>,
+    from_user_id: i64,
+    receiver: Option<i64>,
+    conversation_id: Option<i64>,
+    content: &str,
+    saved: bool,
+    links: MessageLinks,
+) -> SqliteResult<i64> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "INSERT INTO messages
+            (sender, receiver, conversation_id, content, timestamp, reply_to_id, attachment_id,
+             expires_at, view_once)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            from_user_id,
+            receiver,
+            conversation_id,
+            content,
+            ts,
+            links.reply_to_id,
+            links.attachment_id,
+            links.expires_at,
+            links.view_once
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    if saved {
+        bookmark_for_sender(&conn, from_user_id, id, &ts)?;
+    }
+    Ok(id)
+}
+
+/// Quote of `reply_to_id` when a new message from `sender` may reply to it:
+/// it must be a text message of the same conversation, that is the direct
+/// chat with `receiver` or group `conversation_id`, visible to the sender
+/// and not deleted for everyone.
+pub async fn reply_quote(
+    conn: Arc<Mutex<Connection>>,
+    reply_to_id: i64,
+    sender: i64,
+    receiver: Option<i64>,
+    conversation_id: Option<i64>,
+) -> SqliteResult<Option<ReplyQuoteRow>> {
+    let conn = conn.lock().unwrap();
+    match conn.query_row(
+        &format!(
+            "SELECT id, sender, content FROM messages
+             WHERE id = ?1 AND kind = 'text' AND deleted_at IS NULL
+               AND ((sender = ?2 AND receiver = ?3) OR (sender = ?3 AND receiver = ?2)
+                    OR (conversation_id = ?4 AND {}))",
+            visible_to("?2")
+        ),
+        params![reply_to_id, sender, receiver, conversation_id],
+        |row| {
+            Ok(ReplyQuoteRow {
+                message_id: row.get(0)?,
+                sender: row.get(1)?,
+                snippet: snippet(&row.get::<_, String>(2)?),
+                deleted: false,
+            })
+        },
+    ) {
+        Ok(quote) => Ok(Some(quote)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
+/// SQL condition: the `messages` row is visible to the user bound at `user`
+/// (e.g. `?1`). That is the sender, the direct recipient, or anyone who was
+/// a member of the group while the message was posted. Expired messages are
+/// visible to nobody, even before the sweeper deleted them.
+fn visible_to(user: &str) -> String {
+    row_visible_to("messages", user)
+}
+
+/// [`visible_to`] for the `messages` row aliased as `table`.
+fn row_visible_to(table: &str, user: &str) -> String {
+    format!(
+        "({table}.expires_at IS NULL
+            OR {table}.expires_at > CAST(strftime('%s', 'now') AS INTEGER))
+         AND ({table}.sender = {user} OR {table}.receiver = {user} OR EXISTS (
+            SELECT 1 FROM conversation_members cm
+            WHERE cm.conversation_id = {table}.conversation_id
+              AND cm.user_id = {user}
+              AND {table}.id > cm.joined_after
+              AND (cm.left_after IS NULL OR {table}.id <= cm.left_after)))"
+    )
+}
+
+/// Bookmark (`saved`) or unbookmark a message for `user_id` alone, keeping
+/// the folder, tags and note of an existing bookmark. Returns false when the
+/// message is not visible to them or was deleted for everyone.
 pub async fn set_message_saved(
     conn: Arc<Mutex<Connection>>,
     user_id: i64,
     message_id: i64,
     saved: bool,
+) -> SqliteResult<bool> {
+    if saved {
+        save_bookmark(conn, user_id, message_id, &BookmarkUpdate::default()).await
+    } else {
+        remove_bookmark(conn, user_id, message_id).await
+    }
+}
+
+/// Changes [`save_bookmark`] makes; `None` keeps the current value, or the
+/// default for a new bookmark.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookmarkUpdate {
+    /// Empty for no folder.
+    pub folder: Option<String>,
+    /// Replaces every tag.
+    pub tags: Option<Vec<String>>,
+    pub note: Option<String>,
+}
+
+/// Whether `user_id` may bookmark `message_id`: they can see it and it was
+/// not deleted for everyone.
+fn bookmarkable(conn: &Connection, user_id: i64, message_id: i64) -> SqliteResult<bool> {
+    conn.prepare(&format!(
+        "SELECT 1 FROM messages WHERE id = ?1 AND deleted_at IS NULL AND {}",
+        visible_to("?2")
+    ))?
+    .exists(params![message_id, user_id])
+}
+
+/// Bookmark a message for `user_id`, or change their bookmark. Returns false
+/// when they may not, as for [`set_message_saved`].
+pub async fn save_bookmark(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
+    update: &BookmarkUpdate,
+) -> SqliteResult<bool> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    if !bookmarkable(&tx, user_id, message_id)? {
+        return Ok(false);
+    }
+    tx.execute(
+        "INSERT INTO bookmarks (user_id, message_id, created_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(user_id, message_id) DO NOTHING",
+        params![user_id, message_id, chrono::Local::now().to_rfc3339()],
+    )?;
+    let bookmark_id: i64 = tx.query_row(
+        "SELECT id FROM bookmarks WHERE user_id = ?1 AND message_id = ?2",
+        params![user_id, message_id],
+        |row| row.get(0),
+    )?;
+    if let Some(folder) = &update.folder {
+        tx.execute(
+            "UPDATE bookmarks SET folder = ?2 WHERE id = ?1",
+            params![bookmark_id, folder],
+        )?;
+    }
+    if let Some(note) = &update.note {
+        tx.execute(
+            "UPDATE bookmarks SET note = ?2 WHERE id = ?1",
+            params![bookmark_id, note],
+        )?;
+    }
+    if let Some(tags) = &update.tags {
+        tx.execute(
+            "DELETE FROM bookmark_tags WHERE bookmark_id = ?1",
+            params![bookmark_id],
+        )?;
+        for tag in tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO bookmark_tags (bookmark_id, tag) VALUES (?1, ?2)",
+                params![bookmark_id, tag],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Remove `user_id`'s bookmark of a message, with its folder, tags and note.
+/// Returns false when they may not bookmark it, as for [`set_message_saved`].
+pub async fn remove_bookmark(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
 ) -> SqliteResult<bool> {
     let conn = conn.lock().unwrap();
-    let updated = conn.execute(
-        "UPDATE messages SET saved = ?1 WHERE id = ?2 AND (sender = ?3 OR receiver = ?3)",
-        params![if saved { 1 } else { 0 }, message_id, user_id],
+    if !bookmarkable(&conn, user_id, message_id)? {
+        return Ok(false);
+    }
+    conn.execute(
+        "DELETE FROM bookmarks WHERE user_id = ?1 AND message_id = ?2",
+        params![user_id, message_id],
     )?;
-    Ok(updated == 1)
+    Ok(true)
+}
+
+/// Which bookmarks [`list_bookmarks`] returns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookmarkFilter {
+    /// Only this folder; empty for bookmarks without one.
+    pub folder: Option<String>,
+    pub tag: Option<String>,
+    /// Only bookmarks older than this bookmark id, to page back.
+    pub before_id: Option<i64>,
+}
+
+/// A message `user_id` bookmarked.
+#[derive(Debug, Clone)]
+pub struct BookmarkRow {
+    pub id: i64,
+    pub folder: String,
+    /// Alphabetical.
+    pub tags: Vec<String>,
+    pub note: String,
+    pub created_at: String,
+    /// With reactions and attachment, as in history.
+    pub message: RawMessageRow,
+}
+
+/// Up to `limit` of `user_id`'s bookmarks matching `filter`, newest first.
+/// Bookmarks of messages they can no longer see are left out.
+pub async fn list_bookmarks(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    filter: &BookmarkFilter,
+    limit: usize,
+) -> SqliteResult<Vec<BookmarkRow>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT b.id, b.message_id, b.folder, b.note, b.created_at
+         FROM bookmarks b JOIN messages ON messages.id = b.message_id
+         WHERE b.user_id = ?1 AND {}
+           AND (?2 IS NULL OR b.folder = ?2)
+           AND (?3 IS NULL OR EXISTS (SELECT 1 FROM bookmark_tags t
+                                      WHERE t.bookmark_id = b.id AND t.tag = ?3))
+           AND (?4 IS NULL OR b.id < ?4)
+         ORDER BY b.id DESC
+         LIMIT ?5",
+        visible_to("?1")
+    ))?;
+    let page = stmt
+        .query_map(
+            params![
+                user_id,
+                filter.folder,
+                filter.tag,
+                filter.before_id,
+                limit as i64
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            },
+        )?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    let (Some(newest), Some(oldest)) = (page.first(), page.last()) else {
+        return Ok(Vec::new());
+    };
+    let range = params![user_id, oldest.0, newest.0];
+    // Messages of every bookmark in the page's id range, ordered by message id
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM messages
+         WHERE id IN (SELECT message_id FROM bookmarks
+                      WHERE user_id = ?1 AND id BETWEEN ?2 AND ?3)
+         ORDER BY id",
+        message_columns("?1")
+    ))?;
+    let mut messages = stmt
+        .query_map(range, message_from_row)?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    attach_reactions(&conn, user_id, &mut messages)?;
+    attach_attachments(&conn, &mut messages)?;
+    let mut messages: HashMap<i64, RawMessageRow> = messages
+        .into_iter()
+        .map(|mut message| {
+            message.saved = true;
+            (message.id, message)
+        })
+        .collect();
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT t.bookmark_id, t.tag FROM bookmark_tags t JOIN bookmarks b ON b.id = t.bookmark_id
+         WHERE b.user_id = ?1 AND b.id BETWEEN ?2 AND ?3
+         ORDER BY t.tag",
+    )?;
+    for tag in stmt.query_map(range, |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)))? {
+        let (bookmark_id, tag) = tag?;
+        tags.entry(bookmark_id).or_default().push(tag);
+    }
+    Ok(page
+        .into_iter()
+        .filter_map(|(id, message_id, folder, note, created_at)| {
+            Some(BookmarkRow {
+                id,
+                folder,
+                tags: tags.remove(&id).unwrap_or_default(),
+                note,
+                created_at,
+                message: messages.remove(&message_id)?,
+            })
+        })
+        .collect())
 }
 
 #[derive(Debug, Clone)]
 pub struct RawMessageRow {
     pub id: i64,
     pub sender: i64,
-    pub receiver: i64,
+    /// Set for direct messages.
+    pub receiver: Option<i64>,
+    /// Set for group messages.
+    pub conversation_id: Option<i64>,
     pub content: String,
     pub timestamp: String,
     pub saved: bool,
+    /// A group change recorded by the server rather than a user's text.
+    pub system: bool,
+    /// When the sender last edited the text, if ever.
+    pub edited_at: Option<String>,
+    /// Set once the sender deleted the message for everyone; `content` is
+    /// then empty.
+    pub deleted_at: Option<String>,
+    /// Reactions by emoji, in the order they were first used. Only filled
+    /// in by [`fetch_messages_for_user`].
+    pub reactions: Vec<ReactionCountRow>,
+    /// Emoji the reading user reacted with. Only filled in by
+    /// [`fetch_messages_for_user`].
+    pub my_reactions: Vec<String>,
+    /// The message this one replies to, as quoted to readers.
+    pub reply_to: Option<ReplyQuoteRow>,
+    /// The attached file. Only filled in by [`fetch_messages_for_user`] and
+    /// [`fetch_thread`].
+    pub attachment: Option<AttachmentRow>,
+    /// Unix seconds at which the message disappears.
+    pub expires_at: Option<i64>,
+    /// Disappears once the recipient confirmed reading it.
+    pub view_once: bool,
+}
+
+/// Longest quote of a replied-to message, in characters.
+pub const REPLY_SNIPPET_CHARS: usize = 100;
+
+fn snippet(content: &str) -> String {
+    content.chars().take(REPLY_SNIPPET_CHARS).collect()
+}
+
+/// The start of a replied-to message, read when the reply is loaded so that
+/// later edits and deletions show up in the quote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyQuoteRow {
+    pub message_id: i64,
+    pub sender: i64,
+    /// Empty once the quoted message was deleted for everyone.
+    pub snippet: String,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReactionCountRow {
+    pub emoji: String,
+    pub count: i64,
+}
+
+/// Columns [`message_from_row`] reads, for a `messages` row read by the user
+/// bound at `reader`; the query must name the table `messages`, without an
+/// alias. The quote of a reply is left empty when the reader cannot see the
+/// replied-to message.
+fn message_columns(reader: &str) -> String {
+    format!(
+        "id, sender, receiver, conversation_id, content, timestamp,
+         saved, kind, edited_at, deleted_at, reply_to_id,
+         (SELECT q.sender FROM messages q WHERE q.id = messages.reply_to_id),
+         (SELECT q.content FROM messages q
+          WHERE q.id = messages.reply_to_id AND {}),
+         (SELECT q.deleted_at IS NOT NULL FROM messages q WHERE q.id = messages.reply_to_id),
+         expires_at, view_once",
+        row_visible_to("q", reader)
+    )
+}
+
+fn message_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<RawMessageRow> {
+    Ok(RawMessageRow {
+        id: row.get(0)?,
+        sender: row.get(1)?,
+        receiver: row.get(2)?,
+        conversation_id: row.get(3)?,
+        content: row.get(4)?,
+        timestamp: row.get(5)?,
+        saved: row.get::<_, i64>(6)? != 0,
+        system: row.get::<_, String>(7)? == "system",
+        edited_at: row.get(8)?,
+        deleted_at: row.get(9)?,
+        reactions: Vec::new(),
+        my_reactions: Vec::new(),
+        // Purging the target clears `reply_to_id`, so the row reads as no reply
+        reply_to: match (row.get::<_, Option<i64>>(10)?, row.get(11)?) {
+            (Some(message_id), Some(sender)) => Some(ReplyQuoteRow {
+                message_id,
+                sender,
+                snippet: snippet(&row.get::<_, Option<String>>(12)?.unwrap_or_default()),
+                deleted: row.get(13)?,
+            }),
+            _ => None,
+        },
+        attachment: None,
+        expires_at: row.get(14)?,
+        view_once: row.get(15)?,
+    })
 }
 
+/// Direct messages the user sent or received, plus messages of their groups,
+/// without those they deleted for themselves. Messages deleted for everyone
+/// are returned as tombstones so every device learns about the deletion.
 pub async fn fetch_messages_for_user(
     conn: Arc<Mutex<Connection>>,
     user_id: i64,
     limit: usize,
 ) -> SqliteResult<Vec<RawMessageRow>> {
     let conn = conn.lock().unwrap();
-    let mut stmt = conn.prepare(
-        "SELECT id, sender, receiver, content, timestamp, saved
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
          FROM messages
-         WHERE sender = ?1 OR receiver = ?1
+         WHERE {}
+           AND NOT EXISTS (SELECT 1 FROM message_hidden h
+                           WHERE h.message_id = messages.id AND h.user_id = ?1)
          ORDER BY id ASC
          LIMIT ?2",
-    )?;
-    let mut rows = stmt.query(params![user_id, limit as i64])?;
-    let mut out: Vec<RawMessageRow> = Vec::new();
-    while let Some(row) = rows.next()? {
-        let id: i64 = row.get(0)?;
-        let sender: i64 = row.get(1)?;
-        let receiver: i64 = row.get(2)?;
-        let content: String = row.get(3)?;
-        let timestamp: String = row.get(4)?;
-        let saved_i: i64 = row.get(5)?;
-        out.push(RawMessageRow {
-            id,
-            sender,
-            receiver,
-            content,
-            timestamp,
-            saved: saved_i != 0,
-        });
-    }
-    Ok(out)
+        message_columns("?1"),
+        visible_to("?1")
+    ))?;
+    let mut rows = stmt
+        .query_map(params![user_id, limit as i64], message_from_row)?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    attach_reactions(&conn, user_id, &mut rows)?;
+    attach_attachments(&conn, &mut rows)?;
+    attach_bookmarks(&conn, user_id, &mut rows)?;
+    Ok(rows)
 }
 
-#[cfg(test)]
-mod tests {
+/// Fill in reaction counts and `user_id`'s own reactions for `rows`, which
+/// must be ordered by id. One pass over the reactions in the id range.
+fn attach_reactions(
+    conn: &Connection,
+    user_id: i64,
+    rows: &mut [RawMessageRow],
+) -> SqliteResult<()> {
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok(());
+    };
+    let mut stmt = conn.prepare(
+        "SELECT message_id, emoji, COUNT(*), SUM(user_id = ?1)
+         FROM message_reactions
+         WHERE message_id BETWEEN ?2 AND ?3
+         GROUP BY message_id, emoji
+         ORDER BY message_id, MIN(rowid)",
+    )?;
+    let mut reactions = stmt.query(params![user_id, first.id, last.id])?;
+    let mut idx = 0;
+    while let Some(reaction) = reactions.next()? {
+        let message_id: i64 = reaction.get(0)?;
+        while idx < rows.len() && rows[idx].id < message_id {
+            idx += 1;
+        }
+        let Some(row) = rows.get_mut(idx).filter(|row| row.id == message_id) else {
+            continue;
+        };
+        let emoji: String = reaction.get(1)?;
+        if reaction.get::<_, i64>(3)? > 0 {
+            row.my_reactions.push(emoji.clone());
+        }
+        row.reactions.push(ReactionCountRow {
+            emoji,
+            count: reaction.get(2)?,
+        });
+    }
+    Ok(())
+}
+
+/// Mark which of `rows`, which must be ordered by id, `user_id` bookmarked.
+/// `saved` read from the row says whether anyone did.
+fn attach_bookmarks(
+    conn: &Connection,
+    user_id: i64,
+    rows: &mut [RawMessageRow],
+) -> SqliteResult<()> {
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok(());
+    };
+    let mut stmt = conn.prepare(
+        "SELECT message_id FROM bookmarks
+         WHERE user_id = ?1 AND message_id BETWEEN ?2 AND ?3",
+    )?;
+    let saved = stmt
+        .query_map(params![user_id, first.id, last.id], |row| row.get(0))?
+        .collect::<SqliteResult<HashSet<i64>>>()?;
+    for row in rows {
+        row.saved = saved.contains(&row.id);
+    }
+    Ok(())
+}
+
+/// Fill in the attachments of `rows`, which must be ordered by id.
+fn attach_attachments(conn: &Connection, rows: &mut [RawMessageRow]) -> SqliteResult<()> {
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok(());
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT m.id, {ATTACHMENT_COLUMNS}
+         FROM messages m JOIN attachments a ON a.id = m.attachment_id {THUMBNAIL_JOIN}
+         WHERE m.id BETWEEN ?1 AND ?2
+         ORDER BY m.id"
+    ))?;
+    let mut found = stmt.query(params![first.id, last.id])?;
+    let mut idx = 0;
+    while let Some(found) = found.next()? {
+        let message_id: i64 = found.get(0)?;
+        while idx < rows.len() && rows[idx].id < message_id {
+            idx += 1;
+        }
+        if let Some(row) = rows.get_mut(idx).filter(|row| row.id == message_id) {
+            row.attachment = Some(attachment_from_row(found, 1)?);
+        }
+    }
+    Ok(())
+}
+
+/// Replies to `root_id`, including replies to replies, that `user_id` can
+/// see, oldest first and after `after_id`. `None` when the root message is
+/// not visible to them.
+pub async fn fetch_thread(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    root_id: i64,
+    after_id: Option<i64>,
+    limit: usize,
+) -> SqliteResult<Option<Vec<RawMessageRow>>> {
+    let conn = conn.lock().unwrap();
+    let visible = conn
+        .prepare(&format!(
+            "SELECT 1 FROM messages WHERE id = ?1 AND {}",
+            visible_to("?2")
+        ))?
+        .exists(params![root_id, user_id])?;
+    if !visible {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(&format!(
+        "WITH RECURSIVE thread(id) AS (
+            SELECT id FROM messages WHERE reply_to_id = ?2
+            UNION SELECT m.id FROM messages m JOIN thread t ON m.reply_to_id = t.id
+         )
+         SELECT {}
+         FROM messages
+         WHERE id IN thread AND id > ?3 AND {}
+           AND NOT EXISTS (SELECT 1 FROM message_hidden h
+                           WHERE h.message_id = messages.id AND h.user_id = ?1)
+         ORDER BY id ASC
+         LIMIT ?4",
+        message_columns("?1"),
+        visible_to("?1")
+    ))?;
+    let mut rows = stmt
+        .query_map(
+            params![user_id, root_id, after_id.unwrap_or(0), limit as i64],
+            message_from_row,
+        )?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    attach_reactions(&conn, user_id, &mut rows)?;
+    attach_attachments(&conn, &mut rows)?;
+    attach_bookmarks(&conn, user_id, &mut rows)?;
+    Ok(Some(rows))
+}
+
+/// Why a change the sender alone may make to a message was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageRefusal {
+    /// No such message, or the caller cannot see it.
+    NotFound,
+    /// The caller did not send the message.
+    NotSender,
+    /// The message is older than the allowed window.
+    Expired,
+}
+
+/// Check that `user_id` sent `message_id` at most `window_secs` ago.
+/// Group change records are never the caller's own text, and messages
+/// deleted for everyone no longer exist.
+fn check_own_message(
+    conn: &Connection,
+    user_id: i64,
+    message_id: i64,
+    window_secs: u64,
+) -> SqliteResult<Result<(), MessageRefusal>> {
+    let (sender, timestamp) = match conn.query_row(
+        &format!(
+            "SELECT sender, timestamp FROM messages
+             WHERE id = ?1 AND kind = 'text' AND deleted_at IS NULL AND {}",
+            visible_to("?2")
+        ),
+        params![message_id, user_id],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+    ) {
+        Ok(row) => row,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(Err(MessageRefusal::NotFound)),
+        Err(e) => return Err(e),
+    };
+    if sender != user_id {
+        return Ok(Err(MessageRefusal::NotSender));
+    }
+    // Rows whose timestamp cannot be read are treated as too old
+    let within = chrono::DateTime::parse_from_rfc3339(&timestamp).is_ok_and(|sent| {
+        chrono::Local::now()
+            .signed_duration_since(sent)
+            .num_seconds()
+            <= window_secs as i64
+    });
+    Ok(if within {
+        Ok(())
+    } else {
+        Err(MessageRefusal::Expired)
+    })
+}
+
+/// Replace the text of a message `user_id` sent within the last
+/// `window_secs`, keeping the previous text in `message_edits`.
+/// Returns the updated message.
+pub async fn edit_message(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
+    content: &str,
+    window_secs: u64,
+) -> SqliteResult<Result<RawMessageRow, MessageRefusal>> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    if let Err(refusal) = check_own_message(&tx, user_id, message_id, window_secs)? {
+        return Ok(Err(refusal));
+    }
+    let ts = chrono::Local::now().to_rfc3339();
+    tx.execute(
+        "INSERT INTO message_edits (message_id, content, edited_at)
+         SELECT id, content, ?2 FROM messages WHERE id = ?1",
+        params![message_id, ts],
+    )?;
+    tx.execute(
+        "UPDATE messages SET content = ?2, edited_at = ?3 WHERE id = ?1",
+        params![message_id, content, ts],
+    )?;
+    let row = tx.query_row(
+        &format!(
+            "SELECT {} FROM messages WHERE id = ?1",
+            message_columns("?2")
+        ),
+        params![message_id, user_id],
+        message_from_row,
+    )?;
+    tx.commit()?;
+    Ok(Ok(row))
+}
+
+/// Hide a message from `user_id`'s own history and drop their bookmark of
+/// it. Returns false when the message does not exist or is not visible to
+/// them.
+pub async fn hide_message(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
+) -> SqliteResult<bool> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    let visible = conn
+        .prepare(&format!(
+            "SELECT 1 FROM messages WHERE id = ?1 AND {}",
+            visible_to("?2")
+        ))?
+        .exists(params![message_id, user_id])?;
+    if visible {
+        conn.execute(
+            "INSERT OR IGNORE INTO message_hidden (message_id, user_id, hidden_at)
+             VALUES (?1, ?2, ?3)",
+            params![message_id, user_id, ts],
+        )?;
+        conn.execute(
+            "DELETE FROM bookmarks WHERE message_id = ?1 AND user_id = ?2",
+            params![message_id, user_id],
+        )?;
+    }
+    Ok(visible)
+}
+
+/// Replace a message `user_id` sent within the last `window_secs` with a
+/// tombstone: the text, its earlier versions, its reactions and the link to
+/// its attachment are dropped, and so is everyone's bookmark. Returns the tombstone.
+pub async fn delete_message_for_everyone(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
+    window_secs: u64,
+) -> SqliteResult<Result<RawMessageRow, MessageRefusal>> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    if let Err(refusal) = check_own_message(&tx, user_id, message_id, window_secs)? {
+        return Ok(Err(refusal));
+    }
+    let ts = chrono::Local::now().to_rfc3339();
+    for table in ["message_edits", "message_reactions", "bookmarks"] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE message_id = ?1"),
+            params![message_id],
+        )?;
+    }
+    tx.execute(
+        "UPDATE messages
+         SET content = '', edited_at = NULL, deleted_at = ?2, attachment_id = NULL
+         WHERE id = ?1",
+        params![message_id, ts],
+    )?;
+    let row = tx.query_row(
+        &format!(
+            "SELECT {} FROM messages WHERE id = ?1",
+            message_columns("?2")
+        ),
+        params![message_id, user_id],
+        message_from_row,
+    )?;
+    tx.commit()?;
+    Ok(Ok(row))
+}
+
+/// Add (`on`) or remove `user_id`'s `emoji` reaction, with the same
+/// visibility rule as [`set_message_saved`]. Returns the message and whether
+/// anything changed, or `None` when the message is not visible to the user,
+/// was deleted, or is a group change record.
+pub async fn set_reaction(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
+    emoji: &str,
+    on: bool,
+) -> SqliteResult<Option<(RawMessageRow, bool)>> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    let message = match conn.query_row(
+        &format!(
+            "SELECT {} FROM messages
+             WHERE id = ?1 AND kind = 'text' AND deleted_at IS NULL AND {}",
+            message_columns("?2"),
+            visible_to("?2")
+        ),
+        params![message_id, user_id],
+        message_from_row,
+    ) {
+        Ok(message) => message,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let changed = if on {
+        conn.execute(
+            "INSERT OR IGNORE INTO message_reactions (message_id, user_id, emoji, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![message_id, user_id, emoji, ts],
+        )?
+    } else {
+        conn.execute(
+            "DELETE FROM message_reactions WHERE message_id = ?1 AND user_id = ?2 AND emoji = ?3",
+            params![message_id, user_id, emoji],
+        )?
+    };
+    Ok(Some((message, changed == 1)))
+}
+
+/// Earlier text of an edited message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageRevisionRow {
+    pub content: String,
+    /// When this text was replaced by the next version.
+    pub replaced_at: String,
+}
+
+/// Earlier versions of a message visible to `user_id`, oldest first.
+/// `None` when the message does not exist or is not visible to them.
+pub async fn message_revisions(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
+) -> SqliteResult<Option<Vec<MessageRevisionRow>>> {
+    let conn = conn.lock().unwrap();
+    let visible = conn
+        .prepare(&format!(
+            "SELECT 1 FROM messages WHERE id = ?1 AND {}",
+            visible_to("?2")
+        ))?
+        .exists(params![message_id, user_id])?;
+    if !visible {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(
+        "SELECT content, edited_at FROM message_edits WHERE message_id = ?1 ORDER BY id ASC",
+    )?;
+    let rows = stmt.query_map(params![message_id], |row| {
+        Ok(MessageRevisionRow {
+            content: row.get(0)?,
+            replaced_at: row.get(1)?,
+        })
+    })?;
+    rows.collect::<SqliteResult<Vec<_>>>().map(Some)
+}
+
+/// Users who can currently see a message: both sides of a direct message,
+/// or the sender and the group's current members who were present when it
+/// was posted, minus those who deleted it for themselves. Ascending by id.
+pub async fn message_participants(
+    conn: Arc<Mutex<Connection>>,
+    message_id: i64,
+) -> SqliteResult<Vec<i64>> {
+    let conn = conn.lock().unwrap();
+    participants(&conn, message_id)
+}
+
+fn participants(conn: &Connection, message_id: i64) -> SqliteResult<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT sender FROM messages WHERE id = ?1
+         UNION SELECT receiver FROM messages WHERE id = ?1 AND receiver IS NOT NULL
+         UNION SELECT cm.user_id FROM messages m
+               JOIN conversation_members cm ON cm.conversation_id = m.conversation_id
+               WHERE m.id = ?1 AND m.id > cm.joined_after AND cm.left_after IS NULL
+         EXCEPT SELECT user_id FROM message_hidden WHERE message_id = ?1
+         ORDER BY 1",
+    )?;
+    let rows = stmt.query_map(params![message_id], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// A message deleted for good, with who could see it just before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PurgedMessage {
+    pub id: i64,
+    pub sender: i64,
+    pub receiver: Option<i64>,
+    pub conversation_id: Option<i64>,
+    pub attachment_id: Option<i64>,
+    /// As [`message_participants`] reported it, ascending.
+    pub audience: Vec<i64>,
+}
+
+/// Delete `ids` and everything stored about them. Replies to them stay; as
+/// after `messages purge`, the foreign key clears their `reply_to_id`.
+fn delete_messages(tx: &rusqlite::Transaction<'_>, ids: &[i64]) -> SqliteResult<()> {
+    for &id in ids {
+        for table in ["message_edits", "message_reactions", "message_hidden"] {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE message_id = ?1"),
+                params![id],
+            )?;
+        }
+        tx.execute("DELETE FROM messages WHERE id = ?1", params![id])?;
+    }
+    Ok(())
+}
+
+/// Read the rows the `select` query (id, sender, receiver, conversation_id,
+/// attachment_id) picks, then delete them in one transaction.
+fn purge_selected(
+    conn: &mut Connection,
+    select: &str,
+    params: impl rusqlite::Params,
+) -> SqliteResult<Vec<PurgedMessage>> {
+    let tx = conn.transaction()?;
+    let mut purged = {
+        let mut stmt = tx.prepare(select)?;
+        stmt.query_map(params, |row| {
+            Ok(PurgedMessage {
+                id: row.get(0)?,
+                sender: row.get(1)?,
+                receiver: row.get(2)?,
+                conversation_id: row.get(3)?,
+                attachment_id: row.get(4)?,
+                audience: Vec::new(),
+            })
+        })?
+        .collect::<SqliteResult<Vec<_>>>()?
+    };
+    for message in &mut purged {
+        message.audience = participants(&tx, message.id)?;
+    }
+    let ids: Vec<i64> = purged.iter().map(|message| message.id).collect();
+    delete_messages(&tx, &ids)?;
+    tx.commit()?;
+    Ok(purged)
+}
+
+/// Delete up to `limit` messages whose `expires_at` is at or before `now`
+/// (Unix seconds), oldest expiry first. Saved messages go too.
+pub async fn purge_expired_messages(
+    conn: Arc<Mutex<Connection>>,
+    now: i64,
+    limit: usize,
+) -> SqliteResult<Vec<PurgedMessage>> {
+    let mut conn = conn.lock().unwrap();
+    purge_selected(
+        &mut conn,
+        "SELECT id, sender, receiver, conversation_id, attachment_id FROM messages
+         WHERE expires_at <= ?1
+         ORDER BY expires_at, id
+         LIMIT ?2",
+        params![now, limit as i64],
+    )
+}
+
+/// Delete view-once message `message_id` now that its recipient `user_id`
+/// read it. `None` when it is not a view-once message to them, or is gone.
+pub async fn purge_viewed_message(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    message_id: i64,
+) -> SqliteResult<Option<PurgedMessage>> {
+    let mut conn = conn.lock().unwrap();
+    let purged = purge_selected(
+        &mut conn,
+        &format!(
+            "SELECT id, sender, receiver, conversation_id, attachment_id FROM messages
+             WHERE id = ?1 AND view_once != 0 AND receiver = ?2 AND {}",
+            visible_to("?2")
+        ),
+        params![message_id, user_id],
+    )?;
+    Ok(purged.into_iter().next())
+}
+
+/// An uploaded file, complete or still being uploaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentRow {
+    pub id: i64,
+    /// The user who uploaded it; `None` once their account was deleted.
+    pub owner_id: Option<i64>,
+    pub file_name: String,
+    pub mime_type: String,
+    /// Declared size in bytes.
+    pub size: i64,
+    /// Declared SHA-256 of the content, lowercase hex.
+    pub sha256: String,
+    /// Bytes stored so far.
+    pub received: i64,
+    /// The upload finished and matched its checksum.
+    pub completed: bool,
+    /// Size in pixels as displayed, for JPEG and PNG images.
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub thumbnail: Option<ThumbnailRow>,
+}
+
+/// A smaller copy of an image attachment, stored as an attachment itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThumbnailRow {
+    pub id: i64,
+    pub mime_type: String,
+    pub width: i64,
+    pub height: i64,
+}
+
+const ATTACHMENT_COLUMNS: &str = "a.id, a.owner_id, a.file_name, a.mime_type, a.size, a.sha256,
+    a.received, a.completed_at IS NOT NULL, a.width, a.height,
+    t.id, t.mime_type, COALESCE(t.width, 0), COALESCE(t.height, 0)";
+
+/// Joins the thumbnail `t` read by [`ATTACHMENT_COLUMNS`].
+const THUMBNAIL_JOIN: &str = "LEFT JOIN attachments t ON t.id = a.thumbnail_id";
+
+fn attachment_from_row(row: &rusqlite::Row<'_>, first: usize) -> SqliteResult<AttachmentRow> {
+    Ok(AttachmentRow {
+        id: row.get(first)?,
+        owner_id: row.get(first + 1)?,
+        file_name: row.get(first + 2)?,
+        mime_type: row.get(first + 3)?,
+        size: row.get(first + 4)?,
+        sha256: row.get(first + 5)?,
+        received: row.get(first + 6)?,
+        completed: row.get(first + 7)?,
+        width: row.get(first + 8)?,
+        height: row.get(first + 9)?,
+        thumbnail: match row.get::<_, Option<i64>>(first + 10)? {
+            Some(id) => Some(ThumbnailRow {
+                id,
+                mime_type: row.get(first + 11)?,
+                width: row.get(first + 12)?,
+                height: row.get(first + 13)?,
+            }),
+            None => None,
+        },
+    })
+}
+
+/// An upload was refused because it would take its owner past their quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    /// Bytes the owner's attachments take up already.
+    pub used: i64,
+}
+
+/// Bytes charged to `owner_id`: the declared size of every attachment they
+/// uploaded or are uploading, whether or not the content is shared.
+fn attachment_bytes_used(conn: &Connection, owner_id: i64) -> SqliteResult<i64> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(size), 0) FROM attachments WHERE owner_id = ?1",
+        params![owner_id],
+        |row| row.get(0),
+    )
+}
+
+/// Start an upload by `owner_id`; the file itself arrives in chunks.
+/// A `quota_bytes` of 0 means no quota.
+pub async fn create_attachment(
+    conn: Arc<Mutex<Connection>>,
+    owner_id: i64,
+    file_name: &str,
+    mime_type: &str,
+    size: i64,
+    sha256: &str,
+    quota_bytes: u64,
+) -> SqliteResult<Result<AttachmentRow, QuotaExceeded>> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    if quota_bytes > 0 {
+        let used = attachment_bytes_used(&conn, owner_id)?;
+        if (used as u64).saturating_add(size as u64) > quota_bytes {
+            return Ok(Err(QuotaExceeded { used }));
+        }
+    }
+    conn.execute(
+        "INSERT INTO attachments (owner_id, file_name, mime_type, size, sha256, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![owner_id, file_name, mime_type, size, sha256, ts],
+    )?;
+    Ok(Ok(AttachmentRow {
+        id: conn.last_insert_rowid(),
+        owner_id: Some(owner_id),
+        file_name: file_name.to_string(),
+        mime_type: mime_type.to_string(),
+        size,
+        sha256: sha256.to_string(),
+        received: 0,
+        completed: false,
+        width: None,
+        height: None,
+        thumbnail: None,
+    }))
+}
+
+/// An attachment `owner_id` uploaded or is uploading.
+pub async fn get_own_attachment(
+    conn: Arc<Mutex<Connection>>,
+    owner_id: i64,
+    attachment_id: i64,
+) -> SqliteResult<Option<AttachmentRow>> {
+    let conn = conn.lock().unwrap();
+    match conn.query_row(
+        &format!(
+            "SELECT {ATTACHMENT_COLUMNS} FROM attachments a {THUMBNAIL_JOIN}
+             WHERE a.id = ?1 AND a.owner_id = ?2"
+        ),
+        params![attachment_id, owner_id],
+        |row| attachment_from_row(row, 0),
+    ) {
+        Ok(row) => Ok(Some(row)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Record how many bytes of a pending upload are stored.
+pub async fn set_attachment_received(
+    conn: Arc<Mutex<Connection>>,
+    attachment_id: i64,
+    received: i64,
+) -> SqliteResult<()> {
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "UPDATE attachments SET received = ?2 WHERE id = ?1 AND completed_at IS NULL",
+        params![attachment_id, received],
+    )?;
+    Ok(())
+}
+
+/// Record what inspecting a pending upload found: its actual MIME type and
+/// size in pixels if it decoded, its content hash after metadata was
+/// scrubbed, and its thumbnail.
+pub async fn set_attachment_media(
+    conn: Arc<Mutex<Connection>>,
+    attachment_id: i64,
+    mime_type: &str,
+    sha256: &str,
+    size: Option<(i64, i64)>,
+    thumbnail_id: Option<i64>,
+) -> SqliteResult<()> {
+    let (width, height) = size.unzip();
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "UPDATE attachments
+         SET mime_type = ?2, sha256 = ?3, width = ?4, height = ?5, thumbnail_id = ?6
+         WHERE id = ?1 AND completed_at IS NULL",
+        params![
+            attachment_id,
+            mime_type,
+            sha256,
+            width,
+            height,
+            thumbnail_id
+        ],
+    )?;
+    Ok(())
+}
+
+/// Mark a verified upload complete, which takes a reference on the blob for
+/// its content. `publish` moves the file into place while the database is
+/// locked, so [`collect_unreferenced_blobs`] cannot remove a blob that is
+/// about to gain a reference; it returns whether the file is in place.
+pub async fn complete_attachment<F>(
+    conn: Arc<Mutex<Connection>>,
+    attachment_id: i64,
+    publish: F,
+) -> SqliteResult<bool>
+where
+    F: FnOnce() -> bool,
+{
+    let ts = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    if !publish() {
+        return Ok(false);
+    }
+    conn.execute(
+        "UPDATE attachments SET completed_at = ?2 WHERE id = ?1 AND completed_at IS NULL",
+        params![attachment_id, ts],
+    )?;
+    Ok(true)
+}
+
+/// Forget an upload that never completed.
+pub async fn delete_pending_attachment(
+    conn: Arc<Mutex<Connection>>,
+    attachment_id: i64,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let deleted = conn.execute(
+        "DELETE FROM attachments WHERE id = ?1 AND completed_at IS NULL",
+        params![attachment_id],
+    )?;
+    Ok(deleted == 1)
+}
+
+/// Forget up to `limit` attachments no message carries that were started
+/// before `before`: uploads never finished or never sent, and files whose
+/// messages were all deleted or purged. Thumbnails live as long as their
+/// image does. Returns their ids so the files kept
+/// for them alone can be removed; shared content is left to
+/// [`collect_unreferenced_blobs`].
+pub async fn expire_unreferenced_attachments(
+    conn: Arc<Mutex<Connection>>,
+    before: &str,
+    limit: usize,
+) -> SqliteResult<Vec<i64>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "DELETE FROM attachments WHERE id IN (
+            SELECT id FROM attachments
+            WHERE message_refs <= 0 AND created_at < ?1
+              AND NOT EXISTS (SELECT 1 FROM attachments p WHERE p.thumbnail_id = attachments.id)
+            ORDER BY id LIMIT ?2
+         ) RETURNING id",
+    )?;
+    let ids = stmt.query_map(params![before, limit as i64], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Forget those of `ids` no message carries any more, regardless of their
+/// age, together with their thumbnails. Returns the ids forgotten so their
+/// files can be removed like those of [`expire_unreferenced_attachments`].
+pub async fn release_attachments(
+    conn: Arc<Mutex<Connection>>,
+    ids: &[i64],
+) -> SqliteResult<Vec<i64>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "DELETE FROM attachments
+         WHERE id = ?1 AND message_refs <= 0
+           AND NOT EXISTS (SELECT 1 FROM attachments p WHERE p.thumbnail_id = attachments.id)
+         RETURNING thumbnail_id",
+    )?;
+    let mut released = Vec::new();
+    let mut pending = ids.to_vec();
+    while let Some(id) = pending.pop() {
+        let mut rows = stmt.query(params![id])?;
+        if let Some(row) = rows.next()? {
+            released.push(id);
+            pending.extend(row.get::<_, Option<i64>>(0)?);
+        }
+    }
+    Ok(released)
+}
+
+/// Delete up to `limit` blobs no completed attachment uses any more.
+/// `remove` deletes the file while the database is locked (see
+/// [`complete_attachment`]) and returns whether it is gone; blobs it could not
+/// remove stay for the next run. Returns the blobs and bytes freed.
+pub async fn collect_unreferenced_blobs<F>(
+    conn: Arc<Mutex<Connection>>,
+    limit: usize,
+    mut remove: F,
+) -> SqliteResult<(usize, i64)>
+where
+    F: FnMut(&str) -> bool,
+{
+    let conn = conn.lock().unwrap();
+    let blobs = {
+        let mut stmt = conn.prepare(
+            "SELECT sha256, size FROM attachment_blobs WHERE ref_count <= 0 ORDER BY sha256 LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        rows.collect::<SqliteResult<Vec<_>>>()?
+    };
+    let (mut freed, mut bytes) = (0, 0);
+    for (sha256, size) in blobs {
+        if !remove(&sha256) {
+            continue;
+        }
+        conn.execute(
+            "DELETE FROM attachment_blobs WHERE sha256 = ?1",
+            params![sha256],
+        )?;
+        freed += 1;
+        bytes += size;
+    }
+    Ok((freed, bytes))
+}
+
+/// Attachment storage charged to one user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAttachmentUsage {
+    /// `None` for files of deleted accounts that are still sent, together.
+    pub user_id: Option<i64>,
+    pub passphrase: Option<String>,
+    pub attachments: i64,
+    pub bytes: i64,
+}
+
+/// Attachment storage across the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttachmentUsage {
+    /// Users with at least one attachment, largest first.
+    pub users: Vec<UserAttachmentUsage>,
+    /// Bytes charged to users; shared content counts once per attachment.
+    pub charged_bytes: i64,
+    pub blobs: i64,
+    /// Bytes of distinct content actually stored.
+    pub stored_bytes: i64,
+}
+
+pub async fn attachment_usage(conn: Arc<Mutex<Connection>>) -> SqliteResult<AttachmentUsage> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT a.owner_id, u.passphrase, COUNT(*), SUM(a.size)
+         FROM attachments a LEFT JOIN users u ON u.id = a.owner_id
+         GROUP BY a.owner_id
+         ORDER BY SUM(a.size) DESC, a.owner_id",
+    )?;
+    let users = stmt
+        .query_map([], |row| {
+            Ok(UserAttachmentUsage {
+                user_id: row.get(0)?,
+                passphrase: row.get(1)?,
+                attachments: row.get(2)?,
+                bytes: row.get(3)?,
+            })
+        })?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    let (blobs, stored_bytes) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM attachment_blobs",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(AttachmentUsage {
+        charged_bytes: users.iter().map(|user| user.bytes).sum(),
+        users,
+        blobs,
+        stored_bytes,
+    })
+}
+
+/// A completed attachment `user_id` may download and attach to their own
+/// messages: they uploaded it, or it is attached to a message they can see,
+/// or it is the thumbnail of such an attachment.
+pub async fn readable_attachment(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+    attachment_id: i64,
+) -> SqliteResult<Option<AttachmentRow>> {
+    let conn = conn.lock().unwrap();
+    match conn.query_row(
+        &format!(
+            "SELECT {ATTACHMENT_COLUMNS} FROM attachments a {THUMBNAIL_JOIN}
+             WHERE a.id = ?1 AND a.completed_at IS NOT NULL
+               AND (a.owner_id = ?2 OR EXISTS (
+                    SELECT 1 FROM messages WHERE messages.attachment_id = a.id AND {visible})
+                OR EXISTS (
+                    SELECT 1 FROM attachments p JOIN messages ON messages.attachment_id = p.id
+                    WHERE p.thumbnail_id = a.id AND {visible}))",
+            visible = visible_to("?2")
+        ),
+        params![attachment_id, user_id],
+        |row| attachment_from_row(row, 0),
+    ) {
+        Ok(row) => Ok(Some(row)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Role of a group member, from most to least privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GroupRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl GroupRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GroupRole::Owner => "owner",
+            GroupRole::Admin => "admin",
+            GroupRole::Member => "member",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(GroupRole::Owner),
+            "admin" => Some(GroupRole::Admin),
+            "member" => Some(GroupRole::Member),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationMember {
+    pub user_id: i64,
+    pub role: GroupRole,
+    /// Unix seconds until which the member may not post.
+    pub muted_until: Option<i64>,
+}
+
+/// A group conversation with its current members.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationRow {
+    pub id: i64,
+    pub name: String,
+    /// Members that have not left, ascending by user id.
+    pub members: Vec<ConversationMember>,
+}
+
+impl ConversationRow {
+    pub fn member_ids(&self) -> Vec<i64> {
+        self.members.iter().map(|m| m.user_id).collect()
+    }
+
+    pub fn member(&self, user_id: i64) -> Option<&ConversationMember> {
+        self.members.iter().find(|m| m.user_id == user_id)
+    }
+}
+
+// New members only see messages posted after they joined.
+const LATEST_MESSAGE_ID: &str = "(SELECT COALESCE(MAX(id), 0) FROM messages)";
+
+fn load_conversation(
+    conn: &Connection,
+    conversation_id: i64,
+) -> SqliteResult<Option<ConversationRow>> {
+    let name = match conn.query_row(
+        "SELECT name FROM conversations WHERE id = ?1",
+        params![conversation_id],
+        |row| row.get::<_, String>(0),
+    ) {
+        Ok(name) => name,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut stmt = conn.prepare(
+        "SELECT user_id, role, muted_until FROM conversation_members
+         WHERE conversation_id = ?1 AND left_after IS NULL
+         ORDER BY user_id ASC",
+    )?;
+    let members = stmt
+        .query_map(params![conversation_id], |row| {
+            let role: String = row.get(1)?;
+            Ok(ConversationMember {
+                user_id: row.get(0)?,
+                role: GroupRole::parse(&role).unwrap_or(GroupRole::Member),
+                muted_until: row.get(2)?,
+            })
+        })?
+        .collect::<SqliteResult<Vec<_>>>()?;
+    Ok(Some(ConversationRow {
+        id: conversation_id,
+        name,
+        members,
+    }))
+}
+
+/// Ids among `user_ids` that do not belong to any account.
+pub async fn missing_user_ids(
+    conn: Arc<Mutex<Connection>>,
+    user_ids: &[i64],
+) -> SqliteResult<Vec<i64>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT 1 FROM users WHERE id = ?1")?;
+    let mut missing = Vec::new();
+    for &user_id in user_ids {
+        if !stmt.exists(params![user_id])? {
+            missing.push(user_id);
+        }
+    }
+    Ok(missing)
+}
+
+/// Create a group owned by `creator`, with `member_ids` as plain members.
+pub async fn create_conversation(
+    conn: Arc<Mutex<Connection>>,
+    creator: i64,
+    name: &str,
+    member_ids: &[i64],
+) -> SqliteResult<ConversationRow> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO conversations (name, created_by, created_at) VALUES (?1, ?2, ?3)",
+        params![name, creator, ts],
+    )?;
+    let conversation_id = tx.last_insert_rowid();
+    {
+        let mut stmt = tx.prepare(&format!(
+            "INSERT OR IGNORE INTO conversation_members (conversation_id, user_id, role, joined_after)
+             VALUES (?1, ?2, ?3, {LATEST_MESSAGE_ID})"
+        ))?;
+        stmt.execute(params![conversation_id, creator, GroupRole::Owner.as_str()])?;
+        for &user_id in member_ids {
+            stmt.execute(params![
+                conversation_id,
+                user_id,
+                GroupRole::Member.as_str()
+            ])?;
+        }
+    }
+    let row = load_conversation(&tx, conversation_id)?.expect("conversation was just created");
+    tx.commit()?;
+    Ok(row)
+}
+
+pub async fn get_conversation(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+) -> SqliteResult<Option<ConversationRow>> {
+    let conn = conn.lock().unwrap();
+    load_conversation(&conn, conversation_id)
+}
+
+/// Groups `user_id` currently belongs to, oldest first.
+pub async fn conversations_for_user(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+) -> SqliteResult<Vec<ConversationRow>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT conversation_id FROM conversation_members
+         WHERE user_id = ?1 AND left_after IS NULL
+         ORDER BY conversation_id ASC",
+    )?;
+    let ids = stmt
+        .query_map(params![user_id], |row| row.get(0))?
+        .collect::<SqliteResult<Vec<i64>>>()?;
+    ids.into_iter()
+        .filter_map(|id| load_conversation(&conn, id).transpose())
+        .collect()
+}
+
+/// Add members (or bring back ones who left, as plain members; a running
+/// mute is kept). Returns the ids that were not members before.
+pub async fn add_conversation_members(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    user_ids: &[i64],
+) -> SqliteResult<Vec<i64>> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let mut added = Vec::new();
+    {
+        let mut stmt = tx.prepare(&format!(
+            "INSERT INTO conversation_members (conversation_id, user_id, joined_after)
+             VALUES (?1, ?2, {LATEST_MESSAGE_ID})
+             ON CONFLICT(conversation_id, user_id) DO UPDATE
+                SET joined_after = excluded.joined_after, left_after = NULL, role = 'member'
+                WHERE left_after IS NOT NULL"
+        ))?;
+        for &user_id in user_ids {
+            if stmt.execute(params![conversation_id, user_id])? == 1 && !added.contains(&user_id) {
+                added.push(user_id);
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(added)
+}
+
+/// End a membership, by leaving or being kicked. Returns `false` when the
+/// user was not a member.
+pub async fn leave_conversation(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    user_id: i64,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        &format!(
+            "UPDATE conversation_members SET left_after = {LATEST_MESSAGE_ID}
+             WHERE conversation_id = ?1 AND user_id = ?2 AND left_after IS NULL"
+        ),
+        params![conversation_id, user_id],
+    )?;
+    Ok(updated == 1)
+}
+
+pub async fn rename_conversation(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    name: &str,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE conversations SET name = ?1 WHERE id = ?2",
+        params![name, conversation_id],
+    )?;
+    Ok(updated == 1)
+}
+
+/// Make a current member an admin or a plain member. The owner changes only
+/// through [`transfer_conversation_ownership`].
+pub async fn set_member_role(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    user_id: i64,
+    role: GroupRole,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE conversation_members SET role = ?3
+         WHERE conversation_id = ?1 AND user_id = ?2 AND left_after IS NULL AND role != 'owner'",
+        params![conversation_id, user_id, role.as_str()],
+    )?;
+    Ok(updated == 1)
+}
+
+/// `None` lifts a mute.
+pub async fn set_member_muted_until(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    user_id: i64,
+    muted_until: Option<i64>,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE conversation_members SET muted_until = ?3
+         WHERE conversation_id = ?1 AND user_id = ?2 AND left_after IS NULL",
+        params![conversation_id, user_id, muted_until],
+    )?;
+    Ok(updated == 1)
+}
+
+/// Hand the group from `from` (which becomes an admin) to `to`. Returns
+/// `false`, changing nothing, unless `from` is the owner and `to` a member.
+pub async fn transfer_conversation_ownership(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    from: i64,
+    to: i64,
+) -> SqliteResult<bool> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let demoted = tx.execute(
+        "UPDATE conversation_members SET role = 'admin'
+         WHERE conversation_id = ?1 AND user_id = ?2 AND left_after IS NULL AND role = 'owner'",
+        params![conversation_id, from],
+    )?;
+    let promoted = tx.execute(
+        "UPDATE conversation_members SET role = 'owner', muted_until = NULL
+         WHERE conversation_id = ?1 AND user_id = ?2 AND left_after IS NULL AND user_id != ?3",
+        params![conversation_id, to, from],
+    )?;
+    if demoted != 1 || promoted != 1 {
+        return Ok(false);
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+fn insert_group_message(
+    conn: Arc<Mutex<Connection>>,
+    sender: i64,
+    conversation_id: i64,
+    content: &str,
+    saved: bool,
+    kind: &str,
+) -> SqliteResult<i64> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "INSERT INTO messages (sender, conversation_id, content, timestamp, kind)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![sender, conversation_id, content, ts, kind],
+    )?;
+    let id = conn.last_insert_rowid();
+    if saved {
+        bookmark_for_sender(&conn, sender, id, &ts)?;
+    }
+    Ok(id)
+}
+
+pub async fn store_group_message(
+    conn: Arc<Mutex<Connection>>,
+    from_user_id: i64,
+    conversation_id: i64,
+    content: &str,
+    saved: bool,
+) -> SqliteResult<i64> {
+    insert_group_message(conn, from_user_id, conversation_id, content, saved, "text")
+}
+
+/// Record a change to the group in its history, attributed to `actor_id`.
+pub async fn store_system_message(
+    conn: Arc<Mutex<Connection>>,
+    actor_id: i64,
+    conversation_id: i64,
+    content: &str,
+) -> SqliteResult<i64> {
+    insert_group_message(conn, actor_id, conversation_id, content, false, "system")
+}
+
+/// Role of a channel member. Owners and publishers post; subscribers read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChannelRole {
+    Owner,
+    Publisher,
+    Subscriber,
+}
+
+impl ChannelRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChannelRole::Owner => "owner",
+            ChannelRole::Publisher => "publisher",
+            ChannelRole::Subscriber => "subscriber",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(ChannelRole::Owner),
+            "publisher" => Some(ChannelRole::Publisher),
+            "subscriber" => Some(ChannelRole::Subscriber),
+            _ => None,
+        }
+    }
+
+    pub fn can_post(self) -> bool {
+        self <= ChannelRole::Publisher
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelRow {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub invite_only: bool,
+    pub owner_id: Option<i64>,
+    /// Every member, publishers and the owner included.
+    pub member_count: i64,
+}
+
+/// A channel as seen by one user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserChannelRow {
+    pub channel: ChannelRow,
+    /// `None` when the user is not a member.
+    pub role: Option<ChannelRole>,
+    /// The user holds an unused invite.
+    pub invited: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelPostRow {
+    pub id: i64,
+    pub channel_id: i64,
+    pub sender: i64,
+    pub content: String,
+    pub timestamp: String,
+}
+
+const CHANNEL_COLUMNS: &str = "c.id, c.name, c.description, c.invite_only,
+    (SELECT user_id FROM channel_members WHERE channel_id = c.id AND role = 'owner'),
+    (SELECT COUNT(*) FROM channel_members WHERE channel_id = c.id)";
+
+fn channel_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<ChannelRow> {
+    Ok(ChannelRow {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        description: row.get(2)?,
+        invite_only: row.get::<_, i64>(3)? != 0,
+        owner_id: row.get(4)?,
+        member_count: row.get(5)?,
+    })
+}
+
+fn load_channel(conn: &Connection, channel_id: i64) -> SqliteResult<Option<ChannelRow>> {
+    match conn.query_row(
+        &format!("SELECT {CHANNEL_COLUMNS} FROM channels c WHERE c.id = ?1"),
+        params![channel_id],
+        channel_from_row,
+    ) {
+        Ok(row) => Ok(Some(row)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Create a channel owned by `owner`. Returns `None` when the name is taken.
+pub async fn create_channel(
+    conn: Arc<Mutex<Connection>>,
+    owner: i64,
+    name: &str,
+    description: &str,
+    invite_only: bool,
+) -> SqliteResult<Option<ChannelRow>> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let inserted = tx.execute(
+        "INSERT OR IGNORE INTO channels (name, description, invite_only, created_by, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            name,
+            description,
+            if invite_only { 1 } else { 0 },
+            owner,
+            ts
+        ],
+    )?;
+    if inserted == 0 {
+        return Ok(None);
+    }
+    let channel_id = tx.last_insert_rowid();
+    tx.execute(
+        "INSERT INTO channel_members (channel_id, user_id, role, joined_at) VALUES (?1, ?2, 'owner', ?3)",
+        params![channel_id, owner, ts],
+    )?;
+    let row = load_channel(&tx, channel_id)?;
+    tx.commit()?;
+    Ok(row)
+}
+
+pub async fn get_channel(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+) -> SqliteResult<Option<ChannelRow>> {
+    let conn = conn.lock().unwrap();
+    load_channel(&conn, channel_id)
+}
+
+/// `user_id`'s view of one channel.
+pub async fn get_user_channel(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    user_id: i64,
+) -> SqliteResult<Option<UserChannelRow>> {
+    let conn = conn.lock().unwrap();
+    let Some(channel) = load_channel(&conn, channel_id)? else {
+        return Ok(None);
+    };
+    let role = match conn.query_row(
+        "SELECT role FROM channel_members WHERE channel_id = ?1 AND user_id = ?2",
+        params![channel_id, user_id],
+        |row| row.get::<_, String>(0),
+    ) {
+        Ok(role) => ChannelRole::parse(&role),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e),
+    };
+    let invited = conn
+        .prepare("SELECT 1 FROM channel_invites WHERE channel_id = ?1 AND user_id = ?2")?
+        .exists(params![channel_id, user_id])?;
+    Ok(Some(UserChannelRow {
+        channel,
+        role,
+        invited,
+    }))
+}
+
+/// Public channels plus every channel the user belongs to or is invited to,
+/// by id.
+pub async fn channels_for_user(
+    conn: Arc<Mutex<Connection>>,
+    user_id: i64,
+) -> SqliteResult<Vec<UserChannelRow>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {CHANNEL_COLUMNS}, m.role, i.user_id IS NOT NULL
+         FROM channels c
+         LEFT JOIN channel_members m ON m.channel_id = c.id AND m.user_id = ?1
+         LEFT JOIN channel_invites i ON i.channel_id = c.id AND i.user_id = ?1
+         WHERE c.invite_only = 0 OR m.user_id IS NOT NULL OR i.user_id IS NOT NULL
+         ORDER BY c.id ASC"
+    ))?;
+    let rows = stmt.query_map(params![user_id], |row| {
+        let role: Option<String> = row.get(6)?;
+        Ok(UserChannelRow {
+            channel: channel_from_row(row)?,
+            role: role.as_deref().and_then(ChannelRole::parse),
+            invited: row.get(7)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Invite users that are not members yet. Returns the ids that got a new
+/// invite.
+pub async fn invite_to_channel(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    invited_by: i64,
+    user_ids: &[i64],
+) -> SqliteResult<Vec<i64>> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let mut invited = Vec::new();
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR IGNORE INTO channel_invites (channel_id, user_id, invited_by, created_at)
+             SELECT ?1, ?2, ?3, ?4
+             WHERE NOT EXISTS (
+                SELECT 1 FROM channel_members WHERE channel_id = ?1 AND user_id = ?2
+             )",
+        )?;
+        for &user_id in user_ids {
+            if stmt.execute(params![channel_id, user_id, invited_by, ts])? == 1 {
+                invited.push(user_id);
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(invited)
+}
+
+/// Join as a subscriber, using up an invite if there is one. Returns `false`
+/// when the user already was a member.
+pub async fn subscribe_channel(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    user_id: i64,
+) -> SqliteResult<bool> {
+    let ts = chrono::Local::now().to_rfc3339();
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let added = tx.execute(
+        "INSERT OR IGNORE INTO channel_members (channel_id, user_id, role, joined_at)
+         VALUES (?1, ?2, 'subscriber', ?3)",
+        params![channel_id, user_id, ts],
+    )?;
+    tx.execute(
+        "DELETE FROM channel_invites WHERE channel_id = ?1 AND user_id = ?2",
+        params![channel_id, user_id],
+    )?;
+    tx.commit()?;
+    Ok(added == 1)
+}
+
+/// Leave a channel; the owner cannot. Returns `false` when nothing changed.
+pub async fn unsubscribe_channel(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    user_id: i64,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let removed = conn.execute(
+        "DELETE FROM channel_members WHERE channel_id = ?1 AND user_id = ?2 AND role != 'owner'",
+        params![channel_id, user_id],
+    )?;
+    Ok(removed == 1)
+}
+
+/// Make a member a publisher or a subscriber; the owner keeps their role.
+pub async fn set_channel_role(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    user_id: i64,
+    role: ChannelRole,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE channel_members SET role = ?3
+         WHERE channel_id = ?1 AND user_id = ?2 AND role != 'owner'",
+        params![channel_id, user_id, role.as_str()],
+    )?;
+    Ok(updated == 1)
+}
+
+/// Every member of the channel, ascending.
+pub async fn channel_member_ids(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+) -> SqliteResult<Vec<i64>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT user_id FROM channel_members WHERE channel_id = ?1 ORDER BY user_id ASC",
+    )?;
+    stmt.query_map(params![channel_id], |row| row.get(0))?
+        .collect()
+}
+
+pub async fn store_channel_post(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    sender: i64,
+    content: &str,
+) -> SqliteResult<ChannelPostRow> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "INSERT INTO channel_posts (channel_id, sender, content, timestamp) VALUES (?1, ?2, ?3, ?4)",
+        params![channel_id, sender, content, timestamp],
+    )?;
+    Ok(ChannelPostRow {
+        id: conn.last_insert_rowid(),
+        channel_id,
+        sender,
+        content: content.to_string(),
+        timestamp,
+    })
+}
+
+/// Up to `limit` posts with an id above `after_id`, oldest first.
+pub async fn fetch_channel_posts(
+    conn: Arc<Mutex<Connection>>,
+    channel_id: i64,
+    after_id: i64,
+    limit: usize,
+) -> SqliteResult<Vec<ChannelPostRow>> {
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT id, channel_id, sender, content, timestamp FROM channel_posts
+         WHERE channel_id = ?1 AND id > ?2
+         ORDER BY id ASC
+         LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(params![channel_id, after_id, limit as i64], |row| {
+        Ok(ChannelPostRow {
+            id: row.get(0)?,
+            channel_id: row.get(1)?,
+            sender: row.get(2)?,
+            content: row.get(3)?,
+            timestamp: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Delete messages stamped strictly before `before` (RFC 3339). Saved messages
+/// are kept unless `include_saved` is set. Returns the number of rows removed.
+pub async fn purge_messages_before(
+    conn: Arc<Mutex<Connection>>,
+    before: &str,
+    include_saved: bool,
+) -> SqliteResult<usize> {
+    let conn = conn.lock().unwrap();
+    // Compare as instants: stored timestamps carry their own UTC offset
+    conn.execute(
+        "DELETE FROM messages
+         WHERE julianday(timestamp) < julianday(?1)
+           AND (?2 OR saved = 0)",
+        params![before, include_saved],
+    )
+}
+
+/// SQL condition: the `messages m` row (joined with `conversations c`) is
+/// past its retention period as of `?1` (RFC 3339), given the server-wide
+/// `message_days` at `?2`. Saved messages are always kept.
+const PAST_RETENTION: &str = "m.saved = 0
+    AND COALESCE(c.retention_days, ?2) > 0
+    AND julianday(m.timestamp) < julianday(?1) - COALESCE(c.retention_days, ?2)";
+
+/// Delete up to `limit` messages past their retention period as of `now`
+/// (RFC 3339), oldest first, together with their revisions, reactions and
+/// hidden marks. Returns how many were deleted and their attachment ids.
+pub async fn purge_old_messages(
+    conn: Arc<Mutex<Connection>>,
+    now: &str,
+    message_days: u32,
+    limit: usize,
+) -> SqliteResult<(usize, Vec<i64>)> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let rows: Vec<(i64, Option<i64>)> = {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT m.id, m.attachment_id FROM messages m
+             LEFT JOIN conversations c ON c.id = m.conversation_id
+             WHERE {PAST_RETENTION}
+             ORDER BY m.id
+             LIMIT ?3"
+        ))?;
+        stmt.query_map(params![now, message_days, limit as i64], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<SqliteResult<_>>()?
+    };
+    let ids: Vec<i64> = rows.iter().map(|(id, _)| *id).collect();
+    delete_messages(&tx, &ids)?;
+    tx.commit()?;
+    Ok((ids.len(), rows.into_iter().filter_map(|(_, a)| a).collect()))
+}
+
+/// Delete up to `limit` `connections` rows older than `days` as of `now`
+/// (RFC 3339). `days` of 0 keeps them all.
+pub async fn purge_old_connections(
+    conn: Arc<Mutex<Connection>>,
+    now: &str,
+    days: u32,
+    limit: usize,
+) -> SqliteResult<usize> {
+    if days == 0 {
+        return Ok(0);
+    }
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "DELETE FROM connections WHERE id IN (
+            SELECT id FROM connections
+            WHERE julianday(timestamp) < julianday(?1) - ?2
+            ORDER BY id
+            LIMIT ?3
+        )",
+        params![now, days, limit as i64],
+    )
+}
+
+/// Delete up to `limit` `auth_events` rows older than `days` as of `now`
+/// (RFC 3339). `days` of 0 keeps them all.
+pub async fn purge_old_auth_events(
+    conn: Arc<Mutex<Connection>>,
+    now: &str,
+    days: u32,
+    limit: usize,
+) -> SqliteResult<usize> {
+    if days == 0 {
+        return Ok(0);
+    }
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        "DELETE FROM auth_events WHERE id IN (
+            SELECT id FROM auth_events
+            WHERE julianday(timestamp) < julianday(?1) - ?2
+            ORDER BY id
+            LIMIT ?3
+        )",
+        params![now, days, limit as i64],
+    )
+}
+
+/// `login_throttle` rows last touched more than `?2` days before `?1` and
+/// no longer blocking anyone.
+const STALE_THROTTLE: &str = "updated_at < CAST(strftime('%s', ?1) AS INTEGER) - ?2 * 86400
+     AND blocked_until <= CAST(strftime('%s', ?1) AS INTEGER)";
+
+/// Delete up to `limit` failure counters nobody touched in `days` as of
+/// `now` (RFC 3339), keeping blocks that still run. `days` of 0 keeps them
+/// all.
+pub async fn purge_old_login_throttle(
+    conn: Arc<Mutex<Connection>>,
+    now: &str,
+    days: u32,
+    limit: usize,
+) -> SqliteResult<usize> {
+    if days == 0 {
+        return Ok(0);
+    }
+    let conn = conn.lock().unwrap();
+    conn.execute(
+        &format!(
+            "DELETE FROM login_throttle WHERE rowid IN (
+                SELECT rowid FROM login_throttle
+                WHERE {STALE_THROTTLE}
+                ORDER BY updated_at
+                LIMIT ?3
+            )"
+        ),
+        params![now, days, limit as i64],
+    )
+}
+
+/// Messages of one group the retention policy would delete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupRetention {
+    pub conversation_id: i64,
+    pub name: String,
+    /// The group's override; `None` follows `retention.message_days`.
+    pub retention_days: Option<u32>,
+    pub messages: usize,
+}
+
+/// What the retention policy would delete as of `now`; see
+/// [`retention_preview`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPreview {
+    pub direct_messages: usize,
+    /// Groups with an override or with messages to delete, by id.
+    pub groups: Vec<GroupRetention>,
+    pub connections: usize,
+    pub auth_events: usize,
+    pub login_throttle: usize,
+}
+
+/// Count what [`purge_old_messages`], [`purge_old_connections`],
+/// [`purge_old_auth_events`] and [`purge_old_login_throttle`] would delete
+/// as of `now` (RFC 3339), without deleting anything.
+pub async fn retention_preview(
+    conn: Arc<Mutex<Connection>>,
+    now: &str,
+    message_days: u32,
+    connection_days: u32,
+) -> SqliteResult<RetentionPreview> {
+    let conn = conn.lock().unwrap();
+    let direct_messages = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM messages m
+             LEFT JOIN conversations c ON c.id = m.conversation_id
+             WHERE m.conversation_id IS NULL AND {PAST_RETENTION}"
+        ),
+        params![now, message_days],
+        |row| row.get::<_, i64>(0),
+    )? as usize;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT c.id, c.name, c.retention_days,
+                (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id AND {PAST_RETENTION})
+         FROM conversations c
+         ORDER BY c.id"
+    ))?;
+    let groups = stmt
+        .query_map(params![now, message_days], |row| {
+            Ok(GroupRetention {
+                conversation_id: row.get(0)?,
+                name: row.get(1)?,
+                retention_days: row.get(2)?,
+                messages: row.get::<_, i64>(3)? as usize,
+            })
+        })?
+        .filter(|group| {
+            group
+                .as_ref()
+                .map_or(true, |g| g.retention_days.is_some() || g.messages > 0)
+        })
+        .collect::<SqliteResult<Vec<_>>>()?;
+    let count_old = |sql: &str| -> SqliteResult<usize> {
+        if connection_days == 0 {
+            return Ok(0);
+        }
+        Ok(conn.query_row(sql, params![now, connection_days], |row| {
+            row.get::<_, i64>(0)
+        })? as usize)
+    };
+    Ok(RetentionPreview {
+        direct_messages,
+        groups,
+        connections: count_old(
+            "SELECT COUNT(*) FROM connections WHERE julianday(timestamp) < julianday(?1) - ?2",
+        )?,
+        auth_events: count_old(
+            "SELECT COUNT(*) FROM auth_events WHERE julianday(timestamp) < julianday(?1) - ?2",
+        )?,
+        login_throttle: count_old(&format!(
+            "SELECT COUNT(*) FROM login_throttle WHERE {STALE_THROTTLE}"
+        ))?,
+    })
+}
+
+/// Override `retention.message_days` for one group: `Some(0)` keeps its
+/// messages forever, `None` follows the server-wide setting again. Returns
+/// false when there is no such group.
+pub async fn set_conversation_retention(
+    conn: Arc<Mutex<Connection>>,
+    conversation_id: i64,
+    days: Option<u32>,
+) -> SqliteResult<bool> {
+    let conn = conn.lock().unwrap();
+    let updated = conn.execute(
+        "UPDATE conversations SET retention_days = ?2 WHERE id = ?1",
+        params![conversation_id, days],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Results of SQLite's integrity and foreign key checks; empty means healthy.
+pub async fn check_db(conn: Arc<Mutex<Connection>>) -> SqliteResult<Vec<String>> {
+    let conn = conn.lock().unwrap();
+    let mut problems = Vec::new();
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    for row in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        let row = row?;
+        if row != "ok" {
+            problems.push(row);
+        }
+    }
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let violations = stmt.query_map([], |row| {
+        Ok(format!(
+            "foreign key violation in {} (rowid {})",
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<i64>>(1)?.unwrap_or_default()
+        ))
+    })?;
+    for violation in violations {
+        problems.push(violation?);
+    }
+    Ok(problems)
+}
+
+pub async fn vacuum_db(conn: Arc<Mutex<Connection>>) -> SqliteResult<()> {
+    let conn = conn.lock().unwrap();
+    conn.execute_batch("VACUUM")
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbStats {
+    pub schema_version: u32,
+    pub users: i64,
+    pub disabled_users: i64,
+    pub messages: i64,
+    pub saved_messages: i64,
+    pub connections: i64,
+    pub auth_events: i64,
+    pub throttled_logins: i64,
+}
+
+pub async fn db_stats(conn: Arc<Mutex<Connection>>) -> SqliteResult<DbStats> {
+    let conn = conn.lock().unwrap();
+    let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0));
+    Ok(DbStats {
+        schema_version: current_version(&conn)?,
+        users: count("SELECT COUNT(*) FROM users")?,
+        disabled_users: count("SELECT COUNT(*) FROM users WHERE disabled != 0")?,
+        messages: count("SELECT COUNT(*) FROM messages")?,
+        saved_messages: count("SELECT COUNT(*) FROM messages WHERE saved != 0")?,
+        connections: count("SELECT COUNT(*) FROM connections")?,
+        auth_events: count("SELECT COUNT(*) FROM auth_events")?,
+        throttled_logins: count(
+            "SELECT COUNT(*) FROM login_throttle WHERE blocked_until > CAST(strftime('%s', 'now') AS INTEGER)",
+        )?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
     use std::sync::{Arc, Mutex};
@@ -302,6 +2897,17 @@ mod tests {
         assert!(err.to_string().contains("already exists"));
     }
 
+    #[test]
+    fn dummy_password_hash_costs_as_much_as_a_real_one() {
+        let real = hash_password("secret").unwrap();
+        let real = PasswordHash::new(&real).unwrap();
+        let dummy = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        assert_eq!(dummy.algorithm, real.algorithm);
+        assert_eq!(dummy.version, real.version);
+        assert_eq!(dummy.params, real.params);
+        assert!(!password_matches(DUMMY_PASSWORD_HASH, "secret").unwrap());
+    }
+
     #[tokio::test]
     async fn authenticate_user_validates_credentials() {
         let conn = Arc::new(Mutex::new(
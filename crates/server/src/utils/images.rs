@@ -0,0 +1,751 @@
+use std::io::Cursor;
+
+/// PNG frames are only decoded whole for interlaced images; larger ones get
+/// no thumbnail.
+const MAX_FRAME_BYTES: usize = 64 * 1024 * 1024;
+/// JPEG quality of opaque thumbnails.
+const THUMBNAIL_QUALITY: u8 = 80;
+
+/// Image formats recognized by their leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Jpeg,
+    Png,
+}
+
+impl ImageKind {
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+        }
+    }
+}
+
+/// Bounds for [`inspect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageLimits {
+    /// Longest side of a thumbnail; 0 disables thumbnails.
+    pub thumbnail_px: u32,
+    /// Images with more pixels than this are not decoded.
+    pub max_pixels: u64,
+}
+
+/// An encoded thumbnail: JPEG, or PNG when the image has transparency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub kind: ImageKind,
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What [`inspect`] learned about an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageMeta {
+    pub kind: ImageKind,
+    /// EXIF GPS data was found and blanked.
+    pub location_removed: bool,
+    /// Size as displayed, after the EXIF orientation is applied; `None`
+    /// when the header cannot be decoded.
+    pub size: Option<(u32, u32)>,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+/// Inspect `bytes` as a JPEG or PNG image and blank its EXIF GPS data in
+/// place; the file keeps its size and every other tag. The location is
+/// blanked even when the image itself fails to decode. `None` when it is
+/// neither format.
+pub fn inspect(bytes: &mut [u8], limits: &ImageLimits) -> Option<ImageMeta> {
+    let kind = ImageKind::sniff(bytes)?;
+    let exif = match kind {
+        ImageKind::Jpeg => scrub_jpeg(bytes),
+        ImageKind::Png => scrub_png(bytes),
+    };
+    let decoded = match kind {
+        ImageKind::Jpeg => decode_jpeg(bytes, limits),
+        ImageKind::Png => decode_png(bytes, limits),
+    };
+    let Some((width, height, pixels)) = decoded else {
+        return Some(ImageMeta {
+            kind,
+            location_removed: exif.location_removed,
+            size: None,
+            thumbnail: None,
+        });
+    };
+    let size = if exif.orientation >= 5 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let thumbnail = pixels
+        .map(|pixels| pixels.oriented(exif.orientation))
+        .and_then(|pixels| pixels.encode());
+    Some(ImageMeta {
+        kind,
+        location_removed: exif.location_removed,
+        size: Some(size),
+        thumbnail,
+    })
+}
+
+/// Largest size within a `max` by `max` box with the same aspect ratio.
+/// Images that already fit are not enlarged.
+fn fit(width: u32, height: u32, max: u32) -> (u32, u32) {
+    if width <= max && height <= max {
+        return (width, height);
+    }
+    let scale = |short: u32, long: u32| {
+        ((short as u64 * max as u64 + long as u64 / 2) / long as u64).max(1) as u32
+    };
+    if width >= height {
+        (max, scale(height, width))
+    } else {
+        (scale(width, height), max)
+    }
+}
+
+fn wants_thumbnail(width: u32, height: u32, limits: &ImageLimits) -> bool {
+    limits.thumbnail_px > 0
+        && width > 0
+        && height > 0
+        && width as u64 * height as u64 <= limits.max_pixels
+}
+
+/// Decoded RGBA pixels.
+struct Pixels {
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl Pixels {
+    /// Apply an EXIF orientation (1 to 8) so the pixels appear as displayed.
+    fn oriented(self, orientation: u16) -> Self {
+        if !(2..=8).contains(&orientation) {
+            return self;
+        }
+        let (w, h) = (self.width as usize, self.height as usize);
+        let out_w = if orientation >= 5 { h } else { w };
+        let mut rgba = vec![0; self.rgba.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let (nx, ny) = match orientation {
+                    2 => (w - 1 - x, y),
+                    3 => (w - 1 - x, h - 1 - y),
+                    4 => (x, h - 1 - y),
+                    5 => (y, x),
+                    6 => (h - 1 - y, x),
+                    7 => (h - 1 - y, w - 1 - x),
+                    _ => (y, w - 1 - x),
+                };
+                let (from, to) = ((y * w + x) * 4, (ny * out_w + nx) * 4);
+                rgba[to..to + 4].copy_from_slice(&self.rgba[from..from + 4]);
+            }
+        }
+        let (width, height) = if orientation >= 5 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        Self {
+            rgba,
+            width,
+            height,
+        }
+    }
+
+    fn encode(self) -> Option<Thumbnail> {
+        let opaque = self.rgba.chunks_exact(4).all(|px| px[3] == u8::MAX);
+        let mut bytes = Vec::new();
+        let kind = if opaque {
+            let rgb: Vec<u8> = self
+                .rgba
+                .chunks_exact(4)
+                .flat_map(|px| [px[0], px[1], px[2]])
+                .collect();
+            let encoder = jpeg_encoder::Encoder::new(&mut bytes, THUMBNAIL_QUALITY);
+            encoder
+                .encode(
+                    &rgb,
+                    u16::try_from(self.width).ok()?,
+                    u16::try_from(self.height).ok()?,
+                    jpeg_encoder::ColorType::Rgb,
+                )
+                .ok()?;
+            ImageKind::Jpeg
+        } else {
+            let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().ok()?;
+            writer.write_image_data(&self.rgba).ok()?;
+            writer.finish().ok()?;
+            ImageKind::Png
+        };
+        Some(Thumbnail {
+            kind,
+            bytes,
+            width: self.width,
+            height: self.height,
+        })
+    }
+}
+
+/// Box filter fed one source row at a time, so memory stays proportional
+/// to the image width.
+struct Downscaler {
+    src_height: u32,
+    width: u32,
+    height: u32,
+    /// Output column of each source column.
+    columns: Vec<usize>,
+    sums: Vec<u64>,
+    counts: Vec<u64>,
+    src_y: u32,
+    out_y: u32,
+    out: Vec<u8>,
+}
+
+impl Downscaler {
+    fn new(src_width: u32, src_height: u32, width: u32, height: u32) -> Self {
+        Self {
+            src_height,
+            width,
+            height,
+            columns: (0..src_width as u64)
+                .map(|x| (x * width as u64 / src_width as u64) as usize)
+                .collect(),
+            sums: vec![0; width as usize * 4],
+            counts: vec![0; width as usize],
+            src_y: 0,
+            out_y: 0,
+            out: Vec::with_capacity(width as usize * height as usize * 4),
+        }
+    }
+
+    /// Add the next source row, given as RGBA.
+    fn push_row(&mut self, rgba: &[u8]) {
+        let out_y = (self.src_y as u64 * self.height as u64 / self.src_height as u64) as u32;
+        if out_y != self.out_y {
+            self.flush();
+            self.out_y = out_y;
+        }
+        for (px, &column) in rgba.chunks_exact(4).zip(&self.columns) {
+            for (sum, &value) in self.sums[column * 4..column * 4 + 4].iter_mut().zip(px) {
+                *sum += value as u64;
+            }
+            self.counts[column] += 1;
+        }
+        self.src_y += 1;
+    }
+
+    fn flush(&mut self) {
+        for (sums, count) in self.sums.chunks_exact_mut(4).zip(&mut self.counts) {
+            for sum in sums {
+                self.out.push(((*sum + *count / 2) / (*count).max(1)) as u8);
+                *sum = 0;
+            }
+            *count = 0;
+        }
+    }
+
+    /// The scaled pixels; `None` unless every source row was pushed.
+    fn finish(mut self) -> Option<Pixels> {
+        if self.src_y != self.src_height {
+            return None;
+        }
+        self.flush();
+        Some(Pixels {
+            rgba: self.out,
+            width: self.width,
+            height: self.height,
+        })
+    }
+}
+
+/// Expand one row of 1 to 4 channel 8-bit samples to RGBA.
+fn to_rgba(row: &[u8], channels: usize, rgba: &mut [u8]) {
+    for (src, dst) in row.chunks_exact(channels).zip(rgba.chunks_exact_mut(4)) {
+        let px = match *src {
+            [g] => [g, g, g, u8::MAX],
+            [g, a] => [g, g, g, a],
+            [r, g, b] => [r, g, b, u8::MAX],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!("1 to 4 channels"),
+        };
+        dst.copy_from_slice(&px);
+    }
+}
+
+/// Stored size of a JPEG, and its pixels scaled to thumbnail size. The DCT
+/// scales down by up to 8 while decoding, so full-size pixels never exist.
+fn decode_jpeg(bytes: &[u8], limits: &ImageLimits) -> Option<(u32, u32, Option<Pixels>)> {
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(bytes));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let (width, height) = (info.width as u32, info.height as u32);
+    if !wants_thumbnail(width, height, limits) {
+        return Some((width, height, None));
+    }
+    let (out_width, out_height) = fit(width, height, limits.thumbnail_px);
+    let mut thumbnail = || {
+        let channels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 => 1,
+            jpeg_decoder::PixelFormat::RGB24 => 3,
+            // 16-bit and CMYK images are rare enough to go without a preview
+            jpeg_decoder::PixelFormat::L16 | jpeg_decoder::PixelFormat::CMYK32 => return None,
+        };
+        let (scaled_width, scaled_height) =
+            decoder.scale(out_width as u16, out_height as u16).ok()?;
+        decoder.set_max_decoding_buffer_size(usize::try_from(limits.max_pixels * 3).ok()?);
+        let data = decoder.decode().ok()?;
+        let (scaled_width, scaled_height) = (scaled_width as u32, scaled_height as u32);
+        let mut scaler = Downscaler::new(
+            scaled_width,
+            scaled_height,
+            out_width.min(scaled_width),
+            out_height.min(scaled_height),
+        );
+        let mut rgba = vec![0; scaled_width as usize * 4];
+        for row in data.chunks_exact(scaled_width as usize * channels) {
+            to_rgba(row, channels, &mut rgba);
+            scaler.push_row(&rgba);
+        }
+        scaler.finish()
+    };
+    Some((width, height, thumbnail()))
+}
+
+/// Size of a PNG, and its pixels scaled to thumbnail size. Only the first
+/// frame of an animated PNG is used.
+fn decode_png(bytes: &[u8], limits: &ImageLimits) -> Option<(u32, u32, Option<Pixels>)> {
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let (width, height, interlaced) = {
+        let info = reader.info();
+        (info.width, info.height, info.interlaced)
+    };
+    if !wants_thumbnail(width, height, limits) {
+        return Some((width, height, None));
+    }
+    let (out_width, out_height) = fit(width, height, limits.thumbnail_px);
+    let mut thumbnail = || {
+        let channels = reader.output_color_type().0.samples();
+        let mut scaler = Downscaler::new(width, height, out_width, out_height);
+        let mut rgba = vec![0; width as usize * 4];
+        if interlaced {
+            // Passes only make up whole rows once the frame is complete
+            let size = reader.output_buffer_size()?;
+            if size > MAX_FRAME_BYTES {
+                return None;
+            }
+            let mut frame = vec![0; size];
+            let info = reader.next_frame(&mut frame).ok()?;
+            for row in frame.chunks_exact(info.line_size).take(height as usize) {
+                to_rgba(row, channels, &mut rgba);
+                scaler.push_row(&rgba);
+            }
+        } else {
+            while let Some(row) = reader.next_row().ok()? {
+                to_rgba(row.data(), channels, &mut rgba);
+                scaler.push_row(&rgba);
+            }
+        }
+        scaler.finish()
+    };
+    Some((width, height, thumbnail()))
+}
+
+/// What an EXIF block said, and whether its location was blanked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Exif {
+    location_removed: bool,
+    /// EXIF orientation, 1 (as stored) when absent.
+    orientation: u16,
+}
+
+impl Default for Exif {
+    fn default() -> Self {
+        Self {
+            location_removed: false,
+            orientation: 1,
+        }
+    }
+}
+
+/// Scrub the EXIF (APP1) segments before the first scan of a JPEG.
+fn scrub_jpeg(bytes: &mut [u8]) -> Exif {
+    let mut exif = Exif::default();
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+        match marker {
+            // Fill byte
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD8 => {
+                pos += 2;
+                continue;
+            }
+            // Entropy-coded data follows
+            0xD9 | 0xDA => break,
+            _ => {}
+        }
+        let len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > bytes.len() {
+            break;
+        }
+        let segment = &mut bytes[pos + 4..end];
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            exif = exif.merge(scrub_tiff(&mut segment[6..]));
+        }
+        pos = end;
+    }
+    exif
+}
+
+/// Scrub the `eXIf` chunks of a PNG, fixing up their checksums.
+fn scrub_png(bytes: &mut [u8]) -> Exif {
+    let mut exif = Exif::default();
+    let mut pos = 8;
+    while pos + 12 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let Some(end) = (pos + 8)
+            .checked_add(len)
+            .filter(|end| end + 4 <= bytes.len())
+        else {
+            break;
+        };
+        let kind: [u8; 4] = bytes[pos + 4..pos + 8].try_into().unwrap();
+        if &kind == b"eXIf" {
+            let found = scrub_tiff(&mut bytes[pos + 8..end]);
+            if found.location_removed {
+                let crc = crc32fast::hash(&bytes[pos + 4..end]);
+                bytes[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+            }
+            exif = exif.merge(found);
+        } else if &kind == b"IEND" {
+            break;
+        }
+        pos = end + 4;
+    }
+    exif
+}
+
+impl Exif {
+    /// Combine with a later block; the first orientation found wins.
+    fn merge(self, later: Exif) -> Exif {
+        Exif {
+            location_removed: self.location_removed || later.location_removed,
+            orientation: if self.orientation == 1 {
+                later.orientation
+            } else {
+                self.orientation
+            },
+        }
+    }
+}
+
+/// Little- or big-endian reads within a TIFF structure.
+struct Tiff<'a> {
+    data: &'a mut [u8],
+    big_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at.checked_add(2)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        let bytes = self.data.get(at..at.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// Zero out the IFD at `at` and the values it points to, leaving an
+    /// empty directory. Malformed directories are left alone.
+    fn blank_ifd(&mut self, at: usize) -> bool {
+        let Some(count) = self.u16_at(at).map(usize::from) else {
+            return false;
+        };
+        let end = at + 2 + count * 12;
+        if count == 0 || end + 4 > self.data.len() {
+            return false;
+        }
+        for entry in (at + 2..end).step_by(12) {
+            let unit = match self.u16_at(entry + 2) {
+                Some(1 | 2 | 6 | 7) => 1,
+                Some(3 | 8) => 2,
+                Some(4 | 9 | 11) => 4,
+                Some(5 | 10 | 12) => 8,
+                _ => continue,
+            };
+            let len = self.u32_at(entry + 4).map_or(0, |n| n as usize * unit);
+            // Values of up to 4 bytes sit in the entry itself
+            if len > 4
+                && let Some(offset) = self.u32_at(entry + 8)
+                && let Some(value) = self
+                    .data
+                    .get_mut(offset as usize..(offset as usize).saturating_add(len))
+            {
+                value.fill(0);
+            }
+        }
+        self.data[at..end].fill(0);
+        true
+    }
+}
+
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_GPS_IFD: u16 = 0x8825;
+
+/// Read the orientation from IFD0 of a TIFF structure and blank its GPS IFD.
+fn scrub_tiff(data: &mut [u8]) -> Exif {
+    let mut exif = Exif::default();
+    let big_endian = match data.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return exif,
+    };
+    let mut tiff = Tiff { data, big_endian };
+    if tiff.u16_at(2) != Some(42) {
+        return exif;
+    }
+    let Some(ifd0) = tiff.u32_at(4).map(|at| at as usize) else {
+        return exif;
+    };
+    let mut gps = None;
+    for i in 0..tiff.u16_at(ifd0).unwrap_or(0) as usize {
+        let entry = ifd0 + 2 + i * 12;
+        match tiff.u16_at(entry) {
+            Some(TAG_ORIENTATION) => {
+                exif.orientation = tiff
+                    .u16_at(entry + 8)
+                    .filter(|o| (1..=8).contains(o))
+                    .unwrap_or(1);
+            }
+            Some(TAG_GPS_IFD) => gps = tiff.u32_at(entry + 8),
+            _ => {}
+        }
+    }
+    exif.location_removed = gps.is_some_and(|at| tiff.blank_ifd(at as usize));
+    exif
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ImageLimits = ImageLimits {
+        thumbnail_px: 16,
+        max_pixels: 10_000,
+    };
+
+    /// Little-endian TIFF with an orientation tag and a GPS IFD holding a
+    /// latitude (three rationals stored out of line).
+    fn exif_tiff(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        // IFD0 at 8: two entries, then no next IFD
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(TAG_ORIENTATION.to_le_bytes());
+        tiff.extend(3u16.to_le_bytes());
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend((orientation as u32).to_le_bytes());
+        tiff.extend(TAG_GPS_IFD.to_le_bytes());
+        tiff.extend(4u16.to_le_bytes());
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(38u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        // GPS IFD at 38: GPSLatitude, 3 RATIONAL at 56
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(5u16.to_le_bytes());
+        tiff.extend(3u32.to_le_bytes());
+        tiff.extend(56u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        for value in [52u32, 1, 31, 1, 12, 1] {
+            tiff.extend(value.to_le_bytes());
+        }
+        tiff
+    }
+
+    fn jpeg(width: u16, height: u16, exif: Option<Vec<u8>>) -> Vec<u8> {
+        let rgb: Vec<u8> = (0..width as usize * height as usize)
+            .flat_map(|i| [(i % 256) as u8, 128, 64])
+            .collect();
+        let mut bytes = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut bytes, 90);
+        if let Some(tiff) = exif {
+            encoder
+                .add_app_segment(1, [b"Exif\0\0".as_slice(), &tiff].concat())
+                .unwrap();
+        }
+        encoder
+            .encode(&rgb, width, height, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+        bytes
+    }
+
+    fn png(width: u32, height: u32, alpha: u8, exif: Option<Vec<u8>>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        if let Some(tiff) = exif {
+            writer
+                .write_chunk(png::chunk::ChunkType(*b"eXIf"), &tiff)
+                .unwrap();
+        }
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|_| [10, 20, 30, alpha])
+            .collect();
+        writer.write_image_data(&rgba).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn jpeg_location_is_blanked_and_orientation_applied() {
+        let original = jpeg(40, 20, Some(exif_tiff(6)));
+        let mut bytes = original.clone();
+        let meta = inspect(&mut bytes, &LIMITS).expect("a JPEG");
+        assert_eq!(meta.kind, ImageKind::Jpeg);
+        assert!(meta.location_removed);
+        assert_eq!(bytes.len(), original.len(), "scrubbed in place");
+        let latitude: Vec<u8> = [52u32, 1, 31, 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert!(contains(&original, &latitude));
+        assert!(!contains(&bytes, &latitude));
+
+        // Rotated a quarter turn for display
+        assert_eq!(meta.size, Some((20, 40)));
+        let thumbnail = meta.thumbnail.expect("thumbnail");
+        assert_eq!(thumbnail.kind, ImageKind::Jpeg);
+        assert_eq!((thumbnail.width, thumbnail.height), (8, 16));
+        assert_eq!(ImageKind::sniff(&thumbnail.bytes), Some(ImageKind::Jpeg));
+
+        // The scrubbed file still decodes, and scrubbing again finds nothing
+        let again = inspect(&mut bytes, &LIMITS).unwrap();
+        assert!(!again.location_removed);
+        assert_eq!(again.size, Some((20, 40)));
+    }
+
+    #[test]
+    fn png_exif_is_scrubbed_with_a_valid_checksum() {
+        let mut bytes = png(30, 30, u8::MAX, Some(exif_tiff(1)));
+        let meta = inspect(&mut bytes, &LIMITS).expect("a PNG");
+        assert!(meta.location_removed);
+        assert_eq!(meta.size, Some((30, 30)));
+        // A chunk with a stale checksum would fail to decode
+        let mut reader = png::Decoder::new(Cursor::new(&bytes)).read_info().unwrap();
+        let mut frame = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut frame).unwrap();
+        assert_eq!(&frame[..4], &[10, 20, 30, 255]);
+
+        let thumbnail = meta.thumbnail.unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (16, 16));
+        assert_eq!(thumbnail.kind, ImageKind::Jpeg, "opaque images get JPEG");
+    }
+
+    #[test]
+    fn location_is_blanked_even_when_decoding_fails() {
+        let latitude: Vec<u8> = [52u32, 1, 31, 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        // EXIF but no frame header
+        let tiff = exif_tiff(1);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend((2 + 6 + tiff.len() as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(&tiff);
+        jpeg.extend([0xFF, 0xD9]);
+        // A zero width is refused by the decoder
+        let mut png = png(4, 4, u8::MAX, Some(exif_tiff(1)));
+        png[16..20].fill(0);
+
+        for (mut bytes, kind) in [(jpeg, ImageKind::Jpeg), (png, ImageKind::Png)] {
+            let meta = inspect(&mut bytes, &LIMITS).expect("sniffed");
+            assert_eq!(meta.kind, kind);
+            assert!(meta.location_removed);
+            assert_eq!(meta.size, None);
+            assert!(meta.thumbnail.is_none());
+            assert!(!contains(&bytes, &latitude));
+        }
+    }
+
+    #[test]
+    fn transparent_images_keep_alpha_and_small_ones_keep_their_size() {
+        let mut bytes = png(5, 3, 100, None);
+        let meta = inspect(&mut bytes, &LIMITS).unwrap();
+        assert!(!meta.location_removed);
+        let thumbnail = meta.thumbnail.unwrap();
+        assert_eq!(thumbnail.kind, ImageKind::Png);
+        assert_eq!((thumbnail.width, thumbnail.height), (5, 3));
+    }
+
+    #[test]
+    fn oversized_and_foreign_files_get_no_thumbnail() {
+        let mut bytes = png(200, 100, u8::MAX, None);
+        let meta = inspect(&mut bytes, &LIMITS).unwrap();
+        assert_eq!(meta.size, Some((200, 100)));
+        assert!(meta.thumbnail.is_none(), "20000 pixels is over the limit");
+
+        let disabled = ImageLimits {
+            thumbnail_px: 0,
+            ..LIMITS
+        };
+        assert!(
+            inspect(&mut jpeg(8, 8, None), &disabled)
+                .unwrap()
+                .thumbnail
+                .is_none()
+        );
+
+        assert!(inspect(&mut b"GIF89a....".to_vec(), &LIMITS).is_none());
+        // A truncated header still counts as a PNG, just one without a size
+        let truncated = inspect(&mut bytes[..20].to_vec(), &LIMITS).unwrap();
+        assert_eq!((truncated.kind, truncated.size), (ImageKind::Png, None));
+    }
+
+    #[test]
+    fn fit_keeps_aspect_ratio_without_enlarging() {
+        assert_eq!(fit(4000, 3000, 320), (320, 240));
+        assert_eq!(fit(100, 5000, 320), (6, 320));
+        assert_eq!(fit(10000, 1, 320), (320, 1));
+        assert_eq!(fit(200, 100, 320), (200, 100));
+    }
+}
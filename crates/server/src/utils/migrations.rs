@@ -0,0 +1,682 @@
+use rusqlite::{Connection, Result as SqliteResult, ffi};
+
+/// One ordered schema step. `version` is the value stored in `PRAGMA user_version`
+/// once the step has been applied.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    up: fn(&Connection) -> SqliteResult<()>,
+}
+
+/// Every schema step known to this build, in application order.
+/// Append new steps at the end; never edit or reorder released ones.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "legacy schema (users, messages with saved flag, connections)",
+        up: migrate_legacy_schema,
+    },
+    Migration {
+        version: 2,
+        description: "users.disabled flag for admin account control",
+        up: add_user_disabled_flag,
+    },
+    Migration {
+        version: 3,
+        description: "client_certificates table for mutual TLS login",
+        up: add_client_certificates,
+    },
+    Migration {
+        version: 4,
+        description: "auth_events log and login_throttle for brute-force protection",
+        up: add_login_protection,
+    },
+    Migration {
+        version: 5,
+        description: "group conversations: conversations, members, messages.conversation_id",
+        up: add_group_conversations,
+    },
+    Migration {
+        version: 6,
+        description: "group roles: conversation_members.role/muted_until, messages.kind",
+        up: add_group_roles,
+    },
+    Migration {
+        version: 7,
+        description: "broadcast channels: channels, members, invites, posts",
+        up: add_broadcast_channels,
+    },
+    Migration {
+        version: 8,
+        description: "message editing: messages.edited_at, message_edits history",
+        up: add_message_edits,
+    },
+    Migration {
+        version: 9,
+        description: "message deletion: messages.deleted_at tombstones, message_hidden",
+        up: add_message_deletion,
+    },
+    Migration {
+        version: 10,
+        description: "emoji reactions: message_reactions",
+        up: add_message_reactions,
+    },
+    Migration {
+        version: 11,
+        description: "replies: messages.reply_to_id",
+        up: add_message_replies,
+    },
+    Migration {
+        version: 12,
+        description: "attachments: attachments, messages.attachment_id",
+        up: add_attachments,
+    },
+    Migration {
+        version: 13,
+        description: "content-addressed attachments: attachment_blobs, reference counts",
+        up: add_attachment_blobs,
+    },
+    Migration {
+        version: 14,
+        description: "image attachments: dimensions, thumbnail_id",
+        up: add_attachment_images,
+    },
+    Migration {
+        version: 15,
+        description: "disappearing messages: messages.expires_at, messages.view_once",
+        up: add_message_expiry,
+    },
+    Migration {
+        version: 16,
+        description: "retention policies: conversations.retention_days",
+        up: add_conversation_retention,
+    },
+    Migration {
+        version: 17,
+        description: "per-user saved messages: bookmarks, bookmark_tags",
+        up: add_bookmarks,
+    },
+    Migration {
+        version: 18,
+        description: "attachments outlive their uploader: nullable attachments.owner_id",
+        up: make_attachment_owner_nullable,
+    },
+    Migration {
+        version: 19,
+        description: "login throttle retention: login_throttle.updated_at",
+        up: add_login_throttle_updated_at,
+    },
+];
+
+/// Schema version a fully migrated database reports.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn current_version(conn: &Connection) -> SqliteResult<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+/// Migrations that still have to run against `conn`. Fails when the database
+/// was written by a newer server than this one.
+pub fn pending_migrations(conn: &Connection) -> SqliteResult<Vec<&'static Migration>> {
+    let current = current_version(conn)?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(schema_error(format!(
+            "database schema version {current} is newer than supported version {latest}; \
+             upgrade rura_server"
+        )));
+    }
+    Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Apply all pending migrations, each in its own transaction, and return the
+/// versions that were applied.
+pub fn run_migrations(conn: &mut Connection) -> SqliteResult<Vec<u32>> {
+    run_migrations_to(conn, latest_version())
+}
+
+/// Apply pending migrations up to and including `target`.
+pub fn run_migrations_to(conn: &mut Connection, target: u32) -> SqliteResult<Vec<u32>> {
+    let pending = pending_migrations(conn)?;
+    let mut applied = Vec::with_capacity(pending.len());
+    // Rebuilding a table other tables reference drops it for a moment, which
+    // an enforced foreign key refuses; the setting only changes outside a
+    // transaction
+    let foreign_keys: bool = conn.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let result = (|| {
+        for migration in pending.into_iter().filter(|m| m.version <= target) {
+            let tx = conn.transaction()?;
+            (migration.up)(&tx)?;
+            tx.pragma_update(None, "user_version", migration.version)?;
+            tx.commit()?;
+            applied.push(migration.version);
+        }
+        Ok(())
+    })();
+    conn.pragma_update(None, "foreign_keys", foreign_keys)?;
+    result.map(|()| applied)
+}
+
+fn schema_error(message: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_MISMATCH), Some(message))
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> SqliteResult<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let col_name: String = row.get(1)?;
+        if col_name == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Version 1 adopts databases created before versioning existed, so every
+// statement has to tolerate tables that are already there.
+fn migrate_legacy_schema(conn: &Connection) -> SqliteResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            passphrase TEXT NOT NULL UNIQUE,
+            password TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            sender INTEGER NOT NULL,
+            receiver INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            saved INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY(sender) REFERENCES users(id),
+            FOREIGN KEY(receiver) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Databases from before the save feature lack the `saved` column
+    if !has_column(conn, "messages", "saved")? {
+        conn.execute(
+            "ALTER TABLE messages ADD COLUMN saved INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS connections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ip TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn add_user_disabled_flag(conn: &Connection) -> SqliteResult<()> {
+    conn.execute(
+        "ALTER TABLE users ADD COLUMN disabled INTEGER NOT NULL DEFAULT 0",
+        [],
+    )?;
+    Ok(())
+}
+
+// `kind` is 'fingerprint' (SHA-256 of the DER, colon-separated hex) or
+// 'subject' (certificate subject common name).
+fn add_client_certificates(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE client_certificates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            kind TEXT NOT NULL CHECK (kind IN ('fingerprint', 'subject')),
+            value TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            UNIQUE(kind, value),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        CREATE INDEX idx_client_certificates_user ON client_certificates(user_id);",
+    )
+}
+
+// `auth_events` sits next to `connections` and keeps no foreign key, so the
+// trail survives user deletion. `login_throttle` is keyed by the passphrase
+// as typed (scope 'account') or the client IP (scope 'ip'), whether or not
+// such an account exists; `blocked_until` is a unix timestamp.
+fn add_login_protection(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE auth_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ip TEXT NOT NULL,
+            passphrase TEXT,
+            user_id INTEGER,
+            kind TEXT NOT NULL CHECK (kind IN ('success', 'failure', 'lockout')),
+            timestamp TEXT NOT NULL
+        );
+        CREATE TABLE login_throttle (
+            scope TEXT NOT NULL CHECK (scope IN ('account', 'ip')),
+            key TEXT NOT NULL,
+            failures INTEGER NOT NULL DEFAULT 0,
+            blocked_until INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (scope, key)
+        );",
+    )
+}
+
+// A message now goes either to a user (`receiver`) or to a group
+// (`conversation_id`). SQLite cannot relax `receiver NOT NULL` in place, so
+// `messages` is rebuilt with its ids preserved. Members see group messages
+// with `joined_after < id`, up to `left_after` once they left.
+fn add_group_conversations(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE conversations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            created_by INTEGER NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        CREATE TABLE conversation_members (
+            conversation_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            joined_after INTEGER NOT NULL DEFAULT 0,
+            left_after INTEGER,
+            PRIMARY KEY (conversation_id, user_id),
+            FOREIGN KEY(conversation_id) REFERENCES conversations(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        CREATE INDEX idx_conversation_members_user ON conversation_members(user_id);
+
+        CREATE TABLE messages_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            sender INTEGER NOT NULL,
+            receiver INTEGER,
+            conversation_id INTEGER,
+            content TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            saved INTEGER NOT NULL DEFAULT 0,
+            CHECK ((receiver IS NULL) <> (conversation_id IS NULL)),
+            FOREIGN KEY(sender) REFERENCES users(id),
+            FOREIGN KEY(receiver) REFERENCES users(id),
+            FOREIGN KEY(conversation_id) REFERENCES conversations(id)
+        );
+        INSERT INTO messages_new (id, sender, receiver, content, timestamp, saved)
+            SELECT id, sender, receiver, content, timestamp, saved FROM messages;
+        DROP TABLE messages;
+        ALTER TABLE messages_new RENAME TO messages;
+        CREATE INDEX idx_messages_conversation ON messages(conversation_id);",
+    )
+}
+
+fn add_group_roles(conn: &Connection) -> SqliteResult<()> {
+    // Every group gets one owner: its creator, or the longest-standing
+    // member when the creator already left
+    conn.execute_batch(
+        "ALTER TABLE conversation_members ADD COLUMN role TEXT NOT NULL DEFAULT 'member'
+            CHECK (role IN ('owner', 'admin', 'member'));
+        ALTER TABLE conversation_members ADD COLUMN muted_until INTEGER;
+        UPDATE conversation_members SET role = 'owner'
+        WHERE left_after IS NULL AND user_id = (
+            SELECT created_by FROM conversations c WHERE c.id = conversation_id
+        );
+        UPDATE conversation_members SET role = 'owner'
+        WHERE rowid IN (
+            SELECT (SELECT m.rowid FROM conversation_members m
+                    WHERE m.conversation_id = c.id AND m.left_after IS NULL
+                    ORDER BY m.joined_after, m.user_id
+                    LIMIT 1)
+            FROM conversations c
+            WHERE NOT EXISTS (
+                SELECT 1 FROM conversation_members o
+                WHERE o.conversation_id = c.id AND o.role = 'owner'
+            )
+        );
+        ALTER TABLE messages ADD COLUMN kind TEXT NOT NULL DEFAULT 'text'
+            CHECK (kind IN ('text', 'system'));",
+    )
+}
+
+fn add_broadcast_channels(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE channels (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            description TEXT NOT NULL DEFAULT '',
+            invite_only INTEGER NOT NULL DEFAULT 0,
+            created_by INTEGER NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        CREATE TABLE channel_members (
+            channel_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            role TEXT NOT NULL CHECK (role IN ('owner', 'publisher', 'subscriber')),
+            joined_at TEXT NOT NULL,
+            PRIMARY KEY (channel_id, user_id),
+            FOREIGN KEY(channel_id) REFERENCES channels(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        CREATE INDEX idx_channel_members_user ON channel_members(user_id);
+        CREATE TABLE channel_invites (
+            channel_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            invited_by INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (channel_id, user_id),
+            FOREIGN KEY(channel_id) REFERENCES channels(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        CREATE TABLE channel_posts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            channel_id INTEGER NOT NULL,
+            sender INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            FOREIGN KEY(channel_id) REFERENCES channels(id),
+            FOREIGN KEY(sender) REFERENCES users(id)
+        );
+        CREATE INDEX idx_channel_posts_channel ON channel_posts(channel_id, id);",
+    )
+}
+
+fn add_message_edits(conn: &Connection) -> SqliteResult<()> {
+    // Each `message_edits` row keeps a version of the text as it was before
+    // the edit made at `edited_at`
+    conn.execute_batch(
+        "ALTER TABLE messages ADD COLUMN edited_at TEXT;
+        CREATE TABLE message_edits (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            edited_at TEXT NOT NULL,
+            FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE
+        );
+        CREATE INDEX idx_message_edits_message ON message_edits(message_id, id);",
+    )
+}
+
+fn add_message_deletion(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "ALTER TABLE messages ADD COLUMN deleted_at TEXT;
+        CREATE TABLE message_hidden (
+            message_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            hidden_at TEXT NOT NULL,
+            PRIMARY KEY (message_id, user_id),
+            FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );",
+    )
+}
+
+fn add_message_reactions(conn: &Connection) -> SqliteResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE message_reactions (
+            message_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            emoji TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (message_id, user_id, emoji),
+            FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );",
+    )
+}
+
+fn add_message_replies(conn: &Connection) -> SqliteResult<()> {
+    // Replies outlive their target: purging it only drops the link
+    conn.execute_batch(
+        "ALTER TABLE messages ADD COLUMN reply_to_id INTEGER
+            REFERENCES messages(id) ON DELETE SET NULL;
+        CREATE INDEX idx_messages_reply_to ON messages(reply_to_id);",
+    )
+}
+
+fn add_attachments(conn: &Connection) -> SqliteResult<()> {
+    // A row exists from `upload_begin` on; `completed_at` is set once the
+    // file passed its checksum and may be referenced from messages
+    conn.execute_batch(
+        "CREATE TABLE attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            owner_id INTEGER NOT NULL,
+            file_name TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            sha256 TEXT NOT NULL,
+            received INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            completed_at TEXT,
+            FOREIGN KEY(owner_id) REFERENCES users(id)
+        );
+        ALTER TABLE messages ADD COLUMN attachment_id INTEGER REFERENCES attachments(id);
+        CREATE INDEX idx_messages_attachment ON messages(attachment_id);",
+    )
+}
+
+fn add_attachment_blobs(conn: &Connection) -> SqliteResult<()> {
+    // Files are stored once per content hash. Triggers keep both reference
+    // counts, so deleting and purging messages needs no extra steps:
+    // `attachments.message_refs` counts messages carrying the attachment and
+    // `attachment_blobs.ref_count` counts completed attachments with the hash.
+    // Account removal also has to release the uploads the user owns
+    conn.execute_batch(
+        "CREATE TABLE attachment_blobs (
+            sha256 TEXT PRIMARY KEY,
+            size INTEGER NOT NULL,
+            ref_count INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL
+        );
+        ALTER TABLE attachments ADD COLUMN message_refs INTEGER NOT NULL DEFAULT 0;
+        UPDATE attachments SET message_refs =
+            (SELECT COUNT(*) FROM messages WHERE messages.attachment_id = attachments.id);
+        INSERT INTO attachment_blobs (sha256, size, ref_count, created_at)
+            SELECT sha256, MAX(size), COUNT(*), MIN(completed_at) FROM attachments
+            WHERE completed_at IS NOT NULL GROUP BY sha256;
+        CREATE INDEX idx_attachments_owner ON attachments(owner_id);
+        CREATE INDEX idx_attachment_blobs_unreferenced ON attachment_blobs(ref_count)
+            WHERE ref_count <= 0;
+        CREATE TRIGGER messages_attachment_insert AFTER INSERT ON messages
+            WHEN NEW.attachment_id IS NOT NULL
+        BEGIN
+            UPDATE attachments SET message_refs = message_refs + 1
+            WHERE id = NEW.attachment_id;
+        END;
+        CREATE TRIGGER messages_attachment_delete AFTER DELETE ON messages
+            WHEN OLD.attachment_id IS NOT NULL
+        BEGIN
+            UPDATE attachments SET message_refs = message_refs - 1
+            WHERE id = OLD.attachment_id;
+        END;
+        CREATE TRIGGER messages_attachment_update AFTER UPDATE OF attachment_id ON messages
+            WHEN OLD.attachment_id IS NOT NEW.attachment_id
+        BEGIN
+            UPDATE attachments SET message_refs = message_refs - 1
+            WHERE id = OLD.attachment_id;
+            UPDATE attachments SET message_refs = message_refs + 1
+            WHERE id = NEW.attachment_id;
+        END;
+        CREATE TRIGGER attachments_complete AFTER UPDATE OF completed_at ON attachments
+            WHEN OLD.completed_at IS NULL AND NEW.completed_at IS NOT NULL
+        BEGIN
+            INSERT INTO attachment_blobs (sha256, size, ref_count, created_at)
+            VALUES (NEW.sha256, NEW.size, 1, NEW.completed_at)
+            ON CONFLICT(sha256) DO UPDATE SET ref_count = ref_count + 1;
+        END;
+        CREATE TRIGGER attachments_delete AFTER DELETE ON attachments
+            WHEN OLD.completed_at IS NOT NULL
+        BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1
+            WHERE sha256 = OLD.sha256;
+        END;",
+    )
+}
+
+fn add_attachment_images(conn: &Connection) -> SqliteResult<()> {
+    // A thumbnail is an attachment of its own, owned by the uploader of the
+    // image and kept alive by `thumbnail_id` rather than by messages
+    conn.execute_batch(
+        "ALTER TABLE attachments ADD COLUMN width INTEGER;
+        ALTER TABLE attachments ADD COLUMN height INTEGER;
+        ALTER TABLE attachments ADD COLUMN thumbnail_id INTEGER REFERENCES attachments(id);
+        CREATE INDEX idx_attachments_thumbnail ON attachments(thumbnail_id);",
+    )
+}
+
+fn add_message_expiry(conn: &Connection) -> SqliteResult<()> {
+    // `expires_at` is in Unix seconds so the sweeper can compare it directly
+    conn.execute_batch(
+        "ALTER TABLE messages ADD COLUMN expires_at INTEGER;
+        ALTER TABLE messages ADD COLUMN view_once INTEGER NOT NULL DEFAULT 0;
+        CREATE INDEX idx_messages_expires ON messages(expires_at)
+            WHERE expires_at IS NOT NULL;",
+    )
+}
+
+fn add_conversation_retention(conn: &Connection) -> SqliteResult<()> {
+    // NULL follows `retention.message_days`; 0 keeps the group's messages
+    conn.execute_batch("ALTER TABLE conversations ADD COLUMN retention_days INTEGER;")
+}
+
+fn add_bookmarks(conn: &Connection) -> SqliteResult<()> {
+    // The shared `saved` flag becomes one bookmark per user who could see
+    // the message. From here on triggers keep `messages.saved` set while
+    // anyone has it bookmarked, so retention and `messages purge` keep
+    // sparing such messages, and drop bookmarks of deleted messages
+    conn.execute_batch(
+        "CREATE TABLE bookmarks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            folder TEXT NOT NULL DEFAULT '',
+            note TEXT NOT NULL DEFAULT '',
+            created_at TEXT NOT NULL,
+            UNIQUE (user_id, message_id),
+            FOREIGN KEY(user_id) REFERENCES users(id),
+            FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE
+        );
+        CREATE INDEX idx_bookmarks_message ON bookmarks(message_id);
+        CREATE INDEX idx_bookmarks_folder ON bookmarks(user_id, folder);
+        CREATE TABLE bookmark_tags (
+            bookmark_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (bookmark_id, tag),
+            FOREIGN KEY(bookmark_id) REFERENCES bookmarks(id) ON DELETE CASCADE
+        );
+        CREATE INDEX idx_bookmark_tags_tag ON bookmark_tags(tag);
+        INSERT INTO bookmarks (user_id, message_id, created_at)
+            SELECT sender, id, timestamp FROM messages WHERE saved != 0
+            UNION
+            SELECT receiver, id, timestamp FROM messages
+            WHERE saved != 0 AND receiver IS NOT NULL
+            UNION
+            SELECT cm.user_id, m.id, m.timestamp FROM messages m
+            JOIN conversation_members cm ON cm.conversation_id = m.conversation_id
+            WHERE m.saved != 0 AND m.id > cm.joined_after
+              AND (cm.left_after IS NULL OR m.id <= cm.left_after);
+        CREATE TRIGGER bookmarks_insert AFTER INSERT ON bookmarks
+        BEGIN
+            UPDATE messages SET saved = 1 WHERE id = NEW.message_id;
+        END;
+        CREATE TRIGGER bookmarks_delete AFTER DELETE ON bookmarks
+        BEGIN
+            DELETE FROM bookmark_tags WHERE bookmark_id = OLD.id;
+            UPDATE messages
+            SET saved = EXISTS (SELECT 1 FROM bookmarks WHERE message_id = OLD.message_id)
+            WHERE id = OLD.message_id;
+        END;
+        CREATE TRIGGER messages_bookmarks_delete AFTER DELETE ON messages
+        BEGIN
+            DELETE FROM bookmarks WHERE message_id = OLD.id;
+        END;",
+    )
+}
+
+fn make_attachment_owner_nullable(conn: &Connection) -> SqliteResult<()> {
+    // Deleting an account keeps the files other users still send, with no
+    // owner. SQLite cannot drop NOT NULL in place, so the table is rebuilt
+    // with its id sequence; the triggers naming it are dropped first, as a
+    // rename checks every trigger in the schema
+    conn.execute_batch(
+        "DROP TRIGGER messages_attachment_insert;
+        DROP TRIGGER messages_attachment_delete;
+        DROP TRIGGER messages_attachment_update;
+        CREATE TABLE attachments_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            owner_id INTEGER,
+            file_name TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            sha256 TEXT NOT NULL,
+            received INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            completed_at TEXT,
+            message_refs INTEGER NOT NULL DEFAULT 0,
+            width INTEGER,
+            height INTEGER,
+            thumbnail_id INTEGER REFERENCES attachments(id),
+            FOREIGN KEY(owner_id) REFERENCES users(id)
+        );
+        INSERT INTO attachments_new
+            (id, owner_id, file_name, mime_type, size, sha256, received, created_at,
+             completed_at, message_refs, width, height, thumbnail_id)
+        SELECT id, owner_id, file_name, mime_type, size, sha256, received, created_at,
+               completed_at, message_refs, width, height, thumbnail_id
+        FROM attachments;
+        UPDATE sqlite_sequence
+        SET seq = (SELECT seq FROM sqlite_sequence WHERE name = 'attachments')
+        WHERE name = 'attachments_new';
+        DROP TABLE attachments;
+        ALTER TABLE attachments_new RENAME TO attachments;
+        CREATE INDEX idx_attachments_owner ON attachments(owner_id);
+        CREATE INDEX idx_attachments_thumbnail ON attachments(thumbnail_id);
+        CREATE TRIGGER messages_attachment_insert AFTER INSERT ON messages
+            WHEN NEW.attachment_id IS NOT NULL
+        BEGIN
+            UPDATE attachments SET message_refs = message_refs + 1
+            WHERE id = NEW.attachment_id;
+        END;
+        CREATE TRIGGER messages_attachment_delete AFTER DELETE ON messages
+            WHEN OLD.attachment_id IS NOT NULL
+        BEGIN
+            UPDATE attachments SET message_refs = message_refs - 1
+            WHERE id = OLD.attachment_id;
+        END;
+        CREATE TRIGGER messages_attachment_update AFTER UPDATE OF attachment_id ON messages
+            WHEN OLD.attachment_id IS NOT NEW.attachment_id
+        BEGIN
+            UPDATE attachments SET message_refs = message_refs - 1
+            WHERE id = OLD.attachment_id;
+            UPDATE attachments SET message_refs = message_refs + 1
+            WHERE id = NEW.attachment_id;
+        END;
+        CREATE TRIGGER attachments_complete AFTER UPDATE OF completed_at ON attachments
+            WHEN OLD.completed_at IS NULL AND NEW.completed_at IS NOT NULL
+        BEGIN
+            INSERT INTO attachment_blobs (sha256, size, ref_count, created_at)
+            VALUES (NEW.sha256, NEW.size, 1, NEW.completed_at)
+            ON CONFLICT(sha256) DO UPDATE SET ref_count = ref_count + 1;
+        END;
+        CREATE TRIGGER attachments_delete AFTER DELETE ON attachments
+            WHEN OLD.completed_at IS NOT NULL
+        BEGIN
+            UPDATE attachment_blobs SET ref_count = ref_count - 1
+            WHERE sha256 = OLD.sha256;
+        END;",
+    )
+}
+
+fn add_login_throttle_updated_at(conn: &Connection) -> SqliteResult<()> {
+    // Unix seconds of the last failure or block, so retention can forget
+    // counters nobody touched in `retention.connection_days`. Existing rows
+    // start their clock now
+    conn.execute_batch(
+        "ALTER TABLE login_throttle ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+        UPDATE login_throttle SET updated_at = CAST(strftime('%s', 'now') AS INTEGER);",
+    )
+}
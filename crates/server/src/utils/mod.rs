@@ -1,3 +1,9 @@
+pub mod attachment_store;
+pub mod certgen;
 pub mod db_utils;
 pub mod get_local_ip;
+pub mod images;
+pub mod logging;
+pub mod migrations;
+pub mod shutdown;
 pub mod tls;
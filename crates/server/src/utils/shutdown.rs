@@ -0,0 +1,58 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Cloneable signal that the server is shutting down. Every clone observes
+/// the same state; cancelling is permanent.
+#[derive(Clone)]
+pub struct CancellationToken {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self {
+            tx: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once [`Self::cancel`] has been called on any clone.
+    pub async fn cancelled(&self) {
+        let mut rx = self.tx.subscribe();
+        // The sender lives as long as `self`, so this cannot fail
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn clones_observe_cancel() {
+        let token = CancellationToken::new();
+        let waiter = {
+            let token = token.clone();
+            tokio::spawn(async move { token.cancelled().await })
+        };
+        assert!(!token.is_cancelled());
+        token.clone().cancel();
+        waiter.await.unwrap();
+        assert!(token.is_cancelled());
+        // Already cancelled: resolves immediately
+        token.cancelled().await;
+    }
+}
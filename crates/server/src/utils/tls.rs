@@ -1,12 +1,30 @@
 use std::fs::File;
 use std::io::{self, BufReader};
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
+use tokio::task::JoinHandle;
 use tokio_rustls::TlsAcceptor;
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::crypto::ring::{Ticketer, default_provider, sign::any_supported_type};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{
+    self, RootCertStore, ServerConfig, ServerConnection, SupportedCipherSuite,
+    SupportedProtocolVersion,
+};
 
-fn load_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+use super::certgen::sha256_fingerprint;
+use super::logging;
+use crate::models::config::{ConfigError, LogLevel, TlsSection, TlsVersion};
+
+/// ALPN protocol id of the chat protocol; advertised on every handshake.
+pub const RURA_ALPN: &[u8] = b"rura/1";
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
     let mut reader = BufReader::new(File::open(path)?);
     let certs: Vec<CertificateDer<'static>> =
         rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
@@ -19,7 +37,7 @@ fn load_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
     Ok(certs)
 }
 
-fn load_private_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+fn load_private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
     // Try PKCS#8 first
     let mut reader = BufReader::new(File::open(path)?);
     let mut pkcs8 =
@@ -41,19 +59,463 @@ fn load_private_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
     ))
 }
 
-pub fn make_tls_acceptor(cert_path: &str, key_path: &str) -> io::Result<TlsAcceptor> {
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Load the cert chain and key and check that the key belongs to the leaf,
+/// so a mismatched pair is rejected before it can break handshakes.
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> io::Result<CertifiedKey> {
     let certs = load_certs(cert_path)?;
     let key = load_private_key(key_path)?;
+    let signing_key =
+        any_supported_type(&key).map_err(|e| invalid_input(format!("invalid key: {e}")))?;
+
+    let leaf = webpki::EndEntityCert::try_from(&certs[0])
+        .map_err(|e| invalid_input(format!("invalid certificate: {e}")))?;
+    let probe = b"rura certificate/key match probe";
+    let algorithms = default_provider().signature_verification_algorithms;
+    let matched = algorithms.mapping.iter().any(|(scheme, verifiers)| {
+        let Some(signer) = signing_key.choose_scheme(&[*scheme]) else {
+            return false;
+        };
+        let Ok(signature) = signer.sign(probe) else {
+            return false;
+        };
+        verifiers
+            .iter()
+            .any(|alg| leaf.verify_signature(*alg, probe, &signature).is_ok())
+    });
+    if !matched {
+        return Err(invalid_input(
+            "private key does not match the certificate".to_string(),
+        ));
+    }
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Size and modification time of the cert and key files, used to notice rotation.
+type FileStamp = [Option<(SystemTime, u64)>; 2];
+
+fn stamp_of(paths: [&Path; 2]) -> FileStamp {
+    paths.map(|path| {
+        std::fs::metadata(path)
+            .ok()
+            .and_then(|m| Some((m.modified().ok()?, m.len())))
+    })
+}
+
+/// Check that the leaf of `key` is valid for the SNI `name`.
+/// A wildcard name is checked against a sample label under it.
+fn check_covers_name(key: &CertifiedKey, name: &str) -> io::Result<()> {
+    let probe = match name.strip_prefix("*.") {
+        Some(parent) => format!("sni-check.{parent}"),
+        None => name.to_string(),
+    };
+    let server_name = ServerName::try_from(probe.as_str())
+        .map_err(|_| invalid_input(format!("`{name}` is not a valid server name")))?;
+    let leaf = webpki::EndEntityCert::try_from(&key.cert[0])
+        .map_err(|e| invalid_input(format!("invalid certificate: {e}")))?;
+    leaf.verify_is_valid_for_subject_name(&server_name)
+        .map_err(|_| invalid_input(format!("certificate is not valid for `{name}`")))
+}
+
+/// One cert/key pair on disk, optionally bound to an SNI name.
+#[derive(Debug)]
+struct CertSlot {
+    name: Option<String>,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+    stamp: Mutex<FileStamp>,
+}
+
+impl CertSlot {
+    fn load(name: Option<String>, cert_path: PathBuf, key_path: PathBuf) -> io::Result<Self> {
+        let stamp = stamp_of([&cert_path, &key_path]);
+        let slot = Self {
+            current: RwLock::new(Arc::new(Self::read(
+                name.as_deref(),
+                &cert_path,
+                &key_path,
+            )?)),
+            name,
+            cert_path,
+            key_path,
+            stamp: Mutex::new(stamp),
+        };
+        Ok(slot)
+    }
+
+    fn read(name: Option<&str>, cert_path: &Path, key_path: &Path) -> io::Result<CertifiedKey> {
+        let key = load_certified_key(cert_path, key_path)?;
+        if let Some(name) = name {
+            check_covers_name(&key, name)?;
+        }
+        Ok(key)
+    }
+
+    fn reload(&self) -> io::Result<()> {
+        *self.stamp.lock().unwrap() = stamp_of([&self.cert_path, &self.key_path]);
+        let key = Self::read(self.name.as_deref(), &self.cert_path, &self.key_path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", self.cert_path.display())))?;
+        *self.current.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+
+    fn changed(&self) -> bool {
+        *self.stamp.lock().unwrap() != stamp_of([&self.cert_path, &self.key_path])
+    }
+}
+
+/// Serves the current cert/key pairs and swaps them in place on reload.
+/// Only new handshakes see a reloaded certificate; established sessions keep theirs.
+///
+/// Besides the default pair, extra pairs can be bound to SNI names with
+/// [`ReloadingCertResolver::with_sni`]; clients asking for any other name
+/// (or none) get the default.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    default: CertSlot,
+    sni: Vec<CertSlot>,
+}
+
+impl ReloadingCertResolver {
+    /// Load the initial pair; fails if it is missing or invalid.
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> io::Result<Self> {
+        Ok(Self {
+            default: CertSlot::load(None, cert_path.into(), key_path.into())?,
+            sni: Vec::new(),
+        })
+    }
+
+    /// Serve another pair to clients asking for `name` (exact, or `*.domain`
+    /// for one label under `domain`). Fails if the certificate does not cover it.
+    pub fn with_sni(
+        mut self,
+        name: &str,
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        let slot = CertSlot::load(Some(name), cert_path.into(), key_path.into())?;
+        self.sni.push(slot);
+        Ok(self)
+    }
+
+    /// Default certificate chain presented to new clients.
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        Arc::clone(&self.default.current.read().unwrap())
+    }
+
+    /// Certificate chain presented to a client that sent `server_name` via SNI.
+    pub fn current_for(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let Some(server_name) = server_name.map(str::to_ascii_lowercase) else {
+            return self.current();
+        };
+        let exact = self
+            .sni
+            .iter()
+            .find(|slot| slot.name.as_deref() == Some(server_name.as_str()));
+        let wildcard = || {
+            let (_, parent) = server_name.split_once('.')?;
+            self.sni.iter().find(|slot| {
+                slot.name
+                    .as_deref()
+                    .and_then(|name| name.strip_prefix("*."))
+                    == Some(parent)
+            })
+        };
+        match exact.or_else(wildcard) {
+            Some(slot) => Arc::clone(&slot.current.read().unwrap()),
+            None => self.current(),
+        }
+    }
+
+    fn slots(&self) -> impl Iterator<Item = &CertSlot> {
+        std::iter::once(&self.default).chain(self.sni.iter())
+    }
+
+    /// Re-read every pair. A pair that fails keeps its previous certificate
+    /// in service; the first error is returned after all pairs were tried.
+    pub fn reload(&self) -> io::Result<()> {
+        let mut first_error = None;
+        for slot in self.slots() {
+            if let Err(e) = slot.reload() {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Reload only the pairs whose files changed since the last attempt.
+    /// Returns whether any reload was attempted.
+    pub fn reload_if_changed(&self) -> io::Result<bool> {
+        let mut attempted = false;
+        let mut first_error = None;
+        for slot in self.slots().filter(|slot| slot.changed()) {
+            attempted = true;
+            if let Err(e) = slot.reload() {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(attempted), Err)
+    }
+}
 
-    let config: ServerConfig = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("invalid cert/key: {e}"),
-            )
-        })?;
-
-    Ok(TlsAcceptor::from(Arc::new(config)))
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current_for(client_hello.server_name()))
+    }
+}
+
+/// Cipher suites enabled by `tls.cipher_suites`, in the configured order.
+/// An empty list keeps the provider defaults.
+pub fn select_cipher_suites(names: &[String]) -> Result<Vec<SupportedCipherSuite>, String> {
+    let available = default_provider().cipher_suites;
+    if names.is_empty() {
+        return Ok(available);
+    }
+    names
+        .iter()
+        .map(|name| {
+            available
+                .iter()
+                .find(|suite| format!("{:?}", suite.suite()).eq_ignore_ascii_case(name.trim()))
+                .copied()
+                .ok_or_else(|| {
+                    let known: Vec<String> = available
+                        .iter()
+                        .map(|suite| format!("{:?}", suite.suite()))
+                        .collect();
+                    format!(
+                        "unknown cipher suite `{name}` (known: {})",
+                        known.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+
+fn protocol_versions(min_version: TlsVersion) -> &'static [&'static SupportedProtocolVersion] {
+    match min_version {
+        TlsVersion::Tls12 => rustls::ALL_VERSIONS,
+        TlsVersion::Tls13 => TLS13_ONLY,
+    }
+}
+
+/// Startup checks for the protocol policy in `[tls]` that do not touch the filesystem.
+pub fn validate_policy(tls: &TlsSection) -> Result<(), ConfigError> {
+    let suites = select_cipher_suites(&tls.cipher_suites)
+        .map_err(|message| ConfigError::new("tls.cipher_suites", message))?;
+    let versions = protocol_versions(tls.min_version);
+    if !suites
+        .iter()
+        .any(|suite| versions.contains(&suite.version()))
+    {
+        return Err(ConfigError::new(
+            "tls.cipher_suites",
+            "no listed suite is usable with tls.min_version",
+        ));
+    }
+    let mut seen = Vec::new();
+    for entry in &tls.sni {
+        let name = entry.name.trim().to_ascii_lowercase();
+        if name.is_empty() || entry.cert.trim().is_empty() || entry.key.trim().is_empty() {
+            return Err(ConfigError::new(
+                "tls.sni",
+                "every entry needs a name, cert and key",
+            ));
+        }
+        if seen.contains(&name) {
+            return Err(ConfigError::new(
+                "tls.sni",
+                format!("`{name}` is listed more than once"),
+            ));
+        }
+        seen.push(name);
+    }
+    Ok(())
+}
+
+/// Server config enforcing the `[tls]` protocol policy: minimum version,
+/// cipher suites, session tickets, and the `rura/1` ALPN protocol.
+pub fn make_server_config(
+    tls: &TlsSection,
+    resolver: Arc<ReloadingCertResolver>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> io::Result<ServerConfig> {
+    let provider = CryptoProvider {
+        cipher_suites: select_cipher_suites(&tls.cipher_suites).map_err(invalid_input)?,
+        ..default_provider()
+    };
+    let builder = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(protocol_versions(tls.min_version))
+        .map_err(|e| invalid_input(format!("invalid TLS policy: {e}")))?;
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver);
+    config.alpn_protocols = vec![RURA_ALPN.to_vec()];
+    if tls.session_tickets {
+        config.ticketer = Ticketer::new()
+            .map_err(|e| io::Error::other(format!("cannot create session ticketer: {e}")))?;
+    }
+    Ok(config)
+}
+
+/// Whether the handshake settled on the `rura/1` ALPN protocol.
+pub fn negotiated_rura_alpn(conn: &ServerConnection) -> bool {
+    conn.alpn_protocol() == Some(RURA_ALPN)
+}
+
+/// Verifier for mutual TLS: client certificates must chain to the CA in `ca_path`.
+/// Unless `required`, clients without a certificate may still connect and log in
+/// with a password.
+pub fn make_client_verifier(
+    ca_path: &str,
+    required: bool,
+) -> io::Result<Arc<dyn ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(Path::new(ca_path))? {
+        roots
+            .add(cert)
+            .map_err(|e| invalid_input(format!("invalid client CA certificate: {e}")))?;
+    }
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = if required {
+        builder
+    } else {
+        builder.allow_unauthenticated()
+    };
+    builder
+        .build()
+        .map_err(|e| invalid_input(format!("invalid client CA: {e}")))
+}
+
+/// Acceptor with the default `[tls]` policy.
+pub fn make_reloading_acceptor(
+    resolver: Arc<ReloadingCertResolver>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> TlsAcceptor {
+    let config = make_server_config(&TlsSection::default(), resolver, client_verifier)
+        .expect("default TLS policy is valid");
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// Identity of a client certificate that passed verification during the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertIdentity {
+    /// SHA-256 of the leaf DER, colon-separated upper-case hex.
+    pub fingerprint: String,
+    /// Subject common name, if the certificate has one.
+    pub subject: Option<String>,
+}
+
+impl ClientCertIdentity {
+    pub fn from_der(der: &[u8]) -> Self {
+        let subject = x509_parser::parse_x509_certificate(der)
+            .ok()
+            .and_then(|(_, cert)| {
+                cert.subject()
+                    .iter_common_name()
+                    .next()
+                    .and_then(|cn| cn.as_str().ok())
+                    .map(str::to_string)
+            });
+        Self {
+            fingerprint: sha256_fingerprint(der),
+            subject,
+        }
+    }
+}
+
+/// The verified client certificate of a completed handshake, if one was sent.
+pub fn client_cert_identity(conn: &ServerConnection) -> Option<ClientCertIdentity> {
+    let leaf = conn.peer_certificates()?.first()?;
+    Some(ClientCertIdentity::from_der(leaf))
+}
+
+/// Accept a SHA-256 fingerprint with or without colons, in any case,
+/// and return it in the stored `AB:CD:...` form.
+pub fn normalize_fingerprint(raw: &str) -> Option<String> {
+    let hex: String = raw
+        .chars()
+        .filter(|c| !matches!(c, ':' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let pairs: Vec<&str> = (0..hex.len()).step_by(2).map(|i| &hex[i..i + 2]).collect();
+    Some(pairs.join(":"))
+}
+
+fn log_reload(resolver: &ReloadingCertResolver, trigger: &str, result: io::Result<bool>) {
+    match result {
+        Ok(false) => {}
+        Ok(true) => {
+            if logging::enabled(LogLevel::Info) {
+                println!(
+                    "Reloaded TLS certificates from {} ({})",
+                    resolver.default.cert_path.display(),
+                    trigger
+                );
+            }
+        }
+        Err(e) => {
+            if logging::enabled(LogLevel::Error) {
+                eprintln!(
+                    "TLS certificate reload ({}) rejected, keeping previous certificate: {}",
+                    trigger, e
+                );
+            }
+        }
+    }
+}
+
+/// Reload the certificate on SIGHUP and, when `poll_interval` is set,
+/// whenever the cert or key file changes on disk.
+pub fn spawn_cert_reloader(
+    resolver: Arc<ReloadingCertResolver>,
+    poll_interval: Option<Duration>,
+) -> io::Result<JoinHandle<()>> {
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+
+    Ok(tokio::spawn(async move {
+        let mut ticker = poll_interval.map(tokio::time::interval);
+        loop {
+            let poll = async {
+                match ticker.as_mut() {
+                    Some(ticker) => {
+                        ticker.tick().await;
+                    }
+                    None => std::future::pending::<()>().await,
+                }
+            };
+            #[cfg(unix)]
+            let sighup = hangup.recv();
+            #[cfg(not(unix))]
+            let sighup = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = poll => {
+                    log_reload(&resolver, "file change", resolver.reload_if_changed());
+                }
+                _ = sighup => {
+                    log_reload(&resolver, "SIGHUP", resolver.reload().map(|_| true));
+                }
+            }
+        }
+    }))
+}
+
+pub fn make_tls_acceptor(cert_path: &str, key_path: &str) -> io::Result<TlsAcceptor> {
+    let resolver = ReloadingCertResolver::new(cert_path, key_path)?;
+    Ok(make_reloading_acceptor(Arc::new(resolver), None))
 }
@@ -0,0 +1,612 @@
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{Connection, params};
+
+use rura_server::admin::{parse_before, run_admin_command};
+use rura_server::models::args::{
+    AttachmentsCommand, Command, DbCommand, MessagesCommand, RetentionCommand, UserCommand,
+};
+use rura_server::models::config::Config;
+use rura_server::utils::db_utils::{
+    authenticate_user, find_user_by_client_cert, init_db_with_path, store_message,
+};
+
+fn test_db() -> Arc<Mutex<Connection>> {
+    Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()))
+}
+
+async fn run(conn: &Arc<Mutex<Connection>>, command: Command, stdin: &str) -> String {
+    run_with(&Config::default(), conn, command, stdin).await
+}
+
+async fn run_with(
+    config: &Config,
+    conn: &Arc<Mutex<Connection>>,
+    command: Command,
+    stdin: &str,
+) -> String {
+    let mut input = Cursor::new(stdin.as_bytes().to_vec());
+    let mut out = Vec::new();
+    run_admin_command(&command, config, Arc::clone(conn), &mut input, &mut out)
+        .await
+        .expect("admin command failed");
+    String::from_utf8(out).unwrap()
+}
+
+#[tokio::test]
+async fn user_lifecycle_through_admin_commands() {
+    let conn = test_db();
+
+    let out = run(
+        &conn,
+        Command::User(UserCommand::Add {
+            passphrase: "alice".into(),
+            password: Some("secret".into()),
+        }),
+        "",
+    )
+    .await;
+    assert!(out.contains("Created user alice"));
+
+    // Password from stdin when --password is omitted
+    run(
+        &conn,
+        Command::User(UserCommand::Add {
+            passphrase: "bob".into(),
+            password: None,
+        }),
+        "hunter2\n",
+    )
+    .await;
+    assert!(
+        authenticate_user(Arc::clone(&conn), "bob", "hunter2")
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    run(
+        &conn,
+        Command::User(UserCommand::Disable {
+            passphrase: "alice".into(),
+        }),
+        "",
+    )
+    .await;
+    assert_eq!(
+        authenticate_user(Arc::clone(&conn), "alice", "secret")
+            .await
+            .unwrap(),
+        None,
+        "disabled users must not authenticate"
+    );
+    let listing = run(&conn, Command::User(UserCommand::List), "").await;
+    assert!(
+        listing
+            .lines()
+            .any(|l| l.contains("disabled") && l.ends_with("alice"))
+    );
+    assert!(
+        listing
+            .lines()
+            .any(|l| l.contains("active") && l.ends_with("bob"))
+    );
+
+    run(
+        &conn,
+        Command::User(UserCommand::Enable {
+            passphrase: "alice".into(),
+        }),
+        "",
+    )
+    .await;
+    run(
+        &conn,
+        Command::User(UserCommand::ResetPassword {
+            passphrase: "alice".into(),
+            password: Some("changed".into()),
+        }),
+        "",
+    )
+    .await;
+    assert_eq!(
+        authenticate_user(Arc::clone(&conn), "alice", "secret")
+            .await
+            .unwrap(),
+        None
+    );
+    let alice = authenticate_user(Arc::clone(&conn), "alice", "changed")
+        .await
+        .unwrap()
+        .expect("new password works");
+
+    store_message(Arc::clone(&conn), alice, 2, "bye", false)
+        .await
+        .unwrap();
+    run(
+        &conn,
+        Command::User(UserCommand::Delete {
+            passphrase: "alice".into(),
+        }),
+        "",
+    )
+    .await;
+    let (users, messages): (i64, i64) = {
+        let c = conn.lock().unwrap();
+        (
+            c.query_row("SELECT COUNT(*) FROM users", [], |r| r.get(0))
+                .unwrap(),
+            c.query_row("SELECT COUNT(*) FROM messages", [], |r| r.get(0))
+                .unwrap(),
+        )
+    };
+    assert_eq!(users, 1);
+    assert_eq!(messages, 0);
+}
+
+#[tokio::test]
+async fn deleting_an_uploader_keeps_files_others_still_send() {
+    let conn = test_db();
+    conn.lock()
+        .unwrap()
+        .execute_batch(
+            "INSERT INTO users (passphrase, password) VALUES ('alice', 'x'), ('bob', 'x'),
+                                                           ('carol', 'x');
+            INSERT INTO attachments (id, owner_id, file_name, mime_type, size, sha256,
+                                     received, created_at)
+                VALUES (1, 1, 'a.txt', 'text/plain', 3, 'h1', 3, '2020-01-01T00:00:00+00:00'),
+                       (2, 1, 'b.txt', 'text/plain', 5, 'h2', 1, '2020-01-01T00:00:00+00:00');
+            UPDATE attachments SET completed_at = '2020-01-01T00:00:00+00:00' WHERE id = 1;
+            INSERT INTO messages (sender, receiver, content, timestamp, attachment_id)
+                VALUES (1, 2, 'file', 't', 1), (2, 3, 'forwarded', 't', 1);",
+        )
+        .unwrap();
+
+    let out = run(
+        &conn,
+        Command::User(UserCommand::Delete {
+            passphrase: "alice".into(),
+        }),
+        "",
+    )
+    .await;
+    assert!(out.contains("Deleted user alice"), "{out}");
+    let owners: Vec<Option<i64>> = {
+        let c = conn.lock().unwrap();
+        let mut stmt = c
+            .prepare("SELECT owner_id FROM attachments ORDER BY id")
+            .unwrap();
+        stmt.query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    };
+    assert_eq!(owners, [None, None]);
+    let usage = run(&conn, Command::Attachments(AttachmentsCommand::Usage), "").await;
+    assert!(
+        usage
+            .lines()
+            .any(|l| l.trim_start().starts_with('-') && l.ends_with("(deleted)")),
+        "{usage}"
+    );
+
+    // Only bob's forward still carries the file; the unfinished upload goes
+    let refs = |sql: &str| {
+        conn.lock()
+            .unwrap()
+            .query_row(sql, [], |r| r.get::<_, i64>(0))
+            .unwrap()
+    };
+    assert_eq!(refs("SELECT message_refs FROM attachments WHERE id = 1"), 1);
+    let mut config = Config::default();
+    config.attachments.dir = std::env::temp_dir()
+        .join(format!("rura-admin-gc-{}", std::process::id()))
+        .to_string_lossy()
+        .into_owned();
+    config.attachments.orphan_grace_secs = 0;
+    let gc = Command::Attachments(AttachmentsCommand::Gc);
+    let out = run_with(&config, &conn, gc, "").await;
+    assert!(
+        out.starts_with("Removed 1 attachment(s) and 0 blob(s)"),
+        "{out}"
+    );
+    assert_eq!(refs("SELECT COUNT(*) FROM attachments"), 1);
+    assert_eq!(
+        refs("SELECT ref_count FROM attachment_blobs WHERE sha256 = 'h1'"),
+        1
+    );
+
+    // Once bob is gone too, nothing carries it and its content is freed
+    run(
+        &conn,
+        Command::User(UserCommand::Delete {
+            passphrase: "bob".into(),
+        }),
+        "",
+    )
+    .await;
+    let gc = Command::Attachments(AttachmentsCommand::Gc);
+    let out = run_with(&config, &conn, gc, "").await;
+    assert!(
+        out.starts_with("Removed 1 attachment(s) and 1 blob(s)"),
+        "{out}"
+    );
+    assert_eq!(refs("SELECT COUNT(*) FROM attachments"), 0);
+    assert_eq!(refs("SELECT COUNT(*) FROM attachment_blobs"), 0);
+    let _ = std::fs::remove_dir_all(&config.attachments.dir);
+}
+
+#[tokio::test]
+async fn unknown_user_is_reported() {
+    let conn = test_db();
+    let mut out = Vec::new();
+    let err = run_admin_command(
+        &Command::User(UserCommand::Disable {
+            passphrase: "ghost".into(),
+        }),
+        &Config::default(),
+        conn,
+        &mut Cursor::new(Vec::new()),
+        &mut out,
+    )
+    .await
+    .expect_err("missing user must fail");
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(err.to_string().contains("ghost"));
+}
+
+#[tokio::test]
+async fn purge_respects_cutoff_and_saved_flag() {
+    let conn = test_db();
+    {
+        let c = conn.lock().unwrap();
+        c.execute_batch("INSERT INTO users (passphrase, password) VALUES ('a', 'x'), ('b', 'x');")
+            .unwrap();
+        for (content, ts, saved) in [
+            ("old", "2020-01-01T10:00:00.123456789+02:00", 0),
+            ("old saved", "2020-01-01T10:00:00+00:00", 1),
+            ("new", "2030-01-01T10:00:00+00:00", 0),
+        ] {
+            c.execute(
+                "INSERT INTO messages (sender, receiver, content, timestamp, saved)
+                 VALUES (1, 2, ?1, ?2, ?3)",
+                params![content, ts, saved],
+            )
+            .unwrap();
+        }
+    }
+
+    let out = run(
+        &conn,
+        Command::Messages(MessagesCommand::Purge {
+            before: "2025-01-01".into(),
+            include_saved: false,
+        }),
+        "",
+    )
+    .await;
+    assert!(out.starts_with("Purged 1 message(s)"));
+
+    let out = run(
+        &conn,
+        Command::Messages(MessagesCommand::Purge {
+            before: "2025-01-01T00:00:00Z".into(),
+            include_saved: true,
+        }),
+        "",
+    )
+    .await;
+    assert!(out.starts_with("Purged 1 message(s)"));
+
+    let remaining: Vec<String> = {
+        let c = conn.lock().unwrap();
+        let mut stmt = c.prepare("SELECT content FROM messages").unwrap();
+        stmt.query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    };
+    assert_eq!(remaining, vec!["new".to_string()]);
+}
+
+#[tokio::test]
+async fn retention_keeps_saved_messages_and_honours_group_overrides() {
+    let conn = test_db();
+    let days_ago = |days: i64| (chrono::Utc::now() - chrono::Duration::days(days)).to_rfc3339();
+    {
+        let c = conn.lock().unwrap();
+        c.execute_batch(
+            "INSERT INTO users (passphrase, password) VALUES ('a', 'x'), ('b', 'x');
+            INSERT INTO conversations (id, name, created_by, created_at)
+                VALUES (1, 'archive', 1, 't'), (2, 'ephemeral', 1, 't'), (3, 'team', 1, 't');",
+        )
+        .unwrap();
+        let insert = |receiver: Option<i64>, group: Option<i64>, age: i64, saved: i64| {
+            c.execute(
+                "INSERT INTO messages (sender, receiver, conversation_id, content, timestamp, saved)
+                 VALUES (1, ?1, ?2, 'x', ?3, ?4)",
+                params![receiver, group, days_ago(age), saved],
+            )
+            .unwrap();
+            c.last_insert_rowid()
+        };
+        let old = insert(Some(2), None, 100, 0);
+        for _ in 0..4 {
+            insert(Some(2), None, 100, 0);
+        }
+        insert(Some(2), None, 100, 1);
+        insert(Some(2), None, 1, 0);
+        insert(None, Some(1), 100, 0);
+        insert(None, Some(2), 3, 0);
+        insert(None, Some(3), 100, 0);
+        c.execute(
+            "INSERT INTO message_reactions (message_id, user_id, emoji, created_at)
+             VALUES (?1, 2, 'x', 't')",
+            [old],
+        )
+        .unwrap();
+        for age in [10, 10, 10, 1] {
+            c.execute(
+                "INSERT INTO connections (ip, timestamp) VALUES ('127.0.0.1', ?1)",
+                [days_ago(age)],
+            )
+            .unwrap();
+            c.execute(
+                "INSERT INTO auth_events (ip, passphrase, kind, timestamp)
+                 VALUES ('127.0.0.1', 'a', 'failure', ?1)",
+                [days_ago(age)],
+            )
+            .unwrap();
+        }
+        // Stale, stale but still blocked, and recent failure counters
+        let unix_days_ago = |days: i64| chrono::Utc::now().timestamp() - days * 86400;
+        for (key, updated, blocked) in [
+            ("old", unix_days_ago(10), 0),
+            ("locked", unix_days_ago(10), unix_days_ago(-1)),
+            ("new", unix_days_ago(1), 0),
+        ] {
+            c.execute(
+                "INSERT INTO login_throttle (scope, key, failures, blocked_until, updated_at)
+                 VALUES ('account', ?1, 3, ?3, ?2)",
+                params![key, updated, blocked],
+            )
+            .unwrap();
+        }
+    }
+    let mut config = Config::default();
+    config.retention.message_days = 30;
+    config.retention.connection_days = 7;
+    config.retention.batch_size = 2;
+
+    let retention = async |command: RetentionCommand| {
+        run_with(&config, &conn, Command::Retention(command), "").await
+    };
+    retention(RetentionCommand::Set {
+        conversation_id: 1,
+        days: 0,
+    })
+    .await;
+    let out = retention(RetentionCommand::Set {
+        conversation_id: 2,
+        days: 2,
+    })
+    .await;
+    assert!(
+        out.contains("Group 2 keeps unsaved messages 2 day(s)"),
+        "{out}"
+    );
+
+    // The dry run lists every group with an override or something to delete
+    let out = retention(RetentionCommand::Run { dry_run: true }).await;
+    assert!(out.contains("direct messages to delete: 5"), "{out}");
+    let group_lines: Vec<Vec<&str>> = out
+        .lines()
+        .filter(|line| {
+            line.ends_with("archive") || line.ends_with("ephemeral") || line.ends_with("team")
+        })
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(
+        group_lines,
+        [
+            vec!["1", "forever", "0", "archive"],
+            vec!["2", "2", "day(s)", "1", "ephemeral"],
+            vec!["3", "default", "1", "team"],
+        ]
+    );
+    assert!(out.contains("connection records to delete: 3"), "{out}");
+    assert!(out.contains("auth events to delete: 3"), "{out}");
+    assert!(out.contains("login throttle records to delete: 1"), "{out}");
+    let count = |sql: &str| {
+        conn.lock()
+            .unwrap()
+            .query_row(sql, [], |row| row.get::<_, i64>(0))
+            .unwrap()
+    };
+    assert_eq!(count("SELECT COUNT(*) FROM messages"), 10);
+
+    // Deleting takes several batches of two
+    let out = retention(RetentionCommand::Run { dry_run: false }).await;
+    assert_eq!(
+        out,
+        "Deleted 7 message(s), 3 connection record(s), 3 auth event(s) and 1 login throttle record(s)\n"
+    );
+    assert_eq!(count("SELECT COUNT(*) FROM messages"), 3);
+    assert_eq!(count("SELECT COUNT(*) FROM messages WHERE saved = 1"), 1);
+    assert_eq!(
+        count("SELECT COUNT(*) FROM messages WHERE conversation_id = 1"),
+        1
+    );
+    assert_eq!(count("SELECT COUNT(*) FROM message_reactions"), 0);
+    assert_eq!(count("SELECT COUNT(*) FROM connections"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM auth_events"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM login_throttle"), 2);
+
+    let out = retention(RetentionCommand::Run { dry_run: false }).await;
+    assert_eq!(
+        out,
+        "Deleted 0 message(s), 0 connection record(s), 0 auth event(s) and 0 login throttle record(s)\n"
+    );
+
+    // Back to the server-wide 30 days, the archive loses its old message
+    retention(RetentionCommand::Unset { conversation_id: 1 }).await;
+    let out = retention(RetentionCommand::Run { dry_run: false }).await;
+    assert!(out.starts_with("Deleted 1 message(s)"), "{out}");
+
+    let err = run_admin_command(
+        &Command::Retention(RetentionCommand::Unset {
+            conversation_id: 99,
+        }),
+        &config,
+        Arc::clone(&conn),
+        &mut Cursor::new(Vec::new()),
+        &mut Vec::new(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn db_check_vacuum_and_stats() {
+    let conn = test_db();
+    assert_eq!(
+        run(&conn, Command::Db(DbCommand::Check), "").await,
+        "Database OK\n"
+    );
+    assert!(
+        run(&conn, Command::Db(DbCommand::Vacuum), "")
+            .await
+            .contains("vacuumed")
+    );
+
+    run(
+        &conn,
+        Command::User(UserCommand::Add {
+            passphrase: "carol".into(),
+            password: Some("pw".into()),
+        }),
+        "",
+    )
+    .await;
+    store_message(Arc::clone(&conn), 1, 1, "note", true)
+        .await
+        .unwrap();
+    let stats = run(&conn, Command::Stats, "").await;
+    assert!(stats.contains("users: 1\n"));
+    assert!(stats.contains("messages: 1\n"));
+    assert!(stats.contains("saved_messages: 1\n"));
+}
+
+#[tokio::test]
+async fn bind_and_unbind_client_certificates() {
+    let conn = test_db();
+    run(
+        &conn,
+        Command::User(UserCommand::Add {
+            passphrase: "dave".into(),
+            password: Some("pw".into()),
+        }),
+        "",
+    )
+    .await;
+
+    let fingerprint = "ab".repeat(32);
+    let out = run(
+        &conn,
+        Command::User(UserCommand::BindCert {
+            passphrase: "dave".into(),
+            fingerprint: Some(fingerprint.clone()),
+            subject: None,
+            cert: None,
+        }),
+        "",
+    )
+    .await;
+    let normalized = ["AB"; 32].join(":");
+    assert!(out.contains(&normalized), "{out}");
+    assert_eq!(
+        find_user_by_client_cert(Arc::clone(&conn), &normalized, None)
+            .await
+            .unwrap(),
+        Some(1)
+    );
+
+    // The same certificate cannot be bound twice
+    let err = run_admin_command(
+        &Command::User(UserCommand::BindCert {
+            passphrase: "dave".into(),
+            fingerprint: Some(normalized.clone()),
+            subject: None,
+            cert: None,
+        }),
+        &Config::default(),
+        Arc::clone(&conn),
+        &mut Cursor::new(Vec::new()),
+        &mut Vec::new(),
+    )
+    .await
+    .expect_err("duplicate binding");
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+    let err = run_admin_command(
+        &Command::User(UserCommand::BindCert {
+            passphrase: "dave".into(),
+            fingerprint: Some("not-hex".into()),
+            subject: None,
+            cert: None,
+        }),
+        &Config::default(),
+        Arc::clone(&conn),
+        &mut Cursor::new(Vec::new()),
+        &mut Vec::new(),
+    )
+    .await
+    .expect_err("invalid fingerprint");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    run(
+        &conn,
+        Command::User(UserCommand::BindCert {
+            passphrase: "dave".into(),
+            fingerprint: None,
+            subject: Some("dave-laptop".into()),
+            cert: None,
+        }),
+        "",
+    )
+    .await;
+    assert_eq!(
+        find_user_by_client_cert(Arc::clone(&conn), "00", Some("dave-laptop"))
+            .await
+            .unwrap(),
+        Some(1)
+    );
+
+    let out = run(
+        &conn,
+        Command::User(UserCommand::UnbindCert {
+            passphrase: "dave".into(),
+        }),
+        "",
+    )
+    .await;
+    assert!(out.starts_with("Removed 2 certificate binding(s)"));
+    assert_eq!(
+        find_user_by_client_cert(Arc::clone(&conn), &normalized, Some("dave-laptop"))
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn parse_before_accepts_dates_and_rejects_garbage() {
+    assert!(parse_before("2024-05-01").is_ok());
+    assert_eq!(
+        parse_before("2024-05-01T12:00:00Z").unwrap(),
+        "2024-05-01T12:00:00+00:00"
+    );
+    assert!(parse_before("last tuesday").is_err());
+}
@@ -1,12 +1,11 @@
 use rura_server::messaging::state::{AppState, ClientHandle};
 use rura_server::models::client_message::ClientMessage;
 use std::sync::Arc;
-use tokio::sync::mpsc;
 
 #[tokio::test]
 async fn test_register_get_unregister_sender() {
     let state = Arc::new(AppState::default());
-    let (tx, mut rx) = mpsc::unbounded_channel::<ClientMessage>();
+    let (tx, mut rx) = state.outbound_channel();
 
     // No sender registered yet
     assert!(state.get_sender(1).await.is_none());
@@ -32,3 +31,49 @@ async fn test_register_get_unregister_sender() {
     state.unregister(1).await;
     assert!(state.get_sender(1).await.is_none());
 }
+
+#[tokio::test]
+async fn stale_connection_does_not_unregister_newer_one() {
+    let state = Arc::new(AppState::default());
+    let (old_tx, _old_rx) = state.outbound_channel();
+    let (new_tx, _new_rx) = state.outbound_channel();
+
+    state.register(1, ClientHandle { tx: old_tx.clone() }).await;
+    state.register(1, ClientHandle { tx: new_tx.clone() }).await;
+
+    // The first connection closing must leave the second one reachable
+    state.unregister_handle(1, &old_tx).await;
+    let current = state.get_sender(1).await.expect("newer handle kept");
+    assert!(current.same_channel(&new_tx));
+
+    state.unregister_handle(1, &new_tx).await;
+    assert!(state.get_sender(1).await.is_none());
+}
+
+#[tokio::test]
+async fn senders_for_sorted_finds_online_members_either_way() {
+    let state = Arc::new(AppState::default());
+    let mut receivers = Vec::new();
+    for user_id in [2, 5, 9] {
+        let (tx, rx) = state.outbound_channel();
+        state.register(user_id, ClientHandle { tx }).await;
+        receivers.push(rx);
+    }
+
+    // Audience smaller and larger than the online set
+    assert_eq!(state.senders_for_sorted(&[5, 7]).await.len(), 1);
+    let audience: Vec<i64> = (1..=100).collect();
+    let senders = state.senders_for_sorted(&audience).await;
+    assert_eq!(senders.len(), 3);
+
+    let shared = Arc::new(ClientMessage {
+        command: "channel_post".into(),
+        data: "{}".into(),
+    });
+    for tx in senders {
+        tx.send_shared(Arc::clone(&shared)).unwrap();
+    }
+    for rx in &mut receivers {
+        assert!(Arc::ptr_eq(&rx.recv_shared().await.unwrap(), &shared));
+    }
+}
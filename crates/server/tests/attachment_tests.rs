@@ -0,0 +1,605 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rusqlite::Connection;
+
+use rura_server::admin::run_admin_command;
+use rura_server::messaging::attachments::{
+    GarbageReport, collect_garbage, handle_attachment_command,
+};
+use rura_server::messaging::handlers::send_direct;
+use rura_server::messaging::models::{
+    DirectMessageEvent, DirectMessageReq, DownloadChunkResponse, UploadResponse,
+};
+use rura_server::messaging::queue::{SessionReceiver, SessionSender};
+use rura_server::messaging::state::AppState;
+use rura_server::models::args::{AttachmentsCommand, Command, MessagesCommand};
+use rura_server::models::client_message::ClientMessage;
+use rura_server::utils::attachment_store::sha256_hex;
+use rura_server::utils::db_utils::attachment_usage;
+
+use common::{
+    command, db_with_users, files_under, next, online, state_with, state_with_dir, upload,
+    upload_response,
+};
+
+fn download_response(msg: &ClientMessage) -> DownloadChunkResponse {
+    assert_eq!(msg.command, "download_response");
+    serde_json::from_str(&msg.data).unwrap()
+}
+
+async fn admin(state: &AppState, conn: &Arc<Mutex<Connection>>, command: Command) -> String {
+    let mut out = Vec::new();
+    run_admin_command(
+        &command,
+        state.config(),
+        Arc::clone(conn),
+        &mut std::io::Cursor::new(Vec::new()),
+        &mut out,
+    )
+    .await
+    .expect("admin command failed");
+    String::from_utf8(out).unwrap()
+}
+
+#[tokio::test]
+async fn uploads_are_chunked_checked_and_readable_by_the_recipient() {
+    let (state, _dir) = state_with_dir("flow");
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+    let file = b"twelve bytes and then some";
+
+    let begun = upload_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "upload_begin",
+            serde_json::json!({
+                "file_name": " notes.txt ",
+                "mime_type": "text/plain",
+                "size": file.len(),
+                "sha256": sha256_hex(file).to_uppercase(),
+            }),
+        )
+        .await,
+    );
+    assert!(begun.success, "{}", begun.message);
+    assert_eq!(begun.chunk_bytes, 8);
+    let id = begun.attachment_id.unwrap();
+
+    async fn chunk(
+        state: &Arc<AppState>,
+        conn: &Arc<Mutex<Connection>>,
+        session: &mut (SessionSender, SessionReceiver),
+        id: i64,
+        offset: usize,
+        bytes: &[u8],
+    ) -> UploadResponse {
+        upload_response(
+            &command(
+                handle_attachment_command,
+                state,
+                conn,
+                session,
+                1,
+                "upload_chunk",
+                serde_json::json!({
+                    "attachment_id": id,
+                    "offset": offset,
+                    "data": BASE64.encode(bytes),
+                }),
+            )
+            .await,
+        )
+    }
+
+    let mut offset = 0;
+    while offset < file.len() {
+        let end = (offset + 8).min(file.len());
+        let resp = chunk(&state, &conn, &mut alice, id, offset, &file[offset..end]).await;
+        assert!(resp.success, "{}", resp.message);
+        assert_eq!(resp.received, end as u64);
+        offset = end;
+        if offset == 8 {
+            // A repeated chunk is refused with the resume point
+            let resp = chunk(&state, &conn, &mut alice, id, 0, &file[..8]).await;
+            assert!(!resp.success);
+            assert_eq!(resp.message, "Expected offset 8");
+            assert_eq!(resp.received, 8);
+        }
+    }
+    let resp = chunk(&state, &conn, &mut alice, id, offset, b"x").await;
+    assert_eq!(resp.message, "Chunk exceeds declared size");
+
+    // Nobody can read it before it is finished
+    let early = download_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "download_chunk",
+            serde_json::json!({ "attachment_id": id, "offset": 0 }),
+        )
+        .await,
+    );
+    assert_eq!(early.message, "Attachment not found");
+
+    let done = upload_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "upload_finish",
+            serde_json::json!({ "attachment_id": id }),
+        )
+        .await,
+    );
+    assert!(done.success, "{}", done.message);
+    let info = done.attachment.unwrap();
+    assert_eq!(info.file_name, "notes.txt");
+    assert_eq!(info.size, file.len() as u64);
+    assert_eq!(info.sha256, sha256_hex(file));
+
+    // Not shared with bob yet
+    let hidden = download_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "download_chunk",
+            serde_json::json!({ "attachment_id": id, "offset": 0 }),
+        )
+        .await,
+    );
+    assert!(!hidden.success);
+    assert_eq!(hidden.message, "Attachment not found");
+
+    let req = DirectMessageReq {
+        to_user_id: 2,
+        body: "see attached".to_string(),
+        saved: None,
+        reply_to_id: None,
+        attachment_id: Some(id),
+        expires_in_secs: None,
+        view_once: false,
+    };
+    send_direct(Arc::clone(&state), Arc::clone(&conn), 1, req)
+        .await
+        .unwrap();
+    let event = next(&mut bob.1).await;
+    assert_eq!(event.command, "message");
+    let event: DirectMessageEvent = serde_json::from_str(&event.data).unwrap();
+    assert_eq!(event.attachment.unwrap().id, id);
+
+    let mut downloaded = Vec::new();
+    loop {
+        let resp = download_response(
+            &command(
+                handle_attachment_command,
+                &state,
+                &conn,
+                &mut bob,
+                2,
+                "download_chunk",
+                serde_json::json!({
+                    "attachment_id": id,
+                    "offset": downloaded.len(),
+                    "length": 100,
+                }),
+            )
+            .await,
+        );
+        assert!(resp.success, "{}", resp.message);
+        let data = BASE64.decode(&resp.data).unwrap();
+        assert!(data.len() <= 8, "length is capped by chunk_bytes");
+        downloaded.extend(data);
+        if resp.eof {
+            break;
+        }
+    }
+    assert_eq!(downloaded, file);
+
+    let outsider = download_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut carol,
+            3,
+            "download_chunk",
+            serde_json::json!({ "attachment_id": id, "offset": 0 }),
+        )
+        .await,
+    );
+    assert_eq!(outsider.message, "Attachment not found");
+}
+
+#[tokio::test]
+async fn oversized_and_corrupted_uploads_are_refused() {
+    let (state, _dir) = state_with_dir("refused");
+    let conn = db_with_users(&["alice", "bob"]);
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+
+    let too_big = upload_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "upload_begin",
+            serde_json::json!({
+                "file_name": "big.bin",
+                "mime_type": "application/octet-stream",
+                "size": 65,
+                "sha256": sha256_hex(b""),
+            }),
+        )
+        .await,
+    );
+    assert!(!too_big.success);
+    assert!(too_big.message.starts_with("Attachment too large"));
+
+    let begun = upload_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "upload_begin",
+            serde_json::json!({
+                "file_name": "a.txt",
+                "mime_type": "text/plain",
+                "size": 3,
+                "sha256": sha256_hex(b"abc"),
+            }),
+        )
+        .await,
+    );
+    let id = begun.attachment_id.unwrap();
+
+    let finish = async |session: &mut (SessionSender, SessionReceiver), user_id| {
+        upload_response(
+            &command(
+                handle_attachment_command,
+                &state,
+                &conn,
+                session,
+                user_id,
+                "upload_finish",
+                serde_json::json!({ "attachment_id": id }),
+            )
+            .await,
+        )
+    };
+    assert_eq!(finish(&mut bob, 2).await.message, "Upload not found");
+    assert_eq!(
+        finish(&mut alice, 1).await.message,
+        "Upload incomplete: 0 of 3 bytes"
+    );
+
+    let stored = upload_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "upload_chunk",
+            serde_json::json!({
+                "attachment_id": id,
+                "offset": 0,
+                "data": BASE64.encode(b"abd"),
+            }),
+        )
+        .await,
+    );
+    assert!(stored.success, "{}", stored.message);
+    assert_eq!(finish(&mut alice, 1).await.message, "Checksum mismatch");
+    // The corrupted upload is gone
+    assert_eq!(finish(&mut alice, 1).await.message, "Upload not found");
+
+    // An unfinished upload is not attached to a message
+    let pending = upload_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "upload_begin",
+            serde_json::json!({
+                "file_name": "b.txt",
+                "mime_type": "text/plain",
+                "size": 1,
+                "sha256": sha256_hex(b"b"),
+            }),
+        )
+        .await,
+    );
+    let req = DirectMessageReq {
+        to_user_id: 2,
+        body: "hi".to_string(),
+        saved: None,
+        reply_to_id: None,
+        attachment_id: pending.attachment_id,
+        expires_in_secs: None,
+        view_once: false,
+    };
+    send_direct(Arc::clone(&state), Arc::clone(&conn), 1, req)
+        .await
+        .unwrap();
+    let event: DirectMessageEvent = serde_json::from_str(&next(&mut bob.1).await.data).unwrap();
+    assert!(event.attachment.is_none());
+}
+
+#[tokio::test]
+async fn identical_files_are_stored_once_and_collected_when_unreferenced() {
+    let (state, dir) = state_with("dedup", |attachments| {
+        attachments.chunk_bytes = 64;
+        attachments.quota_bytes = 20;
+    });
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let file = b"shared bytes";
+    let blobs = dir.0.join("blobs");
+
+    let first = upload(&state, &conn, 1, file).await;
+    let second = upload(&state, &conn, 2, file).await;
+    assert!(first.success && second.success);
+    let (first, second) = (first.attachment_id.unwrap(), second.attachment_id.unwrap());
+    assert_ne!(first, second);
+    assert_eq!(files_under(&blobs), 1, "identical content is stored once");
+    let usage = attachment_usage(Arc::clone(&conn)).await.unwrap();
+    assert_eq!((usage.charged_bytes, usage.stored_bytes), (24, 12));
+    let report = admin(
+        &state,
+        &conn,
+        Command::Attachments(AttachmentsCommand::Usage),
+    )
+    .await;
+    assert!(report.contains("stored_bytes: 12 in 1 blob(s)"), "{report}");
+
+    // Every upload counts against its owner's quota, shared or not
+    let refused = upload(&state, &conn, 1, &[0; 9]).await;
+    assert!(!refused.success);
+    assert_eq!(
+        refused.message,
+        "Storage quota exceeded: 12 of 20 bytes used"
+    );
+
+    // Alice sends her copy and bob forwards it
+    for (from, to) in [(1, 2), (2, 3)] {
+        let req = DirectMessageReq {
+            to_user_id: to,
+            body: "file".to_string(),
+            saved: None,
+            reply_to_id: None,
+            attachment_id: Some(first),
+            expires_in_secs: None,
+            view_once: false,
+        };
+        send_direct(Arc::clone(&state), Arc::clone(&conn), from, req)
+            .await
+            .unwrap();
+    }
+    let refs = |id: i64| {
+        conn.lock()
+            .unwrap()
+            .query_row(
+                "SELECT message_refs FROM attachments WHERE id = ?1",
+                [id],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap()
+    };
+    assert_eq!(refs(first), 2);
+
+    // Within the grace period even unsent uploads stay
+    let report = collect_garbage(Arc::clone(&conn), &state.config().attachments)
+        .await
+        .unwrap();
+    assert_eq!(report, GarbageReport::default());
+
+    conn.lock()
+        .unwrap()
+        .execute(
+            "UPDATE attachments SET created_at = '2000-01-01T00:00:00+00:00'",
+            [],
+        )
+        .unwrap();
+    let report = collect_garbage(Arc::clone(&conn), &state.config().attachments)
+        .await
+        .unwrap();
+    assert_eq!(report.attachments, 1, "bob's unsent copy is collected");
+    assert_eq!(report.blobs, 0, "alice's copy still uses the content");
+    assert_eq!(files_under(&blobs), 1);
+
+    let purged = admin(
+        &state,
+        &conn,
+        Command::Messages(MessagesCommand::Purge {
+            before: "2999-01-01".to_string(),
+            include_saved: true,
+        }),
+    )
+    .await;
+    assert!(purged.starts_with("Purged 2 message(s)"), "{purged}");
+    let collected = admin(&state, &conn, Command::Attachments(AttachmentsCommand::Gc)).await;
+    assert_eq!(
+        collected.trim(),
+        "Removed 1 attachment(s) and 1 blob(s), 12 byte(s) freed"
+    );
+    assert_eq!(files_under(&blobs), 0);
+    let usage = attachment_usage(Arc::clone(&conn)).await.unwrap();
+    assert_eq!(usage.blobs, 0);
+    assert!(usage.users.is_empty());
+}
+
+/// Opaque 40x20 PNG whose EXIF block records a GPS latitude.
+fn png_with_location() -> (Vec<u8>, Vec<u8>) {
+    let latitude: Vec<u8> = [52u32, 1, 31, 1, 12, 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    // IFD0 holds only the GPS IFD pointer; the GPS IFD holds GPSLatitude
+    let mut exif = b"II*\0\x08\0\0\0\x01\0\x25\x88\x04\0\x01\0\0\0\x1a\0\0\0\0\0\0\0".to_vec();
+    exif.extend(b"\x01\0\x02\0\x05\0\x03\0\0\0\x2c\0\0\0\0\0\0\0");
+    exif.extend(&latitude);
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, 40, 20);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer
+        .write_chunk(png::chunk::ChunkType(*b"eXIf"), &exif)
+        .unwrap();
+    writer.write_image_data(&[200; 40 * 20 * 3]).unwrap();
+    writer.finish().unwrap();
+    (png, latitude)
+}
+
+#[tokio::test]
+async fn images_get_thumbnails_and_lose_their_location() {
+    let (state, _dir) = state_with("images", |attachments| {
+        attachments.max_bytes = 64 * 1024;
+        attachments.chunk_bytes = 64 * 1024;
+        attachments.thumbnail_px = 16;
+    });
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+    let (file, latitude) = png_with_location();
+
+    let done = upload(&state, &conn, 1, &file).await;
+    assert!(done.success, "{}", done.message);
+    let info = done.attachment.unwrap();
+    assert_eq!(info.mime_type, "image/png", "detected from the content");
+    assert_eq!((info.width, info.height), (Some(40), Some(20)));
+    assert_ne!(info.sha256, sha256_hex(&file), "the location was scrubbed");
+    let thumbnail = info.thumbnail.clone().expect("a thumbnail");
+    assert_eq!((thumbnail.width, thumbnail.height), (16, 8));
+    assert_eq!(thumbnail.mime_type, "image/jpeg");
+
+    let req = DirectMessageReq {
+        to_user_id: 2,
+        body: "photo".to_string(),
+        saved: None,
+        reply_to_id: None,
+        attachment_id: Some(info.id),
+        expires_in_secs: None,
+        view_once: false,
+    };
+    send_direct(Arc::clone(&state), Arc::clone(&conn), 1, req)
+        .await
+        .unwrap();
+    let event: DirectMessageEvent = serde_json::from_str(&next(&mut bob.1).await.data).unwrap();
+    assert_eq!(event.attachment.as_ref(), Some(&info));
+
+    let fetch = async |session: &mut (SessionSender, SessionReceiver), user_id, id| {
+        download_response(
+            &command(
+                handle_attachment_command,
+                &state,
+                &conn,
+                session,
+                user_id,
+                "download_chunk",
+                serde_json::json!({ "attachment_id": id, "offset": 0 }),
+            )
+            .await,
+        )
+    };
+    let preview = fetch(&mut bob, 2, thumbnail.id).await;
+    assert!(preview.success && preview.eof, "{}", preview.message);
+    assert!(
+        BASE64
+            .decode(&preview.data)
+            .unwrap()
+            .starts_with(&[0xFF, 0xD8, 0xFF])
+    );
+    let stored = BASE64
+        .decode(&fetch(&mut bob, 2, info.id).await.data)
+        .unwrap();
+    assert_eq!(stored.len(), file.len());
+    assert!(!stored.windows(latitude.len()).any(|w| w == latitude));
+    assert_eq!(
+        fetch(&mut carol, 3, thumbnail.id).await.message,
+        "Attachment not found"
+    );
+
+    // The thumbnail goes with its image, not before
+    conn.lock()
+        .unwrap()
+        .execute(
+            "UPDATE attachments SET created_at = '2000-01-01T00:00:00+00:00'",
+            [],
+        )
+        .unwrap();
+    let report = collect_garbage(Arc::clone(&conn), &state.config().attachments)
+        .await
+        .unwrap();
+    assert_eq!(report, GarbageReport::default());
+    conn.lock()
+        .unwrap()
+        .execute("DELETE FROM messages", [])
+        .unwrap();
+    let report = collect_garbage(Arc::clone(&conn), &state.config().attachments)
+        .await
+        .unwrap();
+    assert_eq!((report.attachments, report.blobs), (2, 2));
+}
+
+#[tokio::test]
+async fn images_that_fail_to_decode_still_lose_their_location() {
+    let (state, _dir) = state_with("undecodable", |attachments| {
+        attachments.max_bytes = 64 * 1024;
+        attachments.chunk_bytes = 64 * 1024;
+    });
+    let conn = db_with_users(&["alice"]);
+    let mut alice = online(&state, 1).await;
+    let (png, latitude) = png_with_location();
+    // A zero width makes the decoder give up after the signature
+    let mut file = png.clone();
+    file[16..20].fill(0);
+
+    let done = upload(&state, &conn, 1, &file).await;
+    assert!(done.success, "{}", done.message);
+    let info = done.attachment.unwrap();
+    assert_eq!(info.mime_type, "application/octet-stream", "as declared");
+    assert_eq!((info.width, info.height), (None, None));
+    assert!(info.thumbnail.is_none());
+    assert_ne!(info.sha256, sha256_hex(&file), "the location was scrubbed");
+
+    let stored = download_response(
+        &command(
+            handle_attachment_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "download_chunk",
+            serde_json::json!({ "attachment_id": info.id, "offset": 0 }),
+        )
+        .await,
+    );
+    assert!(stored.success, "{}", stored.message);
+    let stored = BASE64.decode(&stored.data).unwrap();
+    assert_eq!(stored.len(), file.len());
+    assert!(!stored.windows(latitude.len()).any(|w| w == latitude));
+}
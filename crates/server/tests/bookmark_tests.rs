@@ -0,0 +1,285 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+
+use rura_server::messaging::bookmarks::handle_bookmark_command;
+use rura_server::messaging::models::{SaveResponse, SavedListResponse};
+use rura_server::messaging::queue::{SessionReceiver, SessionSender};
+use rura_server::messaging::state::AppState;
+use rura_server::models::config::Config;
+use rura_server::utils::db_utils::{fetch_messages_for_user, store_message};
+
+use common::{command, db_with_users};
+
+async fn save(
+    state: &Arc<AppState>,
+    conn: &Arc<Mutex<Connection>>,
+    session: &mut (SessionSender, SessionReceiver),
+    user_id: i64,
+    data: serde_json::Value,
+) -> SaveResponse {
+    let resp = command(
+        handle_bookmark_command,
+        state,
+        conn,
+        session,
+        user_id,
+        "save",
+        data,
+    )
+    .await;
+    assert_eq!(resp.command, "save_response");
+    serde_json::from_str(&resp.data).unwrap()
+}
+
+async fn saved_list(
+    state: &Arc<AppState>,
+    conn: &Arc<Mutex<Connection>>,
+    session: &mut (SessionSender, SessionReceiver),
+    user_id: i64,
+    data: serde_json::Value,
+) -> SavedListResponse {
+    let resp = command(
+        handle_bookmark_command,
+        state,
+        conn,
+        session,
+        user_id,
+        "saved_list",
+        data,
+    )
+    .await;
+    assert_eq!(resp.command, "saved_list_response");
+    let list: SavedListResponse = serde_json::from_str(&resp.data).unwrap();
+    assert!(list.success, "{}", list.message);
+    list
+}
+
+async fn saved_in_history(conn: &Arc<Mutex<Connection>>, user_id: i64) -> Vec<(i64, bool)> {
+    fetch_messages_for_user(Arc::clone(conn), user_id, 50)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|m| (m.id, m.saved))
+        .collect()
+}
+
+#[tokio::test]
+async fn saves_belong_to_each_user() {
+    let state = Arc::new(AppState::new(Arc::new(Config::default())));
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let mut session = state.outbound_channel();
+
+    // Sending with `saved` bookmarks the message for the sender only
+    let id = store_message(Arc::clone(&conn), 1, 2, "hello", true)
+        .await
+        .unwrap();
+    assert_eq!(saved_in_history(&conn, 1).await, [(id, true)]);
+    assert_eq!(saved_in_history(&conn, 2).await, [(id, false)]);
+
+    let resp = save(
+        &state,
+        &conn,
+        &mut session,
+        2,
+        serde_json::json!({"message_id": id}),
+    )
+    .await;
+    assert!(resp.success, "{}", resp.message);
+    assert_eq!(resp.message, "Message updated");
+    assert_eq!(resp.saved, Some(true));
+
+    // Unsaving only drops the caller's own bookmark
+    let unsave = serde_json::json!({"message_id": id, "saved": false});
+    let resp = save(&state, &conn, &mut session, 1, unsave).await;
+    assert!(resp.success);
+    assert_eq!(saved_in_history(&conn, 1).await, [(id, false)]);
+    assert_eq!(saved_in_history(&conn, 2).await, [(id, true)]);
+
+    // Someone who cannot see the message cannot save it
+    let resp = save(
+        &state,
+        &conn,
+        &mut session,
+        3,
+        serde_json::json!({"message_id": id}),
+    )
+    .await;
+    assert!(!resp.success);
+    assert_eq!(resp.message, "Message not found or not authorized");
+
+    let resp = command(
+        handle_bookmark_command,
+        &state,
+        &conn,
+        &mut session,
+        1,
+        "save",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(resp.command, "error");
+    assert_eq!(resp.data, "Invalid save format");
+}
+
+#[tokio::test]
+async fn bookmarks_keep_folder_tags_and_note() {
+    let state = Arc::new(AppState::new(Arc::new(Config::default())));
+    let conn = db_with_users(&["alice", "bob"]);
+    let mut session = state.outbound_channel();
+    let id = store_message(Arc::clone(&conn), 2, 1, "soup recipe", false)
+        .await
+        .unwrap();
+
+    let first = serde_json::json!({
+        "message_id": id,
+        "folder": "Recipes",
+        "tags": ["dinner", "quick", "quick"],
+        "note": "try on Sunday",
+    });
+    assert!(save(&state, &conn, &mut session, 1, first).await.success);
+    // Saving again only changes what is given
+    let retag = serde_json::json!({"message_id": id, "tags": ["soup"]});
+    assert!(save(&state, &conn, &mut session, 1, retag).await.success);
+
+    let list = saved_list(&state, &conn, &mut session, 1, serde_json::json!({})).await;
+    assert_eq!(list.bookmarks.len(), 1);
+    let bookmark = &list.bookmarks[0];
+    assert_eq!(bookmark.folder, "Recipes");
+    assert_eq!(bookmark.tags, ["soup"]);
+    assert_eq!(bookmark.note, "try on Sunday");
+    assert_eq!(bookmark.message.id, id);
+    assert_eq!(bookmark.message.body, "soup recipe");
+    assert!(bookmark.message.saved);
+
+    for (bad, message) in [
+        (
+            serde_json::json!({"message_id": id, "tags": ["two words"]}),
+            "Tags must be 1 to 32 bytes without spaces",
+        ),
+        (
+            serde_json::json!({"message_id": id, "folder": "x".repeat(65)}),
+            "Folder must be at most 64 bytes without surrounding spaces",
+        ),
+        (
+            serde_json::json!({"message_id": id, "note": "x".repeat(1025)}),
+            "Note must be at most 1024 bytes",
+        ),
+    ] {
+        let resp = save(&state, &conn, &mut session, 1, bad).await;
+        assert!(!resp.success);
+        assert_eq!(resp.message, message);
+    }
+
+    // Unsaving forgets the folder, tags and note
+    let unsave = serde_json::json!({"message_id": id, "saved": false});
+    assert!(save(&state, &conn, &mut session, 1, unsave).await.success);
+    assert!(
+        save(
+            &state,
+            &conn,
+            &mut session,
+            1,
+            serde_json::json!({"message_id": id})
+        )
+        .await
+        .success
+    );
+    let list = saved_list(&state, &conn, &mut session, 1, serde_json::json!({})).await;
+    assert_eq!(list.bookmarks[0].folder, "");
+    assert!(list.bookmarks[0].tags.is_empty());
+    assert_eq!(list.bookmarks[0].note, "");
+}
+
+#[tokio::test]
+async fn saved_list_filters_and_pages() {
+    let state = Arc::new(AppState::new(Arc::new(Config::default())));
+    let conn = db_with_users(&["alice", "bob"]);
+    let mut session = state.outbound_channel();
+
+    let mut ids = Vec::new();
+    for n in 0..5 {
+        let id = store_message(Arc::clone(&conn), 2, 1, &format!("m{n}"), false)
+            .await
+            .unwrap();
+        let folder = if n % 2 == 0 { "even" } else { "" };
+        let tags = if n < 2 { vec!["early"] } else { Vec::new() };
+        let data = serde_json::json!({"message_id": id, "folder": folder, "tags": tags});
+        assert!(save(&state, &conn, &mut session, 1, data).await.success);
+        ids.push(id);
+    }
+    // bob saved nothing
+    let list = saved_list(&state, &conn, &mut session, 2, serde_json::json!({})).await;
+    assert!(list.bookmarks.is_empty());
+
+    let bodies = |list: &SavedListResponse| -> Vec<String> {
+        list.bookmarks
+            .iter()
+            .map(|b| b.message.body.clone())
+            .collect()
+    };
+    let filter = serde_json::json!({"folder": "even"});
+    let list = saved_list(&state, &conn, &mut session, 1, filter).await;
+    assert_eq!(bodies(&list), ["m4", "m2", "m0"]);
+    let filter = serde_json::json!({"folder": ""});
+    let list = saved_list(&state, &conn, &mut session, 1, filter).await;
+    assert_eq!(bodies(&list), ["m3", "m1"]);
+    let filter = serde_json::json!({"tag": "early", "folder": "even"});
+    let list = saved_list(&state, &conn, &mut session, 1, filter).await;
+    assert_eq!(bodies(&list), ["m0"]);
+
+    // Page back, newest first
+    let page = saved_list(
+        &state,
+        &conn,
+        &mut session,
+        1,
+        serde_json::json!({"limit": 2}),
+    )
+    .await;
+    assert_eq!(bodies(&page), ["m4", "m3"]);
+    let before_id = page.bookmarks.last().unwrap().id;
+    let filter = serde_json::json!({"limit": 2, "before_id": before_id});
+    let page = saved_list(&state, &conn, &mut session, 1, filter).await;
+    assert_eq!(bodies(&page), ["m2", "m1"]);
+    let before_id = page.bookmarks.last().unwrap().id;
+    let filter = serde_json::json!({"limit": 2, "before_id": before_id});
+    let page = saved_list(&state, &conn, &mut session, 1, filter).await;
+    assert_eq!(bodies(&page), ["m0"]);
+
+    // Purging a message takes its bookmarks along
+    conn.lock()
+        .unwrap()
+        .execute("DELETE FROM messages WHERE id = ?1", [ids[4]])
+        .unwrap();
+    let page = saved_list(
+        &state,
+        &conn,
+        &mut session,
+        1,
+        serde_json::json!({"limit": 1}),
+    )
+    .await;
+    assert_eq!(bodies(&page), ["m3"]);
+    let left: i64 = conn
+        .lock()
+        .unwrap()
+        .query_row("SELECT COUNT(*) FROM bookmarks", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(left, 4);
+
+    let resp = command(
+        handle_bookmark_command,
+        &state,
+        &conn,
+        &mut session,
+        1,
+        "saved_list",
+        serde_json::json!({"limit": "many"}),
+    )
+    .await;
+    assert_eq!(resp.command, "error");
+    assert_eq!(resp.data, "Invalid saved_list format");
+}
@@ -0,0 +1,302 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+
+use rura_server::messaging::channels::handle_channel_command;
+use rura_server::messaging::models::{
+    ChannelHistoryResponse, ChannelInvitedEvent, ChannelListResponse, ChannelPostEvent,
+    ChannelResponse,
+};
+use rura_server::messaging::queue::SessionSender;
+use rura_server::messaging::state::AppState;
+use rura_server::models::client_message::ClientMessage;
+
+use common::{assert_forbidden, command, db_with_users, next, online};
+
+async fn send(
+    state: &Arc<AppState>,
+    conn: &Arc<Mutex<Connection>>,
+    session: &SessionSender,
+    user_id: i64,
+    command: &str,
+    data: serde_json::Value,
+) {
+    let msg = ClientMessage {
+        command: command.to_string(),
+        data: data.to_string(),
+    };
+    handle_channel_command(Arc::clone(state), Arc::clone(conn), session, user_id, msg).await;
+}
+
+fn channel_response(msg: &ClientMessage) -> ChannelResponse {
+    assert_eq!(msg.command, "channel_response");
+    serde_json::from_str(&msg.data).unwrap()
+}
+
+#[tokio::test]
+async fn public_channel_fans_out_to_subscribers_and_catches_up() {
+    let conn = db_with_users(&["ops", "bob", "carol"]);
+    let state = Arc::new(AppState::default());
+    let mut ops = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+
+    let resp = channel_response(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut ops,
+            1,
+            "channel_create",
+            serde_json::json!({"name": "announcements", "description": "Ops news"}),
+        )
+        .await,
+    );
+    let channel = resp.channel.unwrap();
+    assert_eq!(
+        (channel.role.as_deref(), channel.owner_id),
+        (Some("owner"), Some(1))
+    );
+    let id = channel.id;
+
+    let dup = channel_response(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "channel_create",
+            serde_json::json!({"name": "announcements"}),
+        )
+        .await,
+    );
+    assert!(!dup.success);
+
+    let resp = channel_response(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "channel_subscribe",
+            serde_json::json!({"channel_id": id}),
+        )
+        .await,
+    );
+    assert_eq!(resp.channel.unwrap().member_count, 2);
+
+    // Subscribers cannot post
+    assert_forbidden(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "channel_post",
+            serde_json::json!({"channel_id": id, "body": "can I?"}),
+        )
+        .await,
+    );
+
+    for body in ["first", "second"] {
+        send(
+            &state,
+            &conn,
+            &ops.0,
+            1,
+            "channel_post",
+            serde_json::json!({"channel_id": id, "body": body}),
+        )
+        .await;
+    }
+    let delivered = next(&mut bob.1).await;
+    assert_eq!(delivered.command, "channel_post");
+    let first: ChannelPostEvent = serde_json::from_str(&delivered.data).unwrap();
+    assert_eq!((first.from_user_id, first.body.as_str()), (1, "first"));
+    assert_eq!(next(&mut bob.1).await.command, "channel_post");
+    // No echo for the publisher, nothing for non-subscribers
+    assert!(ops.1.is_empty());
+    assert!(carol.1.is_empty());
+
+    // Late subscribers catch up through channel_history
+    let history = command(
+        handle_channel_command,
+        &state,
+        &conn,
+        &mut carol,
+        3,
+        "channel_history",
+        serde_json::json!({"channel_id": id, "after_id": first.post_id}),
+    )
+    .await;
+    assert_eq!(history.command, "channel_history_response");
+    let history: ChannelHistoryResponse = serde_json::from_str(&history.data).unwrap();
+    let bodies: Vec<_> = history.posts.iter().map(|p| p.body.as_str()).collect();
+    assert_eq!(bodies, ["second"]);
+
+    let list = command(
+        handle_channel_command,
+        &state,
+        &conn,
+        &mut carol,
+        3,
+        "channel_list",
+        serde_json::json!({}),
+    )
+    .await;
+    let list: ChannelListResponse = serde_json::from_str(&list.data).unwrap();
+    assert_eq!(list.channels.len(), 1);
+    assert_eq!(list.channels[0].role, None);
+}
+
+#[tokio::test]
+async fn invite_only_channel_hides_from_outsiders_and_shares_one_event() {
+    let conn = db_with_users(&["ops", "bob", "carol", "dave"]);
+    let state = Arc::new(AppState::default());
+    let mut ops = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+    let mut dave = online(&state, 4).await;
+
+    let id = channel_response(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut ops,
+            1,
+            "channel_create",
+            serde_json::json!({"name": "staff", "invite_only": true}),
+        )
+        .await,
+    )
+    .channel
+    .unwrap()
+    .id;
+
+    let resp = channel_response(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut dave,
+            4,
+            "channel_subscribe",
+            serde_json::json!({"channel_id": id}),
+        )
+        .await,
+    );
+    assert_eq!(
+        (resp.success, resp.message.as_str()),
+        (false, "Channel not found")
+    );
+    let list = command(
+        handle_channel_command,
+        &state,
+        &conn,
+        &mut dave,
+        4,
+        "channel_list",
+        serde_json::json!({}),
+    )
+    .await;
+    let list: ChannelListResponse = serde_json::from_str(&list.data).unwrap();
+    assert!(list.channels.is_empty());
+
+    let resp = channel_response(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut ops,
+            1,
+            "channel_invite",
+            serde_json::json!({"channel_id": id, "user_ids": [2, 3]}),
+        )
+        .await,
+    );
+    assert!(resp.success, "{}", resp.message);
+    for session in [&mut bob, &mut carol] {
+        let invited = next(&mut session.1).await;
+        assert_eq!(invited.command, "channel_invited");
+        let invited: ChannelInvitedEvent = serde_json::from_str(&invited.data).unwrap();
+        assert!(invited.channel.invited);
+    }
+    for (session, user_id) in [(&mut bob, 2), (&mut carol, 3)] {
+        let resp = channel_response(
+            &command(
+                handle_channel_command,
+                &state,
+                &conn,
+                session,
+                user_id,
+                "channel_subscribe",
+                serde_json::json!({"channel_id": id}),
+            )
+            .await,
+        );
+        assert_eq!(resp.channel.unwrap().role.as_deref(), Some("subscriber"));
+    }
+
+    // Only the owner hands out publisher rights
+    assert_forbidden(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "channel_set_role",
+            serde_json::json!({"channel_id": id, "user_id": 2, "role": "publisher"}),
+        )
+        .await,
+    );
+    let resp = channel_response(
+        &command(
+            handle_channel_command,
+            &state,
+            &conn,
+            &mut ops,
+            1,
+            "channel_set_role",
+            serde_json::json!({"channel_id": id, "user_id": 2, "role": "publisher"}),
+        )
+        .await,
+    );
+    assert!(resp.success);
+
+    send(
+        &state,
+        &conn,
+        &bob.0,
+        2,
+        "channel_post",
+        serde_json::json!({"channel_id": id, "body": "shift change at 6"}),
+    )
+    .await;
+    let to_ops = ops.1.recv_shared().await.unwrap();
+    let to_carol = carol.1.recv_shared().await.unwrap();
+    assert_eq!(to_ops.command, "channel_post");
+    assert!(Arc::ptr_eq(&to_ops, &to_carol));
+    assert!(dave.1.is_empty());
+
+    let history = command(
+        handle_channel_command,
+        &state,
+        &conn,
+        &mut dave,
+        4,
+        "channel_history",
+        serde_json::json!({"channel_id": id}),
+    )
+    .await;
+    let history: ChannelHistoryResponse = serde_json::from_str(&history.data).unwrap();
+    assert!(!history.success);
+    assert!(history.posts.is_empty());
+}
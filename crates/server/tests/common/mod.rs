@@ -0,0 +1,261 @@
+// Helpers shared by the handler tests. Each test binary compiles its own
+// copy and uses only some of them.
+#![allow(dead_code)]
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rusqlite::Connection;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+
+use rura_server::messaging::attachments::handle_attachment_command;
+use rura_server::messaging::models::UploadResponse;
+use rura_server::messaging::queue::{SessionReceiver, SessionSender};
+use rura_server::messaging::state::{AppState, ClientHandle};
+use rura_server::models::client_message::{
+    AuthRequest, AuthResponse, ClientMessage, ErrorResponse,
+};
+use rura_server::models::config::{AttachmentsSection, Config};
+use rura_server::utils::attachment_store::sha256_hex;
+use rura_server::utils::db_utils::init_db_with_path;
+
+pub fn db_with_users(names: &[&str]) -> Arc<Mutex<Connection>> {
+    let conn = init_db_with_path(":memory:").unwrap();
+    for name in names {
+        conn.execute(
+            "INSERT INTO users (passphrase, password) VALUES (?1, 'x')",
+            [name],
+        )
+        .unwrap();
+    }
+    Arc::new(Mutex::new(conn))
+}
+
+pub async fn online(state: &AppState, user_id: i64) -> (SessionSender, SessionReceiver) {
+    let (tx, rx) = state.outbound_channel();
+    state
+        .register(user_id, ClientHandle { tx: tx.clone() })
+        .await;
+    (tx, rx)
+}
+
+pub async fn next(rx: &mut SessionReceiver) -> ClientMessage {
+    timeout(Duration::from_millis(200), rx.recv())
+        .await
+        .expect("timed out waiting for event")
+        .expect("channel closed unexpectedly")
+}
+
+pub fn client_message(command: &str, data: serde_json::Value) -> ClientMessage {
+    ClientMessage {
+        command: command.to_string(),
+        data: data.to_string(),
+    }
+}
+
+/// Run a command through `handler` and return the caller's first reply.
+pub async fn command(
+    handler: impl AsyncFn(Arc<AppState>, Arc<Mutex<Connection>>, &SessionSender, i64, ClientMessage),
+    state: &Arc<AppState>,
+    conn: &Arc<Mutex<Connection>>,
+    session: &mut (SessionSender, SessionReceiver),
+    user_id: i64,
+    command: &str,
+    data: serde_json::Value,
+) -> ClientMessage {
+    let msg = client_message(command, data);
+    handler(
+        Arc::clone(state),
+        Arc::clone(conn),
+        &session.0,
+        user_id,
+        msg,
+    )
+    .await;
+    next(&mut session.1).await
+}
+
+pub fn assert_forbidden(msg: &ClientMessage) {
+    assert_eq!(msg.command, "error");
+    let err: ErrorResponse = serde_json::from_str(&msg.data).unwrap();
+    assert_eq!(err.code, "Forbidden", "{}", err.message);
+}
+
+/// Attachment directory removed again when the test ends.
+pub struct TempDir(pub PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Server state storing attachments under a fresh directory, with small
+/// upload limits: 64 bytes per file, sent in chunks of 8.
+pub fn state_with_dir(name: &str) -> (Arc<AppState>, TempDir) {
+    state_with(name, |_| {})
+}
+
+pub fn state_with(
+    name: &str,
+    configure: impl FnOnce(&mut AttachmentsSection),
+) -> (Arc<AppState>, TempDir) {
+    let dir =
+        std::env::temp_dir().join(format!("rura-attachments-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut config = Config {
+        attachments: AttachmentsSection {
+            dir: dir.to_string_lossy().into_owned(),
+            max_bytes: 64,
+            chunk_bytes: 8,
+            ..AttachmentsSection::default()
+        },
+        ..Config::default()
+    };
+    configure(&mut config.attachments);
+    (Arc::new(AppState::new(Arc::new(config))), TempDir(dir))
+}
+
+/// Files stored under `dir`, at any depth.
+pub fn files_under(dir: &Path) -> usize {
+    std::fs::read_dir(dir).map_or(0, |entries| {
+        entries
+            .map(|entry| entry.unwrap().path())
+            .map(|path| if path.is_dir() { files_under(&path) } else { 1 })
+            .sum()
+    })
+}
+
+pub fn upload_response(msg: &ClientMessage) -> UploadResponse {
+    assert_eq!(msg.command, "upload_response");
+    serde_json::from_str(&msg.data).unwrap()
+}
+
+/// Upload `file` in one chunk and finish it; returns the final response.
+/// `file` must fit in `chunk_bytes`.
+pub async fn upload(
+    state: &Arc<AppState>,
+    conn: &Arc<Mutex<Connection>>,
+    user_id: i64,
+    file: &[u8],
+) -> UploadResponse {
+    let mut session = state.outbound_channel();
+    let mut step = async |command_name: &str, data: serde_json::Value| {
+        let reply = command(
+            handle_attachment_command,
+            state,
+            conn,
+            &mut session,
+            user_id,
+            command_name,
+            data,
+        )
+        .await;
+        upload_response(&reply)
+    };
+    let begun = step(
+        "upload_begin",
+        serde_json::json!({
+            "file_name": "f.bin",
+            "mime_type": "application/octet-stream",
+            "size": file.len(),
+            "sha256": sha256_hex(file),
+        }),
+    )
+    .await;
+    let Some(id) = begun.attachment_id else {
+        return begun;
+    };
+    let stored = step(
+        "upload_chunk",
+        serde_json::json!({ "attachment_id": id, "offset": 0, "data": BASE64.encode(file) }),
+    )
+    .await;
+    assert!(stored.success, "{}", stored.message);
+    step("upload_finish", serde_json::json!({ "attachment_id": id })).await
+}
+
+pub fn app_state(config: Config) -> Arc<AppState> {
+    Arc::new(AppState::new(Arc::new(config)))
+}
+
+/// Run a full client session over an in-memory pipe, with a fresh database.
+pub fn connect(state: &Arc<AppState>) -> (DuplexStream, JoinHandle<std::io::Result<()>>) {
+    connect_with_buffer(state, 4096)
+}
+
+/// Connect through a pipe that holds at most `buffer` unread bytes.
+pub fn connect_with_buffer(
+    state: &Arc<AppState>,
+    buffer: usize,
+) -> (DuplexStream, JoinHandle<std::io::Result<()>>) {
+    let conn = Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()));
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 12345);
+    connect_to(state, conn, addr, buffer)
+}
+
+/// Connect to the server behind `conn`, as a client at `addr`.
+pub fn connect_to(
+    state: &Arc<AppState>,
+    conn: Arc<Mutex<Connection>>,
+    addr: SocketAddr,
+    buffer: usize,
+) -> (DuplexStream, JoinHandle<std::io::Result<()>>) {
+    let (server_stream, client_stream) = tokio::io::duplex(buffer);
+    let handle = tokio::spawn(rura_server::client::handle_client(
+        server_stream,
+        conn,
+        Arc::clone(state),
+        addr,
+    ));
+    (client_stream, handle)
+}
+
+/// Next envelope, or `None` once the server closed the connection.
+pub async fn read_msg(stream: &mut DuplexStream) -> Option<ClientMessage> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    // One byte at a time: a line may be split over several writes, or share
+    // one with the next envelope
+    timeout(Duration::from_secs(5), async {
+        while line.last() != Some(&b'\n') {
+            if stream.read(&mut byte).await.unwrap() == 0 {
+                break;
+            }
+            line.push(byte[0]);
+        }
+    })
+    .await
+    .expect("server went silent");
+    (!line.is_empty()).then(|| serde_json::from_slice(&line).unwrap())
+}
+
+pub async fn send(stream: &mut DuplexStream, command: &str, data: &str) {
+    let msg = ClientMessage {
+        command: command.to_string(),
+        data: data.to_string(),
+    };
+    let mut line = serde_json::to_string(&msg).unwrap();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await.unwrap();
+}
+
+/// Answer the `auth_required` greeting by registering `passphrase`; returns
+/// the new user's id.
+pub async fn register(stream: &mut DuplexStream, passphrase: &str) -> i64 {
+    assert_eq!(read_msg(stream).await.unwrap().command, "auth_required");
+    let req = AuthRequest {
+        passphrase: passphrase.to_string(),
+        password: "secret".to_string(),
+    };
+    send(stream, "register", &serde_json::to_string(&req).unwrap()).await;
+    let resp = read_msg(stream).await.unwrap();
+    assert_eq!(resp.command, "auth_response");
+    let resp: AuthResponse = serde_json::from_str(&resp.data).unwrap();
+    resp.user_id.unwrap()
+}
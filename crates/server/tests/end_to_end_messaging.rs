@@ -1,6 +1,7 @@
 use rura_server::client::handle_client;
 use rura_server::messaging::state::AppState;
 use rura_server::models::client_message::{AuthRequest, AuthResponse, ClientMessage};
+use rura_server::utils::db_utils::init_db_with_path;
 use rusqlite::Connection;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
@@ -8,37 +9,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
 async fn setup_memory_db() -> Arc<Mutex<Connection>> {
-    let conn = Connection::open(":memory:").unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            passphrase TEXT NOT NULL UNIQUE,
-            password TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            sender INTEGER NOT NULL,
-            receiver INTEGER NOT NULL,
-            content TEXT NOT NULL,
-            timestamp TEXT NOT NULL,
-            saved INTEGER NOT NULL DEFAULT 0
-        )",
-        [],
-    )
-    .unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS connections (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            ip TEXT NOT NULL,
-            timestamp TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
+    let conn = init_db_with_path(":memory:").unwrap();
     Arc::new(Mutex::new(conn))
 }
 
@@ -152,7 +123,7 @@ async fn test_full_auth_and_dm_persistence_and_save() {
     assert!(count >= 1);
     assert_eq!(saved, 1);
 
-    // Now flip saved=false via c2 (receiver is authorized)
+    // c2 (the receiver) may unsave it, but that only drops their own bookmark
     let save_cmd = ClientMessage {
         command: "save".into(),
         data: "{\"message_id\":1,\"saved\":false}".into(),
@@ -160,8 +131,9 @@ async fn test_full_auth_and_dm_persistence_and_save() {
     write_json(&mut c2, &save_cmd).await;
     let save_resp = read_msg(&mut c2).await;
     assert_eq!(save_resp.command, "save_response");
+    assert!(save_resp.data.contains("\"success\":true"));
 
-    let new_saved: i64 = {
+    let saved_flag = || -> i64 {
         let guard = db.lock().unwrap();
         guard
             .query_row("SELECT saved FROM messages WHERE id = 1", [], |row| {
@@ -169,7 +141,13 @@ async fn test_full_auth_and_dm_persistence_and_save() {
             })
             .unwrap()
     };
-    assert_eq!(new_saved, 0);
+    assert_eq!(saved_flag(), 1);
+
+    // Once the sender unsaves it too, nobody has it saved
+    write_json(&mut c1, &save_cmd).await;
+    let save_resp = read_msg(&mut c1).await;
+    assert_eq!(save_resp.command, "save_response");
+    assert_eq!(saved_flag(), 0);
 
     // Silence warnings
     let _ = uid1;
@@ -0,0 +1,307 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+
+use rura_server::messaging::actions::handle_message_action;
+use rura_server::messaging::expiry::{invalid_expiry, sweep_expired_messages};
+use rura_server::messaging::groups::handle_group_command;
+use rura_server::messaging::handlers::send_direct;
+use rura_server::messaging::models::{
+    DirectMessageEvent, DirectMessageReq, GroupMessageEvent, GroupResponse, MessageExpiredEvent,
+    ViewedResponse,
+};
+use rura_server::messaging::queue::{SessionReceiver, SessionSender};
+use rura_server::messaging::state::AppState;
+use rura_server::models::client_message::ClientMessage;
+use rura_server::models::config::ExpirySection;
+use rura_server::utils::db_utils::fetch_messages_for_user;
+
+use common::{db_with_users, files_under, next, online, state_with_dir, upload};
+
+async fn viewed(
+    state: &Arc<AppState>,
+    conn: &Arc<Mutex<Connection>>,
+    session: &mut (SessionSender, SessionReceiver),
+    user_id: i64,
+    message_id: i64,
+) -> ViewedResponse {
+    let msg = ClientMessage {
+        command: "viewed".to_string(),
+        data: serde_json::json!({ "message_id": message_id }).to_string(),
+    };
+    handle_message_action(
+        Arc::clone(state),
+        Arc::clone(conn),
+        &session.0,
+        user_id,
+        msg,
+    )
+    .await;
+    let resp = next(&mut session.1).await;
+    assert_eq!(resp.command, "viewed_response");
+    serde_json::from_str(&resp.data).unwrap()
+}
+
+fn expired_event(msg: &ClientMessage) -> MessageExpiredEvent {
+    assert_eq!(msg.command, "message_expired");
+    serde_json::from_str(&msg.data).unwrap()
+}
+
+#[tokio::test]
+async fn expired_messages_vanish_at_once_and_are_swept_with_their_files() {
+    let (state, dir) = state_with_dir("sweep");
+    let conn = db_with_users(&["alice", "bob"]);
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+
+    let uploaded = upload(&state, &conn, 1, b"secret").await;
+    assert!(uploaded.success, "{}", uploaded.message);
+    let attachment_id = uploaded.attachment_id.unwrap();
+    assert_eq!(files_under(&dir.0), 1);
+    let req = DirectMessageReq {
+        to_user_id: 2,
+        body: "gone soon".to_string(),
+        saved: Some(true),
+        reply_to_id: None,
+        attachment_id: Some(attachment_id),
+        expires_in_secs: Some(60),
+        view_once: false,
+    };
+    send_direct(Arc::clone(&state), Arc::clone(&conn), 1, req)
+        .await
+        .unwrap();
+    let event: DirectMessageEvent = serde_json::from_str(&next(&mut bob.1).await.data).unwrap();
+    let message_id = event.message_id.unwrap();
+    let expires_at = event.expires_at.unwrap();
+    let now = chrono::Utc::now().timestamp();
+    assert!((now + 59..=now + 60).contains(&expires_at));
+    assert!(!event.view_once);
+
+    // Still unexpired: kept by the sweeper and listed with its deadline
+    assert_eq!(
+        sweep_expired_messages(&state, Arc::clone(&conn))
+            .await
+            .unwrap(),
+        0
+    );
+    let history = fetch_messages_for_user(Arc::clone(&conn), 2, 10)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].expires_at, Some(expires_at));
+
+    // Once its time is up nobody sees it, even before the sweep
+    conn.lock()
+        .unwrap()
+        .execute(
+            "UPDATE messages SET expires_at = expires_at - 120 WHERE id = ?1",
+            [message_id],
+        )
+        .unwrap();
+    assert!(
+        fetch_messages_for_user(Arc::clone(&conn), 1, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        fetch_messages_for_user(Arc::clone(&conn), 2, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // Saved or not, the sweep deletes it for good and tells both sides
+    assert_eq!(
+        sweep_expired_messages(&state, Arc::clone(&conn))
+            .await
+            .unwrap(),
+        1
+    );
+    for session in [&mut alice, &mut bob] {
+        let event = expired_event(&next(&mut session.1).await);
+        assert_eq!(
+            (event.message_id, event.reason.as_str()),
+            (message_id, "expired")
+        );
+        assert_eq!((event.from_user_id, event.to_user_id), (1, Some(2)));
+    }
+    let count = |sql: &str| {
+        conn.lock()
+            .unwrap()
+            .query_row(sql, [], |row| row.get::<_, i64>(0))
+            .unwrap()
+    };
+    assert_eq!(count("SELECT COUNT(*) FROM messages"), 0);
+    assert_eq!(count("SELECT COUNT(*) FROM attachments"), 0);
+    assert_eq!(files_under(&dir.0), 0);
+    assert_eq!(
+        sweep_expired_messages(&state, Arc::clone(&conn))
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn view_once_messages_are_deleted_when_their_recipient_reads_them() {
+    let conn = db_with_users(&["alice", "bob"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+
+    let req = DirectMessageReq {
+        to_user_id: 2,
+        body: "read me once".to_string(),
+        saved: None,
+        reply_to_id: None,
+        attachment_id: None,
+        expires_in_secs: None,
+        view_once: true,
+    };
+    send_direct(Arc::clone(&state), Arc::clone(&conn), 1, req)
+        .await
+        .unwrap();
+    let event: DirectMessageEvent = serde_json::from_str(&next(&mut bob.1).await.data).unwrap();
+    assert!(event.view_once);
+    assert_eq!(event.expires_at, None);
+    let message_id = event.message_id.unwrap();
+    assert!(
+        fetch_messages_for_user(Arc::clone(&conn), 2, 10)
+            .await
+            .unwrap()[0]
+            .view_once
+    );
+
+    // Only the recipient can confirm it
+    let resp = viewed(&state, &conn, &mut alice, 1, message_id).await;
+    assert!(!resp.success);
+    assert_eq!(resp.message, "View-once message not found");
+
+    let resp = viewed(&state, &conn, &mut bob, 2, message_id).await;
+    assert!(resp.success, "{}", resp.message);
+    assert_eq!(resp.message_id, message_id);
+    let event = expired_event(&next(&mut alice.1).await);
+    assert_eq!(
+        (event.message_id, event.reason.as_str()),
+        (message_id, "viewed")
+    );
+    assert!(bob.1.is_empty());
+    assert!(
+        fetch_messages_for_user(Arc::clone(&conn), 1, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let resp = viewed(&state, &conn, &mut bob, 2, message_id).await;
+    assert!(!resp.success);
+}
+
+#[tokio::test]
+async fn group_messages_expire_but_cannot_be_view_once() {
+    let conn = db_with_users(&["alice", "bob"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+
+    let group = async |user_id: i64,
+                       session: &mut (SessionSender, SessionReceiver),
+                       command: &str,
+                       data: serde_json::Value| {
+        let msg = ClientMessage {
+            command: command.to_string(),
+            data: data.to_string(),
+        };
+        handle_group_command(
+            Arc::clone(&state),
+            Arc::clone(&conn),
+            &session.0,
+            user_id,
+            msg,
+        )
+        .await;
+    };
+    group(
+        1,
+        &mut alice,
+        "group_create",
+        serde_json::json!({"name": "Team", "member_ids": [2]}),
+    )
+    .await;
+    let created: GroupResponse = serde_json::from_str(&next(&mut alice.1).await.data).unwrap();
+    let group_id = created.conversation.unwrap().id;
+    assert_eq!(next(&mut bob.1).await.command, "group_updated");
+
+    for (fields, text) in [
+        (
+            serde_json::json!({"view_once": true}),
+            "View-once messages can only be sent to one user",
+        ),
+        (
+            serde_json::json!({"expires_in_secs": 0}),
+            "expires_in_secs must be between 1 and 2592000",
+        ),
+    ] {
+        let mut data = serde_json::json!({"conversation_id": group_id, "body": "x"});
+        data.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        group(1, &mut alice, "group_message", data).await;
+        let refused = next(&mut alice.1).await;
+        assert_eq!(refused.command, "error");
+        assert!(refused.data.contains(text), "{}", refused.data);
+    }
+    assert!(bob.1.is_empty());
+
+    group(
+        1,
+        &mut alice,
+        "group_message",
+        serde_json::json!({"conversation_id": group_id, "body": "brief", "expires_in_secs": 5}),
+    )
+    .await;
+    let event: GroupMessageEvent = serde_json::from_str(&next(&mut bob.1).await.data).unwrap();
+    let message_id = event.message_id.unwrap();
+    assert!(event.expires_at.is_some());
+
+    conn.lock()
+        .unwrap()
+        .execute(
+            "UPDATE messages SET expires_at = 0 WHERE id = ?1",
+            [message_id],
+        )
+        .unwrap();
+    assert_eq!(
+        sweep_expired_messages(&state, Arc::clone(&conn))
+            .await
+            .unwrap(),
+        1
+    );
+    for session in [&mut alice, &mut bob] {
+        let event = expired_event(&next(&mut session.1).await);
+        assert_eq!(
+            (event.message_id, event.conversation_id),
+            (message_id, Some(group_id))
+        );
+    }
+}
+
+#[test]
+fn lifetimes_outside_the_configured_range_are_refused() {
+    let config = ExpirySection {
+        max_expires_in_secs: 60,
+        ..ExpirySection::default()
+    };
+    assert_eq!(invalid_expiry(&config, None), None);
+    assert_eq!(invalid_expiry(&config, Some(1)), None);
+    assert_eq!(invalid_expiry(&config, Some(60)), None);
+    for secs in [0, 61] {
+        assert_eq!(
+            invalid_expiry(&config, Some(secs)).as_deref(),
+            Some("expires_in_secs must be between 1 and 60")
+        );
+    }
+}
@@ -0,0 +1,430 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+
+use rura_server::messaging::groups::handle_group_command;
+use rura_server::messaging::models::{
+    GroupListResponse, GroupMessageEvent, GroupResponse, GroupSystemMessage, GroupUpdatedEvent,
+};
+use rura_server::messaging::queue::{SessionReceiver, SessionSender};
+use rura_server::messaging::state::AppState;
+use rura_server::models::client_message::ClientMessage;
+use rura_server::utils::db_utils::{fetch_messages_for_user, set_message_saved};
+
+use common::{assert_forbidden, command, db_with_users, next, online};
+
+async fn post(
+    state: &Arc<AppState>,
+    conn: &Arc<Mutex<Connection>>,
+    session: &SessionSender,
+    user_id: i64,
+    conversation_id: i64,
+    body: &str,
+) {
+    let msg = ClientMessage {
+        command: "group_message".to_string(),
+        data: serde_json::json!({"conversation_id": conversation_id, "body": body}).to_string(),
+    };
+    handle_group_command(Arc::clone(state), Arc::clone(conn), session, user_id, msg).await;
+}
+
+/// Discard events already queued for a session.
+async fn drain(rx: &mut SessionReceiver) {
+    while !rx.is_empty() {
+        let _ = rx.recv().await;
+    }
+}
+
+fn group_response(msg: &ClientMessage) -> GroupResponse {
+    assert_eq!(msg.command, "group_response");
+    serde_json::from_str(&msg.data).unwrap()
+}
+
+#[tokio::test]
+async fn group_lifecycle_notifies_members_and_fans_out_messages() {
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    // Carol stays offline and reads the group from history
+
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_create",
+            serde_json::json!({"name": " Team ", "member_ids": [2, 2, 1]}),
+        )
+        .await,
+    );
+    assert!(resp.success, "{}", resp.message);
+    let group = resp.conversation.unwrap();
+    assert_eq!(group.name, "Team");
+    assert_eq!(group.member_ids, [1, 2]);
+
+    let created = next(&mut bob.1).await;
+    assert_eq!(created.command, "group_updated");
+    let created: GroupUpdatedEvent = serde_json::from_str(&created.data).unwrap();
+    assert_eq!((created.change.as_str(), created.actor_id), ("created", 1));
+
+    // Unknown invitees are rejected as a whole
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_invite",
+            serde_json::json!({"conversation_id": group.id, "user_ids": [3, 99]}),
+        )
+        .await,
+    );
+    assert!(!resp.success);
+    assert_eq!(resp.message, "Unknown user ids: 99");
+
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_invite",
+            serde_json::json!({"conversation_id": group.id, "user_ids": [3]}),
+        )
+        .await,
+    );
+    assert_eq!(resp.conversation.unwrap().member_ids, [1, 2, 3]);
+    assert_eq!(next(&mut bob.1).await.command, "group_updated");
+
+    // Bob posts; Alice gets it live, Bob gets no echo
+    post(&state, &conn, &bob.0, 2, group.id, "hi all").await;
+    let delivered = next(&mut alice.1).await;
+    assert_eq!(delivered.command, "group_message");
+    let event: GroupMessageEvent = serde_json::from_str(&delivered.data).unwrap();
+    assert_eq!(
+        (
+            event.conversation_id,
+            event.from_user_id,
+            event.body.as_str()
+        ),
+        (group.id, 2, "hi all")
+    );
+    assert!(bob.1.is_empty());
+
+    let carol_history: Vec<_> = fetch_messages_for_user(Arc::clone(&conn), 3, 10)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|m| !m.system)
+        .collect();
+    assert_eq!(carol_history.len(), 1);
+    assert_eq!(carol_history[0].conversation_id, Some(group.id));
+    assert_eq!(carol_history[0].receiver, None);
+
+    let list = command(
+        handle_group_command,
+        &state,
+        &conn,
+        &mut bob,
+        2,
+        "group_list",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(list.command, "group_list_response");
+    let list: GroupListResponse = serde_json::from_str(&list.data).unwrap();
+    assert_eq!(list.conversations.len(), 1);
+    assert_eq!(list.conversations[0].id, group.id);
+}
+
+#[tokio::test]
+async fn members_only_see_messages_from_their_membership() {
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+
+    let group = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_create",
+            serde_json::json!({"name": "Team", "member_ids": [2]}),
+        )
+        .await,
+    )
+    .conversation
+    .unwrap();
+    let _ = next(&mut bob.1).await;
+
+    post(&state, &conn, &alice.0, 1, group.id, "before carol").await;
+    let _ = next(&mut bob.1).await;
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_invite",
+            serde_json::json!({"conversation_id": group.id, "user_ids": [3]}),
+        )
+        .await,
+    );
+    assert!(resp.success);
+    let _ = next(&mut bob.1).await;
+    post(&state, &conn, &alice.0, 1, group.id, "with carol").await;
+    let _ = next(&mut bob.1).await;
+
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "group_leave",
+            serde_json::json!({"conversation_id": group.id}),
+        )
+        .await,
+    );
+    assert_eq!(resp.conversation.unwrap().member_ids, [1, 3]);
+    assert_eq!(next(&mut alice.1).await.command, "group_updated");
+    post(&state, &conn, &alice.0, 1, group.id, "after bob").await;
+    assert!(bob.1.is_empty());
+
+    let bodies = |user_id: i64| {
+        let conn = Arc::clone(&conn);
+        async move {
+            fetch_messages_for_user(conn, user_id, 10)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|m| !m.system)
+                .map(|m| m.content)
+                .collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(bodies(2).await, ["before carol", "with carol"]);
+    assert_eq!(bodies(3).await, ["with carol", "after bob"]);
+
+    // Former members cannot post or save, non-members get no details
+    let err = {
+        post(&state, &conn, &bob.0, 2, group.id, "still here?").await;
+        next(&mut bob.1).await
+    };
+    assert_eq!(
+        (err.command.as_str(), err.data.as_str()),
+        ("error", "Group not found or not a member")
+    );
+    let first_id = fetch_messages_for_user(Arc::clone(&conn), 1, 1)
+        .await
+        .unwrap()[0]
+        .id;
+    assert!(
+        !set_message_saved(Arc::clone(&conn), 3, first_id, true)
+            .await
+            .unwrap()
+    );
+    assert!(
+        set_message_saved(Arc::clone(&conn), 2, first_id, true)
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn roles_gate_moderation_and_changes_are_recorded() {
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+
+    let group = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_create",
+            serde_json::json!({"name": "Team", "member_ids": [2, 3]}),
+        )
+        .await,
+    )
+    .conversation
+    .unwrap();
+    assert_eq!(group.members[0].role, "owner");
+    let id = group.id;
+
+    // Plain members cannot invite, rename or kick
+    for (cmd, data) in [
+        (
+            "group_invite",
+            serde_json::json!({"conversation_id": id, "user_ids": [1]}),
+        ),
+        (
+            "group_rename",
+            serde_json::json!({"conversation_id": id, "name": "Mine"}),
+        ),
+        (
+            "group_kick",
+            serde_json::json!({"conversation_id": id, "user_id": 3}),
+        ),
+    ] {
+        drain(&mut bob.1).await;
+        assert_forbidden(
+            &command(handle_group_command, &state, &conn, &mut bob, 2, cmd, data).await,
+        );
+    }
+
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_set_role",
+            serde_json::json!({"conversation_id": id, "user_id": 2, "role": "admin"}),
+        )
+        .await,
+    );
+    assert_eq!(resp.conversation.unwrap().members[1].role, "admin");
+
+    // Admins moderate members but not the owner
+    drain(&mut bob.1).await;
+    assert_forbidden(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "group_kick",
+            serde_json::json!({"conversation_id": id, "user_id": 1}),
+        )
+        .await,
+    );
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "group_mute",
+            serde_json::json!({"conversation_id": id, "user_id": 3, "duration_secs": 600}),
+        )
+        .await,
+    );
+    assert!(resp.conversation.unwrap().members[2].muted_until.is_some());
+    drain(&mut carol.1).await;
+    post(&state, &conn, &carol.0, 3, id, "let me talk").await;
+    assert_forbidden(&next(&mut carol.1).await);
+
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "group_kick",
+            serde_json::json!({"conversation_id": id, "user_id": 3}),
+        )
+        .await,
+    );
+    assert_eq!(resp.conversation.unwrap().member_ids, [1, 2]);
+    let kicked = next(&mut carol.1).await;
+    let kicked: GroupUpdatedEvent = serde_json::from_str(&kicked.data).unwrap();
+    assert_eq!(
+        (kicked.change.as_str(), kicked.user_ids),
+        ("kicked", vec![3])
+    );
+
+    // The owner has to hand over the group before leaving
+    drain(&mut alice.1).await;
+    let leave = serde_json::json!({"conversation_id": id});
+    assert_forbidden(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_leave",
+            leave.clone(),
+        )
+        .await,
+    );
+    let resp = group_response(
+        &command(
+            handle_group_command,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "group_transfer",
+            serde_json::json!({"conversation_id": id, "user_id": 2}),
+        )
+        .await,
+    );
+    let roles: Vec<_> = resp
+        .conversation
+        .unwrap()
+        .members
+        .into_iter()
+        .map(|m| m.role)
+        .collect();
+    assert_eq!(roles, ["admin", "owner"]);
+    assert!(
+        group_response(
+            &command(
+                handle_group_command,
+                &state,
+                &conn,
+                &mut alice,
+                1,
+                "group_leave",
+                leave
+            )
+            .await
+        )
+        .success
+    );
+
+    let changes: Vec<String> = fetch_messages_for_user(Arc::clone(&conn), 2, 50)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|m| m.system)
+        .map(|m| {
+            serde_json::from_str::<GroupSystemMessage>(&m.content)
+                .unwrap()
+                .change
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            "created",
+            "role_changed",
+            "muted",
+            "kicked",
+            "transferred",
+            "left"
+        ]
+    );
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use rura_server::models::config::{Config, LimitsSection, TimeoutsSection};
+use rura_server::server::accept_loop;
+use rura_server::utils::certgen::{generate_dev_certs, write_certs};
+use rura_server::utils::db_utils::init_db_with_path;
+use rura_server::utils::tls::{ReloadingCertResolver, make_reloading_acceptor};
+
+use common::{app_state, connect, connect_with_buffer, read_msg, register, send};
+
+#[tokio::test]
+async fn unauthenticated_socket_is_dropped_after_auth_timeout() {
+    let state = app_state(Config {
+        timeouts: TimeoutsSection {
+            auth_secs: 1,
+            idle_secs: 0,
+            ping_interval_secs: 0,
+        },
+        ..Config::default()
+    });
+    let (mut client, handle) = connect(&state);
+    assert_eq!(
+        read_msg(&mut client).await.unwrap().command,
+        "auth_required"
+    );
+
+    // Pings do not extend the login deadline
+    send(&mut client, "ping", "probe").await;
+    let pong = read_msg(&mut client).await.unwrap();
+    assert_eq!(
+        (pong.command.as_str(), pong.data.as_str()),
+        ("pong", "probe")
+    );
+
+    let err = read_msg(&mut client).await.unwrap();
+    assert_eq!(err.command, "error");
+    assert_eq!(err.data, "Authentication timed out");
+    assert!(read_msg(&mut client).await.is_none());
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn socket_that_never_starts_tls_is_dropped_after_auth_timeout() {
+    let dir = std::env::temp_dir().join(format!("rura-heartbeat-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let certs = generate_dev_certs(&["localhost".to_string()], 1).unwrap();
+    let paths = write_certs(&dir, &certs, false).unwrap();
+    let resolver = ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap();
+    let acceptor = make_reloading_acceptor(Arc::new(resolver), None);
+    let state = app_state(Config {
+        timeouts: TimeoutsSection {
+            auth_secs: 1,
+            idle_secs: 0,
+            ping_interval_secs: 0,
+        },
+        ..Config::default()
+    });
+    let conn = Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(accept_loop(listener, acceptor, conn, Arc::clone(&state)));
+
+    // Open TCP and say nothing: the server hangs up once the login time is up
+    let mut tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let n = timeout(Duration::from_secs(5), tcp.read(&mut [0u8; 64]))
+        .await
+        .expect("silent socket was kept open")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+    state.shutdown_token().cancel();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn answered_pings_keep_the_session_alive() {
+    let state = app_state(Config {
+        timeouts: TimeoutsSection {
+            auth_secs: 1,
+            idle_secs: 2,
+            ping_interval_secs: 1,
+        },
+        ..Config::default()
+    });
+    let (mut client, _handle) = connect(&state);
+    let user_id = register(&mut client, "alice").await;
+
+    // Well past both the auth and idle timeouts, as long as pongs come back
+    for expected_seq in 1..=3 {
+        let ping = read_msg(&mut client).await.unwrap();
+        assert_eq!(ping.command, "ping");
+        assert_eq!(ping.data, expected_seq.to_string());
+        send(&mut client, "pong", &ping.data).await;
+    }
+    assert!(state.get_sender(user_id).await.is_some());
+}
+
+#[tokio::test]
+async fn silent_peer_is_dropped_and_unregistered() {
+    let state = app_state(Config {
+        timeouts: TimeoutsSection {
+            auth_secs: 0,
+            idle_secs: 2,
+            ping_interval_secs: 1,
+        },
+        ..Config::default()
+    });
+    let (mut client, handle) = connect(&state);
+    let user_id = register(&mut client, "alice").await;
+    assert!(state.get_sender(user_id).await.is_some());
+
+    // Never answer: one ping, then the idle timeout closes the session
+    assert_eq!(read_msg(&mut client).await.unwrap().command, "ping");
+    assert!(read_msg(&mut client).await.is_none());
+    handle.await.unwrap().unwrap();
+    assert!(state.get_sender(user_id).await.is_none());
+}
+
+#[tokio::test]
+async fn peer_that_stops_reading_pings_is_dropped() {
+    let config = Config {
+        timeouts: TimeoutsSection {
+            auth_secs: 0,
+            idle_secs: 30,
+            ping_interval_secs: 1,
+        },
+        limits: LimitsSection {
+            slow_consumer_secs: 1,
+            ..LimitsSection::default()
+        },
+        ..Config::default()
+    };
+    let state = app_state(config);
+    // Room for two pings; the third blocks until the client reads
+    let (mut client, handle) = connect_with_buffer(&state, 64);
+    let user_id = register(&mut client, "alice").await;
+
+    // Never read again, long before the idle timeout
+    timeout(Duration::from_secs(10), handle)
+        .await
+        .expect("blocked ping kept the session open")
+        .unwrap()
+        .unwrap();
+    assert!(state.get_sender(user_id).await.is_none());
+    drop(client);
+}
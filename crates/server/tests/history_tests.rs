@@ -1,102 +1,46 @@
+mod common;
+
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-
+use rura_server::messaging::models::{HistoryRequest, HistoryResponse};
 use rura_server::messaging::state::AppState;
-use rura_server::models::client_message::{AuthRequest, ClientMessage};
-use rura_server::utils::db_utils::store_message;
+use rura_server::utils::db_utils::{init_db_with_path, store_message};
+
+use common::{connect_to, read_msg, register, send};
 
 #[tokio::test]
 async fn history_returns_persisted_messages_for_user() {
-    // In-memory DB schema
-    let conn = Arc::new(Mutex::new(rusqlite::Connection::open(":memory:").unwrap()));
-    {
-        let c = conn.lock().unwrap();
-        c.execute(
-            "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, passphrase TEXT NOT NULL UNIQUE, password TEXT NOT NULL)",
-            [],
-        ).unwrap();
-        c.execute(
-            "CREATE TABLE messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                sender INTEGER NOT NULL,
-                receiver INTEGER NOT NULL,
-                content TEXT NOT NULL,
-                timestamp TEXT NOT NULL,
-                saved INTEGER NOT NULL DEFAULT 0,
-                FOREIGN KEY(sender) REFERENCES users(id),
-                FOREIGN KEY(receiver) REFERENCES users(id)
-            )",
-            [],
-        )
-        .unwrap();
-        c.execute(
-            "CREATE TABLE connections (id INTEGER PRIMARY KEY AUTOINCREMENT, ip TEXT NOT NULL, timestamp TEXT NOT NULL)",
-            [],
-        ).unwrap();
-    }
+    // In-memory DB with the real schema
+    let conn = Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()));
 
     let state = Arc::new(AppState::default());
 
     // Use a duplex stream to run the full client handler
-    let (server_stream, mut client_stream) = tokio::io::duplex(4096);
     let client_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 12345);
-    let h = tokio::spawn(rura_server::client::handle_client(
-        server_stream,
-        Arc::clone(&conn),
-        Arc::clone(&state),
-        client_addr,
-    ));
-
-    // Read auth_required
-    let mut buf = [0u8; 2048];
-    let n = client_stream.read(&mut buf).await.unwrap();
-    let _ = String::from_utf8_lossy(&buf[..n]).to_string();
+    let (mut client_stream, h) = connect_to(&state, Arc::clone(&conn), client_addr, 4096);
 
     // Register user 1
-    let reg = ClientMessage {
-        command: "register".to_string(),
-        data: serde_json::to_string(&AuthRequest {
-            passphrase: "alice".into(),
-            password: "secret".into(),
-        })
-        .unwrap(),
-    };
-    let mut line = serde_json::to_string(&reg).unwrap();
-    line.push('\n');
-    client_stream.write_all(line.as_bytes()).await.unwrap();
-    client_stream.flush().await.unwrap();
-
-    // Read auth_response
-    let n = client_stream.read(&mut buf).await.unwrap();
-    let raw = String::from_utf8_lossy(&buf[..n]).trim().to_string();
-    let wrap: ClientMessage = serde_json::from_str(&raw).unwrap();
-    assert_eq!(wrap.command, "auth_response");
+    let user_id = register(&mut client_stream, "alice").await;
 
     // Insert a message for user 1 -> 1
-    let _ = store_message(Arc::clone(&conn), 1, 1, "hello history", false)
+    let _ = store_message(Arc::clone(&conn), user_id, user_id, "hello history", false)
         .await
         .unwrap();
 
     // Request history
-    let req = rura_server::messaging::models::HistoryRequest { limit: Some(50) };
-    let msg = ClientMessage {
-        command: "history".into(),
-        data: serde_json::to_string(&req).unwrap(),
-    };
-    let mut line = serde_json::to_string(&msg).unwrap();
-    line.push('\n');
-    client_stream.write_all(line.as_bytes()).await.unwrap();
-    client_stream.flush().await.unwrap();
+    let req = HistoryRequest { limit: Some(50) };
+    send(
+        &mut client_stream,
+        "history",
+        &serde_json::to_string(&req).unwrap(),
+    )
+    .await;
 
     // Read history_response
-    let n = client_stream.read(&mut buf).await.unwrap();
-    let raw = String::from_utf8_lossy(&buf[..n]).trim().to_string();
-    let wrap: ClientMessage = serde_json::from_str(&raw).unwrap();
+    let wrap = read_msg(&mut client_stream).await.unwrap();
     assert_eq!(wrap.command, "history_response");
-    let parsed: rura_server::messaging::models::HistoryResponse =
-        serde_json::from_str(&wrap.data).unwrap();
+    let parsed: HistoryResponse = serde_json::from_str(&wrap.data).unwrap();
     assert!(parsed.success);
     assert!(parsed.messages.iter().any(|m| m.body == "hello history"));
 
@@ -1,6 +1,7 @@
 use rura_server::client::handle_client;
 use rura_server::messaging::state::AppState;
 use rura_server::models::client_message::{AuthRequest, AuthResponse, ClientMessage};
+use rura_server::utils::db_utils::init_db_with_path;
 use rusqlite::Connection;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
@@ -8,37 +9,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
 async fn setup_memory_db() -> Arc<Mutex<Connection>> {
-    let conn = Connection::open(":memory:").unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            passphrase TEXT NOT NULL UNIQUE,
-            password TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            sender INTEGER NOT NULL,
-            receiver INTEGER NOT NULL,
-            content TEXT NOT NULL,
-            timestamp TEXT NOT NULL,
-            saved INTEGER NOT NULL DEFAULT 0
-        )",
-        [],
-    )
-    .unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS connections (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            ip TEXT NOT NULL,
-            timestamp TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
+    let conn = init_db_with_path(":memory:").unwrap();
     Arc::new(Mutex::new(conn))
 }
 
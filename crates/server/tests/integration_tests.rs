@@ -1,6 +1,6 @@
 use rura_server::auth::handlers::handle_auth;
 use rura_server::models::client_message::{AuthRequest, AuthResponse, ClientMessage};
-use rura_server::utils::db_utils::register_user;
+use rura_server::utils::db_utils::{init_db_with_path, register_user};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncReadExt, DuplexStream, duplex};
@@ -10,18 +10,7 @@ fn test_socket_addr() -> SocketAddr {
 }
 
 async fn create_test_db() -> Arc<Mutex<rusqlite::Connection>> {
-    let conn = rusqlite::Connection::open(":memory:").unwrap();
-
-    conn.execute(
-        "CREATE TABLE users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            passphrase TEXT NOT NULL UNIQUE,
-            password TEXT NOT NULL
-        )",
-        [],
-    )
-    .unwrap();
-
+    let conn = init_db_with_path(":memory:").unwrap();
     Arc::new(Mutex::new(conn))
 }
 
@@ -0,0 +1,143 @@
+mod common;
+
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use rura_server::admin::run_admin_command;
+use rura_server::messaging::state::AppState;
+use rura_server::models::args::{Command, UserCommand};
+use rura_server::models::client_message::{AuthRequest, AuthResponse};
+use rura_server::models::config::{Config, LoginSection, RateLimit};
+use rura_server::utils::db_utils::{init_db_with_path, register_user};
+
+use common::{app_state, connect_to, read_msg, send};
+
+struct Server {
+    state: Arc<AppState>,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Server {
+    async fn new(login: LoginSection) -> Self {
+        let mut config = Config {
+            login,
+            ..Config::default()
+        };
+        // Keep the rate limiter out of the way of repeated attempts
+        config.rate_limits.auth = RateLimit::new(0, 0);
+        let conn = Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()));
+        register_user(Arc::clone(&conn), "alice", "secret")
+            .await
+            .unwrap();
+        Self {
+            state: app_state(config),
+            conn,
+        }
+    }
+
+    /// One login over a fresh connection from `ip`.
+    async fn login(&self, ip: u8, passphrase: &str, password: &str) -> AuthResponse {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, ip)), 40000);
+        let (mut client, _handle) = connect_to(&self.state, Arc::clone(&self.conn), addr, 4096);
+        assert_eq!(
+            read_msg(&mut client).await.unwrap().command,
+            "auth_required"
+        );
+        let req = AuthRequest {
+            passphrase: passphrase.to_string(),
+            password: password.to_string(),
+        };
+        send(&mut client, "login", &serde_json::to_string(&req).unwrap()).await;
+        let resp = read_msg(&mut client).await.unwrap();
+        assert_eq!(resp.command, "auth_response");
+        serde_json::from_str(&resp.data).unwrap()
+    }
+
+    fn event_kinds(&self) -> Vec<(String, String)> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT passphrase, kind FROM auth_events ORDER BY id")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn lockout_is_uniform_and_lifted_by_admin() {
+    let server = Server::new(LoginSection {
+        free_failures: 10,
+        lockout_after: 3,
+        ..LoginSection::default()
+    })
+    .await;
+
+    // Same number of failures for a real and an unknown account, from different IPs
+    for ip in 1..=3 {
+        assert!(!server.login(ip, "alice", "wrong").await.success);
+        assert!(!server.login(ip + 10, "mallory", "wrong").await.success);
+    }
+
+    // Locked: even the right password fails, and both accounts look the same
+    let locked = server.login(4, "alice", "secret").await;
+    let unknown = server.login(14, "mallory", "secret").await;
+    assert!(!locked.success);
+    assert_eq!(locked.message, unknown.message);
+    assert_eq!(locked.message, "Invalid passphrase or password");
+
+    let mut out = Vec::new();
+    run_admin_command(
+        &Command::User(UserCommand::Unlock {
+            passphrase: "alice".into(),
+            ip: None,
+        }),
+        &Config::default(),
+        Arc::clone(&server.conn),
+        &mut Cursor::new(Vec::new()),
+        &mut out,
+    )
+    .await
+    .unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "Unlocked alice\n");
+    assert!(server.login(4, "alice", "secret").await.success);
+
+    let alice: Vec<String> = server
+        .event_kinds()
+        .into_iter()
+        .filter(|(passphrase, _)| passphrase == "alice")
+        .map(|(_, kind)| kind)
+        .collect();
+    assert_eq!(
+        alice,
+        [
+            "failure", "failure", "failure", "lockout", "failure", "success"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failures_from_one_ip_back_off_every_account() {
+    let server = Server::new(LoginSection {
+        free_failures: 1,
+        backoff_base_secs: 1,
+        lockout_after: 0,
+        ..LoginSection::default()
+    })
+    .await;
+
+    // Spread over accounts so only the IP counter passes the free failures
+    assert!(!server.login(7, "bob", "guess").await.success);
+    assert!(!server.login(7, "carol", "guess").await.success);
+    assert!(!server.login(7, "alice", "secret").await.success);
+    // Other IPs are unaffected
+    assert!(server.login(8, "alice", "secret").await.success);
+
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    assert!(server.login(7, "alice", "secret").await.success);
+}
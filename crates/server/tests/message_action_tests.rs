@@ -0,0 +1,559 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+
+use rura_server::messaging::actions::handle_message_action;
+use rura_server::messaging::groups::handle_group_command;
+use rura_server::messaging::handlers::send_direct;
+use rura_server::messaging::models::{
+    DeleteMessageResponse, DirectMessageEvent, DirectMessageReq, EditMessageResponse,
+    GroupMessageEvent, MessageDeletedEvent, MessageEditedEvent, MessageRevisionsResponse,
+    ReactionEvent, ReactionResponse, ThreadResponse,
+};
+use rura_server::messaging::queue::{SessionReceiver, SessionSender};
+use rura_server::messaging::state::AppState;
+use rura_server::models::client_message::ClientMessage;
+use rura_server::utils::db_utils::{
+    MessageLinks, RawMessageRow, add_conversation_members, create_conversation, delete_user,
+    fetch_messages_for_user, store_group_message, store_message, store_message_with,
+};
+
+use common::{assert_forbidden, command, db_with_users, next, online};
+
+#[tokio::test]
+async fn sender_edits_direct_message_and_keeps_revisions() {
+    let conn = db_with_users(&["alice", "bob"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let id = store_message(Arc::clone(&conn), 1, 2, "helo", false)
+        .await
+        .unwrap();
+
+    let resp = command(
+        handle_message_action,
+        &state,
+        &conn,
+        &mut alice,
+        1,
+        "edit_message",
+        serde_json::json!({"message_id": id, "body": "hello"}),
+    )
+    .await;
+    assert_eq!(resp.command, "edit_response");
+    let resp: EditMessageResponse = serde_json::from_str(&resp.data).unwrap();
+    assert!(resp.success, "{}", resp.message);
+    let edited_at = resp.edited_at.unwrap();
+
+    let event = next(&mut bob.1).await;
+    assert_eq!(event.command, "message_edited");
+    let event: MessageEditedEvent = serde_json::from_str(&event.data).unwrap();
+    assert_eq!(
+        (event.message_id, event.to_user_id, event.body.as_str()),
+        (id, Some(2), "hello")
+    );
+    assert_eq!(event.edited_at, edited_at);
+    assert!(alice.1.is_empty());
+
+    // Only the sender may edit
+    assert_forbidden(
+        &command(
+            handle_message_action,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "edit_message",
+            serde_json::json!({"message_id": id, "body": "hacked"}),
+        )
+        .await,
+    );
+
+    let history = fetch_messages_for_user(Arc::clone(&conn), 2, 10)
+        .await
+        .unwrap();
+    assert_eq!(history[0].content, "hello");
+    assert_eq!(history[0].edited_at.as_deref(), Some(edited_at.as_str()));
+
+    let resp = command(
+        handle_message_action,
+        &state,
+        &conn,
+        &mut bob,
+        2,
+        "message_revisions",
+        serde_json::json!({"message_id": id}),
+    )
+    .await;
+    assert_eq!(resp.command, "message_revisions_response");
+    let resp: MessageRevisionsResponse = serde_json::from_str(&resp.data).unwrap();
+    let bodies: Vec<_> = resp.revisions.iter().map(|r| r.body.as_str()).collect();
+    assert_eq!(bodies, ["helo"]);
+    assert_eq!(resp.revisions[0].replaced_at, edited_at);
+}
+
+#[tokio::test]
+async fn edits_close_after_the_window_and_reach_group_members() {
+    let conn = db_with_users(&["alice", "bob", "carol", "dave"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+    let mut dave = online(&state, 4).await;
+    let group = create_conversation(Arc::clone(&conn), 1, "team", &[2, 3])
+        .await
+        .unwrap();
+    let old = store_group_message(Arc::clone(&conn), 1, group.id, "yesterday", false)
+        .await
+        .unwrap();
+    let fresh = store_group_message(Arc::clone(&conn), 1, group.id, "tpyo", false)
+        .await
+        .unwrap();
+    let long_ago = (chrono::Local::now() - chrono::Duration::hours(1)).to_rfc3339();
+    conn.lock()
+        .unwrap()
+        .execute(
+            "UPDATE messages SET timestamp = ?1 WHERE id = ?2",
+            rusqlite::params![long_ago, old],
+        )
+        .unwrap();
+
+    assert_forbidden(
+        &command(
+            handle_message_action,
+            &state,
+            &conn,
+            &mut alice,
+            1,
+            "edit_message",
+            serde_json::json!({"message_id": old, "body": "today"}),
+        )
+        .await,
+    );
+
+    // Outsiders cannot tell the message exists
+    let resp = command(
+        handle_message_action,
+        &state,
+        &conn,
+        &mut dave,
+        4,
+        "edit_message",
+        serde_json::json!({"message_id": fresh, "body": "typo"}),
+    )
+    .await;
+    let resp: EditMessageResponse = serde_json::from_str(&resp.data).unwrap();
+    assert_eq!(
+        (resp.success, resp.message.as_str()),
+        (false, "Message not found")
+    );
+
+    let resp = command(
+        handle_message_action,
+        &state,
+        &conn,
+        &mut alice,
+        1,
+        "edit_message",
+        serde_json::json!({"message_id": fresh, "body": "typo"}),
+    )
+    .await;
+    let resp: EditMessageResponse = serde_json::from_str(&resp.data).unwrap();
+    assert!(resp.success, "{}", resp.message);
+    for session in [&mut bob, &mut carol] {
+        let event: MessageEditedEvent =
+            serde_json::from_str(&next(&mut session.1).await.data).unwrap();
+        assert_eq!(
+            (event.conversation_id, event.body.as_str()),
+            (Some(group.id), "typo")
+        );
+    }
+    assert!(dave.1.is_empty());
+}
+
+#[tokio::test]
+async fn delete_for_me_hides_and_delete_for_everyone_leaves_a_tombstone() {
+    let conn = db_with_users(&["alice", "bob"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mine = store_message(Arc::clone(&conn), 1, 2, "just for bob", false)
+        .await
+        .unwrap();
+    let oops = store_message(Arc::clone(&conn), 1, 2, "wrong chat", true)
+        .await
+        .unwrap();
+
+    let resp = command(
+        handle_message_action,
+        &state,
+        &conn,
+        &mut bob,
+        2,
+        "delete_message",
+        serde_json::json!({"message_id": mine, "scope": "me"}),
+    )
+    .await;
+    assert_eq!(resp.command, "delete_response");
+    let resp: DeleteMessageResponse = serde_json::from_str(&resp.data).unwrap();
+    assert!(resp.success, "{}", resp.message);
+    let ids = |rows: Vec<RawMessageRow>| rows.into_iter().map(|m| m.id).collect::<Vec<_>>();
+    assert_eq!(
+        ids(fetch_messages_for_user(Arc::clone(&conn), 2, 10)
+            .await
+            .unwrap()),
+        [oops]
+    );
+    assert_eq!(
+        ids(fetch_messages_for_user(Arc::clone(&conn), 1, 10)
+            .await
+            .unwrap()),
+        [mine, oops]
+    );
+    assert!(alice.1.is_empty());
+
+    // Only the sender deletes for everyone
+    assert_forbidden(
+        &command(
+            handle_message_action,
+            &state,
+            &conn,
+            &mut bob,
+            2,
+            "delete_message",
+            serde_json::json!({"message_id": oops, "scope": "everyone"}),
+        )
+        .await,
+    );
+
+    let resp = command(
+        handle_message_action,
+        &state,
+        &conn,
+        &mut alice,
+        1,
+        "delete_message",
+        serde_json::json!({"message_id": oops, "scope": "everyone"}),
+    )
+    .await;
+    let resp: DeleteMessageResponse = serde_json::from_str(&resp.data).unwrap();
+    assert!(resp.success, "{}", resp.message);
+    let event = next(&mut bob.1).await;
+    assert_eq!(event.command, "message_deleted");
+    let event: MessageDeletedEvent = serde_json::from_str(&event.data).unwrap();
+    assert_eq!((event.message_id, event.to_user_id), (oops, Some(2)));
+
+    // Devices that were offline find the tombstone on their next sync
+    let synced = fetch_messages_for_user(Arc::clone(&conn), 2, 10)
+        .await
+        .unwrap();
+    assert_eq!(synced.len(), 1);
+    assert_eq!(synced[0].content, "");
+    assert!(!synced[0].saved);
+    assert_eq!(
+        synced[0].deleted_at.as_deref(),
+        Some(event.deleted_at.as_str())
+    );
+
+    // A deleted message can no longer be edited
+    let resp = command(
+        handle_message_action,
+        &state,
+        &conn,
+        &mut alice,
+        1,
+        "edit_message",
+        serde_json::json!({"message_id": oops, "body": "right chat"}),
+    )
+    .await;
+    let resp: EditMessageResponse = serde_json::from_str(&resp.data).unwrap();
+    assert_eq!(
+        (resp.success, resp.message.as_str()),
+        (false, "Message not found")
+    );
+
+    assert!(delete_user(Arc::clone(&conn), 2).await.unwrap());
+}
+
+#[tokio::test]
+async fn reactions_are_unique_per_user_and_aggregated_in_history() {
+    let conn = db_with_users(&["alice", "bob", "carol", "dave"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+    let mut dave = online(&state, 4).await;
+    let group = create_conversation(Arc::clone(&conn), 1, "team", &[2, 3])
+        .await
+        .unwrap();
+    let id = store_group_message(Arc::clone(&conn), 1, group.id, "ship it?", false)
+        .await
+        .unwrap();
+
+    async fn react(
+        state: &Arc<AppState>,
+        conn: &Arc<Mutex<Connection>>,
+        session: &mut (SessionSender, SessionReceiver),
+        user_id: i64,
+        command_name: &str,
+        message_id: i64,
+        emoji: &str,
+    ) -> ReactionResponse {
+        let resp = command(
+            handle_message_action,
+            state,
+            conn,
+            session,
+            user_id,
+            command_name,
+            serde_json::json!({"message_id": message_id, "emoji": emoji}),
+        )
+        .await;
+        assert_eq!(resp.command, "reaction_response");
+        serde_json::from_str(&resp.data).unwrap()
+    }
+
+    let resp = react(&state, &conn, &mut bob, 2, "react", id, "👍").await;
+    assert_eq!(resp.message, "Reaction added");
+    for session in [&mut alice, &mut carol] {
+        let event = next(&mut session.1).await;
+        assert_eq!(event.command, "reaction");
+        let event: ReactionEvent = serde_json::from_str(&event.data).unwrap();
+        assert_eq!(
+            (event.user_id, event.emoji.as_str(), event.added),
+            (2, "👍", true)
+        );
+        assert_eq!(event.conversation_id, Some(group.id));
+    }
+    // Reacting twice with the same emoji changes nothing
+    let resp = react(&state, &conn, &mut bob, 2, "react", id, "👍").await;
+    assert_eq!(
+        (resp.success, resp.message.as_str()),
+        (true, "Already reacted")
+    );
+    assert!(alice.1.is_empty());
+
+    react(&state, &conn, &mut carol, 3, "react", id, "👍").await;
+    react(&state, &conn, &mut carol, 3, "react", id, "🎉").await;
+    react(&state, &conn, &mut carol, 3, "unreact", id, "🎉").await;
+    let mut seen = Vec::new();
+    for _ in 0..3 {
+        let event: ReactionEvent = serde_json::from_str(&next(&mut alice.1).await.data).unwrap();
+        seen.push((event.emoji, event.added));
+    }
+    assert_eq!(
+        seen,
+        [
+            ("👍".to_string(), true),
+            ("🎉".to_string(), true),
+            ("🎉".to_string(), false)
+        ]
+    );
+    react(&state, &conn, &mut alice, 1, "react", id, "🎉").await;
+
+    // Non-participants are refused like `save`
+    let resp = react(&state, &conn, &mut dave, 4, "react", id, "👀").await;
+    assert!(!resp.success);
+    let resp = react(&state, &conn, &mut dave, 4, "react", id, "not an emoji").await;
+    assert!(!resp.success);
+
+    let history = fetch_messages_for_user(Arc::clone(&conn), 3, 10)
+        .await
+        .unwrap();
+    let message = history.iter().find(|m| m.id == id).unwrap();
+    let counts: Vec<_> = message
+        .reactions
+        .iter()
+        .map(|r| (r.emoji.as_str(), r.count))
+        .collect();
+    assert_eq!(counts, [("👍", 2), ("🎉", 1)]);
+    assert_eq!(message.my_reactions, ["👍"]);
+}
+
+#[tokio::test]
+async fn replies_quote_their_target_and_threads_page_forward() {
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let mut bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+    let root = store_message(Arc::clone(&conn), 1, 2, "lunch at noon?", false)
+        .await
+        .unwrap();
+    let elsewhere = store_message(Arc::clone(&conn), 1, 3, "unrelated", false)
+        .await
+        .unwrap();
+
+    async fn reply(
+        state: &Arc<AppState>,
+        conn: &Arc<Mutex<Connection>>,
+        from: i64,
+        to: i64,
+        body: &str,
+        reply_to_id: i64,
+    ) {
+        let req = DirectMessageReq {
+            to_user_id: to,
+            body: body.to_string(),
+            saved: None,
+            reply_to_id: Some(reply_to_id),
+            attachment_id: None,
+            expires_in_secs: None,
+            view_once: false,
+        };
+        send_direct(Arc::clone(state), Arc::clone(conn), from, req)
+            .await
+            .unwrap();
+    }
+
+    reply(&state, &conn, 2, 1, "sure", root).await;
+    let event: DirectMessageEvent = serde_json::from_str(&next(&mut alice.1).await.data).unwrap();
+    let quote = event.reply_to.unwrap();
+    assert_eq!(
+        (quote.message_id, quote.from_user_id, quote.snippet.as_str()),
+        (root, 1, "lunch at noon?")
+    );
+    let first = fetch_messages_for_user(Arc::clone(&conn), 1, 10)
+        .await
+        .unwrap()
+        .last()
+        .unwrap()
+        .id;
+    reply(&state, &conn, 1, 2, "great", first).await;
+    next(&mut bob.1).await;
+    reply(&state, &conn, 2, 1, "see you", root).await;
+    next(&mut alice.1).await;
+
+    // A target from another chat is dropped rather than quoted
+    reply(&state, &conn, 2, 1, "which one?", elsewhere).await;
+    let event: DirectMessageEvent = serde_json::from_str(&next(&mut alice.1).await.data).unwrap();
+    assert!(event.reply_to.is_none());
+
+    let history = fetch_messages_for_user(Arc::clone(&conn), 2, 10)
+        .await
+        .unwrap();
+    let quoted: Vec<_> = history
+        .iter()
+        .map(|m| m.reply_to.as_ref().map(|q| q.message_id))
+        .collect();
+    assert_eq!(quoted, [None, Some(root), Some(first), Some(root), None]);
+
+    async fn thread(
+        state: &Arc<AppState>,
+        conn: &Arc<Mutex<Connection>>,
+        session: &mut (SessionSender, SessionReceiver),
+        user_id: i64,
+        root_id: i64,
+        after_id: Option<i64>,
+    ) -> ThreadResponse {
+        let resp = command(
+            handle_message_action,
+            state,
+            conn,
+            session,
+            user_id,
+            "thread",
+            serde_json::json!({"root_id": root_id, "after_id": after_id, "limit": 2}),
+        )
+        .await;
+        assert_eq!(resp.command, "thread_response");
+        serde_json::from_str(&resp.data).unwrap()
+    }
+    let page = thread(&state, &conn, &mut bob, 2, root, None).await;
+    let bodies: Vec<_> = page.messages.iter().map(|m| m.body.as_str()).collect();
+    assert_eq!(bodies, ["sure", "great"]);
+    let page = thread(&state, &conn, &mut bob, 2, root, Some(page.messages[1].id)).await;
+    let bodies: Vec<_> = page.messages.iter().map(|m| m.body.as_str()).collect();
+    assert_eq!(bodies, ["see you"]);
+    assert_eq!(page.messages[0].reply_to.as_ref().unwrap().message_id, root);
+
+    // Outsiders cannot read the thread
+    let page = thread(&state, &conn, &mut carol, 3, root, None).await;
+    assert!(!page.success);
+    assert!(page.messages.is_empty());
+}
+
+/// Quote text of the newest message in `user_id`'s history.
+async fn latest_quote(conn: &Arc<Mutex<Connection>>, user_id: i64) -> (i64, String) {
+    let history = fetch_messages_for_user(Arc::clone(conn), user_id, 50)
+        .await
+        .unwrap();
+    let quote = history.last().unwrap().reply_to.clone().unwrap();
+    (quote.message_id, quote.snippet)
+}
+
+#[tokio::test]
+async fn late_joiners_do_not_see_the_text_of_earlier_quotes() {
+    let conn = db_with_users(&["alice", "bob", "carol"]);
+    let state = Arc::new(AppState::default());
+    let mut alice = online(&state, 1).await;
+    let bob = online(&state, 2).await;
+    let mut carol = online(&state, 3).await;
+    let group = create_conversation(Arc::clone(&conn), 1, "team", &[2])
+        .await
+        .unwrap();
+    let old = store_group_message(Arc::clone(&conn), 1, group.id, "before carol", false)
+        .await
+        .unwrap();
+    add_conversation_members(Arc::clone(&conn), group.id, &[3])
+        .await
+        .unwrap();
+
+    let msg = ClientMessage {
+        command: "group_message".to_string(),
+        data: serde_json::json!({
+            "conversation_id": group.id,
+            "body": "agreed",
+            "reply_to_id": old,
+        })
+        .to_string(),
+    };
+    handle_group_command(Arc::clone(&state), Arc::clone(&conn), &bob.0, 2, msg).await;
+
+    let quote_in = |event: ClientMessage| {
+        assert_eq!(event.command, "group_message");
+        let event: GroupMessageEvent = serde_json::from_str(&event.data).unwrap();
+        let quote = event.reply_to.unwrap();
+        (quote.message_id, quote.snippet)
+    };
+    assert_eq!(
+        quote_in(next(&mut alice.1).await),
+        (old, "before carol".to_string())
+    );
+    assert_eq!(quote_in(next(&mut carol.1).await), (old, String::new()));
+
+    assert_eq!(
+        latest_quote(&conn, 1).await,
+        (old, "before carol".to_string())
+    );
+    assert_eq!(latest_quote(&conn, 3).await, (old, String::new()));
+}
+
+#[tokio::test]
+async fn expired_targets_are_not_quoted_before_the_sweep() {
+    let conn = db_with_users(&["alice", "bob"]);
+    let target = store_message(Arc::clone(&conn), 1, 2, "gone soon", false)
+        .await
+        .unwrap();
+    let links = MessageLinks {
+        reply_to_id: Some(target),
+        ..MessageLinks::default()
+    };
+    store_message_with(Arc::clone(&conn), 2, Some(1), None, "noted", false, links)
+        .await
+        .unwrap();
+    assert_eq!(
+        latest_quote(&conn, 1).await,
+        (target, "gone soon".to_string())
+    );
+
+    // Expired, but the sweeper has not deleted it yet
+    conn.lock()
+        .unwrap()
+        .execute("UPDATE messages SET expires_at = 1 WHERE id = ?1", [target])
+        .unwrap();
+    assert_eq!(latest_quote(&conn, 1).await, (target, String::new()));
+    assert_eq!(latest_quote(&conn, 2).await, (target, String::new()));
+}
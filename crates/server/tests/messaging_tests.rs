@@ -4,7 +4,6 @@ use rura_server::messaging::state::{AppState, ClientHandle};
 use rura_server::models::client_message::ClientMessage;
 use rusqlite::Connection;
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
 use tokio::time::{Duration, timeout};
 
 #[tokio::test]
@@ -28,7 +27,7 @@ async fn test_send_direct_to_online_user_delivers_message() {
     }
 
     // Simulate recipient user with an outbound channel registered in state
-    let (tx_bob, mut rx_bob) = mpsc::unbounded_channel::<ClientMessage>();
+    let (tx_bob, mut rx_bob) = state.outbound_channel();
     let bob_id = 2_i64;
     state.register(bob_id, ClientHandle { tx: tx_bob }).await;
 
@@ -40,6 +39,10 @@ async fn test_send_direct_to_online_user_delivers_message() {
         to_user_id: bob_id,
         body: "hello world".to_string(),
         saved: None,
+        reply_to_id: None,
+        attachment_id: None,
+        expires_in_secs: None,
+        view_once: false,
     };
     send_direct(Arc::clone(&state), Arc::clone(&conn), alice_id, req)
         .await
@@ -88,7 +91,7 @@ async fn test_send_direct_to_unknown_user_sends_nothing() {
     }
 
     // Create a channel for some other user and register them (not the target)
-    let (tx_other, mut rx_other) = mpsc::unbounded_channel::<ClientMessage>();
+    let (tx_other, mut rx_other) = state.outbound_channel();
     state.register(999, ClientHandle { tx: tx_other }).await;
 
     // Attempt to send to a user id that is not registered
@@ -98,6 +101,10 @@ async fn test_send_direct_to_unknown_user_sends_nothing() {
         to_user_id: unknown_user_id,
         body: "are you there?".to_string(),
         saved: None,
+        reply_to_id: None,
+        attachment_id: None,
+        expires_in_secs: None,
+        view_once: false,
     };
     send_direct(Arc::clone(&state), Arc::clone(&conn), from_user_id, req)
         .await
@@ -0,0 +1,337 @@
+use rusqlite::{Connection, params};
+
+use rura_server::utils::migrations::{
+    MIGRATIONS, current_version, latest_version, pending_migrations, run_migrations,
+    run_migrations_to,
+};
+
+fn columns_for(conn: &Connection, table: &str) -> Vec<String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({table})"))
+        .unwrap();
+    stmt.query_map([], |row| row.get::<_, String>(1))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+}
+
+// Schema written by servers that predate the `saved` flag (unversioned).
+fn fixture_pre_saved() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(
+        "CREATE TABLE users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            passphrase TEXT NOT NULL UNIQUE,
+            password TEXT NOT NULL
+        );
+        CREATE TABLE messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            sender INTEGER NOT NULL,
+            receiver INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            FOREIGN KEY(sender) REFERENCES users(id),
+            FOREIGN KEY(receiver) REFERENCES users(id)
+        );
+        CREATE TABLE connections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ip TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        );
+        INSERT INTO users (passphrase, password) VALUES ('alice', 'hash');
+        INSERT INTO messages (sender, receiver, content, timestamp)
+            VALUES (1, 1, 'old message', '2024-01-01T00:00:00+00:00');",
+    )
+    .unwrap();
+    conn
+}
+
+// Schema written by servers with the `saved` flag but before versioning.
+fn fixture_unversioned_with_saved() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(
+        "CREATE TABLE users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            passphrase TEXT NOT NULL UNIQUE,
+            password TEXT NOT NULL
+        );
+        CREATE TABLE messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            sender INTEGER NOT NULL,
+            receiver INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            saved INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY(sender) REFERENCES users(id),
+            FOREIGN KEY(receiver) REFERENCES users(id)
+        );
+        CREATE TABLE connections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ip TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        );
+        INSERT INTO users (passphrase, password) VALUES ('alice', 'hash');
+        INSERT INTO messages (sender, receiver, content, timestamp, saved)
+            VALUES (1, 1, 'saved message', '2024-01-01T00:00:00+00:00', 1);",
+    )
+    .unwrap();
+    conn
+}
+
+fn assert_fully_migrated(conn: &Connection) {
+    assert_eq!(current_version(conn).unwrap(), latest_version());
+    assert!(pending_migrations(conn).unwrap().is_empty());
+    for table in ["users", "messages", "connections"] {
+        assert!(
+            !columns_for(conn, table).is_empty(),
+            "expected table `{}` to exist",
+            table
+        );
+    }
+    let message_columns = columns_for(conn, "messages");
+    assert!(message_columns.contains(&"saved".to_string()));
+    assert!(message_columns.contains(&"conversation_id".to_string()));
+    assert!(message_columns.contains(&"edited_at".to_string()));
+    assert!(!columns_for(conn, "conversation_members").is_empty());
+    assert!(!columns_for(conn, "message_edits").is_empty());
+    assert!(message_columns.contains(&"deleted_at".to_string()));
+    assert!(!columns_for(conn, "message_hidden").is_empty());
+    assert!(!columns_for(conn, "message_reactions").is_empty());
+    assert!(message_columns.contains(&"reply_to_id".to_string()));
+    assert!(!columns_for(conn, "attachments").is_empty());
+    assert!(message_columns.contains(&"attachment_id".to_string()));
+    assert!(!columns_for(conn, "attachment_blobs").is_empty());
+    assert!(columns_for(conn, "attachments").contains(&"thumbnail_id".to_string()));
+    assert!(message_columns.contains(&"expires_at".to_string()));
+    assert!(columns_for(conn, "conversations").contains(&"retention_days".to_string()));
+    assert!(columns_for(conn, "bookmarks").contains(&"folder".to_string()));
+    assert!(!columns_for(conn, "bookmark_tags").is_empty());
+    let owner_required: bool = conn
+        .query_row(
+            "SELECT \"notnull\" FROM pragma_table_info('attachments') WHERE name = 'owner_id'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(!owner_required);
+    assert!(columns_for(conn, "login_throttle").contains(&"updated_at".to_string()));
+}
+
+#[test]
+fn migrations_are_strictly_ordered() {
+    let versions: Vec<u32> = MIGRATIONS.iter().map(|m| m.version).collect();
+    assert_eq!(versions.first(), Some(&1));
+    assert!(versions.windows(2).all(|w| w[1] == w[0] + 1));
+}
+
+#[test]
+fn fresh_database_migrates_to_latest() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    assert_eq!(current_version(&conn).unwrap(), 0);
+    assert_eq!(pending_migrations(&conn).unwrap().len(), MIGRATIONS.len());
+
+    let applied = run_migrations(&mut conn).unwrap();
+    assert_eq!(applied.len(), MIGRATIONS.len());
+    assert_fully_migrated(&conn);
+}
+
+#[test]
+fn pre_saved_database_gains_saved_column_and_keeps_rows() {
+    let mut conn = fixture_pre_saved();
+    run_migrations(&mut conn).unwrap();
+    assert_fully_migrated(&conn);
+
+    let (content, saved): (String, i64) = conn
+        .query_row(
+            "SELECT content, saved FROM messages WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(content, "old message");
+    assert_eq!(saved, 0);
+}
+
+#[test]
+fn unversioned_database_with_saved_column_is_adopted() {
+    let mut conn = fixture_unversioned_with_saved();
+    run_migrations(&mut conn).unwrap();
+    assert_fully_migrated(&conn);
+
+    let saved: i64 = conn
+        .query_row("SELECT saved FROM messages WHERE id = 1", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(saved, 1);
+}
+
+#[test]
+fn every_versioned_database_upgrades_to_latest() {
+    for start in 1..=latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations_to(&mut conn, start).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), start);
+
+        let applied = run_migrations(&mut conn).unwrap();
+        assert_eq!(applied.len() as u32, latest_version() - start);
+        assert_fully_migrated(&conn);
+    }
+}
+
+#[test]
+fn group_roles_migration_assigns_one_owner_per_group() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    run_migrations_to(&mut conn, 5).unwrap();
+    conn.execute_batch(
+        "INSERT INTO users (passphrase, password) VALUES ('a', 'x'), ('b', 'x'), ('c', 'x');
+        INSERT INTO conversations (id, name, created_by, created_at)
+            VALUES (1, 'kept', 1, 't'), (2, 'creator left', 1, 't');
+        INSERT INTO conversation_members (conversation_id, user_id, joined_after, left_after)
+            VALUES (1, 1, 0, NULL), (1, 2, 0, NULL),
+                   (2, 1, 0, 0), (2, 3, 0, NULL), (2, 2, 5, NULL);",
+    )
+    .unwrap();
+
+    run_migrations(&mut conn).unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT conversation_id, user_id FROM conversation_members
+             WHERE role = 'owner' ORDER BY conversation_id",
+        )
+        .unwrap();
+    let owners: Vec<(i64, i64)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(owners, [(1, 1), (2, 3)]);
+}
+
+#[test]
+fn attachment_blobs_migration_counts_existing_references() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    run_migrations_to(&mut conn, 12).unwrap();
+    conn.execute_batch(
+        "INSERT INTO users (passphrase, password) VALUES ('a', 'x'), ('b', 'x');
+        INSERT INTO attachments (id, owner_id, file_name, mime_type, size, sha256, received,
+                                 created_at, completed_at)
+            VALUES (1, 1, 'a', 'text/plain', 3, 'h1', 3, 't', 't'),
+                   (2, 2, 'b', 'text/plain', 3, 'h1', 3, 't', 't'),
+                   (3, 2, 'c', 'text/plain', 3, 'h2', 1, 't', NULL);
+        INSERT INTO messages (sender, receiver, content, timestamp, attachment_id)
+            VALUES (1, 2, 'x', 't', 1), (2, 1, 'y', 't', 1), (2, 1, 'z', 't', NULL);",
+    )
+    .unwrap();
+
+    run_migrations(&mut conn).unwrap();
+    let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap();
+    assert_eq!(
+        count("SELECT message_refs FROM attachments WHERE id = 1"),
+        2
+    );
+    assert_eq!(
+        count("SELECT message_refs FROM attachments WHERE id = 2"),
+        0
+    );
+    // Only completed uploads hold a blob
+    assert_eq!(count("SELECT COUNT(*) FROM attachment_blobs"), 1);
+    assert_eq!(
+        count("SELECT ref_count FROM attachment_blobs WHERE sha256 = 'h1'"),
+        2
+    );
+
+    // From here on the triggers keep both counts
+    conn.execute("DELETE FROM messages WHERE attachment_id = 1", [])
+        .unwrap();
+    conn.execute("DELETE FROM attachments WHERE id = 2", [])
+        .unwrap();
+    conn.execute("UPDATE attachments SET completed_at = 't' WHERE id = 3", [])
+        .unwrap();
+    assert_eq!(
+        count("SELECT message_refs FROM attachments WHERE id = 1"),
+        0
+    );
+    assert_eq!(
+        count("SELECT ref_count FROM attachment_blobs WHERE sha256 = 'h1'"),
+        1
+    );
+    assert_eq!(
+        count("SELECT ref_count FROM attachment_blobs WHERE sha256 = 'h2'"),
+        1
+    );
+}
+
+#[test]
+fn bookmarks_migration_saves_for_everyone_who_could_see() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    run_migrations_to(&mut conn, 16).unwrap();
+    conn.execute_batch(
+        "INSERT INTO users (passphrase, password) VALUES ('a', 'x'), ('b', 'x'), ('c', 'x');
+        INSERT INTO conversations (id, name, created_by, created_at) VALUES (1, 'g', 1, 't');
+        INSERT INTO conversation_members (conversation_id, user_id, joined_after, left_after)
+            VALUES (1, 1, 0, NULL), (1, 2, 0, NULL), (1, 3, 5, NULL);
+        INSERT INTO messages (id, sender, receiver, conversation_id, content, timestamp, saved)
+            VALUES (1, 1, 2, NULL, 'dm', 't', 1), (2, 1, 2, NULL, 'other', 't', 0),
+                   (3, 2, NULL, 1, 'group', 't', 1);",
+    )
+    .unwrap();
+
+    run_migrations(&mut conn).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT user_id, message_id FROM bookmarks ORDER BY message_id, user_id")
+        .unwrap();
+    let bookmarks: Vec<(i64, i64)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    // c joined the group after message 3
+    assert_eq!(bookmarks, [(1, 1), (2, 1), (1, 3), (2, 3)]);
+
+    // From here on `saved` stays set while anyone has the message bookmarked
+    let saved = |conn: &Connection| {
+        conn.query_row("SELECT saved FROM messages WHERE id = 1", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .unwrap()
+    };
+    conn.execute(
+        "DELETE FROM bookmarks WHERE user_id = 1 AND message_id = 1",
+        [],
+    )
+    .unwrap();
+    assert_eq!(saved(&conn), 1);
+    conn.execute("DELETE FROM bookmarks WHERE message_id = 1", [])
+        .unwrap();
+    assert_eq!(saved(&conn), 0);
+}
+
+#[test]
+fn running_migrations_twice_is_a_no_op() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    run_migrations(&mut conn).unwrap();
+    conn.execute(
+        "INSERT INTO users (passphrase, password) VALUES (?1, ?2)",
+        params!["bob", "hash"],
+    )
+    .unwrap();
+
+    let applied = run_migrations(&mut conn).unwrap();
+    assert!(applied.is_empty());
+    let users: i64 = conn
+        .query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(users, 1);
+}
+
+#[test]
+fn newer_database_is_refused() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.pragma_update(None, "user_version", latest_version() + 1)
+        .unwrap();
+
+    let err = run_migrations(&mut conn).expect_err("newer schema must be refused");
+    assert!(err.to_string().contains("newer than supported"));
+    assert!(pending_migrations(&conn).is_err());
+}
@@ -0,0 +1,240 @@
+use rura_server::client::handle_client_with_cert;
+use rura_server::messaging::state::AppState;
+use rura_server::models::client_message::{AuthRequest, AuthResponse, ClientMessage};
+use rura_server::utils::certgen::{
+    GeneratedCerts, GeneratedClientCert, generate_dev_certs, issue_client_cert, write_certs,
+};
+use rura_server::utils::db_utils::{
+    CertBindingKind, bind_client_certificate, init_db_with_path, register_user, set_user_disabled,
+};
+use rura_server::utils::tls::{
+    ReloadingCertResolver, client_cert_identity, make_client_verifier, make_reloading_acceptor,
+};
+use rusqlite::Connection;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+struct Fixture {
+    certs: GeneratedCerts,
+    db: Arc<Mutex<Connection>>,
+    port: u16,
+    dir: PathBuf,
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn start_server(name: &str, require_client_cert: bool) -> Fixture {
+    let dir = std::env::temp_dir().join(format!("rura-mtls-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let certs = generate_dev_certs(&["localhost".to_string()], 1).unwrap();
+    let paths = write_certs(&dir, &certs, false).unwrap();
+
+    let resolver =
+        Arc::new(ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap());
+    let verifier =
+        make_client_verifier(paths.ca_cert.to_str().unwrap(), require_client_cert).unwrap();
+    let acceptor = make_reloading_acceptor(resolver, Some(verifier));
+
+    let db = Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()));
+    let state = Arc::new(AppState::default());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server_db = Arc::clone(&db);
+    tokio::spawn(async move {
+        while let Ok((stream, peer)) = listener.accept().await {
+            let (db, state, acceptor) =
+                (Arc::clone(&server_db), Arc::clone(&state), acceptor.clone());
+            tokio::spawn(async move {
+                if let Ok(tls) = acceptor.accept(stream).await {
+                    let identity = client_cert_identity(tls.get_ref().1);
+                    let _ = handle_client_with_cert(tls, db, state, peer, identity).await;
+                }
+            });
+        }
+    });
+    Fixture {
+        certs,
+        db,
+        port,
+        dir,
+    }
+}
+
+fn client_cert(fixture: &Fixture, name: &str) -> GeneratedClientCert {
+    issue_client_cert(
+        &fixture.certs.ca_cert_pem,
+        &fixture.certs.ca_key_pem,
+        name,
+        1,
+    )
+    .unwrap()
+}
+
+async fn connect(
+    fixture: &Fixture,
+    client: Option<&GeneratedClientCert>,
+) -> std::io::Result<TlsStream<TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut fixture.certs.ca_cert_pem.as_bytes()) {
+        roots.add(cert?).unwrap();
+    }
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let config = match client {
+        Some(client) => {
+            let chain = rustls_pemfile::certs(&mut client.cert_pem.as_bytes())
+                .collect::<Result<Vec<_>, _>>()?;
+            let key = rustls_pemfile::private_key(&mut client.key_pem.as_bytes())?.unwrap();
+            builder.with_client_auth_cert(chain, key).unwrap()
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let tcp = TcpStream::connect(("127.0.0.1", fixture.port)).await?;
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+}
+
+async fn read_msg(stream: &mut TlsStream<TcpStream>) -> std::io::Result<ClientMessage> {
+    let mut buf = [0u8; 4096];
+    let n = stream.read(&mut buf).await?;
+    if n == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(serde_json::from_str(String::from_utf8_lossy(&buf[..n]).trim()).unwrap())
+}
+
+async fn send(stream: &mut TlsStream<TcpStream>, command: &str, data: String) {
+    let msg = ClientMessage {
+        command: command.to_string(),
+        data,
+    };
+    let mut line = serde_json::to_string(&msg).unwrap();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await.unwrap();
+}
+
+fn auth_response(msg: &ClientMessage) -> AuthResponse {
+    assert_eq!(msg.command, "auth_response");
+    serde_json::from_str(&msg.data).unwrap()
+}
+
+#[tokio::test]
+async fn bound_fingerprint_logs_in_without_password() {
+    let fixture = start_server("fingerprint", false).await;
+    let alice = register_user(Arc::clone(&fixture.db), "alice", "secret")
+        .await
+        .unwrap();
+    let cert = client_cert(&fixture, "device-1");
+    bind_client_certificate(
+        Arc::clone(&fixture.db),
+        alice,
+        CertBindingKind::Fingerprint,
+        &cert.fingerprint,
+    )
+    .await
+    .unwrap();
+
+    let mut stream = connect(&fixture, Some(&cert)).await.unwrap();
+    let resp = auth_response(&read_msg(&mut stream).await.unwrap());
+    assert!(resp.success);
+    assert_eq!(resp.user_id, Some(alice));
+
+    // The session is fully authenticated: authed-only commands work
+    send(&mut stream, "history", "{}".to_string()).await;
+    assert_eq!(
+        read_msg(&mut stream).await.unwrap().command,
+        "history_response"
+    );
+}
+
+#[tokio::test]
+async fn bound_subject_logs_in_and_disabled_user_falls_back() {
+    let fixture = start_server("subject", false).await;
+    let bob = register_user(Arc::clone(&fixture.db), "bob", "secret")
+        .await
+        .unwrap();
+    bind_client_certificate(
+        Arc::clone(&fixture.db),
+        bob,
+        CertBindingKind::Subject,
+        "bob",
+    )
+    .await
+    .unwrap();
+    let cert = client_cert(&fixture, "bob");
+
+    let mut stream = connect(&fixture, Some(&cert)).await.unwrap();
+    assert_eq!(
+        auth_response(&read_msg(&mut stream).await.unwrap()).user_id,
+        Some(bob)
+    );
+
+    set_user_disabled(Arc::clone(&fixture.db), bob, true)
+        .await
+        .unwrap();
+    let mut stream = connect(&fixture, Some(&cert)).await.unwrap();
+    assert_eq!(
+        read_msg(&mut stream).await.unwrap().command,
+        "auth_required"
+    );
+}
+
+#[tokio::test]
+async fn unbound_certificate_and_no_certificate_use_password_login() {
+    let fixture = start_server("unbound", false).await;
+    register_user(Arc::clone(&fixture.db), "carol", "secret")
+        .await
+        .unwrap();
+    let cert = client_cert(&fixture, "stranger");
+
+    for client in [Some(&cert), None] {
+        let mut stream = connect(&fixture, client).await.unwrap();
+        assert_eq!(
+            read_msg(&mut stream).await.unwrap().command,
+            "auth_required"
+        );
+        let req = AuthRequest {
+            passphrase: "carol".to_string(),
+            password: "secret".to_string(),
+        };
+        send(&mut stream, "login", serde_json::to_string(&req).unwrap()).await;
+        assert!(auth_response(&read_msg(&mut stream).await.unwrap()).success);
+    }
+}
+
+#[tokio::test]
+async fn required_mode_rejects_missing_or_foreign_certificates() {
+    let fixture = start_server("required", true).await;
+
+    // TLS 1.3 clients finish their side first; the rejection shows up on first read
+    let mut stream = connect(&fixture, None).await.unwrap();
+    assert!(read_msg(&mut stream).await.is_err());
+
+    let foreign_ca = generate_dev_certs(&["localhost".to_string()], 1).unwrap();
+    let foreign = issue_client_cert(
+        &foreign_ca.ca_cert_pem,
+        &foreign_ca.ca_key_pem,
+        "mallory",
+        1,
+    )
+    .unwrap();
+    let mut stream = connect(&fixture, Some(&foreign)).await.unwrap();
+    assert!(read_msg(&mut stream).await.is_err());
+
+    let cert = client_cert(&fixture, "device-2");
+    let mut stream = connect(&fixture, Some(&cert)).await.unwrap();
+    assert_eq!(
+        read_msg(&mut stream).await.unwrap().command,
+        "auth_required"
+    );
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use rura_server::models::client_message::{AuthRequest, ClientMessage, ErrorResponse};
+use rura_server::models::config::{Config, RateLimit, RateLimitsSection};
+
+use common::{app_state, connect, read_msg, register, send};
+
+fn credentials(password: &str) -> String {
+    serde_json::to_string(&AuthRequest {
+        passphrase: "carol".to_string(),
+        password: password.to_string(),
+    })
+    .unwrap()
+}
+
+fn rate_limited(msg: &ClientMessage) -> ErrorResponse {
+    assert_eq!(msg.command, "error");
+    let err: ErrorResponse = serde_json::from_str(&msg.data).unwrap();
+    assert_eq!(err.code, "RateLimited");
+    err
+}
+
+#[tokio::test]
+async fn login_attempts_are_limited_per_ip_before_auth() {
+    let state = app_state(Config {
+        rate_limits: RateLimitsSection {
+            auth: RateLimit::new(2, 6),
+            ..RateLimitsSection::default()
+        },
+        ..Config::default()
+    });
+    let (mut client, _handle) = connect(&state);
+    assert_eq!(
+        read_msg(&mut client).await.unwrap().command,
+        "auth_required"
+    );
+    for _ in 0..2 {
+        send(&mut client, "login", &credentials("wrong")).await;
+        assert_eq!(
+            read_msg(&mut client).await.unwrap().command,
+            "auth_response"
+        );
+    }
+    send(&mut client, "login", &credentials("wrong")).await;
+    let err = rate_limited(&read_msg(&mut client).await.unwrap());
+    // One token every 10 s, minus whatever refilled during the two attempts
+    let retry_after_ms = err.retry_after_ms.unwrap();
+    assert!(
+        (5_000..=10_000).contains(&retry_after_ms),
+        "{retry_after_ms}"
+    );
+
+    // The bucket belongs to the IP, so a fresh connection does not reset it
+    let (mut other, _handle) = connect(&state);
+    read_msg(&mut other).await.unwrap();
+    send(&mut other, "register", &credentials("secret")).await;
+    rate_limited(&read_msg(&mut other).await.unwrap());
+
+    // Pings draw from `other`, not from the exhausted auth bucket
+    send(&mut other, "ping", "still here").await;
+    assert_eq!(read_msg(&mut other).await.unwrap().command, "pong");
+}
+
+#[tokio::test]
+async fn pings_before_auth_are_limited_but_pongs_are_not() {
+    let state = app_state(Config {
+        rate_limits: RateLimitsSection {
+            other: RateLimit::new(2, 1),
+            ..RateLimitsSection::default()
+        },
+        ..Config::default()
+    });
+    let (mut client, _handle) = connect(&state);
+    read_msg(&mut client).await.unwrap();
+    for _ in 0..2 {
+        send(&mut client, "ping", "1").await;
+        assert_eq!(read_msg(&mut client).await.unwrap().command, "pong");
+    }
+    send(&mut client, "ping", "1").await;
+    rate_limited(&read_msg(&mut client).await.unwrap());
+
+    // Answers to the server's heartbeat still cost nothing
+    for _ in 0..5 {
+        send(&mut client, "pong", "1").await;
+    }
+    send(&mut client, "login", &credentials("wrong")).await;
+    assert_eq!(
+        read_msg(&mut client).await.unwrap().command,
+        "auth_response"
+    );
+}
+
+#[tokio::test]
+async fn repeat_offender_is_disconnected() {
+    let state = app_state(Config {
+        rate_limits: RateLimitsSection {
+            disconnect_after: 3,
+            history: RateLimit::new(1, 1),
+            ..RateLimitsSection::default()
+        },
+        ..Config::default()
+    });
+    let (mut client, handle) = connect(&state);
+    let user_id = register(&mut client, "carol").await;
+
+    send(&mut client, "history", "{}").await;
+    assert_eq!(
+        read_msg(&mut client).await.unwrap().command,
+        "history_response"
+    );
+    for _ in 0..2 {
+        send(&mut client, "history", "{}").await;
+        let err = rate_limited(&read_msg(&mut client).await.unwrap());
+        assert!(err.retry_after_ms.unwrap() > 0);
+    }
+
+    // Third rejection within the minute: error, then the connection closes
+    send(&mut client, "history", "{}").await;
+    rate_limited(&read_msg(&mut client).await.unwrap());
+    assert!(read_msg(&mut client).await.is_none());
+    handle.await.unwrap().unwrap();
+    assert!(state.get_sender(user_id).await.is_none());
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+use rura_server::models::client_message::{ClientMessage, ServerShutdownEvent};
+use rura_server::models::config::{Config, ShutdownSection};
+use rura_server::server::accept_loop;
+use rura_server::utils::certgen::{generate_dev_certs, write_certs};
+use rura_server::utils::db_utils::{close_db, init_db_with_path};
+use rura_server::utils::tls::{ReloadingCertResolver, make_reloading_acceptor};
+
+use common::{app_state, connect_with_buffer, read_msg, register};
+
+/// Next envelope over a real socket, or `None` once the server closed it.
+async fn next_msg<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Option<ClientMessage> {
+    let mut line = String::new();
+    let n = timeout(Duration::from_secs(5), reader.read_line(&mut line))
+        .await
+        .expect("server went silent")
+        .unwrap();
+    (n > 0).then(|| serde_json::from_str(&line).unwrap())
+}
+
+fn shutdown_event(msg: &ClientMessage) -> ServerShutdownEvent {
+    assert_eq!(msg.command, "server_shutdown");
+    serde_json::from_str(&msg.data).unwrap()
+}
+
+#[tokio::test]
+async fn sessions_flush_queued_events_then_get_a_reconnect_hint() {
+    let state = app_state(Config {
+        shutdown: ShutdownSection {
+            drain_secs: 5,
+            reconnect_after_secs: 2,
+        },
+        ..Config::default()
+    });
+    let (mut client, handle) = connect_with_buffer(&state, 64 * 1024);
+    let user_id = register(&mut client, "dave").await;
+
+    // Queued right before the signal: still delivered, ahead of the notice
+    let tx = state.get_sender(user_id).await.unwrap();
+    tx.send(ClientMessage {
+        command: "message".to_string(),
+        data: "{\"from_user_id\":2,\"body\":\"last words\"}".to_string(),
+    })
+    .unwrap();
+    state.shutdown_token().cancel();
+
+    assert_eq!(read_msg(&mut client).await.unwrap().command, "message");
+    let event = shutdown_event(&read_msg(&mut client).await.unwrap());
+    assert!((2_000..=4_000).contains(&event.reconnect_after_ms));
+    assert!(read_msg(&mut client).await.is_none());
+    handle.await.unwrap().unwrap();
+    assert!(state.get_sender(user_id).await.is_none());
+}
+
+#[tokio::test]
+async fn accept_loop_stops_accepting_and_drains() {
+    let dir = std::env::temp_dir().join(format!("rura-shutdown-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let certs = generate_dev_certs(&["localhost".to_string()], 1).unwrap();
+    let paths = write_certs(&dir, &certs, false).unwrap();
+    let resolver = ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap();
+    let acceptor = make_reloading_acceptor(Arc::new(resolver), None);
+
+    let state = app_state(Config {
+        shutdown: ShutdownSection {
+            drain_secs: 2,
+            reconnect_after_secs: 0,
+        },
+        ..Config::default()
+    });
+    let conn = Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(accept_loop(
+        listener,
+        acceptor,
+        Arc::clone(&conn),
+        Arc::clone(&state),
+    ));
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut certs.ca_cert_pem.as_bytes()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let tls = TlsConnector::from(Arc::new(client_config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+        .unwrap();
+    let mut client = BufReader::new(tls);
+    assert_eq!(
+        next_msg(&mut client).await.unwrap().command,
+        "auth_required"
+    );
+
+    // Sessions that have not logged in are told as well
+    state.shutdown_token().cancel();
+    let event = shutdown_event(&next_msg(&mut client).await.unwrap());
+    assert_eq!(event.reconnect_after_ms, 0);
+    assert!(next_msg(&mut client).await.is_none());
+
+    timeout(Duration::from_secs(5), server)
+        .await
+        .expect("accept loop did not stop")
+        .unwrap()
+        .unwrap();
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    // No session holds the database any more, so it closes cleanly
+    close_db(conn).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+use rura_server::models::client_message::ClientMessage;
+use rura_server::models::config::{Config, LimitsSection};
+
+use common::{app_state, connect_with_buffer, read_msg, register};
+
+fn event(i: usize) -> ClientMessage {
+    ClientMessage {
+        command: "message".to_string(),
+        data: format!(
+            "{{\"from_user_id\":1,\"body\":\"{}\"}}",
+            "x".repeat(200 + i % 7)
+        ),
+    }
+}
+
+#[tokio::test]
+async fn client_that_stops_reading_is_disconnected() {
+    let config = Config {
+        limits: LimitsSection {
+            outbound_queue_len: 8,
+            slow_consumer_secs: 1,
+            ..LimitsSection::default()
+        },
+        ..Config::default()
+    };
+    let state = app_state(config);
+    // A tiny pipe stands in for a TCP window that the peer no longer drains
+    let (mut client, handle) = connect_with_buffer(&state, 512);
+    let bob = register(&mut client, "bob").await;
+
+    let tx = state.get_sender(bob).await.unwrap();
+    for i in 0..64 {
+        if tx.send(event(i)).is_err() {
+            break;
+        }
+    }
+
+    // The session ends on its own and the user is no longer reachable
+    timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("slow client was not disconnected")
+        .unwrap()
+        .unwrap();
+    assert!(state.get_sender(bob).await.is_none());
+    assert_eq!(state.queue_metrics_snapshot().slow_consumer_disconnects, 1);
+    drop(client);
+}
+
+#[tokio::test]
+async fn reading_client_gets_every_persisted_event() {
+    let config = Config {
+        limits: LimitsSection {
+            outbound_queue_len: 4,
+            ..LimitsSection::default()
+        },
+        ..Config::default()
+    };
+    let state = app_state(config);
+    let (mut client, _handle) = connect_with_buffer(&state, 64 * 1024);
+    let bob = register(&mut client, "bob").await;
+
+    // Bursts beyond the queue length are fine while the client keeps reading
+    let tx = state.get_sender(bob).await.unwrap();
+    for i in 0..6 {
+        tx.send(event(i)).unwrap();
+    }
+    for i in 0..6 {
+        assert_eq!(read_msg(&mut client).await.unwrap().data, event(i).data);
+    }
+    assert_eq!(state.queue_metrics_snapshot().slow_consumer_disconnects, 0);
+    assert!(state.get_sender(bob).await.is_some());
+}
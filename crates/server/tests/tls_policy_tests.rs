@@ -0,0 +1,234 @@
+use rura_server::models::config::{TlsSection, TlsVersion};
+use rura_server::utils::certgen::{CertPaths, GeneratedCerts, generate_dev_certs, write_certs};
+use rura_server::utils::tls::{
+    RURA_ALPN, ReloadingCertResolver, make_server_config, negotiated_rura_alpn,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("rura-tls-policy-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn certs(&self, subdir: &str, names: &[&str]) -> (GeneratedCerts, CertPaths) {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let certs = generate_dev_certs(&names, 1).unwrap();
+        let paths = write_certs(&self.0.join(subdir), &certs, false).unwrap();
+        (certs, paths)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Accepts connections and reports whether each one negotiated `rura/1`.
+async fn start_server(
+    tls: &TlsSection,
+    resolver: ReloadingCertResolver,
+) -> (u16, mpsc::UnboundedReceiver<bool>) {
+    let config = make_server_config(tls, Arc::new(resolver), None).unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (acceptor, tx) = (acceptor.clone(), tx.clone());
+            tokio::spawn(async move {
+                if let Ok(tls) = acceptor.accept(stream).await {
+                    let _ = tx.send(negotiated_rura_alpn(tls.get_ref().1));
+                    // Keep the session open until the client goes away
+                    let (mut reader, _writer) = tokio::io::split(tls);
+                    let _ = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await;
+                }
+            });
+        }
+    });
+    (port, rx)
+}
+
+fn roots(ca_pem: &str) -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut ca_pem.as_bytes()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    roots
+}
+
+fn client_config(ca_pem: &str) -> ClientConfig {
+    ClientConfig::builder()
+        .with_root_certificates(roots(ca_pem))
+        .with_no_client_auth()
+}
+
+async fn connect(
+    port: u16,
+    server_name: &str,
+    config: ClientConfig,
+) -> std::io::Result<TlsStream<TcpStream>> {
+    let tcp = TcpStream::connect(("127.0.0.1", port)).await?;
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from(server_name.to_string()).unwrap(), tcp)
+        .await
+}
+
+#[tokio::test]
+async fn sni_selects_certificate_by_server_name() {
+    let scratch = Scratch::new("sni");
+    let (default, default_paths) = scratch.certs("default", &["localhost"]);
+    let (chat, chat_paths) = scratch.certs("chat", &["chat.rura.test"]);
+    let (wild, wild_paths) = scratch.certs("wild", &["*.dev.rura.test"]);
+    let resolver =
+        ReloadingCertResolver::new(&default_paths.server_cert, &default_paths.server_key)
+            .unwrap()
+            .with_sni(
+                "Chat.Rura.Test",
+                &chat_paths.server_cert,
+                &chat_paths.server_key,
+            )
+            .unwrap()
+            .with_sni(
+                "*.dev.rura.test",
+                &wild_paths.server_cert,
+                &wild_paths.server_key,
+            )
+            .unwrap();
+    let (port, _) = start_server(&TlsSection::default(), resolver).await;
+
+    let cases = [
+        ("localhost", &default),
+        ("chat.rura.test", &chat),
+        ("api.dev.rura.test", &wild),
+    ];
+    for (name, certs) in cases {
+        connect(port, name, client_config(&certs.ca_cert_pem))
+            .await
+            .unwrap_or_else(|e| panic!("{name}: {e}"));
+    }
+    // Unknown names get the default certificate, which does not cover them
+    assert!(
+        connect(port, "other.rura.test", client_config(&default.ca_cert_pem))
+            .await
+            .is_err()
+    );
+    // A wildcard only matches a single label
+    assert!(
+        connect(port, "a.b.dev.rura.test", client_config(&wild.ca_cert_pem))
+            .await
+            .is_err()
+    );
+}
+
+#[test]
+fn sni_certificate_must_cover_its_name() {
+    let scratch = Scratch::new("sni-mismatch");
+    let (_, paths) = scratch.certs("default", &["localhost"]);
+    let err = ReloadingCertResolver::new(&paths.server_cert, &paths.server_key)
+        .unwrap()
+        .with_sni("chat.rura.test", &paths.server_cert, &paths.server_key)
+        .unwrap_err();
+    assert!(err.to_string().contains("not valid for"), "{err}");
+}
+
+#[tokio::test]
+async fn minimum_version_rejects_older_clients() {
+    let scratch = Scratch::new("min-version");
+    let (certs, paths) = scratch.certs("default", &["localhost"]);
+    let tls = TlsSection {
+        min_version: TlsVersion::Tls13,
+        ..TlsSection::default()
+    };
+    let resolver = ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap();
+    let (port, _) = start_server(&tls, resolver).await;
+
+    let tls12_only =
+        ClientConfig::builder_with_protocol_versions(&[&tokio_rustls::rustls::version::TLS12])
+            .with_root_certificates(roots(&certs.ca_cert_pem))
+            .with_no_client_auth();
+    assert!(connect(port, "localhost", tls12_only).await.is_err());
+
+    let stream = connect(port, "localhost", client_config(&certs.ca_cert_pem))
+        .await
+        .unwrap();
+    assert_eq!(
+        stream.get_ref().1.protocol_version(),
+        Some(tokio_rustls::rustls::ProtocolVersion::TLSv1_3)
+    );
+}
+
+#[tokio::test]
+async fn cipher_suites_are_restricted_to_the_configured_list() {
+    let scratch = Scratch::new("suites");
+    let (certs, paths) = scratch.certs("default", &["localhost"]);
+    let tls = TlsSection {
+        cipher_suites: vec!["TLS13_CHACHA20_POLY1305_SHA256".to_string()],
+        ..TlsSection::default()
+    };
+    let resolver = ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap();
+    let (port, _) = start_server(&tls, resolver).await;
+
+    let stream = connect(port, "localhost", client_config(&certs.ca_cert_pem))
+        .await
+        .unwrap();
+    let suite = stream.get_ref().1.negotiated_cipher_suite().unwrap();
+    assert_eq!(
+        format!("{:?}", suite.suite()),
+        "TLS13_CHACHA20_POLY1305_SHA256"
+    );
+}
+
+#[tokio::test]
+async fn alpn_is_advertised_and_reported() {
+    let scratch = Scratch::new("alpn");
+    let (certs, paths) = scratch.certs("default", &["localhost"]);
+    let resolver = ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap();
+    let (port, mut negotiated) = start_server(&TlsSection::default(), resolver).await;
+
+    let mut with_alpn = client_config(&certs.ca_cert_pem);
+    with_alpn.alpn_protocols = vec![RURA_ALPN.to_vec()];
+    let stream = connect(port, "localhost", with_alpn).await.unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(RURA_ALPN));
+    assert!(negotiated.recv().await.unwrap());
+
+    let _plain = connect(port, "localhost", client_config(&certs.ca_cert_pem))
+        .await
+        .unwrap();
+    assert!(!negotiated.recv().await.unwrap());
+
+    let mut foreign = client_config(&certs.ca_cert_pem);
+    foreign.alpn_protocols = vec![b"http/1.1".to_vec()];
+    assert!(connect(port, "localhost", foreign).await.is_err());
+}
+
+#[test]
+fn session_tickets_follow_config() {
+    let scratch = Scratch::new("tickets");
+    let (_, paths) = scratch.certs("default", &["localhost"]);
+    let resolver =
+        Arc::new(ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap());
+
+    let off = make_server_config(&TlsSection::default(), Arc::clone(&resolver), None).unwrap();
+    assert!(!off.ticketer.enabled());
+
+    let tls = TlsSection {
+        session_tickets: true,
+        ..TlsSection::default()
+    };
+    let on = make_server_config(&tls, resolver, None).unwrap();
+    assert!(on.ticketer.enabled());
+}
@@ -0,0 +1,135 @@
+use rura_server::client::handle_client;
+use rura_server::messaging::state::AppState;
+use rura_server::models::client_message::{AuthRequest, ClientMessage};
+use rura_server::utils::certgen::{GeneratedCerts, generate_dev_certs, write_certs};
+use rura_server::utils::db_utils::init_db_with_path;
+use rura_server::utils::tls::{ReloadingCertResolver, make_reloading_acceptor};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rura-tls-reload-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn new_certs() -> GeneratedCerts {
+    generate_dev_certs(&["localhost".to_string()], 1).unwrap()
+}
+
+async fn start_server(resolver: Arc<ReloadingCertResolver>) -> u16 {
+    let db = Arc::new(Mutex::new(init_db_with_path(":memory:").unwrap()));
+    let state = Arc::new(AppState::default());
+    let acceptor = make_reloading_acceptor(resolver, None);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((stream, peer)) = listener.accept().await {
+            let (db, state, acceptor) = (Arc::clone(&db), Arc::clone(&state), acceptor.clone());
+            tokio::spawn(async move {
+                if let Ok(tls) = acceptor.accept(stream).await {
+                    let _ = handle_client(tls, db, state, peer).await;
+                }
+            });
+        }
+    });
+    port
+}
+
+async fn connect(port: u16, ca_pem: &str) -> std::io::Result<TlsStream<TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut ca_pem.as_bytes()) {
+        roots.add(cert?).unwrap();
+    }
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let tcp = TcpStream::connect(("127.0.0.1", port)).await?;
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+}
+
+async fn read_msg(stream: &mut TlsStream<TcpStream>) -> ClientMessage {
+    let mut buf = [0u8; 4096];
+    let n = stream.read(&mut buf).await.unwrap();
+    serde_json::from_str(String::from_utf8_lossy(&buf[..n]).trim()).unwrap()
+}
+
+async fn register(stream: &mut TlsStream<TcpStream>, passphrase: &str) -> ClientMessage {
+    let req = AuthRequest {
+        passphrase: passphrase.to_string(),
+        password: "secret".to_string(),
+    };
+    let msg = ClientMessage {
+        command: "register".to_string(),
+        data: serde_json::to_string(&req).unwrap(),
+    };
+    let mut line = serde_json::to_string(&msg).unwrap();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await.unwrap();
+    read_msg(stream).await
+}
+
+#[tokio::test]
+async fn reload_swaps_certificate_for_new_connections_only() {
+    let dir = scratch_dir("swap");
+    let first = new_certs();
+    let paths = write_certs(&dir, &first, false).unwrap();
+    let resolver =
+        Arc::new(ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap());
+    let port = start_server(Arc::clone(&resolver)).await;
+
+    let mut old_session = connect(port, &first.ca_cert_pem).await.unwrap();
+    assert_eq!(read_msg(&mut old_session).await.command, "auth_required");
+
+    // Rotate to a certificate from a different CA
+    let second = new_certs();
+    write_certs(&dir, &second, true).unwrap();
+    assert!(resolver.reload_if_changed().unwrap());
+    assert!(
+        !resolver.reload_if_changed().unwrap(),
+        "unchanged files are not reloaded"
+    );
+
+    assert!(connect(port, &first.ca_cert_pem).await.is_err());
+    let mut new_session = connect(port, &second.ca_cert_pem).await.unwrap();
+    assert_eq!(read_msg(&mut new_session).await.command, "auth_required");
+
+    // The session established before the rotation keeps working
+    assert_eq!(
+        register(&mut old_session, "alice").await.command,
+        "auth_response"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn bad_certificate_is_rejected_and_old_one_kept() {
+    let dir = scratch_dir("bad");
+    let certs = new_certs();
+    let paths = write_certs(&dir, &certs, false).unwrap();
+    let resolver =
+        Arc::new(ReloadingCertResolver::new(&paths.server_cert, &paths.server_key).unwrap());
+    let port = start_server(Arc::clone(&resolver)).await;
+
+    // Garbage certificate
+    std::fs::write(&paths.server_cert, "not a certificate").unwrap();
+    assert!(resolver.reload().is_err());
+
+    // Valid certificate whose key belongs to another pair
+    let other = new_certs();
+    std::fs::write(&paths.server_cert, &other.server_chain_pem).unwrap();
+    let err = resolver.reload().unwrap_err();
+    assert!(err.to_string().contains("does not match"), "{err}");
+
+    let mut session = connect(port, &certs.ca_cert_pem).await.unwrap();
+    assert_eq!(read_msg(&mut session).await.command, "auth_required");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -7,6 +7,8 @@ set -euo pipefail
 #   PORT: 8443
 #   CERT: <repo>/certs/server.crt
 #   KEY:  <repo>/certs/server.key
+# When the default cert and key are both missing, `gen-cert` creates them
+# (plus certs/ca.crt for clients) before starting.
 
 PORT=8443
 
@@ -40,23 +42,31 @@ while [[ $# -gt 0 ]]; do
   esac
 done
 
+SERVER_DIR="$ROOT_DIR/crates/server"
+if [[ ! -d "$SERVER_DIR" ]]; then
+  echo "[run_server] ERROR: Server crate not found at $SERVER_DIR" >&2
+  exit 5
+fi
+
+# First run with the default paths: create a local CA + server cert
+if [[ "$CERT" == "$CERT_DEFAULT" && "$KEY" == "$KEY_DEFAULT" && ! -f "$CERT" && ! -f "$KEY" ]]; then
+  echo "[run_server] No TLS certificate found; generating dev certs in $ROOT_DIR/certs"
+  pushd "$SERVER_DIR" >/dev/null
+  cargo run --release -- gen-cert --out-dir "$ROOT_DIR/certs"
+  popd >/dev/null
+fi
+
 if [[ ! -f "$CERT" ]]; then
   echo "[run_server] ERROR: TLS certificate not found: $CERT" >&2
-  echo "             Generate or place server.crt at $CERT_DEFAULT or pass --cert PATH" >&2
+  echo "             Run \`rura_server gen-cert\` or pass --cert PATH" >&2
   exit 3
 fi
 if [[ ! -f "$KEY" ]]; then
   echo "[run_server] ERROR: TLS private key not found: $KEY" >&2
-  echo "             Generate or place server.key at $KEY_DEFAULT or pass --key PATH" >&2
+  echo "             Run \`rura_server gen-cert\` or pass --key PATH" >&2
   exit 4
 fi
 
-SERVER_DIR="$ROOT_DIR/crates/server"
-if [[ ! -d "$SERVER_DIR" ]]; then
-  echo "[run_server] ERROR: Server crate not found at $SERVER_DIR" >&2
-  exit 5
-fi
-
 echo "[run_server] Building server (release)"
 pushd "$SERVER_DIR" >/dev/null
 cargo run --release -- --port "$PORT" --tls-cert "$CERT" --tls-key "$KEY"